@@ -1,17 +1,49 @@
 use std::{fs::File, io::Write, path::Path};
 
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+	#[error("{0}")]
+	Io(#[from] std::io::Error),
+	#[error("Couldn't encode the PNG: {0}")]
+	Png(#[from] png::EncodingError),
+	#[error("mozjpeg didn't give us a JPEG")]
+	Jpeg,
+}
+
 // What a great name
+/// Pixels, ready to be encoded. Nothing but the pixels, and the dpi, ICC
+/// profile, and EXIF if you set them, goes in the file. We don't look inside
+/// the EXIF you give us, so apply rawproc's `MetadataPolicy` before you make
+/// it.
 pub struct OutImage {
 	width: usize,
 	height: usize,
+	/// 3 for RGB, 4 for RGBA
+	channels: usize,
 	data: Vec<u8>,
+	/// Pixels per inch, for printing
+	dpi: Option<f32>,
+	/// What colorspace the pixels are in
+	icc: Option<Vec<u8>>,
+	/// A TIFF structure, without JPEG's "Exif\0\0" in front
+	exif: Option<Vec<u8>>,
 }
 
 impl OutImage {
 	/// Make a new Image for Output. If your passed in data is not
 	/// `width * height * 3` bytes long, this function will panic.
 	pub fn new(width: usize, height: usize, data: Vec<u8>) -> Self {
-		if data.len() != width * height * 3 {
+		Self::with_channels(width, height, 3, data)
+	}
+
+	/// Make a new Image for Output that has alpha. Like [new](Self::new),
+	/// except the data is RGBA and has to be `width * height * 4` bytes long.
+	pub fn new_rgba(width: usize, height: usize, data: Vec<u8>) -> Self {
+		Self::with_channels(width, height, 4, data)
+	}
+
+	fn with_channels(width: usize, height: usize, channels: usize, data: Vec<u8>) -> Self {
+		if data.len() != width * height * channels {
 			// Spitting out the square root seems useful 'cause it's a rough
 			// estimation of the dimensions
 			panic!(
@@ -23,48 +55,231 @@ impl OutImage {
 			Self {
 				width,
 				height,
+				channels,
 				data,
+				dpi: None,
+				icc: None,
+				exif: None,
 			}
 		}
 	}
 
-	/// Output the image as a PNG. RGB 8bit depth.
-	// TODO: gen- no more unwrap!
-	pub fn png<P: AsRef<Path>>(&self, path: P) {
-		let file = File::create(path.as_ref()).unwrap();
+	/// Record the resolution the image should be printed at, in pixels per
+	/// inch, so a print driver or lab knows how big to make it. PNG and JPEG
+	/// store it; WebP doesn't have anywhere to put it.
+	pub fn with_dpi(mut self, dpi: f32) -> Self {
+		self.dpi = Some(dpi);
+		self
+	}
+
+	/// Embed an ICC profile so colour managed viewers know what colorspace
+	/// the pixels are in. Without one they guess, usually sRGB. rawproc
+	/// images have an `icc_profile()` that makes the right one.
+	pub fn with_icc_profile(mut self, profile: Vec<u8>) -> Self {
+		self.icc = Some(profile);
+		self
+	}
+
+	/// Embed EXIF, so the file says what it was shot with. It's the TIFF
+	/// structure that rawproc's `exif_bytes()` makes. PNG, JPEG, and WebP
+	/// all store it, though a JPEG can't fit more than 64K of it.
+	pub fn with_exif(mut self, exif: Vec<u8>) -> Self {
+		self.exif = Some(exif);
+		self
+	}
+
+	/// Output the image as a PNG. RGB, or RGBA, 8bit depth.
+	pub fn png<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+		let file = File::create(path.as_ref())?;
 		let mut enc = png::Encoder::new(file, self.width as u32, self.height as u32);
-		enc.set_color(png::ColorType::Rgb);
+		if self.channels == 4 {
+			enc.set_color(png::ColorType::Rgba);
+		} else {
+			enc.set_color(png::ColorType::Rgb);
+		}
 		enc.set_depth(png::BitDepth::Eight);
 
-		let mut writer = enc.write_header().unwrap();
-		writer.write_image_data(&self.data).unwrap()
+		let mut writer = enc.write_header()?;
+		if let Some(dpi) = self.dpi {
+			// pHYs only knows pixels per metre. It's x, y, and then a 1 to say
+			// the unit is metres.
+			let ppm = (dpi / 0.0254).round() as u32;
+			let mut phys = [0; 9];
+			phys[0..4].copy_from_slice(&ppm.to_be_bytes());
+			phys[4..8].copy_from_slice(&ppm.to_be_bytes());
+			phys[8] = 1;
+			writer.write_chunk(png::chunk::pHYs, &phys)?;
+		}
+		if let Some(icc) = &self.icc {
+			// A name, which nobody looks at, then the compression method,
+			// which has to be zlib, then the profile.
+			let mut iccp = b"ICC profile\0\0".to_vec();
+			iccp.extend_from_slice(&miniz_oxide::deflate::compress_to_vec_zlib(icc, 6));
+			writer.write_chunk(png::chunk::iCCP, &iccp)?;
+		}
+		if let Some(exif) = &self.exif {
+			writer.write_chunk(png::chunk::ChunkType(*b"eXIf"), exif)?;
+		}
+		writer.write_image_data(&self.data)?;
+		Ok(())
 	}
 
 	/// Output the image as a JPEG with the provided quality. RGB 8bit depth.
+	/// JPEG has no alpha, so it's dropped.
 	// TODO: gen- Fix panic. mozjpeg will panic if it's unhappy and we should
 	// catch_unwind and return a result
-	pub fn jpeg<P: AsRef<Path>>(&self, path: P, quality: f32) {
-		let mut comp = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_RGB);
+	pub fn jpeg<P: AsRef<Path>>(&self, path: P, quality: f32) -> Result<(), Error> {
+		let colorspace = if self.channels == 4 {
+			mozjpeg::ColorSpace::JCS_EXT_RGBA
+		} else {
+			mozjpeg::ColorSpace::JCS_RGB
+		};
+		let mut comp = mozjpeg::Compress::new(colorspace);
 
 		comp.set_size(self.width, self.height);
 		comp.set_quality(quality);
 		comp.set_mem_dest();
 		comp.start_compress();
-		assert!(comp.write_scanlines(&self.data[..]));
+		if let Some(exif) = &self.exif {
+			write_jpeg_exif(&mut comp, exif);
+		}
+		if let Some(icc) = &self.icc {
+			write_jpeg_icc(&mut comp, icc);
+		}
+		if !comp.write_scanlines(&self.data[..]) {
+			return Err(Error::Jpeg);
+		}
 
 		comp.finish_compress();
 
-		let mut file = File::create(path.as_ref()).unwrap();
-		file.write_all(&comp.data_as_mut_slice().unwrap()).unwrap();
+		let mut data = comp.data_to_vec().map_err(|_| Error::Jpeg)?;
+		if let Some(dpi) = self.dpi {
+			set_jfif_density(&mut data, dpi);
+		}
+
+		let mut file = File::create(path.as_ref())?;
+		file.write_all(&data)?;
+		Ok(())
 	}
 
 	/// Output the image as a lossy WebP with the provided quality.
-	// TODO: gen- no more unwrap :)
-	pub fn webp<P: AsRef<Path>>(&self, path: P, quality: f32) {
-		let enc = webp::Encoder::from_rgb(&self.data, self.width as u32, self.height as u32);
+	pub fn webp<P: AsRef<Path>>(&self, path: P, quality: f32) -> Result<(), Error> {
+		let (width, height) = (self.width as u32, self.height as u32);
+		let enc = if self.channels == 4 {
+			webp::Encoder::from_rgba(&self.data, width, height)
+		} else {
+			webp::Encoder::from_rgb(&self.data, width, height)
+		};
 		let img = enc.encode(quality);
+		let img = match (&self.icc, &self.exif) {
+			(None, None) => img.to_vec(),
+			(icc, exif) => webp_extended(&img, width, height, icc.as_deref(), exif.as_deref()),
+		};
+
+		let mut file = File::create(path.as_ref())?;
+		file.write_all(&img)?;
+		Ok(())
+	}
+}
+
+/// mozjpeg doesn't let us set the density, but it always writes a JFIF
+/// header right after the start of image marker and that's where the
+/// density lives. It's units, 1 being dots per inch, and then the
+/// horizontal and vertical density as big endian u16s.
+fn set_jfif_density(jpeg: &mut [u8], dpi: f32) {
+	if jpeg.len() < 18 || &jpeg[2..4] != b"\xFF\xE0" || &jpeg[6..11] != b"JFIF\0" {
+		return;
+	}
 
-		let mut file = File::create(path.as_ref()).unwrap();
-		file.write_all(&img).unwrap();
+	let density = (dpi.round() as u32).clamp(1, u16::MAX as u32) as u16;
+	jpeg[13] = 1;
+	jpeg[14..16].copy_from_slice(&density.to_be_bytes());
+	jpeg[16..18].copy_from_slice(&density.to_be_bytes());
+}
+
+/// ICC profiles go in APP2 markers, split up if they're too big for one. Each
+/// piece says which it is, counting from 1, and how many there are.
+fn write_jpeg_icc(comp: &mut mozjpeg::Compress, icc: &[u8]) {
+	const MAX_CHUNK: usize = 65519;
+
+	let count = icc.len().div_ceil(MAX_CHUNK);
+	for (idx, chunk) in icc.chunks(MAX_CHUNK).enumerate() {
+		let mut marker = b"ICC_PROFILE\0".to_vec();
+		marker.push(idx as u8 + 1);
+		marker.push(count as u8);
+		marker.extend_from_slice(chunk);
+		comp.write_marker(mozjpeg::Marker::APP(2), &marker);
 	}
 }
+
+/// EXIF goes in a single APP1 marker. If it's too big for one we leave it
+/// out, there's no standard way to split it.
+fn write_jpeg_exif(comp: &mut mozjpeg::Compress, exif: &[u8]) {
+	const MAX_EXIF: usize = 65527;
+
+	if exif.len() > MAX_EXIF {
+		return;
+	}
+
+	let mut marker = b"Exif\0\0".to_vec();
+	marker.extend_from_slice(exif);
+	comp.write_marker(mozjpeg::Marker::APP(1), &marker);
+}
+
+/// The webp crate can't embed a profile or EXIF, so we do it after. Those
+/// need the extended format, where a VP8X chunk comes first with flags saying
+/// what else there is. The ICCP chunk goes right after it and the EXIF chunk
+/// after the image. Lossy with alpha is already extended, and the rest we
+/// wrap.
+fn webp_extended(
+	webp: &[u8],
+	width: u32,
+	height: u32,
+	icc: Option<&[u8]>,
+	exif: Option<&[u8]>,
+) -> Vec<u8> {
+	const ICC_FLAG: u8 = 0x20;
+	const EXIF_FLAG: u8 = 0x08;
+
+	let flags = icc.map(|_| ICC_FLAG).unwrap_or(0) | exif.map(|_| EXIF_FLAG).unwrap_or(0);
+
+	let chunk = |fourcc: &[u8], data: &[u8]| {
+		let mut out = fourcc.to_vec();
+		out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+		out.extend_from_slice(data);
+		if data.len() % 2 == 1 {
+			out.push(0);
+		}
+		out
+	};
+
+	// Everything after "RIFF", the size, and "WEBP"
+	let body = &webp[12..];
+	let mut chunks = vec![];
+	let rest = if &body[0..4] == b"VP8X" {
+		let mut vp8x = body[..18].to_vec();
+		vp8x[8] |= flags;
+		chunks.extend_from_slice(&vp8x);
+		&body[18..]
+	} else {
+		let mut vp8x = [0; 10];
+		vp8x[0] = flags;
+		vp8x[4..7].copy_from_slice(&(width - 1).to_le_bytes()[..3]);
+		vp8x[7..10].copy_from_slice(&(height - 1).to_le_bytes()[..3]);
+		chunks.extend_from_slice(&chunk(b"VP8X", &vp8x));
+		body
+	};
+	if let Some(icc) = icc {
+		chunks.extend_from_slice(&chunk(b"ICCP", icc));
+	}
+	chunks.extend_from_slice(rest);
+	if let Some(exif) = exif {
+		chunks.extend_from_slice(&chunk(b"EXIF", exif));
+	}
+
+	let mut out = b"RIFF".to_vec();
+	out.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+	out.extend_from_slice(b"WEBP");
+	out.extend_from_slice(&chunks);
+	out
+}
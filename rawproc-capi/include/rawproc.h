@@ -0,0 +1,189 @@
+/*
+ * rawproc's C API. Decode a raw, develop it with a recipe, and get the
+ * pixels out.
+ *
+ * Everything rawproc gives you is yours to free, with the _free function
+ * for its type, and only that. Passing NULL to a _free function does
+ * nothing. Every other pointer you pass in has to be NULL or something
+ * rawproc gave you and you haven't freed yet. NULLs are caught and come
+ * back as RAWPROC_NULL_POINTER; anything else is undefined.
+ *
+ * Functions that can fail return a RawprocStatus, and when they make
+ * something they put it in the out pointer they take, which is set to NULL
+ * if they fail. rawproc_last_error says what went wrong.
+ *
+ * Raws, images, recipes, and metadata can be used from any thread, but not
+ * from two at once. Developing uses threads of its own.
+ */
+
+#ifndef RAWPROC_H
+#define RAWPROC_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum RawprocStatus {
+	RAWPROC_OK = 0,
+	/* Anything that isn't one of the others, like a corrupt file */
+	RAWPROC_ERROR = 1,
+	RAWPROC_NULL_POINTER = 2,
+	RAWPROC_INVALID_ARGUMENT = 3,
+	RAWPROC_IO = 4,
+	/* A camera or kind of file rawproc can't read, or can only partly read */
+	RAWPROC_UNSUPPORTED = 5,
+	/* A bug in rawproc. The call didn't finish, but nothing was leaked. */
+	RAWPROC_PANIC = 6,
+} RawprocStatus;
+
+/* A decoded raw, before it's been developed */
+typedef struct RawprocRaw RawprocRaw;
+/* A developed image, linear sRGB in floats */
+typedef struct RawprocImage RawprocImage;
+/* How to develop a raw. The same as the TOML recipes `rawproc` reads. */
+typedef struct RawprocRecipe RawprocRecipe;
+/* What the camera said about a raw */
+typedef struct RawprocMetadata RawprocMetadata;
+
+/*
+ * Errors and strings
+ */
+
+/* The message from the last call on this thread that failed, or NULL if
+ * none has. rawproc's, good until the next failure on this thread. */
+const char *rawproc_last_error(void);
+
+/* Free a string rawproc gave you, like from rawproc_recipe_to_toml */
+void rawproc_string_free(char *s);
+
+/*
+ * Raws
+ */
+
+/* Decode the raw file in data, len bytes long. The bytes are only read
+ * during the call, you can free them after. */
+RawprocStatus rawproc_decode(const uint8_t *data, size_t len, RawprocRaw **out);
+
+/* Decode the raw file at path, a UTF-8 path, memory mapping it instead of
+ * reading it in */
+RawprocStatus rawproc_decode_file(const char *path, RawprocRaw **out);
+
+void rawproc_raw_free(RawprocRaw *raw);
+
+/* The size of the sensor, before any crop. 0 for a NULL raw. */
+size_t rawproc_raw_width(const RawprocRaw *raw);
+size_t rawproc_raw_height(const RawprocRaw *raw);
+
+/* A copy of the raw's metadata, yours to free. NULL for a NULL raw. */
+RawprocMetadata *rawproc_raw_metadata(const RawprocRaw *raw);
+
+/* Develop raw with recipe, or the default recipe if it's NULL. The raw is
+ * left as it was, so you can develop it again with something else. */
+RawprocStatus rawproc_develop(const RawprocRaw *raw, const RawprocRecipe *recipe,
+	RawprocImage **out);
+
+/*
+ * Images
+ */
+
+void rawproc_image_free(RawprocImage *image);
+
+/* 0 for a NULL image */
+size_t rawproc_image_width(const RawprocImage *image);
+size_t rawproc_image_height(const RawprocImage *image);
+
+/* The pixels as they are, linear sRGB, three floats a pixel, row by row
+ * with no padding. They're the image's, good until it's freed or resized.
+ * NULL for a NULL image. */
+const float *rawproc_image_data(const RawprocImage *image);
+
+/* Scale the image to width by height. Neither can be 0. */
+RawprocStatus rawproc_image_resize(RawprocImage *image, size_t width, size_t height);
+
+/* Write the image into out as 8 bit sRGB, three bytes a pixel, row by row
+ * with no padding. len is how many bytes out has room for, at least
+ * width * height * 3. */
+RawprocStatus rawproc_image_srgb8(const RawprocImage *image, uint8_t *out, size_t len);
+
+/* rawproc_image_srgb8 with 16 bits a sample. len is in samples, not bytes. */
+RawprocStatus rawproc_image_srgb16(const RawprocImage *image, uint16_t *out, size_t len);
+
+/* A copy of the image's metadata, yours to free. NULL for a NULL image. */
+RawprocMetadata *rawproc_image_metadata(const RawprocImage *image);
+
+/*
+ * Recipes
+ */
+
+/* The default recipe, which develops the raw the way the camera asked for */
+RawprocRecipe *rawproc_recipe_new(void);
+RawprocStatus rawproc_recipe_from_toml(const char *toml, RawprocRecipe **out);
+/* The recipe as TOML, yours to free with rawproc_string_free. NULL for a
+ * NULL recipe. */
+char *rawproc_recipe_to_toml(const RawprocRecipe *recipe);
+void rawproc_recipe_free(RawprocRecipe *recipe);
+
+/* "none", "active_area", or "default" */
+RawprocStatus rawproc_recipe_set_crop(RawprocRecipe *recipe, const char *crop);
+/* "as_shot", "daylight", "preset:" and a preset name like "preset:shade",
+ * or three multipliers split by commas, like "2.1,1,1.6" */
+RawprocStatus rawproc_recipe_set_whitebalance(RawprocRecipe *recipe, const char *whitebalance);
+/* "bilinear", "ahd", or "nearest_random" */
+RawprocStatus rawproc_recipe_set_demosaic(RawprocRecipe *recipe, const char *demosaic);
+/* Fix pixels further than threshold from their neighbours, or don't if
+ * it's 0 or less */
+RawprocStatus rawproc_recipe_set_hot_pixels(RawprocRecipe *recipe, float threshold);
+/* Correct the lens with what the raw says about it */
+RawprocStatus rawproc_recipe_set_lens(RawprocRecipe *recipe, bool lens);
+/* Noise reduction strengths, 0 for none */
+RawprocStatus rawproc_recipe_set_denoise(RawprocRecipe *recipe, float luminance, float chroma);
+/* In stops */
+RawprocStatus rawproc_recipe_set_exposure(RawprocRecipe *recipe, float stops);
+RawprocStatus rawproc_recipe_set_contrast(RawprocRecipe *recipe, float contrast);
+RawprocStatus rawproc_recipe_set_saturation(RawprocRecipe *recipe, float saturation);
+/* An unsharp mask on the finished image, or none if amount is 0 */
+RawprocStatus rawproc_recipe_set_sharpen(RawprocRecipe *recipe, float radius, float amount,
+	float threshold);
+/* Turn the image upright, from what the camera says */
+RawprocStatus rawproc_recipe_set_orientation(RawprocRecipe *recipe, bool orientation);
+
+/*
+ * Metadata
+ *
+ * The strings belong to the metadata and live as long as it does. They're
+ * NULL when the raw doesn't say, and so is everything for a NULL metadata.
+ */
+
+void rawproc_metadata_free(RawprocMetadata *metadata);
+
+const char *rawproc_metadata_make(const RawprocMetadata *metadata);
+const char *rawproc_metadata_model(const RawprocMetadata *metadata);
+const char *rawproc_metadata_serial(const RawprocMetadata *metadata);
+const char *rawproc_metadata_lens_model(const RawprocMetadata *metadata);
+/* As EXIF writes it, "YYYY:MM:DD HH:MM:SS", in the camera's local time */
+const char *rawproc_metadata_datetime_original(const RawprocMetadata *metadata);
+
+/* These are 0 when they aren't known */
+uint32_t rawproc_metadata_iso(const RawprocMetadata *metadata);
+/* In seconds */
+float rawproc_metadata_exposure_time(const RawprocMetadata *metadata);
+float rawproc_metadata_f_number(const RawprocMetadata *metadata);
+/* In millimetres */
+float rawproc_metadata_focal_length(const RawprocMetadata *metadata);
+
+/* The EXIF orientation, 1 through 8. 1 for a NULL metadata. */
+uint16_t rawproc_metadata_orientation(const RawprocMetadata *metadata);
+
+/* The red, green, and blue multipliers the raw will be developed with,
+ * written to the three floats at out */
+RawprocStatus rawproc_metadata_whitebalance(const RawprocMetadata *metadata, float *out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,129 @@
+//! Developed images and getting the pixels out of them
+
+use rawproc::{
+	colorspace::{LinSrgb, Srgb},
+	image::{Filter, Image},
+};
+
+use crate::{guard, guard_value, metadata::RawprocMetadata, Failure, RawprocStatus};
+
+/// A developed image, linear sRGB in floats
+pub struct RawprocImage(pub(crate) Image<f32, LinSrgb>);
+
+impl RawprocImage {
+	fn samples(&self) -> usize {
+		self.0.width * self.0.height * 3
+	}
+
+	/// Check that a buffer from C is big enough for the image gamma'd, and
+	/// get the gamma'd image
+	fn srgb(&self, out_is_null: bool, len: usize) -> Result<Image<f32, Srgb>, Failure> {
+		if out_is_null {
+			return Err(Failure::null("out"));
+		}
+		if len < self.samples() {
+			return Err(Failure::invalid(format!(
+				"the buffer holds {len} samples but the image is {}",
+				self.samples()
+			)));
+		}
+
+		Ok(self.0.clone().gamma())
+	}
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_image_free(image: *mut RawprocImage) {
+	if !image.is_null() {
+		drop(Box::from_raw(image));
+	}
+}
+
+/// 0 for a null image
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_image_width(image: *const RawprocImage) -> usize {
+	image.as_ref().map(|image| image.0.width).unwrap_or(0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_image_height(image: *const RawprocImage) -> usize {
+	image.as_ref().map(|image| image.0.height).unwrap_or(0)
+}
+
+/// The pixels as they are, linear sRGB, three floats a pixel, row by row.
+/// They're the image's, good until it's freed or resized. Null for a null
+/// image.
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_image_data(image: *const RawprocImage) -> *const f32 {
+	image
+		.as_ref()
+		.map(|image| image.0.data.as_ptr())
+		.unwrap_or(std::ptr::null())
+}
+
+/// Scale the image to `width` by `height` with the default filter. It's
+/// done on the linear pixels, which is the right place for it.
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_image_resize(
+	image: *mut RawprocImage,
+	width: usize,
+	height: usize,
+) -> RawprocStatus {
+	guard(|| {
+		let image = image.as_mut().ok_or_else(|| Failure::null("image"))?;
+		if width == 0 || height == 0 {
+			return Err(Failure::invalid("the image can't be resized to nothing"));
+		}
+
+		image.0 = image.0.resize(width, height, Filter::default());
+		Ok(())
+	})
+}
+
+/// Write the image into `out` as 8 bit sRGB, three bytes a pixel. `len` is
+/// how many bytes `out` has room for, which has to be at least width *
+/// height * 3.
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_image_srgb8(
+	image: *const RawprocImage,
+	out: *mut u8,
+	len: usize,
+) -> RawprocStatus {
+	guard(|| {
+		let image = image.as_ref().ok_or_else(|| Failure::null("image"))?;
+		let srgb: Image<u8, Srgb> = image.srgb(out.is_null(), len)?.into();
+
+		std::slice::from_raw_parts_mut(out, srgb.data.len()).copy_from_slice(&srgb.data);
+		Ok(())
+	})
+}
+
+/// [rawproc_image_srgb8] with 16 bits a sample. `len` is in samples, not
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_image_srgb16(
+	image: *const RawprocImage,
+	out: *mut u16,
+	len: usize,
+) -> RawprocStatus {
+	guard(|| {
+		let image = image.as_ref().ok_or_else(|| Failure::null("image"))?;
+		let srgb: Image<u16, Srgb> = image.srgb(out.is_null(), len)?.into();
+
+		std::slice::from_raw_parts_mut(out, srgb.data.len()).copy_from_slice(&srgb.data);
+		Ok(())
+	})
+}
+
+/// A copy of the image's metadata, yours to free. Null for a null image.
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_image_metadata(
+	image: *const RawprocImage,
+) -> *mut RawprocMetadata {
+	match image.as_ref() {
+		Some(image) => guard_value(std::ptr::null_mut(), || {
+			Box::into_raw(Box::new(RawprocMetadata::new(image.0.metadata.clone())))
+		}),
+		None => std::ptr::null_mut(),
+	}
+}
@@ -0,0 +1,186 @@
+//! rawproc's C API. The header is `include/rawproc.h` and that's where the
+//! documentation for C programmers is; what's here is for us.
+//!
+//! Everything we hand out is a pointer to something we allocated, and it
+//! comes back to us to be freed with the `_free` function for its type.
+//! Functions that can fail return a [RawprocStatus], put what they made in
+//! an out pointer, and leave a message for [rawproc_last_error]. Panics are
+//! caught at the boundary and come out as [RawprocStatus::Panic], since
+//! unwinding into C is undefined.
+//!
+//! Pointers coming in have to be ones we gave out and haven't freed yet, or
+//! null. Null we check for. The rest we can't, which is why everything here
+//! is `unsafe`, and why there's no `# Safety` section on each of them: the
+//! rules are these ones, every time.
+#![allow(clippy::missing_safety_doc)]
+
+mod image;
+mod metadata;
+mod raw;
+mod recipe;
+
+use std::{
+	any::Any,
+	cell::RefCell,
+	ffi::{c_char, CStr, CString},
+	panic::{self, AssertUnwindSafe},
+	ptr,
+};
+
+use rawproc::Error;
+
+pub use image::RawprocImage;
+pub use metadata::RawprocMetadata;
+pub use raw::RawprocRaw;
+pub use recipe::RawprocRecipe;
+
+/// How a call went. The numbers are in the header, so they can't change;
+/// new ones go on the end.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RawprocStatus {
+	Ok = 0,
+	/// Anything that isn't one of the others, like a corrupt file
+	Error = 1,
+	NullPointer = 2,
+	InvalidArgument = 3,
+	Io = 4,
+	/// A camera or kind of file we can't read
+	Unsupported = 5,
+	Panic = 6,
+}
+
+/// Why a call failed, on its way to being a status and a message
+pub(crate) struct Failure {
+	status: RawprocStatus,
+	message: String,
+}
+
+impl Failure {
+	pub(crate) fn new<S: Into<String>>(status: RawprocStatus, message: S) -> Self {
+		Self {
+			status,
+			message: message.into(),
+		}
+	}
+
+	pub(crate) fn null(name: &str) -> Self {
+		Self::new(RawprocStatus::NullPointer, format!("{name} is null"))
+	}
+
+	pub(crate) fn invalid<S: Into<String>>(message: S) -> Self {
+		Self::new(RawprocStatus::InvalidArgument, message)
+	}
+}
+
+impl From<Error> for Failure {
+	fn from(e: Error) -> Self {
+		let status = match e {
+			Error::Io { .. } => RawprocStatus::Io,
+			Error::UnsupportedFormat { .. }
+			| Error::PartialSupport { .. }
+			| Error::FloatImageData
+			| Error::UnsupportedConversion { .. } => RawprocStatus::Unsupported,
+			Error::Recipe { .. } => RawprocStatus::InvalidArgument,
+			_ => RawprocStatus::Error,
+		};
+
+		Self::new(status, e.to_string())
+	}
+}
+
+thread_local! {
+	static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Run the body of an API function, catching a panic if there is one, and
+/// keep the message if it failed
+pub(crate) fn guard<F: FnOnce() -> Result<(), Failure>>(f: F) -> RawprocStatus {
+	let result = panic::catch_unwind(AssertUnwindSafe(f))
+		.unwrap_or_else(|payload| Err(Failure::new(RawprocStatus::Panic, panic_message(payload))));
+
+	match result {
+		Ok(()) => RawprocStatus::Ok,
+		Err(Failure { status, message }) => {
+			// Messages come from Display impls and never have a nul in them,
+			// but if one did we'd rather lose the message than panic
+			let message = CString::new(message).unwrap_or_default();
+			LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+			status
+		}
+	}
+}
+
+/// [guard] for the functions that can't fail, only panic, and give back a
+/// value instead of a status. A panic is `fallback`, with the message kept.
+pub(crate) fn guard_value<T, F: FnOnce() -> T>(fallback: T, f: F) -> T {
+	let mut value = None;
+	guard(|| {
+		value = Some(f());
+		Ok(())
+	});
+	value.unwrap_or(fallback)
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+	if let Some(s) = payload.downcast_ref::<&str>() {
+		format!("rawproc panicked: {s}")
+	} else if let Some(s) = payload.downcast_ref::<String>() {
+		format!("rawproc panicked: {s}")
+	} else {
+		"rawproc panicked".into()
+	}
+}
+
+/// Hand `value` over to C through `out`
+pub(crate) unsafe fn give<T>(out: *mut *mut T, value: T) {
+	*out = Box::into_raw(Box::new(value));
+}
+
+/// Null out an out pointer before we start, so it's null on failure, or
+/// fail if there's nowhere to write
+pub(crate) unsafe fn out_pointer<T>(out: *mut *mut T) -> Result<(), Failure> {
+	if out.is_null() {
+		return Err(Failure::null("out"));
+	}
+
+	*out = ptr::null_mut();
+	Ok(())
+}
+
+/// A borrowed C string, which we want as UTF-8
+pub(crate) unsafe fn string<'a>(s: *const c_char, name: &str) -> Result<&'a str, Failure> {
+	if s.is_null() {
+		return Err(Failure::null(name));
+	}
+
+	CStr::from_ptr(s)
+		.to_str()
+		.map_err(|_| Failure::invalid(format!("{name} isn't UTF-8")))
+}
+
+/// A Rust string as one C can own and give back to [rawproc_string_free].
+/// Null if it has a nul in it, which none of ours do.
+pub(crate) fn owned_string(s: String) -> *mut c_char {
+	CString::new(s)
+		.map(CString::into_raw)
+		.unwrap_or(ptr::null_mut())
+}
+
+/// The message from the last call on this thread that failed, or null if
+/// none has. It's ours, and good until the next failure on this thread.
+#[no_mangle]
+pub extern "C" fn rawproc_last_error() -> *const c_char {
+	LAST_ERROR.with(|last| match &*last.borrow() {
+		Some(message) => message.as_ptr(),
+		None => ptr::null(),
+	})
+}
+
+/// Free a string we gave out, like from [rawproc_recipe_to_toml](recipe::rawproc_recipe_to_toml)
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_string_free(s: *mut c_char) {
+	if !s.is_null() {
+		drop(CString::from_raw(s));
+	}
+}
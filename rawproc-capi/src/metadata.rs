@@ -0,0 +1,152 @@
+//! What the camera said about a raw. C gets a copy of its own, and the
+//! strings in it live as long as it does.
+
+use std::ffi::{c_char, CString};
+
+use rawproc::image::RawMetadata;
+
+use crate::{guard, Failure, RawprocStatus};
+
+pub struct RawprocMetadata {
+	metadata: RawMetadata,
+	// Kept as C strings so we can hand out pointers to them
+	make: CString,
+	model: CString,
+	serial: Option<CString>,
+	lens_model: Option<CString>,
+	datetime_original: Option<CString>,
+}
+
+impl RawprocMetadata {
+	pub(crate) fn new(metadata: RawMetadata) -> Self {
+		let c_string = |s: &str| CString::new(s).unwrap_or_default();
+
+		Self {
+			make: c_string(&metadata.make),
+			model: c_string(&metadata.model),
+			serial: metadata.serial.as_deref().map(c_string),
+			lens_model: metadata.exif.lens_model.as_deref().map(c_string),
+			datetime_original: metadata
+				.exif
+				.datetime_original
+				.map(|dt| c_string(&dt.to_string())),
+			metadata,
+		}
+	}
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_metadata_free(metadata: *mut RawprocMetadata) {
+	if !metadata.is_null() {
+		drop(Box::from_raw(metadata));
+	}
+}
+
+/// A string of the metadata's, or null if either it or the string isn't there
+unsafe fn string_of<F>(metadata: *const RawprocMetadata, f: F) -> *const c_char
+where
+	F: FnOnce(&RawprocMetadata) -> Option<&CString>,
+{
+	metadata
+		.as_ref()
+		.and_then(f)
+		.map(|s| s.as_ptr())
+		.unwrap_or(std::ptr::null())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_metadata_make(metadata: *const RawprocMetadata) -> *const c_char {
+	string_of(metadata, |m| Some(&m.make))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_metadata_model(metadata: *const RawprocMetadata) -> *const c_char {
+	string_of(metadata, |m| Some(&m.model))
+}
+
+/// The body's serial number, or null if the raw doesn't say
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_metadata_serial(
+	metadata: *const RawprocMetadata,
+) -> *const c_char {
+	string_of(metadata, |m| m.serial.as_ref())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_metadata_lens_model(
+	metadata: *const RawprocMetadata,
+) -> *const c_char {
+	string_of(metadata, |m| m.lens_model.as_ref())
+}
+
+/// When the shutter was pressed, as EXIF writes it, `YYYY:MM:DD HH:MM:SS`
+/// in the camera's local time
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_metadata_datetime_original(
+	metadata: *const RawprocMetadata,
+) -> *const c_char {
+	string_of(metadata, |m| m.datetime_original.as_ref())
+}
+
+/// 0 if it isn't known
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_metadata_iso(metadata: *const RawprocMetadata) -> u32 {
+	metadata
+		.as_ref()
+		.and_then(|m| m.metadata.exif.iso)
+		.unwrap_or(0)
+}
+
+/// In seconds, 0 if it isn't known
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_metadata_exposure_time(metadata: *const RawprocMetadata) -> f32 {
+	metadata
+		.as_ref()
+		.and_then(|m| m.metadata.exif.exposure_time)
+		.unwrap_or(0.0)
+}
+
+/// 0 if it isn't known
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_metadata_f_number(metadata: *const RawprocMetadata) -> f32 {
+	metadata
+		.as_ref()
+		.and_then(|m| m.metadata.exif.f_number)
+		.unwrap_or(0.0)
+}
+
+/// In millimetres, 0 if it isn't known
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_metadata_focal_length(metadata: *const RawprocMetadata) -> f32 {
+	metadata
+		.as_ref()
+		.and_then(|m| m.metadata.exif.focal_length)
+		.unwrap_or(0.0)
+}
+
+/// The EXIF orientation, 1 through 8. 1 for a null metadata.
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_metadata_orientation(metadata: *const RawprocMetadata) -> u16 {
+	metadata
+		.as_ref()
+		.map(|m| m.metadata.orientation.exif())
+		.unwrap_or(1)
+}
+
+/// The whitebalance the raw will be developed with, red, green, and blue
+/// multipliers, written to the three floats at `out`
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_metadata_whitebalance(
+	metadata: *const RawprocMetadata,
+	out: *mut f32,
+) -> RawprocStatus {
+	guard(|| {
+		let metadata = metadata.as_ref().ok_or_else(|| Failure::null("metadata"))?;
+		if out.is_null() {
+			return Err(Failure::null("out"));
+		}
+
+		std::slice::from_raw_parts_mut(out, 3).copy_from_slice(&metadata.metadata.whitebalance);
+		Ok(())
+	})
+}
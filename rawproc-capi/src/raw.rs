@@ -0,0 +1,98 @@
+//! Decoding, and developing what was decoded
+
+use std::{ffi::c_char, slice};
+
+use rawproc::image::DynImage;
+
+use crate::{
+	give, guard, guard_value, image::RawprocImage, metadata::RawprocMetadata, out_pointer,
+	recipe::RawprocRecipe, string, Failure, RawprocStatus,
+};
+
+/// A decoded raw, before it's been developed
+pub struct RawprocRaw(pub(crate) DynImage<u16>);
+
+/// Decode the raw file in `data`, `len` bytes long. The bytes are only
+/// read during the call, you can free them after.
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_decode(
+	data: *const u8,
+	len: usize,
+	out: *mut *mut RawprocRaw,
+) -> RawprocStatus {
+	guard(|| {
+		out_pointer(out)?;
+		if data.is_null() {
+			return Err(Failure::null("data"));
+		}
+
+		let raw = rawproc::decode_dyn_slice(slice::from_raw_parts(data, len))?;
+		give(out, RawprocRaw(raw));
+		Ok(())
+	})
+}
+
+/// Decode the raw file at `path`, like
+/// [decode_dyn_file](rawproc::decode_dyn_file) does
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_decode_file(
+	path: *const c_char,
+	out: *mut *mut RawprocRaw,
+) -> RawprocStatus {
+	guard(|| {
+		out_pointer(out)?;
+		let path = string(path, "path")?;
+
+		let raw = rawproc::decode_dyn_file(path)?;
+		give(out, RawprocRaw(raw));
+		Ok(())
+	})
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_raw_free(raw: *mut RawprocRaw) {
+	if !raw.is_null() {
+		drop(Box::from_raw(raw));
+	}
+}
+
+/// The size of the sensor, before any crop. 0 for a null raw.
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_raw_width(raw: *const RawprocRaw) -> usize {
+	raw.as_ref().map(|raw| raw.0.width).unwrap_or(0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_raw_height(raw: *const RawprocRaw) -> usize {
+	raw.as_ref().map(|raw| raw.0.height).unwrap_or(0)
+}
+
+/// A copy of the raw's metadata, yours to free. Null for a null raw.
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_raw_metadata(raw: *const RawprocRaw) -> *mut RawprocMetadata {
+	match raw.as_ref() {
+		Some(raw) => guard_value(std::ptr::null_mut(), || {
+			Box::into_raw(Box::new(RawprocMetadata::new(raw.0.metadata.clone())))
+		}),
+		None => std::ptr::null_mut(),
+	}
+}
+
+/// Develop `raw` with `recipe`, or the default recipe if it's null. The raw
+/// is left as it was, so you can develop it again with something else.
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_develop(
+	raw: *const RawprocRaw,
+	recipe: *const RawprocRecipe,
+	out: *mut *mut RawprocImage,
+) -> RawprocStatus {
+	guard(|| {
+		out_pointer(out)?;
+		let raw = raw.as_ref().ok_or_else(|| Failure::null("raw"))?;
+
+		let recipe = recipe.as_ref().map(|r| r.0.clone()).unwrap_or_default();
+		let image = recipe.apply_dyn(raw.0.clone())?;
+		give(out, RawprocImage(image));
+		Ok(())
+	})
+}
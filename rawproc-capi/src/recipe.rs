@@ -0,0 +1,206 @@
+//! Recipes, to say how a raw is developed. They're the same as the TOML
+//! recipes the `rawproc` command reads, and can be read from and written to
+//! that, or built up a setting at a time.
+
+use std::ffi::c_char;
+
+use rawproc::{
+	image::Demosaic,
+	recipe::{CropMode, Recipe, Sharpen, WhitebalanceMode},
+};
+
+use crate::{give, guard, out_pointer, owned_string, string, Failure, RawprocStatus};
+
+pub struct RawprocRecipe(pub(crate) Recipe);
+
+/// The default recipe, which develops the raw the way the camera asked for
+#[no_mangle]
+pub extern "C" fn rawproc_recipe_new() -> *mut RawprocRecipe {
+	Box::into_raw(Box::new(RawprocRecipe(Recipe::default())))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_recipe_from_toml(
+	toml: *const c_char,
+	out: *mut *mut RawprocRecipe,
+) -> RawprocStatus {
+	guard(|| {
+		out_pointer(out)?;
+		let recipe = Recipe::from_toml(string(toml, "toml")?)?;
+		give(out, RawprocRecipe(recipe));
+		Ok(())
+	})
+}
+
+/// The recipe as TOML, yours to free with `rawproc_string_free`. Null for a
+/// null recipe.
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_recipe_to_toml(recipe: *const RawprocRecipe) -> *mut c_char {
+	match recipe.as_ref() {
+		Some(recipe) => owned_string(recipe.0.to_toml()),
+		None => std::ptr::null_mut(),
+	}
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_recipe_free(recipe: *mut RawprocRecipe) {
+	if !recipe.is_null() {
+		drop(Box::from_raw(recipe));
+	}
+}
+
+/// Change one setting of a recipe that isn't null
+unsafe fn set<F>(recipe: *mut RawprocRecipe, f: F) -> RawprocStatus
+where
+	F: FnOnce(&mut Recipe) -> Result<(), Failure>,
+{
+	guard(|| f(&mut recipe.as_mut().ok_or_else(|| Failure::null("recipe"))?.0))
+}
+
+/// `none`, `active_area`, or `default`
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_recipe_set_crop(
+	recipe: *mut RawprocRecipe,
+	crop: *const c_char,
+) -> RawprocStatus {
+	set(recipe, |recipe| {
+		recipe.crop = match string(crop, "crop")? {
+			"none" => CropMode::None,
+			"active_area" => CropMode::ActiveArea,
+			"default" => CropMode::Default,
+			other => return Err(Failure::invalid(format!("{other} isn't a crop"))),
+		};
+		Ok(())
+	})
+}
+
+/// `as_shot`, `daylight`, `preset:` and a preset name like `preset:shade`,
+/// or three coefficients split by commas
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_recipe_set_whitebalance(
+	recipe: *mut RawprocRecipe,
+	whitebalance: *const c_char,
+) -> RawprocStatus {
+	set(recipe, |recipe| {
+		let wb = string(whitebalance, "whitebalance")?;
+		recipe.whitebalance = WhitebalanceMode::parse(wb)
+			.ok_or_else(|| Failure::invalid(format!("{wb} isn't a whitebalance")))?;
+		Ok(())
+	})
+}
+
+/// `bilinear`, `ahd`, or `nearest_random`
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_recipe_set_demosaic(
+	recipe: *mut RawprocRecipe,
+	demosaic: *const c_char,
+) -> RawprocStatus {
+	set(recipe, |recipe| {
+		let name = string(demosaic, "demosaic")?;
+		recipe.demosaic = Demosaic::from_name(name)
+			.ok_or_else(|| Failure::invalid(format!("{name} isn't a demosaic")))?;
+		Ok(())
+	})
+}
+
+/// Fix hot pixels further than `threshold` from their neighbours, or don't
+/// if it's 0 or less
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_recipe_set_hot_pixels(
+	recipe: *mut RawprocRecipe,
+	threshold: f32,
+) -> RawprocStatus {
+	set(recipe, |recipe| {
+		recipe.hot_pixels = (threshold > 0.0).then_some(threshold);
+		Ok(())
+	})
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_recipe_set_lens(
+	recipe: *mut RawprocRecipe,
+	lens: bool,
+) -> RawprocStatus {
+	set(recipe, |recipe| {
+		recipe.lens = lens;
+		Ok(())
+	})
+}
+
+/// Noise reduction strengths, 0 for none
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_recipe_set_denoise(
+	recipe: *mut RawprocRecipe,
+	luminance: f32,
+	chroma: f32,
+) -> RawprocStatus {
+	set(recipe, |recipe| {
+		recipe.denoise_luminance = luminance;
+		recipe.denoise_chroma = chroma;
+		Ok(())
+	})
+}
+
+/// In stops
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_recipe_set_exposure(
+	recipe: *mut RawprocRecipe,
+	stops: f32,
+) -> RawprocStatus {
+	set(recipe, |recipe| {
+		recipe.exposure = stops;
+		Ok(())
+	})
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_recipe_set_contrast(
+	recipe: *mut RawprocRecipe,
+	contrast: f32,
+) -> RawprocStatus {
+	set(recipe, |recipe| {
+		recipe.contrast = contrast;
+		Ok(())
+	})
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_recipe_set_saturation(
+	recipe: *mut RawprocRecipe,
+	saturation: f32,
+) -> RawprocStatus {
+	set(recipe, |recipe| {
+		recipe.saturation = saturation;
+		Ok(())
+	})
+}
+
+/// An unsharp mask on the finished image, or none if `amount` is 0
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_recipe_set_sharpen(
+	recipe: *mut RawprocRecipe,
+	radius: f32,
+	amount: f32,
+	threshold: f32,
+) -> RawprocStatus {
+	set(recipe, |recipe| {
+		recipe.sharpen = (amount != 0.0).then_some(Sharpen {
+			radius,
+			amount,
+			threshold,
+		});
+		Ok(())
+	})
+}
+
+/// Turn the image upright, from what the camera says
+#[no_mangle]
+pub unsafe extern "C" fn rawproc_recipe_set_orientation(
+	recipe: *mut RawprocRecipe,
+	orientation: bool,
+) -> RawprocStatus {
+	set(recipe, |recipe| {
+		recipe.orientation = orientation;
+		Ok(())
+	})
+}
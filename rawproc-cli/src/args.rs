@@ -0,0 +1,203 @@
+use std::path::PathBuf;
+
+use rawproc::{export::Subsampling, image::Demosaic, recipe::WhitebalanceMode};
+
+pub const USAGE: &str = "\
+usage: rawproc [options] <input> <output>
+
+Develops a raw file and saves it as a PNG, TIFF, or JPEG.
+
+options:
+  -f, --format <png|tiff|jpeg>  what to save as. Guessed from the output's
+                                extension if you leave it out
+  -d, --depth <8|16>            bits per sample. PNGs can be either and are 8
+                                unless you ask, TIFFs are 16, JPEGs are 8
+  -q, --quality <1-100>         JPEG quality, 90 if you don't say
+      --chroma <444|422|420>    how much colour a JPEG keeps. 420 unless you
+                                ask, 444 is best for small coloured detail
+  -r, --recipe <file>           develop with a recipe. The options below
+                                change the recipe's settings
+      --demosaic <name>         bilinear, ahd, or nearest_random
+      --wb <whitebalance>       as_shot, daylight, preset:<name> like
+                                preset:shade, or r,g,b coefficients
+      --exposure <stops>
+      --resize <width>x<height> shrink to fit in a box
+      --long-edge <pixels>      shrink so the longest side is this long
+      --timings                 say how long each step took
+  -h, --help                    this
+";
+
+#[derive(Clone, Debug)]
+pub struct Args {
+	pub input: PathBuf,
+	pub output: PathBuf,
+	pub encoding: Encoding,
+	pub recipe: Option<PathBuf>,
+	pub demosaic: Option<Demosaic>,
+	pub whitebalance: Option<WhitebalanceMode>,
+	pub exposure: Option<f32>,
+	pub resize: Option<Resize>,
+	pub timings: bool,
+}
+
+/// What goes in the output file, worked out from the format and depth so
+/// only combinations we can write get this far
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Encoding {
+	Png {
+		sixteen: bool,
+	},
+	Tiff,
+	Jpeg {
+		quality: u8,
+		subsampling: Subsampling,
+	},
+}
+
+/// Both only ever shrink. Enlarging a photo just makes it blurry and bigger.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Resize {
+	Fit { width: usize, height: usize },
+	LongEdge(usize),
+}
+
+impl Resize {
+	/// The size a `width` by `height` image ends up, keeping its shape
+	pub fn size(&self, width: usize, height: usize) -> (usize, usize) {
+		let (w, h) = (width as f32, height as f32);
+		let scale = match *self {
+			Resize::Fit {
+				width: fit_width,
+				height: fit_height,
+			} => (fit_width as f32 / w).min(fit_height as f32 / h),
+			Resize::LongEdge(edge) => edge as f32 / w.max(h),
+		};
+
+		if scale >= 1.0 {
+			return (width, height);
+		}
+		(
+			((w * scale).round() as usize).max(1),
+			((h * scale).round() as usize).max(1),
+		)
+	}
+}
+
+impl Args {
+	pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+		let mut args = args.into_iter();
+		let mut paths = vec![];
+		let mut format = None;
+		let mut depth = None;
+		let mut quality = None;
+		let mut subsampling = Subsampling::default();
+		let mut parsed = Args {
+			input: PathBuf::new(),
+			output: PathBuf::new(),
+			encoding: Encoding::Tiff,
+			recipe: None,
+			demosaic: None,
+			whitebalance: None,
+			exposure: None,
+			resize: None,
+			timings: false,
+		};
+
+		while let Some(arg) = args.next() {
+			if !arg.starts_with('-') {
+				paths.push(PathBuf::from(arg));
+				continue;
+			}
+
+			// Take --name=value as well as --name value
+			let (name, inline) = match arg.split_once('=') {
+				Some((name, value)) => (name.to_owned(), Some(value.to_owned())),
+				None => (arg, None),
+			};
+			let mut value = || {
+				inline
+					.clone()
+					.or_else(|| args.next())
+					.ok_or_else(|| format!("{name} needs a value"))
+			};
+
+			match name.as_str() {
+				"-f" | "--format" => format = Some(value()?),
+				"-d" | "--depth" => depth = Some(number::<u8>(&name, &value()?)?),
+				"-q" | "--quality" => quality = Some(number::<u8>(&name, &value()?)?),
+				"--chroma" => {
+					subsampling = match value()?.as_str() {
+						"444" => Subsampling::Full,
+						"422" => Subsampling::Half,
+						"420" => Subsampling::Quarter,
+						other => return Err(format!("--chroma is 444, 422, or 420, not {other}")),
+					}
+				}
+				"-r" | "--recipe" => parsed.recipe = Some(PathBuf::from(value()?)),
+				"--demosaic" => {
+					let value = value()?;
+					let demosaic = Demosaic::from_name(&value)
+						.ok_or_else(|| format!("there's no demosaic called {value}"))?;
+					parsed.demosaic = Some(demosaic);
+				}
+				"--wb" => {
+					let value = value()?;
+					let wb = WhitebalanceMode::parse(&value)
+						.ok_or_else(|| format!("{value} isn't a whitebalance we know"))?;
+					parsed.whitebalance = Some(wb);
+				}
+				"--exposure" => parsed.exposure = Some(number(&name, &value()?)?),
+				"--resize" => {
+					let value = value()?;
+					let (width, height) = value.split_once('x').ok_or_else(|| {
+						format!("--resize wants a size like 1920x1080, not {value}")
+					})?;
+					parsed.resize = Some(Resize::Fit {
+						width: number(&name, width)?,
+						height: number(&name, height)?,
+					});
+				}
+				"--long-edge" => parsed.resize = Some(Resize::LongEdge(number(&name, &value()?)?)),
+				"--timings" => parsed.timings = true,
+				_ => return Err(format!("we don't know the option {name}")),
+			}
+		}
+
+		let [input, output]: [PathBuf; 2] = paths
+			.try_into()
+			.map_err(|_| String::from("give an input and an output, and nothing else"))?;
+
+		let format = match format {
+			Some(format) => format,
+			None => output
+				.extension()
+				.map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+				.ok_or("the output has no extension, so say which --format you want")?,
+		};
+
+		parsed.encoding = match (format.as_str(), depth) {
+			("png", None | Some(8)) => Encoding::Png { sixteen: false },
+			("png", Some(16)) => Encoding::Png { sixteen: true },
+			("tif" | "tiff", None | Some(16)) => Encoding::Tiff,
+			("jpg" | "jpeg", None | Some(8)) => Encoding::Jpeg {
+				quality: quality.unwrap_or(90),
+				subsampling,
+			},
+			("png" | "tif" | "tiff" | "jpg" | "jpeg", Some(depth)) => {
+				return Err(format!("we can't write {depth} bit {format} files"))
+			}
+			_ => return Err(format!("we can't write {format} files")),
+		};
+
+		parsed.input = input;
+		parsed.output = output;
+		Ok(parsed)
+	}
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+	value
+		.trim()
+		.parse()
+		.map_err(|_| format!("{name} wants a number, not {value}"))
+}
@@ -0,0 +1,179 @@
+mod args;
+
+use std::{error::Error, fs::File, io::BufWriter, path::Path, process::ExitCode, time::Instant};
+
+use args::{Args, Encoding, USAGE};
+use imgout::OutImage;
+use rawproc::{
+	colorspace::Srgb,
+	export::{JpegWriter, TiffWriter},
+	image::{Filter, Image},
+	recipe::Recipe,
+};
+
+fn main() -> ExitCode {
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	if args.is_empty() || args.iter().any(|a| a == "-h" || a == "--help") {
+		print!("{USAGE}");
+		return ExitCode::SUCCESS;
+	}
+
+	let args = match Args::parse(args) {
+		Ok(args) => args,
+		Err(e) => {
+			eprintln!("rawproc: {e}\nrun rawproc --help to see the options");
+			return ExitCode::from(2);
+		}
+	};
+
+	match run(&args) {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(e) => {
+			eprintln!("rawproc: {e}");
+			ExitCode::FAILURE
+		}
+	}
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+	if same_file(&args.input, &args.output) {
+		return Err("the output is the input, and we won't write over a raw".into());
+	}
+
+	let mut recipe = match &args.recipe {
+		Some(path) => {
+			let toml = std::fs::read_to_string(path)
+				.map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+			Recipe::from_toml(&toml)?
+		}
+		None => Recipe::default(),
+	};
+	if let Some(demosaic) = args.demosaic {
+		recipe.demosaic = demosaic;
+	}
+	if let Some(wb) = args.whitebalance {
+		recipe.whitebalance = wb;
+	}
+	if let Some(stops) = args.exposure {
+		recipe.exposure = stops;
+	}
+
+	let mut timer = Timer::new(args.timings);
+	let raw = rawproc::decode_dyn_file(&args.input)
+		.map_err(|e| format!("couldn't decode {}: {e}", args.input.display()))?;
+	timer.lap("Decode");
+
+	let mut image = recipe.apply_dyn(raw)?;
+	timer.lap("Develop");
+
+	if let Some(resize) = args.resize {
+		let (width, height) = resize.size(image.width, image.height);
+		if (width, height) != (image.width, image.height) {
+			image = image.resize(width, height, Filter::default());
+			timer.lap("Resize");
+		}
+	}
+
+	write(&args.output, args.encoding, image.gamma())?;
+	timer.lap("Encode");
+	timer.total();
+
+	Ok(())
+}
+
+fn write(path: &Path, encoding: Encoding, image: Image<f32, Srgb>) -> Result<(), Box<dyn Error>> {
+	let exif = image.metadata.exif_bytes();
+
+	match encoding {
+		Encoding::Tiff => {
+			let mut file = BufWriter::new(File::create(path)?);
+			TiffWriter::new().write(&image, &mut file)?;
+		}
+		Encoding::Jpeg {
+			quality,
+			subsampling,
+		} => {
+			let mut file = BufWriter::new(File::create(path)?);
+			JpegWriter::new()
+				.quality(quality)
+				.subsampling(subsampling)
+				.write(&image, &mut file)?;
+		}
+		Encoding::Png { sixteen: true } => png16(path, image, &exif)?,
+		Encoding::Png { sixteen: false } => {
+			let icc = image.icc_profile();
+			let bytes = image.bytes();
+			let mut out = OutImage::new(bytes.width, bytes.height, bytes.data).with_exif(exif);
+			if let Some(icc) = icc {
+				out = out.with_icc_profile(icc);
+			}
+			out.png(path)?;
+		}
+	}
+
+	Ok(())
+}
+
+/// imgout only does 8 bit, so we do 16 bit PNGs ourselves. They're always
+/// sRGB, which has its own chunk, so there's no need for a whole profile.
+fn png16(path: &Path, image: Image<f32, Srgb>, exif: &[u8]) -> Result<(), Box<dyn Error>> {
+	let file = BufWriter::new(File::create(path)?);
+	let mut enc = png::Encoder::new(file, image.width as u32, image.height as u32);
+	enc.set_color(png::ColorType::Rgb);
+	enc.set_depth(png::BitDepth::Sixteen);
+	enc.set_srgb(png::SrgbRenderingIntent::Perceptual);
+
+	let mut writer = enc.write_header()?;
+	writer.write_chunk(png::chunk::ChunkType(*b"eXIf"), exif)?;
+
+	// PNG is big endian
+	let data: Vec<u8> = image
+		.sxiteen()
+		.data
+		.into_iter()
+		.flat_map(u16::to_be_bytes)
+		.collect();
+	writer.write_image_data(&data)?;
+
+	Ok(())
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+	match (a.canonicalize(), b.canonicalize()) {
+		(Ok(a), Ok(b)) => a == b,
+		// If the output isn't there yet it can't be the input
+		_ => false,
+	}
+}
+
+/// Times each step, for --timings. Does nothing if it's off.
+struct Timer {
+	enabled: bool,
+	start: Instant,
+	last: Instant,
+}
+
+impl Timer {
+	fn new(enabled: bool) -> Self {
+		let now = Instant::now();
+		Self {
+			enabled,
+			start: now,
+			last: now,
+		}
+	}
+
+	fn lap(&mut self, step: &str) {
+		let now = Instant::now();
+		if self.enabled {
+			eprintln!("{step:<8} {}ms", now.duration_since(self.last).as_millis());
+		}
+		self.last = now;
+	}
+
+	fn total(&self) {
+		if self.enabled {
+			eprintln!("{:<8} {}ms", "Total", self.start.elapsed().as_millis());
+		}
+	}
+}
@@ -0,0 +1,87 @@
+//! rawproc for the browser. It's small on purpose, just enough to show a
+//! raw in a web page; anything more and you're better off writing your own
+//! against rawproc directly, the way this is.
+//!
+//! There are no threads on wasm32-unknown-unknown, so everything runs on
+//! whatever thread calls in. Call it from a worker or the page will hang
+//! while it works.
+
+use rawproc::{image::Filter, recipe::Recipe};
+use wasm_bindgen::prelude::*;
+
+/// A developed raw, as 8 bit sRGB
+#[wasm_bindgen]
+pub struct Developed {
+	width: usize,
+	height: usize,
+	rgba: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl Developed {
+	#[wasm_bindgen(getter)]
+	pub fn width(&self) -> u32 {
+		self.width as u32
+	}
+
+	#[wasm_bindgen(getter)]
+	pub fn height(&self) -> u32 {
+		self.height as u32
+	}
+
+	/// The pixels, RGBA with the alpha all the way up, ready for
+	/// `new ImageData(new Uint8ClampedArray(developed.rgba), developed.width)`.
+	/// It's a copy every time you get it, so hold on to it.
+	#[wasm_bindgen(getter)]
+	pub fn rgba(&self) -> Vec<u8> {
+		self.rgba.clone()
+	}
+}
+
+/// Develop the raw in `raw`, a whole file's bytes, with `recipe` if you have
+/// one or the default if you don't. The recipe is the same TOML the
+/// `rawproc` command reads. If `max_size` isn't 0 the image is scaled down
+/// so its long edge is no longer than that, which for a preview saves a lot
+/// of time in the gamma and the copy out.
+#[wasm_bindgen]
+pub fn develop(raw: &[u8], recipe: Option<String>, max_size: u32) -> Result<Developed, JsError> {
+	let recipe = match recipe {
+		Some(toml) => Recipe::from_toml(&toml)?,
+		None => Recipe::default(),
+	};
+
+	let raw = rawproc::decode_dyn_slice(raw)?;
+	let mut image = recipe.apply_dyn(raw)?;
+
+	let max_size = max_size as usize;
+	let long_edge = image.width.max(image.height);
+	if max_size > 0 && long_edge > max_size {
+		let scale = max_size as f32 / long_edge as f32;
+		let width = ((image.width as f32 * scale).round() as usize).max(1);
+		let height = ((image.height as f32 * scale).round() as usize).max(1);
+		image = image.resize(width, height, Filter::default());
+	}
+
+	let srgb = image.gamma().bytes();
+	let mut rgba = Vec::with_capacity(srgb.width * srgb.height * 4);
+	for &[r, g, b] in srgb.pixels() {
+		rgba.extend_from_slice(&[r, g, b, u8::MAX]);
+	}
+
+	Ok(Developed {
+		width: srgb.width,
+		height: srgb.height,
+		rgba,
+	})
+}
+
+/// The biggest JPEG preview the camera embedded in the raw, if there is one.
+/// It's a lot faster than [develop] when all you want is a thumbnail; make a
+/// `Blob` of it and give that to an `<img>`.
+#[wasm_bindgen]
+pub fn thumbnail(raw: &[u8]) -> Result<Option<Vec<u8>>, JsError> {
+	let mut reader = raw;
+	let previews = rawproc::decode_thumbnail(&mut reader)?;
+
+	Ok(previews.into_iter().next().map(|preview| preview.data))
+}
@@ -0,0 +1,54 @@
+//! What the benches share: a made up 24 megapixel image
+
+use criterion::Criterion;
+use nalgebra::Matrix3;
+use rawproc::{
+	colormatrix,
+	exif::Exif,
+	image::{Orientation, RawMetadata},
+};
+
+pub const WIDTH: usize = 6000;
+pub const HEIGHT: usize = 4000;
+
+/// Every step takes long enough at 24 megapixels that criterion's usual
+/// hundred samples is minutes a bench. Ten is plenty.
+pub fn criterion() -> Criterion {
+	Criterion::default().sample_size(10)
+}
+
+/// Something that looks enough like light, and covers the whole curve
+pub fn samples(count: usize) -> Vec<f32> {
+	(0..count)
+		.map(|i| (i.wrapping_mul(2_654_435_761) % 65536) as f32 / 65535.0)
+		.collect()
+}
+
+pub fn metadata() -> RawMetadata {
+	// A real camera's, so the pipeline bench's DNG decodes
+	let xyz_to_cam = colormatrix::lookup("Sony", "ILCE-7M3").expect("it's in the table");
+
+	RawMetadata {
+		whitebalance: [2.1, 1.0, 1.6],
+		as_shot_whitebalance: [2.1, 1.0, 1.6],
+		daylight_whitebalance: [2.1, 1.0, 1.6],
+		whitebalance_presets: vec![],
+		whitebalance_selected: None,
+		whitebalance_fine_tune: None,
+		whitelevels: [u16::MAX; 3],
+		blacklevels: [0; 3],
+		active_area: None,
+		default_crop: None,
+		cfa: rawloader::CFA::new("RGGB"),
+		xyz_to_cam,
+		cam_to_xyz: xyz_to_cam.try_inverse().unwrap_or_else(Matrix3::identity),
+		make: String::from("Bench"),
+		model: String::from("Mark"),
+		serial: None,
+		makernote: None,
+		sub_images: vec![],
+		lens_correction: None,
+		orientation: Orientation::Normal,
+		exif: Exif::default(),
+	}
+}
@@ -0,0 +1,204 @@
+//! Times each step of developing a raw, on a made up 24 megapixel DNG, so
+//! there's nothing to download first:
+//!
+//! ```text
+//! cargo bench --bench pipeline
+//! ```
+//!
+//! The steps that can write into a buffer you already have are timed both
+//! ways. The "into" benches are what an editor re-rendering the same raw
+//! over and over pays, once its buffers are warm.
+
+mod common;
+
+use std::{
+	mem,
+	time::{Duration, Instant},
+};
+
+use common::{metadata, samples, HEIGHT, WIDTH};
+use criterion::{criterion_group, criterion_main, BatchSize, Bencher, Criterion};
+use rawproc::{
+	colorspace::{BayerRgb, LinRgb, LinSrgb, XYZ},
+	encode::DngWriter,
+	image::{Demosaic, Filter, Image, Region},
+};
+
+/// Time `f` over and over, handing it back the buffer it made last time so
+/// it's warm. `setup` makes whatever else it needs, outside the timing.
+fn warm<S, T>(
+	b: &mut Bencher,
+	buffer: &mut Vec<T>,
+	mut setup: impl FnMut() -> S,
+	mut f: impl FnMut(S, Vec<T>) -> Vec<T>,
+) {
+	b.iter_custom(|iters| {
+		let mut took = Duration::ZERO;
+		for _ in 0..iters {
+			let input = setup();
+			let start = Instant::now();
+			let out = f(input, mem::take(buffer));
+			took += start.elapsed();
+			*buffer = out;
+		}
+		took
+	});
+}
+
+fn pipeline(c: &mut Criterion) {
+	// Twelve bits, like most cameras
+	let mut meta = metadata();
+	meta.whitelevels = [4095; 3];
+	meta.blacklevels = [256; 3];
+	let mosaic = samples(WIDTH * HEIGHT)
+		.into_iter()
+		.map(|f| (f * 4095.0) as u16)
+		.collect();
+	let made: Image<u16, BayerRgb> = Image::from_raw_parts(WIDTH, HEIGHT, meta, mosaic);
+	let dng = DngWriter::new().encode(&made);
+
+	let mut group = c.benchmark_group("pipeline");
+	group.bench_function("decode dng", |b| {
+		b.iter(|| rawproc::decode_slice(&dng).expect("we just wrote that"))
+	});
+	let raw = rawproc::decode_slice(&dng).expect("we just wrote that");
+
+	group.bench_function("crop", |b| {
+		let region = Region::new(8, 8, raw.width - 16, raw.height - 16);
+		b.iter_batched_ref(
+			|| raw.clone(),
+			|raw| raw.crop_to(region),
+			BatchSize::LargeInput,
+		)
+	});
+
+	group.bench_function("normalize", |b| {
+		b.iter_batched(|| raw.clone(), |raw| raw.normalize(), BatchSize::LargeInput)
+	});
+
+	let mut floats = vec![];
+	group.bench_function("normalize into", |b| {
+		warm(
+			b,
+			&mut floats,
+			|| (),
+			|_, buffer| raw.normalize_into(buffer).data,
+		)
+	});
+
+	let mut normalized = raw.normalize();
+	group.bench_function("whitebalance bayer", |b| {
+		b.iter_batched_ref(
+			|| normalized.clone(),
+			|image| image.whitebalance(),
+			BatchSize::LargeInput,
+		)
+	});
+	normalized.whitebalance();
+
+	let mut rgb = vec![];
+	for demosaic in [
+		Demosaic::Bilinear,
+		Demosaic::Ahd,
+		Demosaic::NearestRandom { seed: 0 },
+	] {
+		let name = demosaic.name();
+		group.bench_function(format!("debayer {name}"), |b| {
+			b.iter_batched(
+				|| normalized.clone(),
+				|image| image.debayer_with(demosaic),
+				BatchSize::LargeInput,
+			)
+		});
+
+		group.bench_function(format!("debayer {name} into"), |b| {
+			warm(
+				b,
+				&mut rgb,
+				|| normalized.clone(),
+				|image, buffer| image.debayer_with_into(demosaic, buffer).data,
+			)
+		});
+	}
+
+	let debayered = normalized.debayer();
+	group.bench_function("whitebalance rgb", |b| {
+		b.iter_batched_ref(
+			|| -> Image<f32, LinRgb> { debayered.clone() },
+			|image| image.whitebalance(),
+			BatchSize::LargeInput,
+		)
+	});
+
+	group.bench_function("to xyz", |b| {
+		b.iter_batched(
+			|| debayered.clone(),
+			|image| image.to_xyz(),
+			BatchSize::LargeInput,
+		)
+	});
+
+	let xyz: Image<f32, XYZ> = debayered.to_xyz();
+	group.bench_function("to linear srgb", |b| {
+		b.iter_batched(
+			|| xyz.clone(),
+			|image| image.to_linsrgb(),
+			BatchSize::LargeInput,
+		)
+	});
+
+	let linear: Image<f32, LinSrgb> = xyz.to_linsrgb();
+	group.bench_function("gamma", |b| {
+		b.iter_batched(
+			|| linear.clone(),
+			|image| image.gamma(),
+			BatchSize::LargeInput,
+		)
+	});
+
+	let srgb = linear.gamma();
+	let (width, height) = (WIDTH / 4, HEIGHT / 4);
+	group.bench_function("resize quarter", |b| {
+		b.iter(|| srgb.resize(width, height, Filter::Lanczos3))
+	});
+
+	let mut resized = vec![];
+	group.bench_function("resize quarter into", |b| {
+		warm(
+			b,
+			&mut resized,
+			|| (),
+			|_, buffer| {
+				srgb.resize_into(width, height, Filter::Lanczos3, buffer)
+					.data
+			},
+		)
+	});
+
+	group.bench_function("to bytes", |b| {
+		b.iter_batched(
+			|| srgb.clone(),
+			|image| image.to_u8(),
+			BatchSize::LargeInput,
+		)
+	});
+
+	let mut bytes = vec![];
+	group.bench_function("to bytes into", |b| {
+		warm(
+			b,
+			&mut bytes,
+			|| (),
+			|_, buffer| srgb.to_u8_into(buffer).data,
+		)
+	});
+
+	group.finish();
+}
+
+criterion_group! {
+	name = benches;
+	config = common::criterion();
+	targets = pipeline
+}
+criterion_main!(benches);
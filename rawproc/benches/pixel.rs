@@ -0,0 +1,61 @@
+//! Times the per pixel loops on a 24 megapixel image. Run it without the
+//! simd feature and then with, and criterion tells you what changed:
+//!
+//! ```text
+//! cargo bench --bench pixel
+//! cargo bench --bench pixel --features simd
+//! ```
+
+mod common;
+
+use common::{metadata, samples, HEIGHT, WIDTH};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use rawproc::{
+	colorspace::{BayerRgb, LinRgb, LinSrgb},
+	image::Image,
+};
+
+fn pixel(c: &mut Criterion) {
+	let mosaic = samples(WIDTH * HEIGHT);
+	let rgb = samples(WIDTH * HEIGHT * 3);
+	let mut group = c.benchmark_group("pixel");
+
+	group.bench_function("whitebalance bayer", |b| {
+		b.iter_batched_ref(
+			|| -> Image<f32, BayerRgb> {
+				Image::from_raw_parts(WIDTH, HEIGHT, metadata(), mosaic.clone())
+			},
+			|image| image.whitebalance(),
+			BatchSize::LargeInput,
+		)
+	});
+
+	group.bench_function("whitebalance rgb", |b| {
+		b.iter_batched_ref(
+			|| -> Image<f32, LinRgb> {
+				Image::from_raw_parts(WIDTH, HEIGHT, metadata(), rgb.clone())
+			},
+			|image| image.whitebalance(),
+			BatchSize::LargeInput,
+		)
+	});
+
+	group.bench_function("gamma", |b| {
+		b.iter_batched(
+			|| -> Image<f32, LinSrgb> {
+				Image::from_raw_parts(WIDTH, HEIGHT, metadata(), rgb.clone())
+			},
+			|image| image.gamma(),
+			BatchSize::LargeInput,
+		)
+	});
+
+	group.finish();
+}
+
+criterion_group! {
+	name = benches;
+	config = common::criterion();
+	targets = pixel
+}
+criterion_main!(benches);
@@ -14,6 +14,15 @@ pub fn contrast(float: f32, adjustment: f32) -> f32 {
 	(adjustment * (float - 0.5) + 0.5).clamp(0.0, 1.0)
 }
 
+/// Rec. 709 luminance for linear RGB, and the value itself for one channel
+#[inline]
+pub fn luminance(px: &[f32]) -> f32 {
+	match *px {
+		[r, g, b] => 0.2126 * r + 0.7152 * g + 0.0722 * b,
+		_ => px.iter().sum::<f32>() / px.len() as f32,
+	}
+}
+
 #[inline]
 pub fn pixel_rgb_to_hsv(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
 	let value = r.max(g.max(b));
@@ -0,0 +1,621 @@
+//! Steps that only make sense across a whole run of images, like the frames
+//! of a timelapse, instead of one at a time. And [develop_all], for putting
+//! a whole shoot through the same [Recipe].
+
+#[cfg(feature = "fs")]
+use std::{fs::File, io::BufReader, path::Path, sync::atomic::AtomicUsize};
+use std::{
+	io::Read,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+};
+
+use crate::{
+	algorithms::luminance,
+	colorspace::{BayerRgb, Colorspace, ColorspaceKind, LinRgb, Monochrome},
+	dng,
+	image::{Image, Region},
+	Error,
+};
+#[cfg(feature = "fs")]
+use crate::{colorspace::LinSrgb, par::*, recipe::Recipe};
+
+// Anything darker than this is noise as far as brightness is concerned, and
+// it keeps the log away from zero
+const BLACK_FLOOR: f32 = 1.0 / 65536.0;
+
+/// How bright an image is, as the log average of its luminance. A log
+/// average doesn't get dragged around by a few blown highlights the way a
+/// plain average does, and it's what exposure adjustments act on.
+///
+/// This expects linear RGB, or linear single channel, data. For anything
+/// else it's only a rough guess.
+pub fn brightness<C: Colorspace>(img: &Image<f32, C>) -> f32 {
+	let pixels = img.data.len() / C::COMPONENTS;
+	if pixels == 0 {
+		return 0.0;
+	}
+
+	let sum: f64 = img
+		.data
+		.chunks_exact(C::COMPONENTS)
+		.map(|px| luminance(px).max(BLACK_FLOOR).ln() as f64)
+		.sum();
+
+	(sum / pixels as f64).exp() as f32
+}
+
+/// The gain to give each frame so its brightness follows the average of the
+/// frames around it. `window` is how many frames the average spans and it's
+/// centered on the frame, so a window of 1 changes nothing and a bigger one
+/// irons out slower flicker. Gradual changes, like a sunset, are longer than
+/// any sensible window and are left alone.
+///
+/// `brightness` is one [brightness] per frame, in order. Measure them as you
+/// go and you never need to hold every frame in memory at once.
+pub fn deflicker_gains(brightness: &[f32], window: usize) -> Vec<f32> {
+	let radius = window.max(1) / 2;
+	let logs: Vec<f32> = brightness.iter().map(|b| b.max(BLACK_FLOOR).ln()).collect();
+
+	(0..logs.len())
+		.map(|idx| {
+			// The window shrinks at the ends instead of running off them
+			let start = idx.saturating_sub(radius);
+			let end = (idx + radius + 1).min(logs.len());
+			let smoothed = logs[start..end].iter().sum::<f32>() / (end - start) as f32;
+
+			(smoothed - logs[idx]).exp()
+		})
+		.collect()
+}
+
+/// Deflicker a sequence that's all in memory. Every frame is measured, given
+/// the gain from [deflicker_gains], and the gains are returned in case you
+/// want to apply them to something else, like a full size render of a
+/// sequence you measured at preview size.
+///
+/// Like [brightness], the frames should be linear.
+pub fn deflicker<C: Colorspace>(frames: &mut [Image<f32, C>], window: usize) -> Vec<f32> {
+	let brightness: Vec<f32> = frames.iter().map(brightness).collect();
+	let gains = deflicker_gains(&brightness, window);
+
+	for (frame, gain) in frames.iter_mut().zip(gains.iter()) {
+		frame.data.iter_mut().for_each(|v| *v *= gain);
+	}
+
+	gains
+}
+
+// Where a locked exposure puts the reference brightness. It's middle grey,
+// in linear light.
+const MIDDLE_GREY: f32 = 0.18;
+
+/// Where a [SequenceLock] gets its settings from
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LockMode {
+	/// Use the first frame's whitebalance and exposure for all of them
+	FirstFrame,
+	/// Average over every frame. It takes a pass over the whole sequence
+	/// but one odd frame at the start can't throw the rest off.
+	Average,
+}
+
+/// Whitebalance and exposure, worked out once and then given to every frame
+/// of a sequence so none of them drift. Timelapse and stop-motion need this;
+/// letting every frame pick its own whitebalance makes the colour pump.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SequenceLock {
+	/// Whitebalance coefficients, green normalized to 1.0
+	pub whitebalance: [f32; 3],
+	/// What every sample is multiplied by
+	pub gain: f32,
+}
+
+impl SequenceLock {
+	/// Lock to one frame. Its whitebalance is the one in its metadata, and the
+	/// gain is whatever puts its [brightness] at middle grey.
+	///
+	/// Frames should be normalized and linear, like a
+	/// [normalized](Image::normalize) raw that hasn't been whitebalanced.
+	pub fn from_frame<C: Colorspace>(frame: &Image<f32, C>) -> Self {
+		let wb = frame.metadata.whitebalance;
+
+		Self {
+			whitebalance: [wb[0] / wb[1], 1.0, wb[2] / wb[1]],
+			gain: MIDDLE_GREY / brightness(frame).max(BLACK_FLOOR),
+		}
+	}
+
+	/// The average of a few locks, like one [from_frame](Self::from_frame)
+	/// per frame. Gains are averaged in stops so one dark frame doesn't count
+	/// for more than one bright one. None if `locks` is empty.
+	pub fn average(locks: &[SequenceLock]) -> Option<Self> {
+		if locks.is_empty() {
+			return None;
+		}
+		let count = locks.len() as f32;
+
+		let mut whitebalance = [0.0; 3];
+		let mut log_gain = 0.0;
+		for lock in locks {
+			for (sum, wb) in whitebalance.iter_mut().zip(lock.whitebalance) {
+				*sum += wb / count;
+			}
+			log_gain += lock.gain.ln() / count;
+		}
+
+		Some(Self {
+			whitebalance,
+			gain: log_gain.exp(),
+		})
+	}
+
+	/// Give a frame the locked settings. The whitebalance goes in its metadata,
+	/// ready for `whitebalance()`, and the gain is applied right away.
+	pub fn apply<C: Colorspace>(&self, frame: &mut Image<f32, C>) {
+		frame.metadata.whitebalance = self.whitebalance;
+		frame.data.iter_mut().for_each(|v| *v *= self.gain);
+	}
+}
+
+/// Where the dust on the sensor is, found from a reference frame. Dust sits
+/// still for a whole shoot, so one map fixes every frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DustMap {
+	/// The size of the reference frame the spots were found in
+	pub width: usize,
+	pub height: usize,
+	/// Every spot, with a little room around its shadow
+	pub spots: Vec<Region>,
+}
+
+impl DustMap {
+	/// Find the dust in a reference frame: something plain, like sky or a
+	/// white wall, shot stopped down and out of focus so the only detail left
+	/// is the shadows dust casts. A pixel is dust if it's darker than its
+	/// surroundings by more than `threshold`, where 0.05 is five percent.
+	///
+	/// The reference should be linear, and it can be any size as long as it
+	/// has the same shape as the frames the map is used on.
+	pub fn detect<C: Colorspace>(reference: &Image<f32, C>, threshold: f32) -> Self {
+		let (width, height) = (reference.width, reference.height);
+		let lum: Vec<f32> = reference
+			.data
+			.chunks_exact(C::COMPONENTS)
+			.map(luminance)
+			.collect();
+
+		// The background is a big blur of the frame. Vignetting and the
+		// gradient across the sky are much wider than any dust.
+		let radius = (width.min(height) / 32).max(4);
+		let background = box_blur(&lum, width, height, radius);
+
+		let dusty: Vec<bool> = lum
+			.iter()
+			.zip(&background)
+			.map(|(l, b)| *b > BLACK_FLOOR && l / b < 1.0 - threshold)
+			.collect();
+
+		// Anything bigger than this is the scene, not dust
+		let largest = (radius * radius * 4).max(64);
+		let spots = components(&dusty, width, height)
+			.into_iter()
+			.filter(|(_, area)| *area <= largest)
+			.map(|(bounds, _)| pad(bounds, width, height))
+			.collect();
+
+		Self {
+			width,
+			height,
+			spots,
+		}
+	}
+
+	/// Fill in every spot on `frame`. The map is scaled to the frame, so a
+	/// map from a small reference works on full size frames, but it doesn't
+	/// know about crops or rotations; use it before those.
+	pub fn remove<C: Colorspace>(&self, frame: &mut Image<f32, C>) {
+		let sx = frame.width as f32 / self.width.max(1) as f32;
+		let sy = frame.height as f32 / self.height.max(1) as f32;
+
+		for spot in &self.spots {
+			let x = (spot.x as f32 * sx) as usize;
+			let y = (spot.y as f32 * sy) as usize;
+			let right = ((spot.x + spot.width) as f32 * sx).ceil() as usize;
+			let bottom = ((spot.y + spot.height) as f32 * sy).ceil() as usize;
+
+			let right = right.min(frame.width);
+			let bottom = bottom.min(frame.height);
+			if x >= right || y >= bottom {
+				continue;
+			}
+
+			frame.fill(Region::new(x, y, right - x, bottom - y));
+		}
+	}
+
+	/// [remove](Self::remove) the dust from every frame
+	pub fn remove_all<C: Colorspace>(&self, frames: &mut [Image<f32, C>]) {
+		for frame in frames {
+			self.remove(frame);
+		}
+	}
+}
+
+/// Average every value with the ones within `radius` of it, using a summed
+/// area table so the radius doesn't change how long it takes
+fn box_blur(data: &[f32], width: usize, height: usize, radius: usize) -> Vec<f32> {
+	let stride = width + 1;
+	let mut table = vec![0.0f64; stride * (height + 1)];
+	for y in 0..height {
+		let mut row = 0.0;
+		for x in 0..width {
+			row += data[y * width + x] as f64;
+			table[(y + 1) * stride + x + 1] = table[y * stride + x + 1] + row;
+		}
+	}
+
+	let mut out = Vec::with_capacity(data.len());
+	for y in 0..height {
+		let top = y.saturating_sub(radius);
+		let bottom = (y + radius + 1).min(height);
+		for x in 0..width {
+			let left = x.saturating_sub(radius);
+			let right = (x + radius + 1).min(width);
+
+			let sum = table[bottom * stride + right]
+				- table[top * stride + right]
+				- table[bottom * stride + left]
+				+ table[top * stride + left];
+			let count = ((bottom - top) * (right - left)) as f64;
+			out.push((sum / count) as f32);
+		}
+	}
+
+	out
+}
+
+/// The bounding box and area of every group of touching `true`s
+fn components(set: &[bool], width: usize, height: usize) -> Vec<(Region, usize)> {
+	let mut seen = vec![false; set.len()];
+	let mut found = vec![];
+	let mut stack = vec![];
+
+	for start in 0..set.len() {
+		if !set[start] || seen[start] {
+			continue;
+		}
+
+		seen[start] = true;
+		stack.push(start);
+		let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+		let mut area = 0;
+
+		while let Some(idx) = stack.pop() {
+			let (x, y) = (idx % width, idx / width);
+			left = left.min(x);
+			top = top.min(y);
+			right = right.max(x + 1);
+			bottom = bottom.max(y + 1);
+			area += 1;
+
+			let mut visit = |n: usize| {
+				if set[n] && !seen[n] {
+					seen[n] = true;
+					stack.push(n);
+				}
+			};
+			if x > 0 {
+				visit(idx - 1);
+			}
+			if x + 1 < width {
+				visit(idx + 1);
+			}
+			if y > 0 {
+				visit(idx - width);
+			}
+			if y + 1 < height {
+				visit(idx + width);
+			}
+		}
+
+		found.push((Region::new(left, top, right - left, bottom - top), area));
+	}
+
+	found
+}
+
+/// Grow a spot by half again so the soft edge of its shadow gets covered
+/// and the fill has a feathered edge to blend with
+fn pad(spot: Region, width: usize, height: usize) -> Region {
+	let grow = (spot.width.max(spot.height) / 2).max(2);
+	let x = spot.x.saturating_sub(grow);
+	let y = spot.y.saturating_sub(grow);
+	let right = (spot.x + spot.width + grow).min(width);
+	let bottom = (spot.y + spot.height + grow).min(height);
+
+	Region::new(x, y, right - x, bottom - y)
+}
+
+/// What a quick look at a file says about it, for sorting a shoot before
+/// spending time on a full render.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Analysis {
+	/// How sharp the middle of the frame is. See [sharpness]. It only means
+	/// something compared to other frames from the same camera.
+	pub sharpness: f32,
+	/// How much of the frame is blown out, from 0.0 to 1.0
+	pub clipped_highlights: f32,
+	/// How much of the frame is too dark to pull anything out of, from 0.0
+	/// to 1.0
+	pub crushed_shadows: f32,
+	/// The average brightness, normalized so 0.0 is black and 1.0 is white
+	pub mean_luminance: f32,
+}
+
+// A pixel this close to white is clipped. Normalizing and the half size
+// debayer averaging the greens don't leave clipped pixels at exactly 1.0.
+const CLIPPED: f32 = 0.99;
+// Ten stops down from white is as far as a raw can usefully be pushed
+const CRUSHED: f32 = 1.0 / 1024.0;
+
+impl Analysis {
+	/// Is most of the frame either blown out or black? Good for throwing out
+	/// the shots where the flash didn't fire, or did when it shouldn't have.
+	pub fn is_unusable(&self) -> bool {
+		self.clipped_highlights + self.crushed_shadows > 0.5
+	}
+}
+
+/// Take a quick look at a raw file. It's decoded but not demosaiced, just
+/// [halved](Image::debayer_half), and everything is measured on that.
+/// Monochrome and LinearRaw files don't need either, so they're measured as
+/// they are.
+#[cfg(feature = "fs")]
+pub fn analyze<P: AsRef<Path>>(path: P) -> Result<Analysis, Error> {
+	analyze_reader(&mut BufReader::new(File::open(path)?))
+}
+
+/// [analyze] a raw that isn't in a file, or is one you've already opened
+pub fn analyze_reader<R: Read>(reader: &mut R) -> Result<Analysis, Error> {
+	let mut bytes = vec![];
+	reader.read_to_end(&mut bytes)?;
+
+	// Floating point DNGs were never whole numbers to begin with
+	if dng::is_dng(&bytes) && dng::is_float(&bytes) {
+		let mut raw = dng::decode_float(&bytes)?;
+		raw.crop();
+		return Ok(measure(&raw.debayer_half()));
+	}
+
+	let image = crate::decode_dyn_slice(&bytes)?;
+	Ok(match image.colorspace {
+		ColorspaceKind::Monochrome => {
+			let mut raw: Image<u16, Monochrome> = image.try_into()?;
+			raw.crop();
+			measure(&raw.normalize())
+		}
+		ColorspaceKind::LinRgb => {
+			let raw: Image<u16, LinRgb> = image.try_into()?;
+			measure(&raw.normalize())
+		}
+		_ => {
+			let mut raw: Image<u16, BayerRgb> = image.try_into()?;
+			raw.crop();
+			measure(&raw.debayer_half().normalize())
+		}
+	})
+}
+
+/// [analyze] every file, spread over rayon's thread pool. The results are in
+/// the same order as `paths`, so sort them however you like.
+#[cfg(feature = "fs")]
+pub fn analyze_all<P: AsRef<Path> + Sync>(paths: &[P]) -> Vec<Result<Analysis, Error>> {
+	paths.par_iter().map(analyze).collect()
+}
+
+/// How sharp an image is, as the variance of the Laplacian over the middle
+/// of it. A sharp image has a lot of hard edges and so a lot of variance;
+/// missed focus and camera shake smear the edges out and the number drops.
+/// The middle is where the subject usually is, and leaving out the edges
+/// keeps a soft corner or a blurry foreground from counting.
+pub fn sharpness<C: Colorspace>(img: &Image<f32, C>) -> f32 {
+	let lum: Vec<f32> = img
+		.data
+		.chunks_exact(C::COMPONENTS)
+		.map(luminance)
+		.collect();
+
+	laplacian_variance(&lum, img.width, img.height)
+}
+
+/// Everything in an [Analysis], from a normalized preview of the raw
+fn measure<C: Colorspace>(preview: &Image<f32, C>) -> Analysis {
+	let lum: Vec<f32> = preview
+		.data
+		.chunks_exact(C::COMPONENTS)
+		.map(luminance)
+		.collect();
+	let pixels = lum.len().max(1) as f32;
+
+	// One channel blowing out is enough to lose the highlight, and it'd be
+	// hidden in the luminance
+	let clipped = preview
+		.data
+		.chunks_exact(C::COMPONENTS)
+		.filter(|px| px.iter().any(|v| *v >= CLIPPED))
+		.count();
+	let crushed = lum.iter().filter(|v| **v < CRUSHED).count();
+
+	Analysis {
+		sharpness: laplacian_variance(&lum, preview.width, preview.height),
+		clipped_highlights: clipped as f32 / pixels,
+		crushed_shadows: crushed as f32 / pixels,
+		mean_luminance: lum.iter().sum::<f32>() / pixels,
+	}
+}
+
+/// The variance of the Laplacian over the middle half of the image, each
+/// way. The values are put through a square root first, a rough stand in
+/// for a gamma curve, so the shadows count for about as much as they look.
+fn laplacian_variance(lum: &[f32], width: usize, height: usize) -> f32 {
+	let (left, right) = (width / 4, width - width / 4);
+	let (top, bottom) = (height / 4, height - height / 4);
+	let at = |x: usize, y: usize| lum[y * width + x].max(0.0).sqrt();
+
+	let mut sum = 0.0f64;
+	let mut sum_squared = 0.0f64;
+	let mut count = 0;
+	for y in top.max(1)..bottom.min(height.saturating_sub(1)) {
+		for x in left.max(1)..right.min(width.saturating_sub(1)) {
+			let laplacian =
+				at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y);
+			sum += laplacian as f64;
+			sum_squared += (laplacian * laplacian) as f64;
+			count += 1;
+		}
+	}
+
+	if count == 0 {
+		return 0.0;
+	}
+	let mean = sum / count as f64;
+	(sum_squared / count as f64 - mean * mean) as f32
+}
+
+/// Stops a [develop_all] part way through. Clone it, hand a clone to
+/// whatever has the cancel button, and call [cancel](Self::cancel).
+///
+/// It's cooperative: files that have started are finished, the ones that
+/// haven't are skipped. A file is checked before it's decoded and again
+/// before its output is written, so a cancel never leaves half a file.
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn cancel(&self) {
+		self.0.store(true, Ordering::Relaxed);
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.0.load(Ordering::Relaxed)
+	}
+}
+
+/// Something happening to one of the files in a [develop_all]
+#[cfg(feature = "fs")]
+#[derive(Debug)]
+pub struct Progress<'a> {
+	/// Where the file is in the list you gave us
+	pub index: usize,
+	pub path: &'a Path,
+	pub status: Status<'a>,
+	/// How many files are through, including this one if it just got
+	/// through, and how many there are. They finish out of order, so this is
+	/// what to drive a progress bar with, not `index`.
+	pub done: usize,
+	pub total: usize,
+}
+
+#[cfg(feature = "fs")]
+#[derive(Debug)]
+pub enum Status<'a> {
+	/// We're decoding it
+	Started,
+	Finished,
+	Failed(&'a Error),
+	/// It was skipped, or its output wasn't written, because of a [Cancel]
+	Cancelled,
+}
+
+/// Decode and develop every file with `recipe` and hand each finished image
+/// to `output`, which might save it or might keep it around. The files are
+/// spread over rayon's thread pool; to use fewer threads, call this from
+/// inside rayon's `ThreadPool::install` on a smaller pool. Without the
+/// `rayon` feature they're done one at a time, in order.
+///
+/// `progress` hears about every file starting and getting through, from
+/// whichever thread it's on, so keep it quick. The results are in the same
+/// order as `paths`, with [Error::Cancelled] for anything a cancel stopped.
+///
+/// ```no_run
+/// # use rawproc::{batch::{self, Cancel, Status}, export::TiffWriter, recipe::Recipe};
+/// let paths = ["one.nef", "two.nef"];
+/// let results = batch::develop_all(
+///     &paths,
+///     &Recipe::default(),
+///     &Cancel::new(),
+///     |p| {
+///         if let Status::Finished = p.status {
+///             println!("{}/{} {}", p.done, p.total, p.path.display());
+///         }
+///     },
+///     |_, path, image| {
+///         let mut out = std::fs::File::create(path.with_extension("tif"))?;
+///         TiffWriter::new().write(&image.gamma(), &mut out)
+///     },
+/// );
+/// ```
+#[cfg(feature = "fs")]
+pub fn develop_all<P, T, F, O>(
+	paths: &[P],
+	recipe: &Recipe,
+	cancel: &Cancel,
+	progress: F,
+	output: O,
+) -> Vec<Result<T, Error>>
+where
+	P: AsRef<Path> + Sync,
+	T: Send,
+	F: Fn(Progress) + Sync,
+	O: Fn(usize, &Path, Image<f32, LinSrgb>) -> Result<T, Error> + Sync,
+{
+	let total = paths.len();
+	let done = AtomicUsize::new(0);
+
+	paths
+		.par_iter()
+		.enumerate()
+		.map(|(index, path)| {
+			let path = path.as_ref();
+			let report = |status| {
+				let done = match status {
+					Status::Started => done.load(Ordering::Relaxed),
+					_ => done.fetch_add(1, Ordering::Relaxed) + 1,
+				};
+				progress(Progress {
+					index,
+					path,
+					status,
+					done,
+					total,
+				})
+			};
+
+			let result = if cancel.is_cancelled() {
+				Err(Error::Cancelled)
+			} else {
+				report(Status::Started);
+				crate::decode_dyn_file(path)
+					.and_then(|raw| recipe.apply_dyn(raw))
+					.and_then(|image| match cancel.is_cancelled() {
+						true => Err(Error::Cancelled),
+						false => output(index, path, image),
+					})
+			};
+
+			match &result {
+				Ok(_) => report(Status::Finished),
+				Err(Error::Cancelled) => report(Status::Cancelled),
+				Err(e) => report(Status::Failed(e)),
+			}
+			result
+		})
+		.collect()
+}
@@ -0,0 +1,124 @@
+//! Picking how to process an image based on how much memory we're allowed.
+//!
+//! Full frame f32 processing is the nicest but it's also the hungriest, a
+//! 24MP image peaks somewhere around 450MB. That's fine on a desktop and not
+//! at all fine on a little ARM board. A [MemoryBudget] works out the best
+//! [Strategy] that stays under the limit you give it.
+//!
+//! The numbers here are estimates of the peak, which is usually while a
+//! conversion has both the old and new buffers alive, not an exact count.
+
+use std::mem::size_of;
+
+/// The type used for the RGB image between debayering and output
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Intermediate {
+	U16,
+	F32,
+}
+
+impl Intermediate {
+	fn size(&self) -> usize {
+		match self {
+			Intermediate::U16 => size_of::<u16>(),
+			Intermediate::F32 => size_of::<f32>(),
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Strategy {
+	pub intermediate: Intermediate,
+	/// Rows per tile, or None to process the whole frame at once
+	pub tile_height: Option<usize>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryBudget {
+	pub bytes: usize,
+}
+
+impl MemoryBudget {
+	/// Tiles shorter than this spend more time on their overlap than on the
+	/// image, so we won't go below it.
+	pub const MIN_TILE_HEIGHT: usize = 64;
+
+	pub fn new(bytes: usize) -> Self {
+		Self { bytes }
+	}
+
+	pub fn megabytes(mb: usize) -> Self {
+		Self::new(mb * 1024 * 1024)
+	}
+
+	/// Choose how to process an image of this size. We prefer, in order, full
+	/// frame f32, tiled f32, full frame u16, and then tiled u16. Tiles are as
+	/// tall as will fit. None if nothing fits at all.
+	pub fn strategy(&self, width: usize, height: usize) -> Option<Strategy> {
+		for intermediate in [Intermediate::F32, Intermediate::U16] {
+			if full_frame_peak(width, height, intermediate) <= self.bytes {
+				return Some(Strategy {
+					intermediate,
+					tile_height: None,
+				});
+			}
+
+			if let Some(tile_height) = self.largest_tile(width, height, intermediate) {
+				return Some(Strategy {
+					intermediate,
+					tile_height: Some(tile_height),
+				});
+			}
+		}
+
+		None
+	}
+
+	fn largest_tile(&self, width: usize, height: usize, inter: Intermediate) -> Option<usize> {
+		if tiled_peak(width, height, Self::MIN_TILE_HEIGHT, inter) > self.bytes {
+			return None;
+		}
+
+		// The peak grows linearly with tile height so we can solve for it
+		let fixed = tiled_peak(width, height, 0, inter);
+		let per_row = tiled_peak(width, height, 1, inter) - fixed;
+		let rows = (self.bytes - fixed) / per_row.max(1);
+
+		Some(rows.clamp(Self::MIN_TILE_HEIGHT, height.max(Self::MIN_TILE_HEIGHT)))
+	}
+}
+
+/// Estimated peak memory, in bytes, processing the whole frame at once.
+///
+/// Debayering holds the u16 mosaic and the RGB image at the same time, and
+/// converting to f32 holds the u16 and f32 RGB images at the same time.
+pub fn full_frame_peak(width: usize, height: usize, intermediate: Intermediate) -> usize {
+	let pixels = width * height;
+	let mosaic = pixels * size_of::<u16>();
+	let rgb16 = pixels * 3 * size_of::<u16>();
+
+	let debayer = mosaic + rgb16;
+	let convert = match intermediate {
+		Intermediate::U16 => rgb16,
+		Intermediate::F32 => rgb16 + pixels * 3 * intermediate.size(),
+	};
+
+	debayer.max(convert) + output_size(pixels)
+}
+
+/// Estimated peak memory, in bytes, processing in tiles of `tile_height`
+/// rows. The mosaic and the output have to be whole, but only one tile's
+/// worth of intermediate is alive at a time. Tiles get two rows of overlap
+/// on each side for the debayer.
+pub fn tiled_peak(width: usize, height: usize, tile_height: usize, inter: Intermediate) -> usize {
+	let mosaic = width * height * size_of::<u16>();
+	let tile_pixels = width * (tile_height + 4);
+	let tile = tile_pixels * 3 * (size_of::<u16>() + inter.size());
+
+	mosaic + tile + output_size(width * height)
+}
+
+// 8-bit RGB for the output
+fn output_size(pixels: usize) -> usize {
+	pixels * 3
+}
@@ -0,0 +1,184 @@
+//! Colour grading the way video does it: the ASC Color Decision List, and
+//! lift, gamma, and gain, which is the same thing with the knobs labelled
+//! differently. A CDL is slope, offset, and power per channel and then one
+//! saturation, and it's what colourists hand each other so a grade looks the
+//! same in every tool.
+//!
+//! The files are XML. We read and write the single correction kind, `.cc`,
+//! and reading takes the first correction out of a `.cdl` list too.
+
+use crate::{algorithms, colorspace::Colorspace, image::Image, Error};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CdlError {
+	#[error("The CDL is missing its {0} element")]
+	MissingElement(&'static str),
+	#[error("The CDL's {0} element should be three numbers")]
+	BadValues(&'static str),
+}
+
+/// An ASC CDL. Each channel is `(in * slope + offset) ^ power`, and then the
+/// saturation is applied to all three.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cdl {
+	pub slope: [f32; 3],
+	pub offset: [f32; 3],
+	pub power: [f32; 3],
+	pub saturation: f32,
+}
+
+impl Default for Cdl {
+	fn default() -> Self {
+		Self {
+			slope: [1.0; 3],
+			offset: [0.0; 3],
+			power: [1.0; 3],
+			saturation: 1.0,
+		}
+	}
+}
+
+/// The colour wheels. Lift moves the blacks and leaves white alone, gain
+/// moves the whites and leaves black alone, and gamma bends the middle.
+/// Lift is 0.0 for no change, gain and gamma are 1.0.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LiftGammaGain {
+	pub lift: [f32; 3],
+	pub gamma: [f32; 3],
+	pub gain: [f32; 3],
+}
+
+impl Default for LiftGammaGain {
+	fn default() -> Self {
+		Self {
+			lift: [0.0; 3],
+			gamma: [1.0; 3],
+			gain: [1.0; 3],
+		}
+	}
+}
+
+impl From<LiftGammaGain> for Cdl {
+	/// Lift, gamma, and gain is `(gain * (in + lift * (1 - in))) ^ (1 / gamma)`
+	/// and that multiplies out to a slope and offset.
+	fn from(lgg: LiftGammaGain) -> Self {
+		let mut cdl = Cdl::default();
+		for c in 0..3 {
+			cdl.slope[c] = lgg.gain[c] * (1.0 - lgg.lift[c]);
+			cdl.offset[c] = lgg.gain[c] * lgg.lift[c];
+			cdl.power[c] = 1.0 / lgg.gamma[c].max(f32::EPSILON);
+		}
+
+		cdl
+	}
+}
+
+impl Cdl {
+	/// Read the first colour correction out of a `.cc` or `.cdl` file.
+	/// Saturation is optional and is 1.0 if it's not there.
+	pub fn from_xml(xml: &str) -> Result<Self, Error> {
+		let triple = |name: &'static str| -> Result<[f32; 3], CdlError> {
+			let text = element(xml, name).ok_or(CdlError::MissingElement(name))?;
+			let values: Vec<f32> = text
+				.split_whitespace()
+				.map(|v| v.parse::<f32>())
+				.collect::<Result<_, _>>()
+				.map_err(|_| CdlError::BadValues(name))?;
+
+			values.try_into().map_err(|_| CdlError::BadValues(name))
+		};
+
+		let saturation = match element(xml, "Saturation") {
+			Some(text) => text
+				.trim()
+				.parse()
+				.map_err(|_| CdlError::BadValues("Saturation"))?,
+			None => 1.0,
+		};
+
+		Ok(Self {
+			slope: triple("Slope")?,
+			offset: triple("Offset")?,
+			power: triple("Power")?,
+			saturation,
+		})
+	}
+
+	/// Write the CDL as a `.cc` file, a lone ColorCorrection with `id`.
+	pub fn to_xml(&self, id: &str) -> String {
+		let triple = |v: [f32; 3]| format!("{:.6} {:.6} {:.6}", v[0], v[1], v[2]);
+		let id: String = id
+			.chars()
+			.map(|c| match c {
+				'"' | '<' | '>' | '&' => '_',
+				c => c,
+			})
+			.collect();
+
+		format!(
+			"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+			<ColorCorrection id=\"{id}\" xmlns=\"urn:ASC:CDL:v1.01\">\n\
+			\t<SOPNode>\n\
+			\t\t<Slope>{}</Slope>\n\
+			\t\t<Offset>{}</Offset>\n\
+			\t\t<Power>{}</Power>\n\
+			\t</SOPNode>\n\
+			\t<SatNode>\n\
+			\t\t<Saturation>{:.6}</Saturation>\n\
+			\t</SatNode>\n\
+			</ColorCorrection>\n",
+			triple(self.slope),
+			triple(self.offset),
+			triple(self.power),
+			self.saturation,
+		)
+	}
+
+	/// Grade one pixel. Like the spec says, values are clamped to 0..1
+	/// before the power and again after the saturation.
+	pub fn apply_pixel(&self, rgb: [f32; 3]) -> [f32; 3] {
+		let mut out = [0.0; 3];
+		for c in 0..3 {
+			let sop = (rgb[c] * self.slope[c] + self.offset[c]).clamp(0.0, 1.0);
+			out[c] = sop.powf(self.power[c]);
+		}
+
+		let luma = algorithms::luminance(&out);
+		out.map(|v| (luma + self.saturation * (v - luma)).clamp(0.0, 1.0))
+	}
+}
+
+impl<C: Colorspace> Image<f32, C> {
+	/// Grade the image with a CDL. A CDL is meant for display referred
+	/// values, so this goes on after the tone curve, usually on sRGB.
+	///
+	/// # Panics
+	/// If the colorspace doesn't have three components.
+	pub fn apply_cdl(&mut self, cdl: &Cdl) {
+		self.par_map_pixels(|rgb: [f32; 3]| cdl.apply_pixel(rgb));
+	}
+}
+
+/// The text inside the first `<name>` element, ignoring attributes
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+	let open = format!("<{name}");
+	let mut from = 0;
+
+	loop {
+		let start = from + xml[from..].find(&open)?;
+		let after = start + open.len();
+		from = after;
+
+		// Make sure it's <Slope> and not <SlopeSomethingElse>
+		match xml[after..].chars().next()? {
+			'>' | ' ' | '\t' | '\n' | '\r' => (),
+			_ => continue,
+		}
+
+		let body = after + xml[after..].find('>')? + 1;
+		let end = body + xml[body..].find(&format!("</{name}>"))?;
+		return Some(&xml[body..end]);
+	}
+}
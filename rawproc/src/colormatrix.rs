@@ -0,0 +1,73 @@
+//! Colour matrices for cameras, for when a file doesn't bring a usable one of
+//! its own. DNGs are supposed to carry a ColorMatrix but not every converter
+//! writes one, and without it all we can do is pretend the camera sees in
+//! XYZ, which it very much doesn't.
+//!
+//! Like dcraw's adobe_coeff, these are the D65 matrices Adobe's DNG
+//! converter uses, taken from rawloader's camera data.
+
+use nalgebra::Matrix3;
+
+/// Make, model, and the XYZ to camera matrix, times 10,000. Makes and models
+/// are the cleaned up ones, the same as [RawMetadata](crate::image::RawMetadata)
+/// has for files rawloader reads.
+#[rustfmt::skip]
+const TABLE: &[(&str, &str, [i32; 9])] = &[
+	("Canon", "EOS 5D Mark II", [4716, 603, -830, -7798, 15474, 2480, -1496, 1937, 6651]),
+	("Canon", "EOS 5D Mark III", [6722, -635, -963, -4287, 12460, 2028, -908, 2162, 5668]),
+	("Canon", "EOS 5DS R", [6250, -711, -808, -5153, 12794, 2636, -1249, 2198, 5610]),
+	("Canon", "EOS 6D", [7034, -804, -1014, -4420, 12564, 2058, -851, 1994, 5758]),
+	("Canon", "EOS 6D Mark II", [6875, -970, -932, -4691, 12459, 2501, -874, 1953, 5809]),
+	("Canon", "EOS 7D", [6844, -996, -856, -3876, 11761, 2396, -593, 1772, 6198]),
+	("Canon", "EOS 7D Mark II", [7268, -1082, -969, -4186, 11839, 2663, -825, 2029, 5839]),
+	("Fujifilm", "GFX 50S", [11756, -4754, -874, -3056, 11045, 2305, -381, 1457, 6006]),
+	("Fujifilm", "X-E3", [11434, -4948, -1210, -3746, 12042, 1903, -666, 1479, 5235]),
+	("Fujifilm", "X-Pro2", [11434, -4948, -1210, -3746, 12042, 1903, -666, 1479, 5235]),
+	("Fujifilm", "X-T2", [11434, -4948, -1210, -3746, 12042, 1903, -666, 1479, 5235]),
+	("Fujifilm", "X-T20", [11434, -4948, -1210, -3746, 12042, 1903, -666, 1479, 5235]),
+	("Fujifilm", "X100F", [11434, -4948, -1210, -3746, 12042, 1903, -666, 1479, 5235]),
+	("Nikon", "D5300", [6988, -1384, -714, -5631, 13410, 2447, -1485, 2204, 7318]),
+	("Nikon", "D610", [8178, -2245, -609, -4857, 12394, 2776, -1207, 2086, 7298]),
+	("Nikon", "D700", [8139, -2171, -663, -8747, 16541, 2295, -1925, 2008, 8093]),
+	("Nikon", "D7200", [8322, -3112, -1047, -6367, 14342, 2179, -988, 1638, 6394]),
+	("Nikon", "D750", [9020, -2890, -715, -4535, 12436, 2348, -934, 1919, 7086]),
+	("Nikon", "D800", [7866, -2108, -555, -4869, 12483, 2681, -1176, 2069, 7501]),
+	("Nikon", "D810", [9369, -3195, -791, -4488, 12430, 2301, -893, 1796, 6872]),
+	("Olympus", "PEN-F", [9476, -3182, -765, -2613, 10958, 1893, -449, 1315, 5268]),
+	("Panasonic", "DC-G9", [7685, -2375, -634, -3687, 11700, 2249, -748, 1546, 5111]),
+	("Panasonic", "DC-GH5", [7641, -2336, -605, -3218, 11299, 2187, -485, 1338, 5121]),
+	("Panasonic", "DMC-GH4", [7122, -2108, -512, -3155, 11201, 2231, -541, 1423, 5045]),
+	("Pentax", "K-3", [7415, -2052, -721, -5186, 12788, 2682, -1446, 2157, 6773]),
+	("Pentax", "K-5", [8713, -2833, -743, -4342, 11900, 2772, -722, 1543, 6247]),
+	("Pentax", "KP", [8617, -3228, -1034, -4674, 12821, 2044, -803, 1577, 5728]),
+	("Sony", "DSC-RX100M3", [6596, -2079, -562, -4782, 13016, 1933, -970, 1581, 5181]),
+	("Sony", "ILCE-6000", [5991, -1456, -455, -4764, 12135, 2980, -707, 1425, 6701]),
+	("Sony", "ILCE-7M2", [5271, -712, -347, -6153, 13653, 2763, -1601, 2366, 7242]),
+	("Sony", "ILCE-7M3", [7374, -2389, -551, -5435, 13162, 2519, -1006, 1795, 6552]),
+	("Sony", "ILCE-7RM2", [6629, -1900, -483, -4618, 12349, 2550, -622, 1381, 6514]),
+	("Sony", "ILCE-7RM3", [6640, -1847, -503, -5238, 13010, 2474, -993, 1673, 6527]),
+	("Sony", "ILCE-7SM2", [5838, -1430, -246, -3497, 11477, 2297, -748, 1885, 5778]),
+];
+
+/// The XYZ to camera matrix for a camera, the same as a DNG's ColorMatrix
+/// for D65. The make matches if it starts with ours, so "NIKON CORPORATION"
+/// is Nikon, and the model if it's ours or ends with it, so "Canon EOS 6D"
+/// is the EOS 6D. Case doesn't matter.
+pub fn lookup(make: &str, model: &str) -> Option<Matrix3<f32>> {
+	let (make, model) = (make.to_lowercase(), model.to_lowercase());
+
+	TABLE
+		.iter()
+		.find(|(m, md, _)| {
+			let md = md.to_lowercase();
+			make.starts_with(&m.to_lowercase())
+				&& (model == md || model.ends_with(&format!(" {md}")))
+		})
+		.map(|(_, _, matrix)| Matrix3::from_row_slice(&matrix.map(|v| v as f32 / 10_000.0)))
+}
+
+/// Can we get colour out of this matrix? Files without one tend to give us
+/// zeros, and we have to be able to invert it.
+pub(crate) fn is_usable(matrix: &Matrix3<f32>) -> bool {
+	matrix.iter().all(|v| v.is_finite()) && matrix.determinant().abs() > f32::EPSILON
+}
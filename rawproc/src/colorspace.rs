@@ -1,3 +1,7 @@
+use nalgebra::{Matrix3, Vector3};
+
+use crate::{icc, transfer::TransferFunction};
+
 /*
 We need to be able to represent:
 - Sensor data
@@ -8,6 +12,194 @@ We need to be able to represent:
 pub trait Colorspace: Clone {
 	/// Number of elements per pixel
 	const COMPONENTS: usize;
+	/// Which colorspace this is, for when we only know at runtime
+	const KIND: ColorspaceKind;
+}
+
+/// The colorspaces that are three channels of red, green, and blue, linear
+/// or not. XYZ, HSV, and the Labs have three components too, but they mean
+/// something else.
+pub trait Rgb: Colorspace {}
+
+impl Rgb for LinRgb {}
+impl Rgb for LinSrgb {}
+impl Rgb for Srgb {}
+impl Rgb for AdobeRgb {}
+impl Rgb for ProPhotoRgb {}
+impl Rgb for Rec2020 {}
+impl Rgb for DisplayP3 {}
+
+/// Every colorspace we have as a value instead of a type. Useful for picking
+/// one at runtime, see [DynImage](crate::image::DynImage).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColorspaceKind {
+	BayerRgb,
+	Monochrome,
+	LinRgb,
+	XYZ,
+	LinSrgb,
+	Srgb,
+	Hsv,
+	Lab,
+	Oklab,
+	AdobeRgb,
+	ProPhotoRgb,
+	Rec2020,
+	DisplayP3,
+}
+
+impl ColorspaceKind {
+	/// How to tag a file holding this colorspace. Camera RGB and XYZ don't
+	/// have fixed primaries, and HSV and the Labs aren't RGB at all, so they
+	/// get None and are best written untagged.
+	pub fn color_tag(&self) -> Option<ColorTag> {
+		match self {
+			ColorspaceKind::Srgb => Some(ColorTag::SRGB),
+			ColorspaceKind::LinSrgb => Some(ColorTag::LINEAR_SRGB),
+			ColorspaceKind::AdobeRgb => Some(ColorTag::ADOBE_RGB),
+			ColorspaceKind::ProPhotoRgb => Some(ColorTag::PROPHOTO_RGB),
+			ColorspaceKind::Rec2020 => Some(ColorTag::REC2020),
+			ColorspaceKind::DisplayP3 => Some(ColorTag::DISPLAY_P3),
+			_ => None,
+		}
+	}
+
+	pub fn components(&self) -> usize {
+		match self {
+			ColorspaceKind::BayerRgb => BayerRgb::COMPONENTS,
+			ColorspaceKind::Monochrome => Monochrome::COMPONENTS,
+			ColorspaceKind::LinRgb => LinRgb::COMPONENTS,
+			ColorspaceKind::XYZ => XYZ::COMPONENTS,
+			ColorspaceKind::LinSrgb => LinSrgb::COMPONENTS,
+			ColorspaceKind::Srgb => Srgb::COMPONENTS,
+			ColorspaceKind::Hsv => Hsv::COMPONENTS,
+			ColorspaceKind::Lab => Lab::COMPONENTS,
+			ColorspaceKind::Oklab => Oklab::COMPONENTS,
+			ColorspaceKind::AdobeRgb => AdobeRgb::COMPONENTS,
+			ColorspaceKind::ProPhotoRgb => ProPhotoRgb::COMPONENTS,
+			ColorspaceKind::Rec2020 => Rec2020::COMPONENTS,
+			ColorspaceKind::DisplayP3 => DisplayP3::COMPONENTS,
+		}
+	}
+}
+
+/// Everything an encoder needs to tell a colour managed viewer what the
+/// numbers in a file mean: the primaries, the white, and the curve. PNG wants
+/// these as its cHRM, gAMA, and sRGB chunks, and most everything else wants
+/// the [ICC profile](Self::icc_profile).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ColorTag {
+	/// What the ICC profile gets called
+	pub name: &'static str,
+	/// Red, green, then blue, as CIE x and y
+	pub primaries: [[f32; 2]; 3],
+	/// The white point, as CIE x and y
+	pub white: [f32; 2],
+	pub transfer: TransferFunction,
+}
+
+impl ColorTag {
+	// Rec. 709 primaries
+	const SRGB_PRIMARIES: [[f32; 2]; 3] = [[0.64, 0.33], [0.30, 0.60], [0.15, 0.06]];
+
+	pub const SRGB: ColorTag = ColorTag {
+		name: "sRGB",
+		primaries: Self::SRGB_PRIMARIES,
+		white: D65,
+		transfer: TransferFunction::Srgb,
+	};
+
+	pub const LINEAR_SRGB: ColorTag = ColorTag {
+		name: "Linear sRGB",
+		primaries: Self::SRGB_PRIMARIES,
+		white: D65,
+		transfer: TransferFunction::Linear,
+	};
+
+	/// Adobe RGB (1998). A greener green than sRGB, made with CMYK printing
+	/// in mind.
+	pub const ADOBE_RGB: ColorTag = ColorTag {
+		name: "Adobe RGB (1998)",
+		primaries: [[0.64, 0.33], [0.21, 0.71], [0.15, 0.06]],
+		white: D65,
+		// 2.2, as written in the spec
+		transfer: TransferFunction::Gamma(563.0 / 256.0),
+	};
+
+	/// ProPhoto RGB, also called ROMM. Big enough to hold nearly every
+	/// colour there is, and some that aren't, so it wants 16 bits. The spec
+	/// has a tiny linear part near black which we leave off.
+	pub const PROPHOTO_RGB: ColorTag = ColorTag {
+		name: "ProPhoto RGB",
+		primaries: [[0.7347, 0.2653], [0.1596, 0.8404], [0.0366, 0.0001]],
+		white: D50,
+		transfer: TransferFunction::GAMMA_18,
+	};
+
+	/// ITU-R BT.2020, the UHD TV primaries, with the Rec. 709 curve it
+	/// shares for SDR.
+	pub const REC2020: ColorTag = ColorTag {
+		name: "Rec. 2020",
+		primaries: [[0.708, 0.292], [0.170, 0.797], [0.131, 0.046]],
+		white: D65,
+		transfer: TransferFunction::Rec709,
+	};
+
+	/// Display P3, what Apple's screens are. DCI-P3 primaries with sRGB's
+	/// white and curve.
+	pub const DISPLAY_P3: ColorTag = ColorTag {
+		name: "Display P3",
+		primaries: [[0.680, 0.320], [0.265, 0.690], [0.150, 0.060]],
+		white: D65,
+		transfer: TransferFunction::Srgb,
+	};
+
+	/// Is this exactly sRGB? PNG has a chunk just for saying so, which
+	/// viewers prefer over the chromaticities and gamma.
+	pub fn is_srgb(&self) -> bool {
+		self.primaries == Self::SRGB_PRIMARIES
+			&& self.white == D65
+			&& self.transfer == TransferFunction::Srgb
+	}
+
+	/// The gamma the curve is closest to, for formats that only take a
+	/// number. PNG's gAMA wants the inverse of this.
+	pub fn gamma(&self) -> f32 {
+		self.transfer.approximate_gamma()
+	}
+
+	/// The matrix that takes linear RGB in this colorspace to XYZ, with
+	/// `(1, 1, 1)` landing on the white point
+	pub fn rgb_to_xyz(&self) -> Matrix3<f32> {
+		let primaries = Matrix3::from_columns(&self.primaries.map(xy_to_xyz));
+		let scale = primaries.try_inverse().unwrap_or_else(Matrix3::identity) * self.white_xyz();
+		primaries * Matrix3::from_diagonal(&scale)
+	}
+
+	/// The white point as XYZ, with a Y of 1.0
+	pub fn white_xyz(&self) -> Vector3<f32> {
+		xy_to_xyz(self.white)
+	}
+
+	/// A version 2 ICC display profile describing this colorspace. It's a
+	/// couple kilobytes, most of that being the curve.
+	pub fn icc_profile(&self) -> Vec<u8> {
+		icc::rgb_profile(self)
+	}
+}
+
+/// Noon daylight, what most screens and sRGB are made for. CIE x and y.
+pub const D65: [f32; 2] = [0.3127, 0.3290];
+/// Horizon light, a bit warmer than D65. Print and ICC profiles use it.
+pub const D50: [f32; 2] = [0.3457, 0.3585];
+/// Tungsten, an ordinary light bulb. Cameras usually have a matrix for this
+/// one and one for D65.
+pub const ILLUMINANT_A: [f32; 2] = [0.44757, 0.40745];
+
+/// A white given as CIE x and y to XYZ, with a Y of 1.0
+pub fn xy_to_xyz([x, y]: [f32; 2]) -> Vector3<f32> {
+	Vector3::new(x / y, 1.0, (1.0 - x - y) / y)
 }
 
 /// Straight-from-the-camera colours. Almost certainly linear.
@@ -16,6 +208,19 @@ pub struct BayerRgb {}
 
 impl Colorspace for BayerRgb {
 	const COMPONENTS: usize = 1;
+	const KIND: ColorspaceKind = ColorspaceKind::BayerRgb;
+}
+
+/// Straight from a sensor without a colour filter array, like a Leica
+/// Monochrom or an astro camera with the filters scraped off. One sample a
+/// pixel, and it's all the same colour so there's nothing to debayer or
+/// whitebalance.
+#[derive(Clone, Debug)]
+pub struct Monochrome {}
+
+impl Colorspace for Monochrome {
+	const COMPONENTS: usize = 1;
+	const KIND: ColorspaceKind = ColorspaceKind::Monochrome;
 }
 
 /// Linear RGB.
@@ -24,6 +229,7 @@ pub struct LinRgb {}
 
 impl Colorspace for LinRgb {
 	const COMPONENTS: usize = 3;
+	const KIND: ColorspaceKind = ColorspaceKind::LinRgb;
 }
 
 #[derive(Clone, Debug)]
@@ -31,6 +237,7 @@ pub struct XYZ {}
 
 impl Colorspace for XYZ {
 	const COMPONENTS: usize = 3;
+	const KIND: ColorspaceKind = ColorspaceKind::XYZ;
 }
 
 #[derive(Clone, Debug)]
@@ -38,6 +245,7 @@ pub struct LinSrgb {}
 
 impl Colorspace for LinSrgb {
 	const COMPONENTS: usize = 3;
+	const KIND: ColorspaceKind = ColorspaceKind::LinSrgb;
 }
 
 #[derive(Clone, Debug)]
@@ -45,6 +253,7 @@ pub struct Srgb {}
 
 impl Colorspace for Srgb {
 	const COMPONENTS: usize = 3;
+	const KIND: ColorspaceKind = ColorspaceKind::Srgb;
 }
 
 //TODO: gen- Not really a colorspace but more like, representation?
@@ -53,4 +262,61 @@ pub struct Hsv {}
 
 impl Colorspace for Hsv {
 	const COMPONENTS: usize = 3;
+	const KIND: ColorspaceKind = ColorspaceKind::Hsv;
+}
+
+/// CIE L\*a\*b\*. Lightness from 0 to 100, then green to red and blue to
+/// yellow. White is the image's white, so neutrals have no a or b.
+#[derive(Clone, Debug)]
+pub struct Lab {}
+
+impl Colorspace for Lab {
+	const COMPONENTS: usize = 3;
+	const KIND: ColorspaceKind = ColorspaceKind::Lab;
+}
+
+/// Björn Ottosson's Oklab. Like Lab but better at keeping hues where they
+/// are as lightness and chroma change. Lightness goes from 0 to 1.
+#[derive(Clone, Debug)]
+pub struct Oklab {}
+
+impl Colorspace for Oklab {
+	const COMPONENTS: usize = 3;
+	const KIND: ColorspaceKind = ColorspaceKind::Oklab;
+}
+
+/// Adobe RGB (1998), gamma encoded. See [ColorTag::ADOBE_RGB].
+#[derive(Clone, Debug)]
+pub struct AdobeRgb {}
+
+impl Colorspace for AdobeRgb {
+	const COMPONENTS: usize = 3;
+	const KIND: ColorspaceKind = ColorspaceKind::AdobeRgb;
+}
+
+/// ProPhoto RGB, gamma encoded. See [ColorTag::PROPHOTO_RGB].
+#[derive(Clone, Debug)]
+pub struct ProPhotoRgb {}
+
+impl Colorspace for ProPhotoRgb {
+	const COMPONENTS: usize = 3;
+	const KIND: ColorspaceKind = ColorspaceKind::ProPhotoRgb;
+}
+
+/// Rec. 2020 with the Rec. 709 curve. See [ColorTag::REC2020].
+#[derive(Clone, Debug)]
+pub struct Rec2020 {}
+
+impl Colorspace for Rec2020 {
+	const COMPONENTS: usize = 3;
+	const KIND: ColorspaceKind = ColorspaceKind::Rec2020;
+}
+
+/// Display P3 with the sRGB curve. See [ColorTag::DISPLAY_P3].
+#[derive(Clone, Debug)]
+pub struct DisplayP3 {}
+
+impl Colorspace for DisplayP3 {
+	const COMPONENTS: usize = 3;
+	const KIND: ColorspaceKind = ColorspaceKind::DisplayP3;
 }
@@ -0,0 +1,171 @@
+//! The part of Canon's CR2 that rawloader doesn't read. Dual Pixel RAW files
+//! have a second raw frame after the usual one: the usual frame is both
+//! halves of every pixel added together, A+B, and the second is only the A
+//! half. B is the difference of the two. rawloader only ever decodes the
+//! first frame, so we go get the second one ourselves.
+//!
+//! CR3 is a different container entirely and rawloader can't open those at
+//! all, Dual Pixel or not. We spot them so that [decode](crate::decode) can
+//! say so, instead of that it doesn't know the file at all.
+
+use crate::{
+	image::SubImage,
+	ljpeg,
+	tiff::{Ifd, Tiff},
+	Error,
+};
+
+const TAG_NEW_SUBFILE_TYPE: u16 = 0x00FE;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_CR2_ID: u16 = 0xC5D8;
+const TAG_CR2_SLICES: u16 = 0xC640;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Cr2Error {
+	#[error("There's no raw frame {0}")]
+	NoFrame(usize),
+	#[error("The raw frame points outside of the file")]
+	Truncated,
+	#[error("Raw frame {index} is {width}x{height} but the first one isn't")]
+	FrameSize {
+		index: usize,
+		width: usize,
+		height: usize,
+	},
+}
+
+/// Is this a CR2? They're a TIFF with "CR" and a major version of 2 right
+/// after the header.
+pub(crate) fn is_cr2(data: &[u8]) -> bool {
+	Tiff::new(data).is_some() && data.get(8..11) == Some(b"CR\x02")
+}
+
+/// Is this a CR3? They're ISO base media, like an MP4, with a file type box
+/// of "crx ".
+pub(crate) fn is_cr3(data: &[u8]) -> bool {
+	data.get(4..12) == Some(b"ftypcrx ")
+}
+
+/// Every raw frame in the CR2, A+B first. Empty if there's only the one,
+/// which is every CR2 that isn't Dual Pixel. Every frame is the same size as
+/// the first, so that's what `width` and `height` are.
+pub(crate) fn sub_images(data: &[u8], width: usize, height: usize) -> Vec<SubImage> {
+	let tiff = match Tiff::new(data) {
+		Some(tiff) if is_cr2(data) => tiff,
+		_ => return vec![],
+	};
+
+	let frames = frames(&tiff);
+	if frames.len() < 2 {
+		return vec![];
+	}
+
+	frames
+		.iter()
+		.enumerate()
+		.map(|(idx, ifd)| SubImage {
+			width,
+			height,
+			subfile_type: ifd
+				.get(TAG_NEW_SUBFILE_TYPE)
+				.and_then(|e| tiff.u32s(e))
+				.and_then(|v| v.first().copied())
+				.unwrap_or(0),
+			primary: idx == 0,
+			linear: false,
+			float: false,
+		})
+		.collect()
+}
+
+/// Decode one of the raw frames. It has to come out `width` by `height`,
+/// the size of the first frame, or we don't know what we're looking at.
+pub(crate) fn decode_frame(
+	data: &[u8],
+	index: usize,
+	width: usize,
+	height: usize,
+) -> Result<Vec<u16>, Error> {
+	let tiff = Tiff::new(data).ok_or(Cr2Error::NoFrame(index))?;
+	let ifd = frames(&tiff)
+		.into_iter()
+		.nth(index)
+		.ok_or(Cr2Error::NoFrame(index))?;
+
+	let first = |tag| {
+		ifd.get(tag)
+			.and_then(|e| tiff.u32s(e))
+			.and_then(|v| v.first().copied())
+	};
+	let offset = first(TAG_STRIP_OFFSETS).ok_or(Cr2Error::Truncated)? as usize;
+	let count = first(TAG_STRIP_BYTE_COUNTS).ok_or(Cr2Error::Truncated)? as usize;
+	let bytes = data
+		.get(offset..offset.saturating_add(count))
+		.ok_or(Cr2Error::Truncated)?;
+
+	let jpeg = ljpeg::decode(bytes)?;
+	let slices = ifd.get(TAG_CR2_SLICES).and_then(|e| tiff.u32s(e));
+	let (frame_width, samples) = unslice(jpeg, slices.as_deref());
+
+	let frame_height = samples.len() / frame_width.max(1);
+	if frame_width != width || frame_height != height {
+		return Err(Cr2Error::FrameSize {
+			index,
+			width: frame_width,
+			height: frame_height,
+		}
+		.into());
+	}
+
+	Ok(samples)
+}
+
+/// The IFDs in the chain that hold raw data. IFD3 is the usual one and a
+/// Dual Pixel file has another after it.
+fn frames(tiff: &Tiff) -> Vec<Ifd> {
+	tiff.chain()
+		.into_iter()
+		.filter(|ifd| {
+			ifd.get(TAG_STRIP_OFFSETS).is_some()
+				&& (ifd.get(TAG_CR2_SLICES).is_some() || ifd.get(TAG_CR2_ID).is_some())
+		})
+		.collect()
+}
+
+/// Canon cuts the image into vertical slices and the lossless JPEG holds
+/// them one after the other, each top to bottom. The slice tag is how many
+/// slices there are, how wide they are, and how wide the last one is.
+/// Returns the width of the image and its samples in the usual order.
+fn unslice(jpeg: ljpeg::LjpegImage, slices: Option<&[u32]>) -> (usize, Vec<u16>) {
+	let jpeg_width = jpeg.width * jpeg.components;
+	let (count, slice_width, last_width) = match slices {
+		Some([count, slice_width, last_width, ..]) if *count > 0 => {
+			(*count as usize, *slice_width as usize, *last_width as usize)
+		}
+		_ => return (jpeg_width, jpeg.data),
+	};
+
+	let width = count * slice_width + last_width;
+	let height = jpeg.data.len() / width.max(1);
+
+	let mut out = vec![0; width * height];
+	let mut from = 0;
+	let mut left = 0;
+	for slice in 0..=count {
+		let slice_width = if slice == count {
+			last_width
+		} else {
+			slice_width
+		};
+
+		for row in 0..height {
+			let at = row * width + left;
+			out[at..at + slice_width].copy_from_slice(&jpeg.data[from..from + slice_width]);
+			from += slice_width;
+		}
+		left += slice_width;
+	}
+
+	(width, out)
+}
@@ -0,0 +1,88 @@
+//! Bits of DNG that rawloader doesn't tell us about, and the DNGs it can't
+//! read at all. Writing them is in [encode](crate::encode).
+
+mod reader;
+
+pub use reader::DngError;
+pub(crate) use reader::{
+	daylight_whitebalance, decode, decode_float, decode_into, decode_region, decode_sub_image,
+	is_dng, is_float, is_sixteen_bit_ljpeg, sub_images,
+};
+
+use crate::{
+	image::Crop,
+	tiff::{Ifd, Tiff},
+};
+
+const TAG_NEW_SUBFILE_TYPE: u16 = 0x00FE;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_DEFAULT_CROP_ORIGIN: u16 = 0xC61F;
+const TAG_DEFAULT_CROP_SIZE: u16 = 0xC620;
+
+/// Find the IFD that holds the raw data. It's the one with a NewSubFileType
+/// of 0, and it's either IFD0 or one of its SubIFDs.
+pub(crate) fn raw_ifd(tiff: &Tiff) -> Option<Ifd> {
+	let ifd0 = tiff.first_ifd()?;
+	let is_raw = |ifd: &Ifd| {
+		ifd.get(TAG_NEW_SUBFILE_TYPE)
+			.and_then(|e| tiff.u32s(e))
+			.and_then(|v| v.first().copied())
+			.unwrap_or(0)
+			== 0
+	};
+
+	if let Some(subs) = ifd0.get(TAG_SUB_IFDS).and_then(|e| tiff.u32s(e)) {
+		for offset in subs {
+			if let Some(ifd) = tiff.ifd(offset as usize) {
+				if is_raw(&ifd) {
+					return Some(ifd);
+				}
+			}
+		}
+	}
+
+	is_raw(&ifd0).then_some(ifd0)
+}
+
+/// Every IFD that could hold raw data, in the order we'd pick them: the
+/// SubIFDs of IFD0, IFD0 itself, and then the rest of the IFD chain.
+pub(crate) fn image_ifds(tiff: &Tiff) -> Vec<Ifd> {
+	let ifd0 = match tiff.first_ifd() {
+		Some(ifd) => ifd,
+		None => return vec![],
+	};
+
+	let mut ifds: Vec<Ifd> = ifd0
+		.get(TAG_SUB_IFDS)
+		.and_then(|e| tiff.u32s(e))
+		.unwrap_or_default()
+		.into_iter()
+		.filter_map(|offset| tiff.ifd(offset as usize))
+		.collect();
+	ifds.extend(tiff.chain());
+
+	ifds
+}
+
+/// The DefaultCrop of a DNG, relative to the active area, which is `width`
+/// by `height`. None if this isn't a DNG or there's no default crop.
+pub(crate) fn default_crop(data: &[u8], width: usize, height: usize) -> Option<Crop> {
+	let tiff = Tiff::new(data)?;
+	let ifd = raw_ifd(&tiff)?;
+
+	let origin = tiff.rationals(ifd.get(TAG_DEFAULT_CROP_ORIGIN)?)?;
+	let size = tiff.rationals(ifd.get(TAG_DEFAULT_CROP_SIZE)?)?;
+	if origin.len() < 2 || size.len() < 2 {
+		return None;
+	}
+
+	let (left, top) = (origin[0] as usize, origin[1] as usize);
+	let (crop_width, crop_height) = (size[0] as usize, size[1] as usize);
+
+	Some(Crop {
+		top,
+		right: width.checked_sub(left + crop_width)?,
+		bottom: height.checked_sub(top + crop_height)?,
+		left,
+	})
+}
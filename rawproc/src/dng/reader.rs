@@ -0,0 +1,921 @@
+//! Our own DNG decoder, for the DNGs rawloader won't open. That's mostly
+//! ones that were deflate compressed or split into tiles, which is what a lot
+//! of converters and phone apps write.
+
+use nalgebra::{Matrix3, Vector3};
+use rawloader::CFA;
+
+use crate::{
+	colormatrix,
+	colorspace::{BayerRgb, ColorspaceKind},
+	exif,
+	image::{
+		cfa, cfa_from_pattern, Crop, DynImage, Image, Orientation, RawMetadata, Region, SubImage,
+	},
+	lens, ljpeg,
+	tiff::{
+		self, Endian, Ifd, Tiff, COMPRESSION_DEFLATE, COMPRESSION_LJPEG, COMPRESSION_NONE,
+		TAG_BITS_PER_SAMPLE, TAG_COMPRESSION, TAG_IMAGE_LENGTH, TAG_IMAGE_WIDTH,
+	},
+	Error,
+};
+
+use super::{image_ifds, raw_ifd, TAG_NEW_SUBFILE_TYPE};
+
+const TAG_PHOTOMETRIC: u16 = 0x0106;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_SAMPLES_PER_PIXEL: u16 = 0x0115;
+const TAG_ROWS_PER_STRIP: u16 = 0x0116;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_PREDICTOR: u16 = 0x013D;
+const TAG_TILE_WIDTH: u16 = 0x0142;
+const TAG_TILE_LENGTH: u16 = 0x0143;
+const TAG_TILE_OFFSETS: u16 = 0x0144;
+const TAG_TILE_BYTE_COUNTS: u16 = 0x0145;
+const TAG_SAMPLE_FORMAT: u16 = 0x0153;
+const TAG_CFA_REPEAT_PATTERN_DIM: u16 = 0x828D;
+const TAG_CFA_PATTERN: u16 = 0x828E;
+const TAG_DNG_VERSION: u16 = 0xC612;
+const TAG_UNIQUE_CAMERA_MODEL: u16 = 0xC614;
+const TAG_BLACK_LEVEL_REPEAT_DIM: u16 = 0xC619;
+const TAG_BLACK_LEVEL: u16 = 0xC61A;
+const TAG_WHITE_LEVEL: u16 = 0xC61D;
+const TAG_COLOR_MATRIX1: u16 = 0xC621;
+const TAG_COLOR_MATRIX2: u16 = 0xC622;
+const TAG_AS_SHOT_NEUTRAL: u16 = 0xC628;
+const TAG_CALIBRATION_ILLUMINANT1: u16 = 0xC65A;
+const TAG_ACTIVE_AREA: u16 = 0xC68D;
+
+const PHOTOMETRIC_CFA: u16 = 32803;
+const PHOTOMETRIC_LINEAR_RAW: u16 = 34892;
+
+const PREDICTOR_NONE: u16 = 1;
+const PREDICTOR_HORIZONTAL: u16 = 2;
+// From DNG 1.4, horizontal differencing with samples two and four apart
+const PREDICTOR_HORIZONTAL_X2: u16 = 34892;
+const PREDICTOR_HORIZONTAL_X4: u16 = 34893;
+
+// And the floating point ones. 3 is from TIFF and the other two are DNG 1.4
+const PREDICTOR_FLOAT: u16 = 3;
+const PREDICTOR_FLOAT_X2: u16 = 34894;
+const PREDICTOR_FLOAT_X4: u16 = 34895;
+
+const ILLUMINANT_D65: u16 = 21;
+
+// The whitepoint of D65 in XYZ
+const D65: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+#[derive(Debug, thiserror::Error)]
+pub enum DngError {
+	#[error("Couldn't find the IFD with the raw data in it")]
+	NoRawIfd,
+	#[error("The raw IFD is missing the {0} tag")]
+	MissingTag(&'static str),
+	#[error("Compression {0} isn't something we can decode")]
+	UnsupportedCompression(u16),
+	#[error("PhotometricInterpretation {0} isn't something we can decode")]
+	UnsupportedPhotometric(u16),
+	#[error("We can't read {0} bit samples")]
+	UnsupportedBitDepth(u16),
+	#[error("Predictor {0} isn't one we know")]
+	UnsupportedPredictor(u16),
+	#[error("A {0}x{1} CFA pattern isn't supported. It has to fit into 12x12 evenly")]
+	UnsupportedCfa(usize, usize),
+	#[error("A strip or tile points outside of the file")]
+	Truncated,
+	#[error("A strip or tile failed to inflate")]
+	Deflate,
+	#[error("There's no sub-image {0}")]
+	NoSubImage(usize),
+}
+
+/// Is this a DNG at all? It is if IFD0 has a DNGVersion.
+pub(crate) fn is_dng(data: &[u8]) -> bool {
+	Tiff::new(data)
+		.and_then(|tiff| tiff.first_ifd())
+		.map(|ifd| ifd.get(TAG_DNG_VERSION).is_some())
+		.unwrap_or(false)
+}
+
+/// Decode the raw IFD. It comes out as BayerRgb if it's a mosaic, or LinRgb
+/// if it's a LinearRaw DNG that was already demosaiced.
+pub(crate) fn decode(data: &[u8]) -> Result<DynImage<u16>, Error> {
+	decode_into(data, vec![])
+}
+
+/// [decode], with the samples going in `samples` instead of a new buffer
+pub(crate) fn decode_into(data: &[u8], samples: Vec<u16>) -> Result<DynImage<u16>, Error> {
+	let tiff = Tiff::new(data).ok_or(DngError::NoRawIfd)?;
+	let ifd = raw_ifd(&tiff).ok_or(DngError::NoRawIfd)?;
+	decode_ifd(&tiff, &ifd, samples)
+}
+
+/// Decode just `region` of the raw IFD, only decoding the strips or tiles it
+/// touches. The CFA is shifted to match and the crops are dropped, they were
+/// for the whole sensor.
+pub(crate) fn decode_region(data: &[u8], region: Region) -> Result<DynImage<u16>, Error> {
+	let tiff = Tiff::new(data).ok_or(DngError::NoRawIfd)?;
+	let ifd0 = tiff.first_ifd().ok_or(DngError::NoRawIfd)?;
+	let ifd = raw_ifd(&tiff).ok_or(DngError::NoRawIfd)?;
+	let raw = RawIfd::new(&tiff, &ifd)?;
+
+	if raw.float {
+		return Err(Error::FloatImageData);
+	}
+	raw.check_photometric()?;
+	if !region.fits(raw.width, raw.height) {
+		return Err(Error::RegionOutOfBounds {
+			width: raw.width,
+			height: raw.height,
+		});
+	}
+
+	let data = raw.assemble(region, vec![], |bytes, width, height| {
+		raw.tile(bytes, width, height)
+	})?;
+	let mut metadata = raw.metadata(&ifd0)?;
+	metadata.cfa = metadata.cfa.shift(region.x, region.y);
+	metadata.active_area = None;
+	metadata.default_crop = None;
+
+	Ok(DynImage {
+		width: region.width,
+		height: region.height,
+		metadata,
+		colorspace: raw.colorspace(),
+
+		data,
+	})
+}
+
+/// Decode one of the images [sub_images] lists
+pub(crate) fn decode_sub_image(data: &[u8], index: usize) -> Result<DynImage<u16>, Error> {
+	let tiff = Tiff::new(data).ok_or(DngError::NoRawIfd)?;
+	let ifd = raw_image_ifds(&tiff)
+		.into_iter()
+		.nth(index)
+		.ok_or(DngError::NoSubImage(index))?;
+	decode_ifd(&tiff, &ifd, vec![])
+}
+
+/// Every raw image in the DNG. Empty if there's only the one, since then
+/// there's nothing to choose between.
+pub(crate) fn sub_images(data: &[u8]) -> Vec<SubImage> {
+	let tiff = match Tiff::new(data) {
+		Some(tiff) if is_dng(data) => tiff,
+		_ => return vec![],
+	};
+
+	let ifds = raw_image_ifds(&tiff);
+	if ifds.len() < 2 {
+		return vec![];
+	}
+
+	let mut found_primary = false;
+	ifds.iter()
+		.filter_map(|ifd| {
+			let raw = RawIfd::new(&tiff, ifd).ok()?;
+			let subfile_type = raw.long(TAG_NEW_SUBFILE_TYPE).unwrap_or(0);
+			// raw_ifd picks the first full resolution image, and so do we
+			let primary = subfile_type == 0 && !found_primary;
+			found_primary |= primary;
+
+			Some(SubImage {
+				width: raw.width,
+				height: raw.height,
+				subfile_type,
+				primary,
+				linear: raw.linear,
+				float: raw.float,
+			})
+		})
+		.collect()
+}
+
+/// The IFDs that are actually raw data and not a preview
+fn raw_image_ifds(tiff: &Tiff) -> Vec<Ifd> {
+	image_ifds(tiff)
+		.into_iter()
+		.filter(|ifd| {
+			ifd.get(TAG_PHOTOMETRIC).is_some()
+				&& RawIfd::new(tiff, ifd)
+					.map(|raw| raw.check_photometric().is_ok())
+					.unwrap_or(false)
+		})
+		.collect()
+}
+
+fn decode_ifd(tiff: &Tiff, ifd: &Ifd, samples: Vec<u16>) -> Result<DynImage<u16>, Error> {
+	let ifd0 = tiff.first_ifd().ok_or(DngError::NoRawIfd)?;
+	let raw = RawIfd::new(tiff, ifd)?;
+
+	if raw.float {
+		return Err(Error::FloatImageData);
+	}
+	raw.check_photometric()?;
+
+	let data = raw.samples(samples)?;
+	let metadata = raw.metadata(&ifd0)?;
+
+	Ok(DynImage {
+		width: raw.width,
+		height: raw.height,
+		metadata,
+		colorspace: raw.colorspace(),
+
+		data,
+	})
+}
+
+/// Does the DNG have floating point samples?
+pub(crate) fn is_float(data: &[u8]) -> bool {
+	let tiff = match Tiff::new(data) {
+		Some(tiff) => tiff,
+		None => return false,
+	};
+
+	raw_ifd(&tiff)
+		.and_then(|ifd| RawIfd::new(&tiff, &ifd).ok().map(|raw| raw.float))
+		.unwrap_or(false)
+}
+
+/// Is the raw IFD lossless JPEG at 16 bits? rawloader gets these wrong, it
+/// skips 16 bits after every difference of 32768 if the code was short
+/// enough to be in its cache. The DNG spec says there's nothing there.
+pub(crate) fn is_sixteen_bit_ljpeg(data: &[u8]) -> bool {
+	let tiff = match Tiff::new(data) {
+		Some(tiff) => tiff,
+		None => return false,
+	};
+
+	raw_ifd(&tiff)
+		.and_then(|ifd| {
+			RawIfd::new(&tiff, &ifd)
+				.ok()
+				.map(|raw| raw.compression == COMPRESSION_LJPEG && raw.bits == 16)
+		})
+		.unwrap_or(false)
+}
+
+/// Decode a DNG with floating point samples. Floats are already normalized,
+/// white is 1.0 unless the file says otherwise, so all we do is take off the
+/// black level.
+pub(crate) fn decode_float(data: &[u8]) -> Result<Image<f32, BayerRgb>, Error> {
+	let tiff = Tiff::new(data).ok_or(DngError::NoRawIfd)?;
+	let ifd0 = tiff.first_ifd().ok_or(DngError::NoRawIfd)?;
+	let ifd = raw_ifd(&tiff).ok_or(DngError::NoRawIfd)?;
+	let raw = RawIfd::new(&tiff, &ifd)?;
+	raw.check_photometric()?;
+	if raw.linear {
+		return Err(DngError::UnsupportedPhotometric(PHOTOMETRIC_LINEAR_RAW).into());
+	}
+
+	let mut data = raw.float_samples()?;
+	let mut metadata = raw.metadata(&ifd0)?;
+
+	let black = raw.float_blacklevels(&metadata.cfa);
+	let white = raw
+		.rationals(TAG_WHITE_LEVEL)
+		.and_then(|w| w.first().copied())
+		.unwrap_or(1.0);
+	let range = black.map(|b| (white - b).max(f32::EPSILON));
+	for (idx, float) in data.iter_mut().enumerate() {
+		let (x, y) = (idx % raw.width, idx / raw.width);
+		let c = cfa::channel(metadata.cfa.color_at(y, x));
+		*float = (*float - black[c]) / range[c];
+	}
+
+	metadata.blacklevels = [0; 3];
+	metadata.whitelevels = [1; 3];
+
+	Ok(Image {
+		width: raw.width,
+		height: raw.height,
+		metadata,
+		phantom: Default::default(),
+
+		data,
+	})
+}
+
+/// The raw IFD and the parts of it we need over and over
+struct RawIfd<'a, 'b> {
+	tiff: &'b Tiff<'a>,
+	ifd: &'b Ifd,
+	width: usize,
+	height: usize,
+	bits: u16,
+	samples_per_pixel: usize,
+	compression: u16,
+	predictor: u16,
+	float: bool,
+	linear: bool,
+}
+
+impl<'a, 'b> RawIfd<'a, 'b> {
+	fn new(tiff: &'b Tiff<'a>, ifd: &'b Ifd) -> Result<Self, DngError> {
+		let mut raw = Self {
+			tiff,
+			ifd,
+			width: 0,
+			height: 0,
+			bits: 0,
+			samples_per_pixel: 1,
+			compression: 0,
+			predictor: 0,
+			float: false,
+			linear: false,
+		};
+
+		raw.width = raw
+			.long(TAG_IMAGE_WIDTH)
+			.ok_or(DngError::MissingTag("ImageWidth"))? as usize;
+		raw.height = raw
+			.long(TAG_IMAGE_LENGTH)
+			.ok_or(DngError::MissingTag("ImageLength"))? as usize;
+		raw.bits = raw.short(TAG_BITS_PER_SAMPLE).unwrap_or(1);
+		raw.samples_per_pixel = raw.short(TAG_SAMPLES_PER_PIXEL).unwrap_or(1) as usize;
+		raw.compression = raw.short(TAG_COMPRESSION).unwrap_or(COMPRESSION_NONE);
+		raw.predictor = raw.short(TAG_PREDICTOR).unwrap_or(PREDICTOR_NONE);
+		// SampleFormat 3 is IEEE floating point
+		raw.float = raw.short(TAG_SAMPLE_FORMAT) == Some(3);
+		raw.linear = raw.short(TAG_PHOTOMETRIC) == Some(PHOTOMETRIC_LINEAR_RAW);
+
+		Ok(raw)
+	}
+
+	fn short(&self, tag: u16) -> Option<u16> {
+		self.long(tag).map(|v| v as u16)
+	}
+
+	fn long(&self, tag: u16) -> Option<u32> {
+		self.tiff
+			.u32s(self.ifd.get(tag)?)
+			.and_then(|v| v.first().copied())
+	}
+
+	fn longs(&self, tag: u16) -> Option<Vec<u32>> {
+		self.tiff.u32s(self.ifd.get(tag)?)
+	}
+
+	fn rationals(&self, tag: u16) -> Option<Vec<f32>> {
+		self.tiff.rationals(self.ifd.get(tag)?)
+	}
+
+	/// We know what to do with a single channel CFA image, or a LinearRaw one
+	/// with all three colours at every pixel or just the one, which is what
+	/// monochrome cameras write
+	fn check_photometric(&self) -> Result<(), DngError> {
+		let photometric = self.short(TAG_PHOTOMETRIC).unwrap_or(PHOTOMETRIC_CFA);
+		match (photometric, self.samples_per_pixel) {
+			(PHOTOMETRIC_CFA, 1) | (PHOTOMETRIC_LINEAR_RAW, 1 | 3) => Ok(()),
+			_ => Err(DngError::UnsupportedPhotometric(photometric)),
+		}
+	}
+
+	fn colorspace(&self) -> ColorspaceKind {
+		match (self.linear, self.samples_per_pixel) {
+			(true, 1) => ColorspaceKind::Monochrome,
+			(true, _) => ColorspaceKind::LinRgb,
+			(false, _) => ColorspaceKind::BayerRgb,
+		}
+	}
+
+	fn samples(&self, out: Vec<u16>) -> Result<Vec<u16>, Error> {
+		self.assemble(self.full(), out, |bytes, width, height| {
+			self.tile(bytes, width, height)
+		})
+	}
+
+	fn float_samples(&self) -> Result<Vec<f32>, Error> {
+		self.assemble(self.full(), vec![], |bytes, width, height| {
+			self.float_tile(bytes, width, height)
+		})
+	}
+
+	fn full(&self) -> Region {
+		Region::new(0, 0, self.width, self.height)
+	}
+
+	/// Decode the strips or tiles that overlap `region` and put them
+	/// together. A strip is just a tile as wide as the image, so we treat
+	/// them the same. The samples go in `out`, whatever was in it is gone.
+	fn assemble<T, F>(
+		&self,
+		region: Region,
+		mut out: Vec<T>,
+		decode_tile: F,
+	) -> Result<Vec<T>, Error>
+	where
+		T: Copy + Default,
+		F: Fn(&[u8], usize, usize) -> Result<Vec<T>, Error>,
+	{
+		let tiled = self.ifd.get(TAG_TILE_OFFSETS).is_some();
+		let (tile_width, tile_height, offsets, counts) = match self.longs(TAG_TILE_OFFSETS) {
+			Some(offsets) => (
+				self.long(TAG_TILE_WIDTH)
+					.ok_or(DngError::MissingTag("TileWidth"))? as usize,
+				self.long(TAG_TILE_LENGTH)
+					.ok_or(DngError::MissingTag("TileLength"))? as usize,
+				offsets,
+				self.longs(TAG_TILE_BYTE_COUNTS)
+					.ok_or(DngError::MissingTag("TileByteCounts"))?,
+			),
+			None => (
+				self.width,
+				self.long(TAG_ROWS_PER_STRIP)
+					.map(|rows| rows as usize)
+					.unwrap_or(self.height)
+					.min(self.height),
+				self.longs(TAG_STRIP_OFFSETS)
+					.ok_or(DngError::MissingTag("StripOffsets"))?,
+				self.longs(TAG_STRIP_BYTE_COUNTS)
+					.ok_or(DngError::MissingTag("StripByteCounts"))?,
+			),
+		};
+
+		if tile_width == 0 || tile_height == 0 {
+			return Err(DngError::Truncated.into());
+		}
+
+		let spp = self.samples_per_pixel;
+		let tiles_across = self.width.div_ceil(tile_width);
+		let tiles_down = self.height.div_ceil(tile_height);
+		let tiles = tiles_across
+			.checked_mul(tiles_down)
+			.ok_or(DngError::Truncated)?;
+		if offsets.len() < tiles || counts.len() < offsets.len() {
+			return Err(DngError::Truncated.into());
+		}
+
+		// The sizes are only what the tags say, so before we make room for
+		// them we make sure the file has enough data to fill it
+		let data = self.tiff.data();
+		let present = offsets
+			.iter()
+			.zip(counts.iter())
+			.take(tiles)
+			.map(|(offset, count)| {
+				(*count as usize).min(data.len().saturating_sub(*offset as usize))
+			})
+			.fold(0usize, usize::saturating_add);
+		let len = region
+			.width
+			.checked_mul(region.height)
+			.and_then(|len| len.checked_mul(spp))
+			.filter(|len| *len <= self.most_samples(present))
+			.ok_or(DngError::Truncated)?;
+
+		out.clear();
+		out.resize(len, T::default());
+		for (idx, (offset, count)) in offsets.iter().zip(counts.iter()).enumerate() {
+			let (tx, ty) = (idx % tiles_across, idx / tiles_across);
+			if ty >= tiles_down {
+				break;
+			}
+
+			// Tiles on the right and bottom edge hang off the image, but the
+			// last strip is only as tall as it needs to be
+			let (left, top) = (tx * tile_width, ty * tile_height);
+			let right = (left + tile_width).min(self.width);
+			let bottom = (top + tile_height).min(self.height);
+
+			// The part of this tile that's in the region
+			let (x0, x1) = (left.max(region.x), right.min(region.x + region.width));
+			let (y0, y1) = (top.max(region.y), bottom.min(region.y + region.height));
+			if x0 >= x1 || y0 >= y1 {
+				continue;
+			}
+
+			let start = *offset as usize;
+			let bytes = self
+				.tiff
+				.data()
+				.get(start..start + *count as usize)
+				.ok_or(DngError::Truncated)?;
+
+			let rows = if tiled { tile_height } else { bottom - top };
+			let tile = decode_tile(bytes, tile_width, rows)?;
+			let copy_width = (x1 - x0) * spp;
+			for y in y0..y1 {
+				let from = &tile[((y - top) * tile_width + x0 - left) * spp..][..copy_width];
+				let at = ((y - region.y) * region.width + x0 - region.x) * spp;
+				out[at..at + copy_width].copy_from_slice(from);
+			}
+		}
+
+		Ok(out)
+	}
+
+	/// The most samples `bytes` of strips or tiles could have in them. Nothing
+	/// packs a sample into less than a bit, and deflate can't squeeze things
+	/// down any more than about 1032 to 1.
+	fn most_samples(&self, bytes: usize) -> usize {
+		match self.compression {
+			COMPRESSION_DEFLATE => bytes.saturating_mul(8 * 1032),
+			_ => bytes.saturating_mul(8),
+		}
+	}
+
+	/// Decode one tile into `width * height * samples_per_pixel` samples
+	fn tile(&self, bytes: &[u8], width: usize, height: usize) -> Result<Vec<u16>, Error> {
+		let len = width * height * self.samples_per_pixel;
+
+		let mut samples = match self.compression {
+			COMPRESSION_NONE => self.unpack(bytes, width, height)?,
+			COMPRESSION_DEFLATE => {
+				let inflated = miniz_oxide::inflate::decompress_to_vec_zlib(bytes)
+					.map_err(|_| DngError::Deflate)?;
+				self.unpack(&inflated, width, height)?
+			}
+			// The lossless JPEG might be laid out differently than the tile,
+			// two components at half width and such, but the samples come out
+			// in the same order.
+			COMPRESSION_LJPEG => ljpeg::decode(bytes)?.data,
+			other => return Err(DngError::UnsupportedCompression(other).into()),
+		};
+
+		if samples.len() < len {
+			return Err(DngError::Truncated.into());
+		}
+		samples.truncate(len);
+
+		if self.compression != COMPRESSION_LJPEG {
+			self.undo_predictor(&mut samples, width)?;
+		}
+
+		Ok(samples)
+	}
+
+	/// Decode one tile of floating point samples. Only deflate and
+	/// uncompressed make sense for these.
+	fn float_tile(&self, bytes: &[u8], width: usize, height: usize) -> Result<Vec<f32>, Error> {
+		let mut bytes = match self.compression {
+			COMPRESSION_NONE => bytes.to_vec(),
+			COMPRESSION_DEFLATE => miniz_oxide::inflate::decompress_to_vec_zlib(bytes)
+				.map_err(|_| DngError::Deflate)?,
+			other => return Err(DngError::UnsupportedCompression(other).into()),
+		};
+
+		let size = match self.bits {
+			16 | 24 | 32 => self.bits as usize / 8,
+			other => return Err(DngError::UnsupportedBitDepth(other).into()),
+		};
+		let row_len = width * self.samples_per_pixel * size;
+		if bytes.len() < row_len * height {
+			return Err(DngError::Truncated.into());
+		}
+
+		// The floating point predictors difference the bytes and then shuffle
+		// them so every sample's most significant byte comes first in the row,
+		// then every sample's next byte, and so on. It's always big endian.
+		let factor = match self.predictor {
+			PREDICTOR_NONE => None,
+			PREDICTOR_FLOAT => Some(1),
+			PREDICTOR_FLOAT_X2 => Some(2),
+			PREDICTOR_FLOAT_X4 => Some(4),
+			other => return Err(DngError::UnsupportedPredictor(other).into()),
+		};
+
+		let mut out = Vec::with_capacity(width * height * self.samples_per_pixel);
+		let mut sample = [0u8; 4];
+		let row_samples = row_len / size;
+		for row in bytes.chunks_exact_mut(row_len).take(height) {
+			match factor {
+				Some(factor) => {
+					let stride = self.samples_per_pixel * factor;
+					for idx in stride..row.len() {
+						row[idx] = row[idx].wrapping_add(row[idx - stride]);
+					}
+
+					for idx in 0..row_samples {
+						for byte in 0..size {
+							sample[byte] = row[byte * row_samples + idx];
+						}
+						out.push(float_from_be(&sample[..size]));
+					}
+				}
+				None => {
+					for chunk in row.chunks_exact(size) {
+						sample[..size].copy_from_slice(chunk);
+						if self.tiff.endian() == Endian::Little {
+							sample[..size].reverse();
+						}
+						out.push(float_from_be(&sample[..size]));
+					}
+				}
+			}
+		}
+
+		Ok(out)
+	}
+
+	/// Unpack samples of any bit depth up to 16. TIFF packs them most
+	/// significant bit first and starts every row on a byte boundary.
+	fn unpack(&self, bytes: &[u8], width: usize, height: usize) -> Result<Vec<u16>, DngError> {
+		let row_samples = width * self.samples_per_pixel;
+
+		match self.bits {
+			8 => Ok(bytes.iter().map(|b| *b as u16).collect()),
+			16 => Ok(bytes
+				.chunks_exact(2)
+				.map(|c| self.tiff.endian().u16([c[0], c[1]]))
+				.collect()),
+			bits @ 1..=15 => {
+				let bits = bits as usize;
+				let row_bytes = (row_samples * bits).div_ceil(8);
+				// A tile that's cut short only has so many rows to unpack
+				let rows = height.min(bytes.len().div_ceil(row_bytes.max(1)));
+				let mut out = Vec::with_capacity(row_samples * rows);
+
+				for row in bytes.chunks(row_bytes).take(height) {
+					let mut acc = 0u32;
+					let mut have = 0;
+					let mut bytes = row.iter();
+
+					for _ in 0..row_samples {
+						while have < bits {
+							acc = (acc << 8) | *bytes.next().unwrap_or(&0) as u32;
+							have += 8;
+						}
+						have -= bits;
+						out.push(((acc >> have) & ((1 << bits) - 1)) as u16);
+					}
+				}
+
+				Ok(out)
+			}
+			other => Err(DngError::UnsupportedBitDepth(other)),
+		}
+	}
+
+	fn undo_predictor(&self, samples: &mut [u16], width: usize) -> Result<(), DngError> {
+		let spp = self.samples_per_pixel;
+		let stride = match self.predictor {
+			PREDICTOR_NONE => return Ok(()),
+			PREDICTOR_HORIZONTAL => spp,
+			PREDICTOR_HORIZONTAL_X2 => spp * 2,
+			PREDICTOR_HORIZONTAL_X4 => spp * 4,
+			other => return Err(DngError::UnsupportedPredictor(other)),
+		};
+
+		for row in samples.chunks_mut(width * spp) {
+			for idx in stride..row.len() {
+				row[idx] = row[idx].wrapping_add(row[idx - stride]);
+			}
+		}
+
+		Ok(())
+	}
+
+	fn metadata(&self, ifd0: &Ifd) -> Result<RawMetadata, Error> {
+		// LinearRaw has no pattern, so it gets the same empty one rawloader
+		// gives these
+		let cfa = if self.linear {
+			CFA::new("")
+		} else {
+			self.cfa()?
+		};
+
+		let white = self
+			.long(TAG_WHITE_LEVEL)
+			.unwrap_or((1 << self.bits.min(16)) - 1);
+		let white = white.min(u16::MAX as u32) as u16;
+		let blacklevels = self.blacklevels(&cfa);
+
+		let string = |tag| ifd0.get(tag).and_then(|e| self.tiff.string(e));
+		let make = string(tiff::TAG_MAKE).unwrap_or_default();
+		let model = string(tiff::TAG_MODEL)
+			.or_else(|| string(TAG_UNIQUE_CAMERA_MODEL))
+			.unwrap_or_default();
+
+		// Not every DNG has a colour matrix, so we might know the camera
+		let xyz_to_cam = self
+			.color_matrix()
+			.or_else(|| colormatrix::lookup(&make, &model))
+			.unwrap_or_else(Matrix3::identity);
+		let cam_to_xyz = xyz_to_cam
+			.try_inverse()
+			.unwrap_or_else(Matrix3::identity)
+			.normalize();
+
+		let daylight_whitebalance = daylight_whitebalance(&xyz_to_cam);
+
+		// AsShotNeutral is the camera's idea of white, so the multipliers are
+		// the inverse of it
+		let whitebalance = self
+			.rationals(TAG_AS_SHOT_NEUTRAL)
+			.filter(|n| n.len() >= 3 && n.iter().all(|c| *c > 0.0))
+			.map(|n| [n[1] / n[0], 1.0, n[1] / n[2]])
+			.unwrap_or(daylight_whitebalance);
+
+		// ActiveArea is top, left, bottom, right with the last two as
+		// coordinates rather than insets
+		let active_area = self
+			.longs(TAG_ACTIVE_AREA)
+			.filter(|a| a.len() >= 4)
+			.and_then(|a| {
+				Crop::from_css_quad([
+					a[0] as usize,
+					self.width.checked_sub(a[3] as usize)?,
+					self.height.checked_sub(a[2] as usize)?,
+					a[1] as usize,
+				])
+			});
+		let default_crop = {
+			let (width, height) = match active_area {
+				Some(area) => (
+					self.width.saturating_sub(area.left + area.right),
+					self.height.saturating_sub(area.top + area.bottom),
+				),
+				None => (self.width, self.height),
+			};
+
+			super::default_crop(self.tiff.data(), width, height)
+		};
+
+		Ok(RawMetadata {
+			whitebalance,
+			as_shot_whitebalance: whitebalance,
+			daylight_whitebalance,
+			whitebalance_presets: vec![],
+			whitebalance_selected: None,
+			whitebalance_fine_tune: None,
+			makernote: None,
+			sub_images: sub_images(self.tiff.data()),
+			lens_correction: lens::embedded(self.tiff.data()),
+			orientation: ifd0
+				.get(TAG_ORIENTATION)
+				.and_then(|e| self.tiff.u16s(e))
+				.and_then(|v| v.first().copied())
+				.map(Orientation::from_exif)
+				.unwrap_or_default(),
+			exif: exif::read(self.tiff.data()),
+			active_area,
+			default_crop,
+			whitelevels: [white; 3],
+			blacklevels,
+			cfa,
+			xyz_to_cam,
+			cam_to_xyz,
+			make,
+			model,
+			serial: tiff::serial_number(self.tiff.data()),
+		})
+	}
+
+	fn cfa(&self) -> Result<CFA, DngError> {
+		let dim = self
+			.longs(TAG_CFA_REPEAT_PATTERN_DIM)
+			.filter(|d| d.len() >= 2)
+			.map(|d| (d[0] as usize, d[1] as usize))
+			.unwrap_or((2, 2));
+		let len = dim.0 * dim.1;
+
+		let pattern = self
+			.ifd
+			.get(TAG_CFA_PATTERN)
+			.and_then(|e| self.tiff.bytes(e))
+			.filter(|p| p.len() >= len)
+			.ok_or(DngError::MissingTag("CFAPattern"))?;
+
+		// rawloader wants the pattern spelled out, row by row. It doesn't
+		// have cyan, so that's its fourth colour like emerald.
+		let name: String = pattern[..len]
+			.iter()
+			.map(|c| match c {
+				0 => 'R',
+				1 => 'G',
+				2 => 'B',
+				3 => 'E',
+				4 => 'M',
+				5 => 'Y',
+				_ => 'E',
+			})
+			.collect();
+
+		cfa_from_pattern(&name, dim.1, dim.0).ok_or(DngError::UnsupportedCfa(dim.0, dim.1))
+	}
+
+	fn blacklevels(&self, cfa: &CFA) -> [u16; 3] {
+		self.float_blacklevels(cfa)
+			.map(|level| level.round() as u16)
+	}
+
+	// BlackLevel repeats over a small pattern, usually the same size as the
+	// CFA. We want one per colour, so we average the ones that land on each.
+	fn float_blacklevels(&self, cfa: &CFA) -> [f32; 3] {
+		let levels = match self.rationals(TAG_BLACK_LEVEL) {
+			Some(levels) if !levels.is_empty() => levels,
+			_ => return [0.0; 3],
+		};
+		let dim = self
+			.longs(TAG_BLACK_LEVEL_REPEAT_DIM)
+			.filter(|d| d.len() >= 2)
+			.map(|d| (d[0] as usize, d[1] as usize))
+			.unwrap_or((1, 1));
+
+		// LinearRaw has a level per sample instead
+		if self.linear {
+			return match levels.len() {
+				3 => [levels[0], levels[1], levels[2]],
+				_ => [levels[0]; 3],
+			};
+		}
+
+		if dim.0 * dim.1 != levels.len() || levels.len() == 1 {
+			return [levels[0]; 3];
+		}
+
+		let mut sums = [0.0f32; 3];
+		let mut counts = [0usize; 3];
+		for row in 0..dim.0 {
+			for col in 0..dim.1 {
+				let color = cfa::channel(cfa.color_at(row, col));
+				sums[color] += levels[row * dim.1 + col];
+				counts[color] += 1;
+			}
+		}
+
+		let mut blacklevels = [levels[0]; 3];
+		for color in 0..3 {
+			if counts[color] > 0 {
+				blacklevels[color] = sums[color] / counts[color] as f32;
+			}
+		}
+		blacklevels
+	}
+
+	// There are usually two matricies, one for each calibration illuminant.
+	// We'd like the D65 one, which is normally the second.
+	fn color_matrix(&self) -> Option<Matrix3<f32>> {
+		let first_is_d65 = self.short(TAG_CALIBRATION_ILLUMINANT1) == Some(ILLUMINANT_D65);
+		let matrix = |tag| self.rationals(tag).filter(|m| m.len() >= 9);
+
+		let m = if first_is_d65 {
+			matrix(TAG_COLOR_MATRIX1)
+		} else {
+			matrix(TAG_COLOR_MATRIX2).or_else(|| matrix(TAG_COLOR_MATRIX1))
+		};
+
+		#[rustfmt::skip]
+		let matrix = m.map(|m| Matrix3::new(
+			m[0], m[1], m[2],
+			m[3], m[4], m[5],
+			m[6], m[7], m[8],
+		));
+		matrix.filter(colormatrix::is_usable)
+	}
+}
+
+/// The whitebalance that neutralizes the colour matrix, which is the white
+/// the camera would see in daylight. Same thing rawloader gives us for files
+/// it reads.
+pub(crate) fn daylight_whitebalance(xyz_to_cam: &Matrix3<f32>) -> [f32; 3] {
+	let cam_white = xyz_to_cam * Vector3::from(D65);
+	[
+		cam_white[1] / cam_white[0],
+		1.0,
+		cam_white[1] / cam_white[2],
+	]
+}
+
+/// Half, 24-bit, or single precision floats, given as big endian bytes.
+fn float_from_be(bytes: &[u8]) -> f32 {
+	match *bytes {
+		[a, b] => float_from_parts(u16::from_be_bytes([a, b]) as u32, 5, 10),
+		// DNG's own 24-bit float has a 7 bit exponent and a 16 bit mantissa
+		[a, b, c] => float_from_parts(u32::from_be_bytes([0, a, b, c]), 7, 16),
+		[a, b, c, d] => f32::from_be_bytes([a, b, c, d]),
+		_ => 0.0,
+	}
+}
+
+/// Widen a smaller IEEE-style float, sign in the top bit, to an f32.
+fn float_from_parts(bits: u32, exponent_bits: u32, mantissa_bits: u32) -> f32 {
+	let sign = if (bits >> (exponent_bits + mantissa_bits)) & 1 == 1 {
+		-1.0
+	} else {
+		1.0
+	};
+	let exponent_max = (1 << exponent_bits) - 1;
+	let exponent = (bits >> mantissa_bits) & exponent_max;
+	let mantissa = (bits & ((1 << mantissa_bits) - 1)) as f32 / (1 << mantissa_bits) as f32;
+	let bias = (exponent_max >> 1) as i32;
+
+	let value = if exponent == 0 {
+		// Subnormal
+		mantissa * 2f32.powi(1 - bias)
+	} else if exponent == exponent_max {
+		if mantissa == 0.0 {
+			f32::INFINITY
+		} else {
+			f32::NAN
+		}
+	} else {
+		(1.0 + mantissa) * 2f32.powi(exponent as i32 - bias)
+	};
+
+	sign * value
+}
@@ -0,0 +1,407 @@
+//! Writing raw images back out, as DNGs, so other tools can pick up where
+//! we left off.
+
+use std::io::Write;
+
+use crate::{
+	colorspace::{BayerRgb, LinRgb},
+	image::{cfa, CfaColors, Image, MetadataPolicy, RawMetadata},
+	ljpeg,
+	tiff::IfdWriter,
+	Error,
+};
+
+const DNG_VERSION: [u8; 4] = [1, 4, 0, 0];
+const DNG_BACKWARD_VERSION: [u8; 4] = [1, 1, 0, 0];
+
+// Illuminant 21 is D65
+const ILLUMINANT_D65: u16 = 21;
+
+/// Writes a bayer image out as a DNG, or a linear RGB one as a LinearRaw
+/// DNG. Uncompressed unless you ask.
+///
+/// ```no_run
+/// # use rawproc::encode::DngWriter;
+/// # let mut file = std::fs::File::open("goose.nef").unwrap();
+/// # let raw = rawproc::decode(&mut file).unwrap();
+/// let original = std::fs::read("goose.nef").unwrap();
+/// let mut out = std::fs::File::create("goose.dng").unwrap();
+///
+/// DngWriter::new()
+///     .embed_original("goose.nef", original)
+///     .write(&raw, &mut out)
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DngWriter {
+	original: Option<(String, Vec<u8>)>,
+	compress: bool,
+	policy: MetadataPolicy,
+	balanced: bool,
+}
+
+/// How the samples are laid out, which decides the tags that describe them
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Layout {
+	/// One sample per pixel, the colour of the CFA there
+	Cfa,
+	/// Red, green, and blue at every pixel
+	Linear,
+}
+
+/// The image data ready for the file
+struct Strip {
+	data: Vec<u8>,
+	bits: u16,
+	compression: u16,
+	float: bool,
+}
+
+impl DngWriter {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Compress the raw data with lossless JPEG. It's usually about half the
+	/// size and takes a little longer to write. Float data is always written
+	/// uncompressed.
+	pub fn compress(mut self, compress: bool) -> Self {
+		self.compress = compress;
+		self
+	}
+
+	/// Embed the original raw file in the DNG so it can be extracted later.
+	/// The name is stored too, so you get back exactly what you put in.
+	pub fn embed_original<S: Into<String>>(mut self, name: S, data: Vec<u8>) -> Self {
+		self.original = Some((name.into(), data));
+		self
+	}
+
+	/// What metadata goes in the file. The original raw has every bit of the
+	/// camera's metadata in it, GPS and serial numbers too, so it's only
+	/// embedded if the policy keeps everything.
+	pub fn metadata_policy(mut self, policy: MetadataPolicy) -> Self {
+		self.policy = policy;
+		self
+	}
+
+	/// Say the image has already been whitebalanced, like anything that was
+	/// balanced and then debayered. The file then says the camera saw white
+	/// as white so whatever opens it doesn't balance it a second time.
+	pub fn balanced(mut self, balanced: bool) -> Self {
+		self.balanced = balanced;
+		self
+	}
+
+	pub fn write<W: Write>(
+		&self,
+		image: &Image<u16, BayerRgb>,
+		writer: &mut W,
+	) -> Result<(), Error> {
+		let bytes = self.encode(image);
+		writer.write_all(&bytes)?;
+		Ok(())
+	}
+
+	/// Build the whole DNG in memory
+	pub fn encode(&self, image: &Image<u16, BayerRgb>) -> Vec<u8> {
+		let strip = self.strip(image.width, image.height, 1, &image.data, &image.metadata);
+		self.encode_strip(
+			image.width,
+			image.height,
+			&image.metadata,
+			Layout::Cfa,
+			strip,
+		)
+	}
+
+	/// Write a debayered image as a LinearRaw DNG, so other tools can pick
+	/// up where we left off. It's still camera RGB, the colour matrix goes
+	/// in with it.
+	pub fn write_linear<W: Write>(
+		&self,
+		image: &Image<u16, LinRgb>,
+		writer: &mut W,
+	) -> Result<(), Error> {
+		let bytes = self.encode_linear(image);
+		writer.write_all(&bytes)?;
+		Ok(())
+	}
+
+	/// [write_linear](Self::write_linear), but in memory
+	pub fn encode_linear(&self, image: &Image<u16, LinRgb>) -> Vec<u8> {
+		let strip = self.strip(image.width, image.height, 3, &image.data, &image.metadata);
+		self.encode_strip(
+			image.width,
+			image.height,
+			&image.metadata,
+			Layout::Linear,
+			strip,
+		)
+	}
+
+	/// Write a debayered float image as a LinearRaw DNG with 32-bit float
+	/// samples. The values go in as they are, so it should be
+	/// [normalized](Image::normalize) with black at 0.0 and white at 1.0,
+	/// but anything brighter than white is kept.
+	pub fn write_linear_float<W: Write>(
+		&self,
+		image: &Image<f32, LinRgb>,
+		writer: &mut W,
+	) -> Result<(), Error> {
+		let bytes = self.encode_linear_float(image);
+		writer.write_all(&bytes)?;
+		Ok(())
+	}
+
+	/// [write_linear_float](Self::write_linear_float), but in memory
+	pub fn encode_linear_float(&self, image: &Image<f32, LinRgb>) -> Vec<u8> {
+		let strip = Strip {
+			data: image.data.iter().flat_map(|f| f.to_le_bytes()).collect(),
+			bits: 32,
+			compression: 1,
+			float: true,
+		};
+		self.encode_strip(
+			image.width,
+			image.height,
+			&image.metadata,
+			Layout::Linear,
+			strip,
+		)
+	}
+
+	fn encode_strip(
+		&self,
+		width: usize,
+		height: usize,
+		metadata: &RawMetadata,
+		layout: Layout,
+		strip: Strip,
+	) -> Vec<u8> {
+		let mut meta = metadata.clone();
+		self.policy.apply(&mut meta);
+		let mut ifd = IfdWriter::new();
+
+		let samples: u16 = match layout {
+			Layout::Cfa => 1,
+			Layout::Linear => 3,
+		};
+
+		ifd.long(0x00FE, &[0]); // NewSubFileType, main image
+		ifd.long(0x0100, &[width as u32]);
+		ifd.long(0x0101, &[height as u32]);
+		ifd.short(0x0102, &vec![strip.bits; samples as usize]); // BitsPerSample
+		ifd.short(0x0103, &[strip.compression]);
+		match layout {
+			Layout::Cfa => ifd.short(0x0106, &[32803]), // PhotometricInterpretation, CFA
+			Layout::Linear => ifd.short(0x0106, &[34892]), // LinearRaw
+		}
+		if self.policy.keep_camera {
+			ifd.ascii(0x010F, &meta.make);
+			ifd.ascii(0x0110, &meta.model);
+		}
+		ifd.short(0x0115, &[samples]); // SamplesPerPixel
+		ifd.long(0x0116, &[height as u32]); // RowsPerStrip
+		ifd.short(0x011C, &[1]); // PlanarConfiguration
+		if strip.float {
+			ifd.short(0x0153, &vec![3; samples as usize]); // SampleFormat, IEEE float
+		}
+
+		// The pattern, row by row, and the black level the same way so it
+		// lines up with the colours
+		let dim = [meta.cfa.height as u16, meta.cfa.width as u16];
+		let positions: Vec<(usize, usize)> = (0..meta.cfa.height)
+			.flat_map(|row| (0..meta.cfa.width).map(move |col| (row, col)))
+			.collect();
+
+		if layout == Layout::Cfa {
+			ifd.short(0x828D, &dim); // CFARepeatPatternDim
+
+			// DNG's fourth colour is cyan and yellow is its own. There isn't
+			// an emerald, so that's cyan, which we read back as emerald.
+			let fourth = match CfaColors::of(&meta.cfa) {
+				CfaColors::Ryb => 5,
+				_ => 3,
+			};
+			let pattern: Vec<u8> = positions
+				.iter()
+				.map(|(row, col)| match meta.cfa.color_at(*row, *col) {
+					3 => fourth,
+					color => color as u8,
+				})
+				.collect();
+			ifd.byte(0x828E, &pattern);
+		}
+
+		ifd.byte(0xC612, &DNG_VERSION);
+		ifd.byte(0xC613, &DNG_BACKWARD_VERSION);
+		// UniqueCameraModel is required, so it's there even when the camera
+		// isn't supposed to be
+		let unique_model = if self.policy.keep_camera {
+			format!("{} {}", meta.make, meta.model)
+		} else {
+			String::from("Camera")
+		};
+		ifd.ascii(0xC614, &unique_model);
+		if let Some(serial) = &meta.serial {
+			ifd.ascii(0xC62F, serial); // CameraSerialNumber
+		}
+
+		// Floats are black at 0.0 and white at 1.0, which is what DNG assumes
+		// if the levels aren't there
+		match (layout, strip.float) {
+			(_, true) => (),
+			(Layout::Cfa, false) => {
+				ifd.short(0xC619, &dim); // BlackLevelRepeatDim
+				let black: Vec<u32> = positions
+					.iter()
+					.map(|(row, col)| {
+						let color = cfa::channel(meta.cfa.color_at(*row, *col));
+						meta.blacklevels[color] as u32
+					})
+					.collect();
+				ifd.long(0xC61A, &black);
+				let white = meta.whitelevels.iter().max().copied().unwrap_or(u16::MAX);
+				ifd.long(0xC61D, &[white as u32]);
+			}
+			(Layout::Linear, false) => {
+				// One level for each sample
+				ifd.short(0xC619, &[1, 1]);
+				ifd.long(0xC61A, &meta.blacklevels.map(|b| b as u32));
+				ifd.long(0xC61D, &meta.whitelevels.map(|w| w as u32));
+			}
+		}
+
+		if let Some(crop) = meta.default_crop {
+			let width = width - meta.active_area.map(|a| a.left + a.right).unwrap_or(0);
+			let height = height - meta.active_area.map(|a| a.top + a.bottom).unwrap_or(0);
+
+			ifd.long(0xC61F, &[crop.left as u32, crop.top as u32]);
+			ifd.long(
+				0xC620,
+				&[
+					(width - crop.left - crop.right) as u32,
+					(height - crop.top - crop.bottom) as u32,
+				],
+			);
+		}
+
+		let m = meta.xyz_to_cam;
+		#[rustfmt::skip]
+		let matrix = [
+			m[(0, 0)], m[(0, 1)], m[(0, 2)],
+			m[(1, 0)], m[(1, 1)], m[(1, 2)],
+			m[(2, 0)], m[(2, 1)], m[(2, 2)],
+		];
+		ifd.srational(0xC621, &matrix);
+
+		// AsShotNeutral is the colour of white as the camera sees it, which
+		// is the inverse of the whitebalance multipliers
+		let neutral = if self.balanced {
+			[1.0; 3]
+		} else {
+			let wb = meta.as_shot_whitebalance;
+			[wb[1] / wb[0], 1.0, wb[1] / wb[2]]
+		};
+		ifd.rational(0xC628, &neutral);
+		ifd.short(0xC65A, &[ILLUMINANT_D65]);
+
+		if let Some(area) = meta.active_area {
+			ifd.long(
+				0xC68D,
+				&[
+					area.top as u32,
+					area.left as u32,
+					(height - area.bottom) as u32,
+					(width - area.right) as u32,
+				],
+			);
+		}
+
+		if let Some((name, data)) = self.original.as_ref().filter(|_| self.policy.keeps_all()) {
+			ifd.ascii(0xC68B, name);
+			ifd.undefined(0xC68C, &original_raw_file_data(data));
+		}
+
+		ifd.strip(strip.data);
+		ifd.finish()
+	}
+
+	/// The image data, how many bits per sample it is, and the compression tag
+	fn strip(
+		&self,
+		width: usize,
+		height: usize,
+		samples: usize,
+		data: &[u16],
+		metadata: &RawMetadata,
+	) -> Strip {
+		if !self.compress {
+			return Strip {
+				data: data.iter().flat_map(|px| px.to_le_bytes()).collect(),
+				bits: 16,
+				compression: 1,
+				float: false,
+			};
+		}
+
+		// The fewest bits that'll hold every value. We look at the data, too,
+		// because nothing stops a camera from going over its whitelevel.
+		let max = data.iter().max().copied().unwrap_or(0);
+		let white = metadata.whitelevels.iter().max().copied().unwrap_or(0);
+		let precision = (16 - max.max(white).leading_zeros()).max(2) as u8;
+
+		// Encoding a bayer row as two components, one for each colour in the
+		// row, means each sample is predicted from the last one of the same
+		// colour. The DNG spec is fine with this as long as it adds up.
+		// Linear data already has a component for each colour.
+		let (width, components) = if samples == 1 && width.is_multiple_of(2) {
+			(width / 2, 2)
+		} else {
+			(width, samples)
+		};
+
+		Strip {
+			data: ljpeg::encode(data, width, height, components, precision),
+			bits: precision as u16,
+			compression: 7,
+			float: false,
+		}
+	}
+}
+
+// OriginalRawFileData has its own little format. The file is split into
+// 64KiB blocks that are each zlib compressed. We write the length of the
+// file, big endian, and then an index of where each block starts with one
+// extra entry for where the last one ends. Offsets are from the start of the
+// length field. After that comes the resource fork, which we don't have, so
+// it's a length of zero.
+fn original_raw_file_data(data: &[u8]) -> Vec<u8> {
+	const BLOCK: usize = 65536;
+
+	let blocks: Vec<Vec<u8>> = data
+		.chunks(BLOCK)
+		.map(|chunk| miniz_oxide::deflate::compress_to_vec_zlib(chunk, 6))
+		.collect();
+
+	let mut out = vec![];
+	out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+	if !data.is_empty() {
+		let mut offset = 4 + (blocks.len() + 1) * 4;
+		for block in &blocks {
+			out.extend_from_slice(&(offset as u32).to_be_bytes());
+			offset += block.len();
+		}
+		out.extend_from_slice(&(offset as u32).to_be_bytes());
+
+		for block in blocks {
+			out.extend_from_slice(&block);
+		}
+	}
+
+	// Empty resource fork
+	out.extend_from_slice(&0u32.to_be_bytes());
+	out
+}
@@ -0,0 +1,343 @@
+//! How the photo was taken: the exposure, the lens, when, and where. This is
+//! the part of EXIF that's about the shot and not the file, read from the
+//! EXIF and GPS IFDs of TIFF based raws and kept on
+//! [RawMetadata::exif](crate::image::RawMetadata::exif).
+//!
+//! To carry it into an export, [exif_bytes](RawMetadata::exif_bytes) makes
+//! the TIFF structure that JPEG's APP1, PNG's eXIf, and WebP's EXIF chunk
+//! all want.
+
+use std::fmt;
+
+use crate::{
+	image::RawMetadata,
+	tiff::{self, IfdWriter, Tiff, TYPE_RATIONAL},
+};
+
+const TAG_GPS_IFD: u16 = 0x8825;
+
+const TAG_EXPOSURE_TIME: u16 = 0x829A;
+const TAG_F_NUMBER: u16 = 0x829D;
+const TAG_ISO: u16 = 0x8827;
+const TAG_ISO_SPEED: u16 = 0x8833;
+const TAG_EXIF_VERSION: u16 = 0x9000;
+const TAG_DATETIME_ORIGINAL: u16 = 0x9003;
+const TAG_OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+const TAG_EXPOSURE_BIAS: u16 = 0x9204;
+const TAG_FOCAL_LENGTH: u16 = 0x920A;
+const TAG_FOCAL_LENGTH_35MM: u16 = 0xA405;
+const TAG_LENS_MAKE: u16 = 0xA433;
+const TAG_LENS_MODEL: u16 = 0xA434;
+const TAG_LENS_SERIAL: u16 = 0xA435;
+
+const TAG_GPS_VERSION: u16 = 0x0000;
+const TAG_GPS_LATITUDE_REF: u16 = 0x0001;
+const TAG_GPS_LATITUDE: u16 = 0x0002;
+const TAG_GPS_LONGITUDE_REF: u16 = 0x0003;
+const TAG_GPS_LONGITUDE: u16 = 0x0004;
+const TAG_GPS_ALTITUDE_REF: u16 = 0x0005;
+const TAG_GPS_ALTITUDE: u16 = 0x0006;
+
+/// The shooting metadata. Everything's optional, cameras leave out what they
+/// like.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Exif {
+	pub iso: Option<u32>,
+	/// In seconds
+	pub exposure_time: Option<f32>,
+	pub f_number: Option<f32>,
+	/// In millimetres
+	pub focal_length: Option<f32>,
+	/// What the focal length would be on a full frame camera
+	pub focal_length_35mm: Option<u16>,
+	/// Exposure compensation, in stops
+	pub exposure_bias: Option<f32>,
+	pub lens_make: Option<String>,
+	pub lens_model: Option<String>,
+	pub lens_serial: Option<String>,
+	/// When the shutter was pressed
+	pub datetime_original: Option<DateTime>,
+	pub gps: Option<Gps>,
+}
+
+/// A date and time the way EXIF has them, in the camera's local time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DateTime {
+	pub year: u16,
+	pub month: u8,
+	pub day: u8,
+	pub hour: u8,
+	pub minute: u8,
+	pub second: u8,
+	/// Minutes ahead of UTC, if the camera knew its timezone
+	pub offset: Option<i16>,
+}
+
+impl DateTime {
+	/// From EXIF's `YYYY:MM:DD HH:MM:SS`. None if it's blank, which cameras
+	/// write as spaces or zeroes when the clock wasn't set.
+	pub fn parse(s: &str) -> Option<Self> {
+		let (date, time) = s.trim().split_once(' ')?;
+		let mut date = date.split(':').map(|n| n.parse::<u16>().ok());
+		let mut time = time.split(':').map(|n| n.parse::<u8>().ok());
+
+		let datetime = Self {
+			year: date.next()??,
+			month: date.next()?? as u8,
+			day: date.next()?? as u8,
+			hour: time.next()??,
+			minute: time.next()??,
+			second: time.next()??,
+			offset: None,
+		};
+
+		(datetime.year != 0).then_some(datetime)
+	}
+
+	/// The offset from an OffsetTime tag, like `+09:00`
+	fn parse_offset(s: &str) -> Option<i16> {
+		let s = s.trim();
+		let sign = match s.get(..1)? {
+			"+" => 1,
+			"-" => -1,
+			_ => return None,
+		};
+		let (hours, minutes) = s[1..].split_once(':')?;
+		Some(sign * (hours.parse::<i16>().ok()? * 60 + minutes.parse::<i16>().ok()?))
+	}
+
+	fn offset_string(&self) -> Option<String> {
+		self.offset.map(|offset| {
+			let sign = if offset < 0 { '-' } else { '+' };
+			let offset = offset.unsigned_abs();
+			format!("{sign}{:02}:{:02}", offset / 60, offset % 60)
+		})
+	}
+}
+
+/// Back to EXIF's format
+impl fmt::Display for DateTime {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{:04}:{:02}:{:02} {:02}:{:02}:{:02}",
+			self.year, self.month, self.day, self.hour, self.minute, self.second
+		)
+	}
+}
+
+/// Where the photo was taken
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Gps {
+	/// Degrees, north positive
+	pub latitude: f64,
+	/// Degrees, east positive
+	pub longitude: f64,
+	/// Metres above sea level
+	pub altitude: Option<f32>,
+}
+
+/// Read the shooting metadata out of a TIFF based raw. Anything that isn't
+/// TIFF, or doesn't have an EXIF IFD, gets the default of nothing.
+pub(crate) fn read(data: &[u8]) -> Exif {
+	let Some(tiff) = Tiff::new(data) else {
+		return Exif::default();
+	};
+	let Some(ifd0) = tiff.first_ifd() else {
+		return Exif::default();
+	};
+
+	let mut exif = Exif {
+		gps: tiff
+			.sub_ifd(&ifd0, TAG_GPS_IFD)
+			.and_then(|gps| read_gps(&tiff, &gps)),
+		..Default::default()
+	};
+
+	let Some(ifd) = tiff.sub_ifd(&ifd0, tiff::TAG_EXIF_IFD) else {
+		return exif;
+	};
+	let rational = |tag| Some(*tiff.rationals(ifd.get(tag)?)?.first()?).filter(|v| v.is_finite());
+	let long = |tag| tiff.u32s(ifd.get(tag)?)?.first().copied();
+	let string = |tag| Some(tiff.string(ifd.get(tag)?)?).filter(|s| !s.is_empty());
+
+	// ISOSpeed is there for when the ISO is too big for the old tag's short
+	exif.iso = long(TAG_ISO_SPEED)
+		.or_else(|| long(TAG_ISO))
+		.filter(|iso| *iso > 0);
+	exif.exposure_time = rational(TAG_EXPOSURE_TIME);
+	exif.f_number = rational(TAG_F_NUMBER).filter(|f| *f > 0.0);
+	exif.focal_length = rational(TAG_FOCAL_LENGTH).filter(|f| *f > 0.0);
+	exif.focal_length_35mm = long(TAG_FOCAL_LENGTH_35MM)
+		.filter(|f| *f > 0)
+		.map(|f| f as u16);
+	exif.exposure_bias = rational(TAG_EXPOSURE_BIAS);
+	exif.lens_make = string(TAG_LENS_MAKE);
+	exif.lens_model = string(TAG_LENS_MODEL);
+	exif.lens_serial = string(TAG_LENS_SERIAL);
+	exif.datetime_original = string(TAG_DATETIME_ORIGINAL)
+		.and_then(|s| DateTime::parse(&s))
+		.map(|datetime| DateTime {
+			offset: string(TAG_OFFSET_TIME_ORIGINAL).and_then(|s| DateTime::parse_offset(&s)),
+			..datetime
+		});
+
+	exif
+}
+
+fn read_gps(tiff: &Tiff, ifd: &tiff::Ifd) -> Option<Gps> {
+	let degrees = |tag| -> Option<f64> {
+		let dms = tiff.rationals(ifd.get(tag)?)?;
+		let degrees = dms.first()?;
+		let minutes = dms.get(1).unwrap_or(&0.0);
+		let seconds = dms.get(2).unwrap_or(&0.0);
+		let degrees = *degrees as f64 + *minutes as f64 / 60.0 + *seconds as f64 / 3600.0;
+		degrees.is_finite().then_some(degrees)
+	};
+	let reference = |tag| tiff.string(ifd.get(tag)?);
+
+	let mut latitude = degrees(TAG_GPS_LATITUDE)?;
+	let mut longitude = degrees(TAG_GPS_LONGITUDE)?;
+	if reference(TAG_GPS_LATITUDE_REF).as_deref() == Some("S") {
+		latitude = -latitude;
+	}
+	if reference(TAG_GPS_LONGITUDE_REF).as_deref() == Some("W") {
+		longitude = -longitude;
+	}
+
+	// AltitudeRef is a byte, 1 for below sea level
+	let below = ifd
+		.get(TAG_GPS_ALTITUDE_REF)
+		.and_then(|e| tiff.u16s(e))
+		.and_then(|v| v.first().copied())
+		== Some(1);
+	let altitude = ifd
+		.get(TAG_GPS_ALTITUDE)
+		.and_then(|e| tiff.rationals(e))
+		.and_then(|v| v.first().copied())
+		.filter(|a| a.is_finite())
+		.map(|a| if below { -a } else { a });
+
+	Some(Gps {
+		latitude,
+		longitude,
+		altitude,
+	})
+}
+
+impl RawMetadata {
+	/// The make, model, orientation, and [exif](Self::exif) as a little
+	/// endian TIFF, ready to go in an encoder's EXIF block. Apply a
+	/// [MetadataPolicy](crate::image::MetadataPolicy) first to leave things
+	/// out.
+	pub fn exif_bytes(&self) -> Vec<u8> {
+		let mut ifd0 = IfdWriter::new();
+		if !self.make.is_empty() {
+			ifd0.ascii(tiff::TAG_MAKE, &self.make);
+		}
+		if !self.model.is_empty() {
+			ifd0.ascii(tiff::TAG_MODEL, &self.model);
+		}
+		ifd0.short(tiff::TAG_ORIENTATION, &[self.orientation.exif()]);
+		write_ifds(&self.exif, &mut ifd0);
+
+		let mut out = Vec::with_capacity(8 + ifd0.len());
+		out.extend_from_slice(b"II");
+		out.extend_from_slice(&42u16.to_le_bytes());
+		out.extend_from_slice(&8u32.to_le_bytes());
+		out.extend_from_slice(&ifd0.encode_at(8));
+		out
+	}
+}
+
+/// Hang the EXIF, and GPS if there is any, off of `ifd0`
+pub(crate) fn write_ifds(exif: &Exif, ifd0: &mut IfdWriter) {
+	let mut ifd = IfdWriter::new();
+	ifd.undefined(TAG_EXIF_VERSION, b"0232");
+	if let Some(exposure_time) = exif.exposure_time {
+		rational(&mut ifd, TAG_EXPOSURE_TIME, &[exposure_time as f64]);
+	}
+	if let Some(f_number) = exif.f_number {
+		rational(&mut ifd, TAG_F_NUMBER, &[f_number as f64]);
+	}
+	if let Some(iso) = exif.iso {
+		ifd.short(TAG_ISO, &[iso.min(u16::MAX as u32) as u16]);
+		if iso > u16::MAX as u32 {
+			ifd.long(TAG_ISO_SPEED, &[iso]);
+		}
+	}
+	if let Some(datetime) = exif.datetime_original {
+		ifd.ascii(TAG_DATETIME_ORIGINAL, &datetime.to_string());
+		if let Some(offset) = datetime.offset_string() {
+			ifd.ascii(TAG_OFFSET_TIME_ORIGINAL, &offset);
+		}
+	}
+	if let Some(bias) = exif.exposure_bias {
+		ifd.srational(TAG_EXPOSURE_BIAS, &[bias]);
+	}
+	if let Some(focal_length) = exif.focal_length {
+		rational(&mut ifd, TAG_FOCAL_LENGTH, &[focal_length as f64]);
+	}
+	if let Some(focal_length) = exif.focal_length_35mm {
+		ifd.short(TAG_FOCAL_LENGTH_35MM, &[focal_length]);
+	}
+	for (tag, value) in [
+		(TAG_LENS_MAKE, &exif.lens_make),
+		(TAG_LENS_MODEL, &exif.lens_model),
+		(TAG_LENS_SERIAL, &exif.lens_serial),
+	] {
+		if let Some(value) = value {
+			ifd.ascii(tag, value);
+		}
+	}
+	ifd0.sub_ifd(tiff::TAG_EXIF_IFD, ifd);
+
+	if let Some(location) = exif.gps {
+		let mut gps = IfdWriter::new();
+		gps.byte(TAG_GPS_VERSION, &[2, 3, 0, 0]);
+		let latitude_ref = if location.latitude < 0.0 { "S" } else { "N" };
+		gps.ascii(TAG_GPS_LATITUDE_REF, latitude_ref);
+		rational(&mut gps, TAG_GPS_LATITUDE, &dms(location.latitude));
+		let longitude_ref = if location.longitude < 0.0 { "W" } else { "E" };
+		gps.ascii(TAG_GPS_LONGITUDE_REF, longitude_ref);
+		rational(&mut gps, TAG_GPS_LONGITUDE, &dms(location.longitude));
+		if let Some(altitude) = location.altitude {
+			gps.byte(TAG_GPS_ALTITUDE_REF, &[(altitude < 0.0) as u8]);
+			rational(&mut gps, TAG_GPS_ALTITUDE, &[altitude.abs() as f64]);
+		}
+		ifd0.sub_ifd(TAG_GPS_IFD, gps);
+	}
+}
+
+/// Degrees, minutes, and seconds, which is how GPS wants its angles
+fn dms(degrees: f64) -> [f64; 3] {
+	let degrees = degrees.abs();
+	let minutes = degrees.fract() * 60.0;
+	[degrees.trunc(), minutes.trunc(), minutes.fract() * 60.0]
+}
+
+/// IfdWriter's rationals are all over a million, which is too small for
+/// altitudes and makes exposure times look odd. Short exposures become
+/// `1/n`, like a camera writes them, and everything else is over a thousand
+/// if it fits.
+fn rational(ifd: &mut IfdWriter, tag: u16, v: &[f64]) {
+	let data = v
+		.iter()
+		.flat_map(|v| {
+			let v = v.max(0.0);
+			let (num, den) = if v > 0.0 && v < 1.0 && ((1.0 / v).round() * v - 1.0).abs() < 1e-3 {
+				(1, (1.0 / v).round() as u32)
+			} else if v * 1000.0 < u32::MAX as f64 {
+				((v * 1000.0).round() as u32, 1000)
+			} else {
+				(v.round().min(u32::MAX as f64) as u32, 1)
+			};
+			[num.to_le_bytes(), den.to_le_bytes()]
+		})
+		.flatten()
+		.collect();
+	ifd.push(tag, TYPE_RATIONAL, v.len(), data);
+}
@@ -0,0 +1,343 @@
+//! Getting finished images out without throwing away what the raw had. 16-bit
+//! TIFFs for editors and printers, float EXRs for anything that wants the
+//! full range, and JPEGs for sharing.
+//!
+//! All of them are tagged with what colorspace they're in so colour managed
+//! viewers show them right. TIFFs and JPEGs get an ICC profile and EXRs get
+//! their chromaticities.
+
+use std::io::Write;
+
+use jpeg_encoder::{ColorType, Density, Encoder, SamplingFactor};
+
+use crate::{
+	colorspace::{Colorspace, LinSrgb},
+	exif,
+	exr::ExrWriter,
+	image::{AlphaImage, Image, MetadataPolicy},
+	tiff::{self, IfdWriter},
+	Error,
+};
+
+/// Writes images as uncompressed 16-bit TIFFs.
+///
+/// Images in an RGB colorspace with known primaries, like sRGB or
+/// [Adobe RGB](crate::colorspace::AdobeRgb), get an ICC profile, and the
+/// white point and primaries tags for the readers that look at those
+/// instead. Anything else, like camera RGB, doesn't have primaries we can
+/// describe and is written as it is, untagged.
+///
+/// ```no_run
+/// # use rawproc::export::TiffWriter;
+/// # let mut file = std::fs::File::open("goose.nef").unwrap();
+/// let srgb = rawproc::decode(&mut file)
+///     .unwrap()
+///     .normalize()
+///     .debayer()
+///     .to_srgb();
+///
+/// let mut out = std::fs::File::create("goose.tif").unwrap();
+/// TiffWriter::new().dpi(300.0).write(&srgb, &mut out).unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TiffWriter {
+	policy: MetadataPolicy,
+	dpi: Option<f32>,
+}
+
+impl TiffWriter {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// What camera metadata to keep. We write the make, model, and
+	/// [exif](crate::image::RawMetadata::exif).
+	pub fn metadata_policy(mut self, policy: MetadataPolicy) -> Self {
+		self.policy = policy;
+		self
+	}
+
+	/// The resolution to tag the file with, in dots per inch, so it prints
+	/// at the right size
+	pub fn dpi(mut self, dpi: f32) -> Self {
+		self.dpi = Some(dpi);
+		self
+	}
+
+	pub fn write<C: Colorspace, W: Write>(
+		&self,
+		image: &Image<f32, C>,
+		writer: &mut W,
+	) -> Result<(), Error> {
+		let bytes = self.encode(image);
+		writer.write_all(&bytes)?;
+		Ok(())
+	}
+
+	/// Write an image with its alpha as an extra sample on every pixel
+	pub fn write_alpha<C: Colorspace, W: Write>(
+		&self,
+		image: &AlphaImage<f32, C>,
+		writer: &mut W,
+	) -> Result<(), Error> {
+		let bytes = self.encode_alpha(image);
+		writer.write_all(&bytes)?;
+		Ok(())
+	}
+
+	/// Build the whole file in memory. Values are clamped to 0.0 through 1.0
+	/// before they're scaled to 16 bits.
+	pub fn encode<C: Colorspace>(&self, image: &Image<f32, C>) -> Vec<u8> {
+		self.encode_planes(image, None)
+	}
+
+	/// [encode](Self::encode), with alpha. It's straight alpha, which TIFF
+	/// calls unassociated.
+	pub fn encode_alpha<C: Colorspace>(&self, image: &AlphaImage<f32, C>) -> Vec<u8> {
+		self.encode_planes(&image.image, Some(&image.alpha))
+	}
+
+	fn encode_planes<C: Colorspace>(
+		&self,
+		image: &Image<f32, C>,
+		alpha: Option<&[f32]>,
+	) -> Vec<u8> {
+		let mut meta = image.metadata.clone();
+		self.policy.apply(&mut meta);
+
+		let samples = C::COMPONENTS as u16 + alpha.is_some() as u16;
+		let mut ifd = IfdWriter::new();
+		ifd.long(0x00FE, &[0]); // NewSubFileType, main image
+		ifd.long(0x0100, &[image.width as u32]);
+		ifd.long(0x0101, &[image.height as u32]);
+		ifd.short(0x0102, &vec![16; samples as usize]); // BitsPerSample
+		ifd.short(0x0103, &[1]); // Compression, none
+		match C::COMPONENTS {
+			1 => ifd.short(0x0106, &[1]), // PhotometricInterpretation, BlackIsZero
+			_ => ifd.short(0x0106, &[2]), // RGB
+		}
+		if !meta.make.is_empty() {
+			ifd.ascii(0x010F, &meta.make);
+		}
+		if !meta.model.is_empty() {
+			ifd.ascii(0x0110, &meta.model);
+		}
+		ifd.short(tiff::TAG_ORIENTATION, &[1]); // The data's already upright
+		ifd.short(0x0115, &[samples]); // SamplesPerPixel
+		ifd.long(0x0116, &[image.height as u32]); // RowsPerStrip
+		ifd.short(0x011C, &[1]); // PlanarConfiguration, chunky
+		if alpha.is_some() {
+			ifd.short(0x0152, &[2]); // ExtraSamples, unassociated alpha
+		}
+
+		if let Some(dpi) = self.dpi {
+			ifd.rational(0x011A, &[dpi]); // XResolution
+			ifd.rational(0x011B, &[dpi]); // YResolution
+			ifd.short(0x0128, &[2]); // ResolutionUnit, inches
+		}
+
+		if let Some(tag) = image.color_tag() {
+			ifd.rational(0x013E, &tag.white); // WhitePoint
+			ifd.rational(0x013F, tag.primaries.as_flattened()); // PrimaryChromaticities
+			ifd.undefined(0x8773, &tag.icc_profile()); // InterColorProfile
+		}
+
+		exif::write_ifds(&meta.exif, &mut ifd);
+
+		let sample = |f: &f32| ((f.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16).to_le_bytes();
+		let strip = match alpha {
+			Some(alpha) => image
+				.data
+				.chunks_exact(C::COMPONENTS)
+				.zip(alpha)
+				.flat_map(|(px, a)| px.iter().chain([a]).flat_map(sample))
+				.collect(),
+			None => image.data.iter().flat_map(sample).collect(),
+		};
+		ifd.strip(strip);
+		ifd.finish()
+	}
+}
+
+/// How much of the colour to keep in a JPEG. We see detail in brightness
+/// much better than in colour, so JPEGs usually keep less colour than
+/// brightness, but small coloured detail like red text gets smeared.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Subsampling {
+	/// 4:4:4, all of it. The biggest files.
+	Full,
+	/// 4:2:2, half as much across
+	Half,
+	/// 4:2:0, half as much both ways. What cameras and most websites use.
+	#[default]
+	Quarter,
+}
+
+/// Writes images as JPEGs, for sharing.
+///
+/// Like [TiffWriter], images in a colorspace we can describe get an ICC
+/// profile. Without one, viewers guess sRGB, which is why a Display P3 or
+/// Adobe RGB JPEG looks dull next to the preview. The camera metadata goes
+/// in as EXIF, as the [MetadataPolicy] allows.
+///
+/// JPEGs are 8 bits, so give it an image with a curve, like
+/// [Srgb](crate::colorspace::Srgb), and not a linear one, or the shadows
+/// band.
+///
+/// ```no_run
+/// # use rawproc::export::{JpegWriter, Subsampling};
+/// # let mut file = std::fs::File::open("goose.nef").unwrap();
+/// let srgb = rawproc::decode(&mut file)
+///     .unwrap()
+///     .normalize()
+///     .debayer()
+///     .to_srgb();
+///
+/// let mut out = std::fs::File::create("goose.jpg").unwrap();
+/// JpegWriter::new()
+///     .quality(85)
+///     .subsampling(Subsampling::Full)
+///     .write(&srgb, &mut out)
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct JpegWriter {
+	policy: MetadataPolicy,
+	dpi: Option<f32>,
+	quality: u8,
+	subsampling: Subsampling,
+}
+
+impl Default for JpegWriter {
+	fn default() -> Self {
+		Self {
+			policy: MetadataPolicy::default(),
+			dpi: None,
+			quality: 90,
+			subsampling: Subsampling::default(),
+		}
+	}
+}
+
+impl JpegWriter {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// From 1 to 100. 90 if you don't say, which is hard to tell from the
+	/// original.
+	pub fn quality(mut self, quality: u8) -> Self {
+		self.quality = quality.clamp(1, 100);
+		self
+	}
+
+	pub fn subsampling(mut self, subsampling: Subsampling) -> Self {
+		self.subsampling = subsampling;
+		self
+	}
+
+	/// What camera metadata to keep
+	pub fn metadata_policy(mut self, policy: MetadataPolicy) -> Self {
+		self.policy = policy;
+		self
+	}
+
+	/// The resolution to tag the file with, in dots per inch
+	pub fn dpi(mut self, dpi: f32) -> Self {
+		self.dpi = Some(dpi);
+		self
+	}
+
+	pub fn write<C: Colorspace, W: Write>(
+		&self,
+		image: &Image<f32, C>,
+		writer: &mut W,
+	) -> Result<(), Error> {
+		let bytes = self.encode(image)?;
+		writer.write_all(&bytes)?;
+		Ok(())
+	}
+
+	/// Build the whole file in memory. Values are clamped to 0.0 through 1.0
+	/// before they're scaled to 8 bits. Single channel images come out
+	/// greyscale, and a JPEG can't be more than 65535 pixels either way.
+	pub fn encode<C: Colorspace>(&self, image: &Image<f32, C>) -> Result<Vec<u8>, Error> {
+		let mut meta = image.metadata.clone();
+		self.policy.apply(&mut meta);
+
+		let mut out = vec![];
+		let mut enc = Encoder::new(&mut out, self.quality);
+		enc.set_sampling_factor(match self.subsampling {
+			Subsampling::Full => SamplingFactor::R_4_4_4,
+			Subsampling::Half => SamplingFactor::R_4_2_2,
+			Subsampling::Quarter => SamplingFactor::R_4_2_0,
+		});
+		if let Some(dpi) = self.dpi {
+			let dpi = (dpi.round() as u32).clamp(1, u16::MAX as u32) as u16;
+			enc.set_density(Density::Inch { x: dpi, y: dpi });
+		}
+
+		// There's no standard way to split EXIF over more than one segment,
+		// so if it doesn't fit in one we leave it out
+		let mut exif = b"Exif\0\0".to_vec();
+		exif.extend_from_slice(&meta.exif_bytes());
+		if exif.len() <= MAX_APP_SEGMENT {
+			enc.add_app_segment(1, &exif)?;
+		}
+		if let Some(icc) = image.icc_profile() {
+			enc.add_icc_profile(&icc)?;
+		}
+
+		let color = match C::COMPONENTS {
+			1 => ColorType::Luma,
+			_ => ColorType::Rgb,
+		};
+		let data: Vec<u8> = image
+			.data
+			.iter()
+			.map(|f| (f.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8)
+			.collect();
+		let too_big = || Error::TooBigForJpeg {
+			width: image.width,
+			height: image.height,
+		};
+		let width = u16::try_from(image.width).map_err(|_| too_big())?;
+		let height = u16::try_from(image.height).map_err(|_| too_big())?;
+		enc.encode(&data, width, height, color)?;
+
+		Ok(out)
+	}
+}
+
+// An APP segment's length is a u16 that counts itself and the two byte
+// marker doesn't go in it
+const MAX_APP_SEGMENT: usize = 65533;
+
+impl<C: Colorspace> Image<f32, C> {
+	/// This image as a JPEG at `quality`, from 1 to 100. See [JpegWriter] for
+	/// subsampling and what metadata to keep.
+	pub fn to_jpeg(&self, quality: u8) -> Result<Vec<u8>, Error> {
+		JpegWriter::new().quality(quality).encode(self)
+	}
+
+	/// This image as a 16-bit TIFF, tagged with its colorspace if we can.
+	/// See [TiffWriter] for DPI and what metadata to keep.
+	pub fn to_tiff16(&self) -> Vec<u8> {
+		TiffWriter::new().encode(self)
+	}
+}
+
+impl<C: Colorspace> AlphaImage<f32, C> {
+	/// [Image::to_tiff16], with the alpha
+	pub fn to_tiff16(&self) -> Vec<u8> {
+		TiffWriter::new().encode_alpha(self)
+	}
+}
+
+impl Image<f32, LinSrgb> {
+	/// This image as a 32-bit float OpenEXR. Nothing is clamped. See
+	/// [ExrWriter] for half floats and sequences.
+	pub fn to_exr(&self) -> Vec<u8> {
+		ExrWriter::new().encode(self)
+	}
+}
@@ -0,0 +1,334 @@
+//! OpenEXR output. It's what compositors and grading software want for
+//! linear, floating point frames, and it's simple enough to write the
+//! uncompressed flavour ourselves.
+//!
+//! Layout is from the OpenEXR file layout document. We write one scanline
+//! per block, no compression, with the channels in the alphabetical order
+//! the format wants: A, if there's alpha, then B, G, R.
+
+use std::io::Write;
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+
+use crate::{
+	colorspace::{ColorTag, LinSrgb},
+	image::{AlphaImage, Image},
+	Error,
+};
+
+const MAGIC: [u8; 4] = [0x76, 0x2F, 0x31, 0x01];
+const VERSION: u32 = 2;
+
+const PIXEL_HALF: i32 = 1;
+const PIXEL_FLOAT: i32 = 2;
+
+/// Writes linear sRGB images as OpenEXR. Full 32-bit floats unless you ask
+/// for half floats, which are half the size and still plenty for grading.
+#[derive(Clone, Debug, Default)]
+pub struct ExrWriter {
+	half: bool,
+	frame_rate: Option<(i32, u32)>,
+	timecode: Option<Timecode>,
+}
+
+/// A SMPTE timecode, the way EXR's timeCode attribute wants it
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Timecode {
+	pub hours: u8,
+	pub minutes: u8,
+	pub seconds: u8,
+	pub frame: u8,
+}
+
+impl Timecode {
+	/// The timecode of frame `frame`, counting from 0, at `fps` frames a
+	/// second. Non-integer rates are rounded, since EXR timecodes don't
+	/// do drop frame for us.
+	pub fn from_frame(frame: u64, fps: f32) -> Self {
+		let fps = (fps.round() as u64).max(1);
+		let seconds = frame / fps;
+
+		Self {
+			hours: ((seconds / 3600) % 24) as u8,
+			minutes: ((seconds / 60) % 60) as u8,
+			seconds: (seconds % 60) as u8,
+			frame: (frame % fps) as u8,
+		}
+	}
+
+	/// Packed as binary coded decimal, two digits a field, frames in the low
+	/// byte and hours in the high one. The flag bits are left unset.
+	fn packed(&self) -> u32 {
+		let bcd = |v: u8| (((v / 10) << 4) | (v % 10)) as u32;
+
+		bcd(self.frame) & 0x3F
+			| (bcd(self.seconds) & 0x7F) << 8
+			| (bcd(self.minutes) & 0x7F) << 16
+			| (bcd(self.hours) & 0x3F) << 24
+	}
+}
+
+impl ExrWriter {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Write 16-bit half floats instead of 32-bit ones
+	pub fn half(mut self, half: bool) -> Self {
+		self.half = half;
+		self
+	}
+
+	/// The frame rate, as a fraction so 24000/1001 can be exact
+	pub fn frame_rate(mut self, numerator: i32, denominator: u32) -> Self {
+		self.frame_rate = Some((numerator, denominator));
+		self
+	}
+
+	pub fn timecode(mut self, timecode: Timecode) -> Self {
+		self.timecode = Some(timecode);
+		self
+	}
+
+	pub fn write<W: Write>(
+		&self,
+		image: &Image<f32, LinSrgb>,
+		writer: &mut W,
+	) -> Result<(), Error> {
+		let bytes = self.encode(image);
+		writer.write_all(&bytes)?;
+		Ok(())
+	}
+
+	/// Write an image with its alpha as a fourth channel
+	pub fn write_alpha<W: Write>(
+		&self,
+		image: &AlphaImage<f32, LinSrgb>,
+		writer: &mut W,
+	) -> Result<(), Error> {
+		let bytes = self.encode_alpha(image);
+		writer.write_all(&bytes)?;
+		Ok(())
+	}
+
+	/// Build the whole file in memory
+	pub fn encode(&self, image: &Image<f32, LinSrgb>) -> Vec<u8> {
+		self.encode_planes(image, None)
+	}
+
+	/// [encode](Self::encode), with alpha
+	pub fn encode_alpha(&self, image: &AlphaImage<f32, LinSrgb>) -> Vec<u8> {
+		self.encode_planes(&image.image, Some(&image.alpha))
+	}
+
+	fn encode_planes(&self, image: &Image<f32, LinSrgb>, alpha: Option<&[f32]>) -> Vec<u8> {
+		let (width, height) = (image.width, image.height);
+		let names: &[&str] = match alpha {
+			Some(_) => &["A", "B", "G", "R"],
+			None => &["B", "G", "R"],
+		};
+		let (pixel_type, sample_size) = if self.half {
+			(PIXEL_HALF, 2)
+		} else {
+			(PIXEL_FLOAT, 4)
+		};
+
+		let mut out = vec![];
+		out.extend_from_slice(&MAGIC);
+		out.extend_from_slice(&VERSION.to_le_bytes());
+
+		let mut channels = vec![];
+		for name in names {
+			channels.extend_from_slice(name.as_bytes());
+			channels.push(0);
+			channels.extend_from_slice(&pixel_type.to_le_bytes());
+			// pLinear and three reserved bytes, then x and y sampling
+			channels.extend_from_slice(&[0; 4]);
+			channels.extend_from_slice(&1i32.to_le_bytes());
+			channels.extend_from_slice(&1i32.to_le_bytes());
+		}
+		channels.push(0);
+		attribute(&mut out, "channels", "chlist", &channels);
+
+		// Red, green, blue, and white, each as x and y
+		let tag = ColorTag::LINEAR_SRGB;
+		let chromaticities: Vec<u8> = tag
+			.primaries
+			.iter()
+			.chain([&tag.white])
+			.flatten()
+			.flat_map(|f| f.to_le_bytes())
+			.collect();
+		attribute(
+			&mut out,
+			"chromaticities",
+			"chromaticities",
+			&chromaticities,
+		);
+		attribute(&mut out, "compression", "compression", &[0]);
+
+		let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+			.iter()
+			.flat_map(|v| v.to_le_bytes())
+			.collect();
+		attribute(&mut out, "dataWindow", "box2i", &window);
+		attribute(&mut out, "displayWindow", "box2i", &window);
+
+		if let Some((num, den)) = self.frame_rate {
+			let mut rational = num.to_le_bytes().to_vec();
+			rational.extend_from_slice(&den.to_le_bytes());
+			attribute(&mut out, "framesPerSecond", "rational", &rational);
+		}
+
+		attribute(&mut out, "lineOrder", "lineOrder", &[0]);
+		attribute(&mut out, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+		attribute(&mut out, "screenWindowCenter", "v2f", &[0; 8]);
+		attribute(
+			&mut out,
+			"screenWindowWidth",
+			"float",
+			&1.0f32.to_le_bytes(),
+		);
+
+		if let Some(timecode) = self.timecode {
+			let mut packed = timecode.packed().to_le_bytes().to_vec();
+			packed.extend_from_slice(&0u32.to_le_bytes());
+			attribute(&mut out, "timeCode", "timecode", &packed);
+		}
+		out.push(0);
+
+		// The offset table, one for every scanline, comes before the lines
+		let line_len = width * names.len() * sample_size;
+		let table_start = out.len();
+		let first_line = table_start + height * 8;
+		for y in 0..height {
+			let offset = (first_line + y * (8 + line_len)) as u64;
+			out.extend_from_slice(&offset.to_le_bytes());
+		}
+
+		for y in 0..height {
+			let row = &image.data[y * width * 3..(y + 1) * width * 3];
+			out.extend_from_slice(&(y as i32).to_le_bytes());
+			out.extend_from_slice(&(line_len as i32).to_le_bytes());
+
+			// Each channel's samples for the whole line, A then B, G, and R
+			if let Some(alpha) = alpha {
+				for a in &alpha[y * width..(y + 1) * width] {
+					self.sample(&mut out, *a);
+				}
+			}
+			for c in [2, 1, 0] {
+				for px in row.chunks_exact(3) {
+					self.sample(&mut out, px[c]);
+				}
+			}
+		}
+
+		out
+	}
+
+	fn sample(&self, out: &mut Vec<u8>, value: f32) {
+		if self.half {
+			out.extend_from_slice(&f32_to_half(value).to_le_bytes());
+		} else {
+			out.extend_from_slice(&value.to_le_bytes());
+		}
+	}
+}
+
+/// Writes frames of a sequence as `frame_000000.exr`, `frame_000001.exr`,
+/// and so on, each with its timecode, so they come into Resolve or Nuke as a
+/// clip.
+#[cfg(feature = "fs")]
+pub struct ExrSequence {
+	folder: PathBuf,
+	writer: ExrWriter,
+	fps: f32,
+	next: u64,
+}
+
+#[cfg(feature = "fs")]
+impl ExrSequence {
+	/// Write into `folder` at `fps` frames a second. `writer` is used for
+	/// every frame, with the frame rate and timecode filled in.
+	pub fn new<P: AsRef<Path>>(folder: P, fps: f32, writer: ExrWriter) -> Self {
+		// Rates like 23.976 are stored as 24000/1001
+		let writer = if fps.fract() == 0.0 {
+			writer.frame_rate(fps as i32, 1)
+		} else {
+			writer.frame_rate((fps * 1001.0).round() as i32, 1001)
+		};
+
+		Self {
+			folder: folder.as_ref().to_owned(),
+			writer,
+			fps,
+			next: 0,
+		}
+	}
+
+	/// Start numbering from `frame` instead of 0
+	pub fn starting_at(mut self, frame: u64) -> Self {
+		self.next = frame;
+		self
+	}
+
+	/// Write the next frame, returning where it went
+	pub fn write_frame(&mut self, image: &Image<f32, LinSrgb>) -> Result<PathBuf, Error> {
+		let path = self.folder.join(format!("frame_{:06}.exr", self.next));
+		let writer = self
+			.writer
+			.clone()
+			.timecode(Timecode::from_frame(self.next, self.fps));
+
+		let mut file = std::fs::File::create(&path)?;
+		writer.write(image, &mut file)?;
+
+		self.next += 1;
+		Ok(path)
+	}
+}
+
+fn attribute(out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+	out.extend_from_slice(name.as_bytes());
+	out.push(0);
+	out.extend_from_slice(kind.as_bytes());
+	out.push(0);
+	out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+	out.extend_from_slice(value);
+}
+
+/// Round an f32 to the nearest half float
+fn f32_to_half(f: f32) -> u16 {
+	let bits = f.to_bits();
+	let sign = ((bits >> 16) & 0x8000) as u16;
+	let exponent = ((bits >> 23) & 0xFF) as i32;
+	let mantissa = bits & 0x7F_FFFF;
+
+	if exponent == 0xFF {
+		// Infinity stays infinity and NaN stays NaN
+		let nan = if mantissa != 0 { 0x200 } else { 0 };
+		return sign | 0x7C00 | nan;
+	}
+
+	let exponent = exponent - 127 + 15;
+	if exponent >= 0x1F {
+		// Too big, so it's infinity
+		sign | 0x7C00
+	} else if exponent <= 0 {
+		// Subnormal, or too small and it's zero
+		if exponent < -10 {
+			return sign;
+		}
+		let mantissa = mantissa | 0x80_0000;
+		let shift = (14 - exponent) as u32;
+		let half = mantissa >> shift;
+		let round = (mantissa >> (shift - 1)) & 1;
+		sign | (half + round) as u16
+	} else {
+		let half = ((exponent as u32) << 10) | (mantissa >> 13);
+		let round = (mantissa >> 12) & 1;
+		// Rounding can carry into the exponent, which is what we want
+		sign | (half + round) as u16
+	}
+}
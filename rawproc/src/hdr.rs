@@ -0,0 +1,194 @@
+//! Radiance HDR output, the `.hdr` files that HDR and lighting tools take.
+//! Each pixel is RGBE: red, green, and blue bytes sharing an exponent byte.
+//! That's eight bits of precision at any brightness, so it keeps the full
+//! range of a raw without the size of floats.
+//!
+//! Layout is from Greg Ward's Radiance file format notes. Scanlines are run
+//! length encoded, each channel on its own, which is what everyone reads.
+
+use std::io::Write;
+
+use crate::{
+	colorspace::{ColorTag, LinRgb, LinSrgb},
+	image::Image,
+	Error,
+};
+
+// Scanlines outside these widths can't be run length encoded and are
+// written flat
+const MIN_RLE_WIDTH: usize = 8;
+const MAX_RLE_WIDTH: usize = 0x7FFF;
+
+// A run has to be this long to be worth a run instead of a literal
+const MIN_RUN: usize = 4;
+const MAX_RUN: usize = 127;
+const MAX_LITERAL: usize = 128;
+
+// The exponent byte is biased by 128 and the mantissa is a byte, so this,
+// 255/256 of 2^127, is as bright as RGBE goes
+const BRIGHTEST: f32 = 1.6947656e38;
+
+/// One pixel to RGBE. The biggest channel decides the exponent and the
+/// others get what precision is left, so a dim channel next to a bright one
+/// loses some. Negative values are clamped to black.
+pub fn float_to_rgbe(rgb: [f32; 3]) -> [u8; 4] {
+	let [r, g, b] = rgb.map(|c| {
+		if c.is_nan() {
+			0.0
+		} else {
+			c.clamp(0.0, BRIGHTEST)
+		}
+	});
+	let max = r.max(g).max(b);
+	if max < 1e-32 {
+		return [0; 4];
+	}
+
+	let (mantissa, exponent) = frexp(max);
+	let scale = mantissa * 256.0 / max;
+
+	[
+		(r * scale) as u8,
+		(g * scale) as u8,
+		(b * scale) as u8,
+		(exponent + 128) as u8,
+	]
+}
+
+/// And back. A pixel comes back at the middle of the range its bytes cover.
+pub fn rgbe_to_float(rgbe: [u8; 4]) -> [f32; 3] {
+	if rgbe[3] == 0 {
+		return [0.0; 3];
+	}
+
+	let scale = 2f32.powi(rgbe[3] as i32 - (128 + 8));
+	[
+		(rgbe[0] as f32 + 0.5) * scale,
+		(rgbe[1] as f32 + 0.5) * scale,
+		(rgbe[2] as f32 + 0.5) * scale,
+	]
+}
+
+/// Split a positive, finite float into a mantissa in [0.5, 1) and an
+/// exponent, like C's frexp
+fn frexp(value: f32) -> (f32, i32) {
+	let mut exponent = value.log2().floor() as i32 + 1;
+	let mut mantissa = value / 2f32.powi(exponent);
+	// log2 can be off by a hair right at powers of two
+	if mantissa >= 1.0 {
+		mantissa /= 2.0;
+		exponent += 1;
+	} else if mantissa < 0.5 {
+		mantissa *= 2.0;
+		exponent -= 1;
+	}
+
+	(mantissa, exponent)
+}
+
+impl Image<f32, LinRgb> {
+	/// Write the image as a Radiance HDR. It's camera RGB, which doesn't have
+	/// primaries we can describe, so the file is untagged and most tools
+	/// will take it as sRGB. Convert to [LinSrgb] first for a tagged one.
+	pub fn to_hdr<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+		writer.write_all(&encode(self.width, self.height, &self.data, None))?;
+		Ok(())
+	}
+}
+
+impl Image<f32, LinSrgb> {
+	/// Write the image as a Radiance HDR, tagged with sRGB's primaries
+	pub fn to_hdr<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+		let tag = self.color_tag();
+		writer.write_all(&encode(self.width, self.height, &self.data, tag))?;
+		Ok(())
+	}
+}
+
+fn encode(width: usize, height: usize, data: &[f32], tag: Option<ColorTag>) -> Vec<u8> {
+	let mut out = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n".to_vec();
+	if let Some(tag) = tag {
+		let [r, g, b] = tag.primaries;
+		let w = tag.white;
+		let primaries = format!(
+			"PRIMARIES= {} {} {} {} {} {} {} {}\n",
+			r[0], r[1], g[0], g[1], b[0], b[1], w[0], w[1]
+		);
+		out.extend_from_slice(primaries.as_bytes());
+	}
+	// A blank line ends the header, then the size. -Y then +X is top to
+	// bottom, left to right, the way everything else is.
+	out.extend_from_slice(format!("\n-Y {height} +X {width}\n").as_bytes());
+
+	// No columns, no pixels, and there's nothing to cut into rows
+	if width == 0 {
+		return out;
+	}
+
+	let rle = (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width);
+	let mut channels = vec![vec![0; width]; 4];
+	for row in data.chunks_exact(width * 3).take(height) {
+		let pixels = row
+			.chunks_exact(3)
+			.map(|px| float_to_rgbe([px[0], px[1], px[2]]));
+
+		if !rle {
+			pixels.for_each(|rgbe| out.extend_from_slice(&rgbe));
+			continue;
+		}
+
+		for (x, rgbe) in pixels.enumerate() {
+			for (channel, byte) in channels.iter_mut().zip(rgbe) {
+				channel[x] = byte;
+			}
+		}
+
+		out.extend_from_slice(&[2, 2, (width >> 8) as u8, width as u8]);
+		for channel in &channels {
+			run_length_encode(channel, &mut out);
+		}
+	}
+
+	out
+}
+
+/// Runs are a count over 128 and the byte to repeat. Anything that isn't
+/// worth a run goes out as literals, a count of up to 128 and the bytes.
+fn run_length_encode(data: &[u8], out: &mut Vec<u8>) {
+	let mut at = 0;
+	while at < data.len() {
+		// Find the next run long enough to bother with
+		let mut run_start = at;
+		let mut run = 0;
+		let mut previous_run = 0;
+		while run < MIN_RUN && run_start < data.len() {
+			run_start += run;
+			previous_run = run;
+			run = 1;
+			while run_start + run < data.len()
+				&& run < MAX_RUN
+				&& data[run_start + run] == data[run_start]
+			{
+				run += 1;
+			}
+		}
+
+		// A short run right before the long one is cheaper as a run too
+		if previous_run > 1 && previous_run == run_start - at {
+			out.extend_from_slice(&[128 + previous_run as u8, data[at]]);
+			at = run_start;
+		}
+
+		while at < run_start {
+			let count = (run_start - at).min(MAX_LITERAL);
+			out.push(count as u8);
+			out.extend_from_slice(&data[at..at + count]);
+			at += count;
+		}
+
+		if run >= MIN_RUN {
+			out.extend_from_slice(&[128 + run as u8, data[run_start]]);
+			at += run;
+		}
+	}
+}
@@ -0,0 +1,364 @@
+//! Hot pixels, the ones that read bright no matter what light they got.
+//! They get worse with long exposures and heat, but they're always the same
+//! pixels on a sensor, so what we find in one frame is kept in a map for
+//! the camera and the map grows every time we look.
+//!
+//! For a quick fix without keeping a map there's
+//! [fix_hot_pixels](Image::fix_hot_pixels), and if you already know where
+//! the bad pixels are, [fix_bad_pixels](Image::fix_bad_pixels).
+//!
+//! Maps are stored one file per camera body, keyed by serial number, in
+//! `$XDG_DATA_HOME/rawproc/hotpixels`, or `~/.local/share/rawproc/hotpixels`
+//! if that isn't set. The file is plain text: a `width height runs` line
+//! and then an `x y hits` line for every hot pixel. Keeping them needs the
+//! `fs` feature.
+
+use std::collections::BTreeMap;
+#[cfg(feature = "fs")]
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+
+use rawloader::CFA;
+
+use crate::{
+	colorspace::BayerRgb,
+	image::{cfa, Image, Sample},
+};
+#[cfg(feature = "fs")]
+use crate::{image::RawMetadata, Error};
+
+#[derive(Debug, thiserror::Error)]
+pub enum HotPixelError {
+	#[error("Line {0} of the hot pixel map doesn't make sense")]
+	Malformed(usize),
+	#[error("There's nowhere to keep hot pixel maps. Neither XDG_DATA_HOME or HOME are set")]
+	NoDataDirectory,
+}
+
+/// Every hot pixel found on one sensor, in sensor coordinates.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HotPixelMap {
+	/// The size of the sensor, or 0 by 0 if nothing's been found yet
+	pub width: usize,
+	pub height: usize,
+	/// How many frames have been looked at
+	pub runs: u32,
+	/// Each hot pixel, `(x, y)`, and how many of the frames it was hot in
+	pub pixels: BTreeMap<(usize, usize), u32>,
+}
+
+impl HotPixelMap {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Find the hot pixels in a raw. A pixel is hot if it's brighter than
+	/// the middle of its same coloured neighbours by more than `threshold`
+	/// of the range between black and white, so 0.1 is a tenth of the range.
+	///
+	/// Look at the raw before cropping it. Maps are in sensor coordinates and
+	/// a crop would shift everything over.
+	pub fn detect<T: Sample>(raw: &Image<T, BayerRgb>, threshold: f32) -> Self {
+		Self {
+			width: raw.width,
+			height: raw.height,
+			runs: 1,
+			pixels: outliers(raw, threshold, false),
+		}
+	}
+
+	/// A map of pixels you already know are bad, like from the camera
+	/// maker's list or a dark frame you looked through yourself. `width` and
+	/// `height` are the sensor's.
+	pub fn from_pixels<I: IntoIterator<Item = (usize, usize)>>(
+		width: usize,
+		height: usize,
+		pixels: I,
+	) -> Self {
+		Self {
+			width,
+			height,
+			runs: 1,
+			pixels: pixels.into_iter().map(|pixel| (pixel, 1)).collect(),
+		}
+	}
+
+	/// Add what another map found to this one. If the maps are for different
+	/// sized sensors, like if one was made from a cropped frame, the other
+	/// map replaces this one; they can't both be right.
+	pub fn merge(&mut self, other: &HotPixelMap) {
+		if (self.width, self.height) != (other.width, other.height) && self.runs > 0 {
+			*self = other.clone();
+			return;
+		}
+
+		self.width = other.width;
+		self.height = other.height;
+		self.runs += other.runs;
+		for (pixel, hits) in &other.pixels {
+			*self.pixels.entry(*pixel).or_insert(0) += hits;
+		}
+	}
+
+	/// Only the pixels that were hot in at least `min_hits` frames. A pixel
+	/// that was hot once might have been a cosmic ray or a star; one that's
+	/// hot every time is the sensor.
+	pub fn confirmed(&self, min_hits: u32) -> HotPixelMap {
+		Self {
+			pixels: self
+				.pixels
+				.iter()
+				.filter(|(_, hits)| **hits >= min_hits)
+				.map(|(pixel, hits)| (*pixel, *hits))
+				.collect(),
+			..self.clone()
+		}
+	}
+
+	/// Replace every hot pixel with the middle of its same coloured
+	/// neighbours, leaving out the ones that are hot too. Pixels outside the
+	/// raw are skipped, so a map for the wrong camera won't panic, but it
+	/// won't do anything useful either.
+	pub fn correct<T: Copy + Clone + PartialOrd>(&self, raw: &mut Image<T, BayerRgb>) {
+		let (width, height) = (raw.width, raw.height);
+		let mut neighbours = Vec::with_capacity(8);
+
+		for (x, y) in self.pixels.keys().copied() {
+			if x >= width || y >= height {
+				continue;
+			}
+
+			neighbours.clear();
+			neighbours.extend(
+				same_colour(&raw.metadata.cfa, x, y, width, height)
+					.filter(|n| !self.pixels.contains_key(n))
+					.map(|(nx, ny)| raw.data[ny * width + nx]),
+			);
+			if neighbours.is_empty() {
+				continue;
+			}
+			neighbours
+				.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+			raw.data[y * width + x] = neighbours[neighbours.len() / 2];
+		}
+	}
+
+	/// Read a map that was [saved](Self::save). A file that isn't there is
+	/// an empty map, since that's what it'd be on the first run.
+	#[cfg(feature = "fs")]
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+		let text = match fs::read_to_string(path) {
+			Ok(text) => text,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::new()),
+			Err(e) => return Err(e.into()),
+		};
+
+		let mut lines = text.lines().enumerate();
+		let numbers = |(idx, line): (usize, &str)| -> Result<[usize; 3], Error> {
+			let mut parts = line.split_whitespace().map(|n| n.parse::<usize>());
+			let mut next = || match parts.next() {
+				Some(Ok(n)) => Ok(n),
+				_ => Err(HotPixelError::Malformed(idx + 1)),
+			};
+
+			Ok([next()?, next()?, next()?])
+		};
+
+		let [width, height, runs] = match lines.next() {
+			Some(line) => numbers(line)?,
+			None => return Ok(Self::new()),
+		};
+
+		let mut pixels = BTreeMap::new();
+		for line in lines.filter(|(_, line)| !line.trim().is_empty()) {
+			let [x, y, hits] = numbers(line)?;
+			pixels.insert((x, y), hits as u32);
+		}
+
+		Ok(Self {
+			width,
+			height,
+			runs: runs as u32,
+			pixels,
+		})
+	}
+
+	/// Write the map out, making the folder it goes in if it has to.
+	#[cfg(feature = "fs")]
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+		let path = path.as_ref();
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)?;
+		}
+
+		let mut text = format!("{} {} {}\n", self.width, self.height, self.runs);
+		for ((x, y), hits) in &self.pixels {
+			text.push_str(&format!("{x} {y} {hits}\n"));
+		}
+
+		fs::write(path, text)?;
+		Ok(())
+	}
+
+	/// Where the map for the camera that took this raw lives. It's the make,
+	/// model, and serial number. Without a serial number every body of that
+	/// model shares a map, which isn't great, but it's better than nothing.
+	#[cfg(feature = "fs")]
+	pub fn path_for(metadata: &RawMetadata) -> Result<PathBuf, Error> {
+		let name = [
+			metadata.make.as_str(),
+			metadata.model.as_str(),
+			metadata.serial.as_deref().unwrap_or(""),
+		]
+		.iter()
+		.filter(|part| !part.is_empty())
+		.map(|part| {
+			part.chars()
+				.map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+				.collect::<String>()
+		})
+		.collect::<Vec<_>>()
+		.join("-");
+
+		Ok(data_directory()?.join(format!("{name}.txt")))
+	}
+
+	/// Detect the hot pixels in a raw and fold them into the map kept for
+	/// its camera, saving it, so every frame you process makes the map
+	/// better. Returns the updated map, ready to [correct](Self::correct)
+	/// with.
+	#[cfg(feature = "fs")]
+	pub fn learn(raw: &Image<u16, BayerRgb>, threshold: f32) -> Result<Self, Error> {
+		let path = Self::path_for(&raw.metadata)?;
+		let mut map = Self::load(&path)?;
+		map.merge(&Self::detect(raw, threshold));
+		map.save(&path)?;
+
+		Ok(map)
+	}
+}
+
+impl<T: Sample + PartialOrd> Image<T, BayerRgb> {
+	/// Find the pixels that don't agree with their same coloured neighbours
+	/// and replace them with the middle of those neighbours. That's hot
+	/// pixels, brighter than the middle by more than `threshold` of the
+	/// range between black and white, and dead ones, darker by as much.
+	/// Returns how many were fixed.
+	///
+	/// This only looks at this frame, so a low threshold can take out stars
+	/// and specular highlights. For a sensor you'll see again, a
+	/// [HotPixelMap] built over many frames is more careful.
+	pub fn fix_hot_pixels(&mut self, threshold: f32) -> usize {
+		let map = HotPixelMap {
+			width: self.width,
+			height: self.height,
+			runs: 1,
+			pixels: outliers(self, threshold, true),
+		};
+		map.correct(self);
+
+		map.pixels.len()
+	}
+
+	/// Replace the pixels in `pixels`, each `(x, y)` in sensor coordinates,
+	/// with the middle of their same coloured neighbours. Pixels outside the
+	/// image are skipped.
+	pub fn fix_bad_pixels(&mut self, pixels: &[(usize, usize)]) {
+		HotPixelMap::from_pixels(self.width, self.height, pixels.iter().copied()).correct(self);
+	}
+}
+
+/// Every pixel that's further from the middle of its same coloured
+/// neighbours than `threshold` of the range, brighter or, if `dead` is set,
+/// darker too.
+fn outliers<T: Sample>(
+	raw: &Image<T, BayerRgb>,
+	threshold: f32,
+	dead: bool,
+) -> BTreeMap<(usize, usize), u32> {
+	let (width, height) = (raw.width, raw.height);
+	let meta = &raw.metadata;
+
+	// Black and white can be different for each colour, so the range is too
+	let levels: [(f32, f32); 3] = std::array::from_fn(|c| {
+		let black = meta.blacklevels[c] as f32;
+		let range = (meta.whitelevels[c] as f32 - black).max(1.0);
+		(black, threshold * range)
+	});
+
+	let mut pixels = BTreeMap::new();
+	let mut neighbours = Vec::with_capacity(8);
+	for y in 0..height {
+		for x in 0..width {
+			let value = raw.data[y * width + x].to_f32();
+			let (black, limit) = levels[cfa::channel(meta.cfa.color_at(y, x))];
+			// Nothing this close to black can be hot, and skipping it saves
+			// sorting neighbours for most of the frame
+			if !dead && value - black < limit {
+				continue;
+			}
+
+			neighbours.clear();
+			neighbours.extend(
+				same_colour(&raw.metadata.cfa, x, y, width, height)
+					.map(|(nx, ny)| raw.data[ny * width + nx].to_f32()),
+			);
+			if neighbours.is_empty() {
+				continue;
+			}
+			neighbours.sort_unstable_by(f32::total_cmp);
+
+			let middle = neighbours[neighbours.len() / 2];
+			if value - middle > limit || (dead && middle - value > limit) {
+				pixels.insert((x, y), 1);
+			}
+		}
+	}
+
+	pixels
+}
+
+#[cfg(feature = "fs")]
+fn data_directory() -> Result<PathBuf, HotPixelError> {
+	let base = match std::env::var_os("XDG_DATA_HOME").filter(|v| !v.is_empty()) {
+		Some(data) => PathBuf::from(data),
+		None => std::env::var_os("HOME")
+			.filter(|v| !v.is_empty())
+			.map(|home| PathBuf::from(home).join(".local").join("share"))
+			.ok_or(HotPixelError::NoDataDirectory)?,
+	};
+
+	Ok(base.join("rawproc").join("hotpixels"))
+}
+
+/// The pixels two over, where the same colour is in a Bayer mosaic, that
+/// are on the sensor and really are the same colour. In an X-Trans mosaic
+/// not all of them are.
+fn same_colour<'a>(
+	cfa: &'a CFA,
+	x: usize,
+	y: usize,
+	width: usize,
+	height: usize,
+) -> impl Iterator<Item = (usize, usize)> + 'a {
+	const OFFSETS: [(isize, isize); 8] = [
+		(-2, -2),
+		(0, -2),
+		(2, -2),
+		(-2, 0),
+		(2, 0),
+		(-2, 2),
+		(0, 2),
+		(2, 2),
+	];
+
+	OFFSETS.iter().filter_map(move |(dx, dy)| {
+		let nx = x.checked_add_signed(*dx)?;
+		let ny = y.checked_add_signed(*dy)?;
+		let same = cfa.color_at(ny, nx) == cfa.color_at(y, x);
+		(nx < width && ny < height && same).then_some((nx, ny))
+	})
+}
@@ -0,0 +1,150 @@
+//! ICC profiles, so colour managed viewers know what our numbers mean.
+//!
+//! We only ever need the simple kind of display profile: three primaries, a
+//! white point, and a tone curve. That's a matrix/TRC profile and it's small
+//! enough to build by hand. They're version 2 profiles, which is what
+//! everything can read. Layout is from ICC.1:2001-04.
+
+use nalgebra::{Matrix3, Vector3};
+
+use crate::{colorspace::ColorTag, image::ChromaticAdaptation, transfer::TransferFunction};
+
+// The profile connection space is always D50
+const D50_XYZ: [f32; 3] = [0.9642, 1.0, 0.8249];
+
+// How many entries a sampled tone curve gets. Plenty for 16-bit data.
+const CURVE_POINTS: usize = 1024;
+
+/// Build a matrix/TRC display profile. The same curve is used for all three
+/// channels.
+pub(crate) fn rgb_profile(tag: &ColorTag) -> Vec<u8> {
+	let matrix = rgb_to_d50_xyz(tag);
+	let column = |c: usize| xyz(&[matrix[(0, c)], matrix[(1, c)], matrix[(2, c)]]);
+	let trc = curve(tag.transfer);
+
+	let tags: Vec<([u8; 4], Vec<u8>)> = vec![
+		(*b"desc", text_description(tag.name)),
+		(*b"cprt", text("No copyright, use freely")),
+		(*b"wtpt", xyz(&D50_XYZ)),
+		(*b"rXYZ", column(0)),
+		(*b"gXYZ", column(1)),
+		(*b"bXYZ", column(2)),
+		(*b"rTRC", trc.clone()),
+		(*b"gTRC", trc.clone()),
+		(*b"bTRC", trc),
+	];
+
+	// Tag data starts after the header, the count, and the tag table. Tags
+	// with the same data, like the three curves, share it.
+	let data_start = 128 + 4 + tags.len() * 12;
+	let mut table = vec![];
+	let mut data: Vec<u8> = vec![];
+	let mut placed: Vec<(usize, &[u8])> = vec![];
+	for (signature, tag) in &tags {
+		let at = match placed.iter().find(|(_, d)| *d == tag.as_slice()) {
+			Some((at, _)) => *at,
+			None => {
+				let at = data_start + data.len();
+				data.extend_from_slice(tag);
+				// Every tag starts on a four byte boundary
+				while !data.len().is_multiple_of(4) {
+					data.push(0);
+				}
+				placed.push((at, tag));
+				at
+			}
+		};
+
+		table.extend_from_slice(signature);
+		table.extend_from_slice(&(at as u32).to_be_bytes());
+		table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+	}
+
+	let size = 128 + 4 + table.len() + data.len();
+	let mut out = Vec::with_capacity(size);
+	out.extend_from_slice(&(size as u32).to_be_bytes());
+	out.extend_from_slice(&[0; 4]); // Preferred CMM
+	out.extend_from_slice(&[2, 0x10, 0, 0]); // Version 2.1
+	out.extend_from_slice(b"mntr");
+	out.extend_from_slice(b"RGB ");
+	out.extend_from_slice(b"XYZ ");
+	out.extend_from_slice(&[0; 12]); // Creation date, which we don't know
+	out.extend_from_slice(b"acsp");
+	// Platform, flags, manufacturer, model, attributes, and rendering intent
+	out.extend_from_slice(&[0; 28]);
+	out.extend_from_slice(&xyz(&D50_XYZ)[8..]);
+	out.extend_from_slice(&[0; 48]); // Creator and reserved
+	out.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+	out.extend_from_slice(&table);
+	out.extend_from_slice(&data);
+
+	out
+}
+
+/// The matrix that takes RGB in this colorspace to XYZ, adapted to D50 with
+/// Bradford since that's what the profile connection space is.
+fn rgb_to_d50_xyz(tag: &ColorTag) -> Matrix3<f32> {
+	let adapt = ChromaticAdaptation::Bradford.matrix_xyz(tag.white_xyz(), Vector3::from(D50_XYZ));
+
+	adapt * tag.rgb_to_xyz()
+}
+
+/// A curveType that takes the encoded values back to linear. Straight gammas
+/// only need the one number, everything else is sampled.
+fn curve(tf: TransferFunction) -> Vec<u8> {
+	let mut out = tag_header(b"curv");
+	match tf {
+		TransferFunction::Linear => out.extend_from_slice(&0u32.to_be_bytes()),
+		TransferFunction::Gamma(gamma) => {
+			out.extend_from_slice(&1u32.to_be_bytes());
+			out.extend_from_slice(&((gamma * 256.0).round() as u16).to_be_bytes());
+		}
+		_ => {
+			out.extend_from_slice(&(CURVE_POINTS as u32).to_be_bytes());
+			for idx in 0..CURVE_POINTS {
+				let linear = tf.decode(idx as f32 / (CURVE_POINTS - 1) as f32);
+				let sample = (linear.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+				out.extend_from_slice(&sample.to_be_bytes());
+			}
+		}
+	}
+	out
+}
+
+fn xyz(v: &[f32; 3]) -> Vec<u8> {
+	let mut out = tag_header(b"XYZ ");
+	for f in v {
+		out.extend_from_slice(&s15_fixed16(*f).to_be_bytes());
+	}
+	out
+}
+
+fn text(s: &str) -> Vec<u8> {
+	let mut out = tag_header(b"text");
+	out.extend_from_slice(s.as_bytes());
+	out.push(0);
+	out
+}
+
+/// The version 2 textDescriptionType. It has room for Unicode and Macintosh
+/// ScriptCode versions too, which we leave empty.
+fn text_description(s: &str) -> Vec<u8> {
+	let mut out = tag_header(b"desc");
+	out.extend_from_slice(&(s.len() as u32 + 1).to_be_bytes());
+	out.extend_from_slice(s.as_bytes());
+	out.push(0);
+	// Unicode language and count, ScriptCode code and count, then the 67
+	// bytes ScriptCode always takes up
+	out.extend_from_slice(&[0; 8 + 3 + 67]);
+	out
+}
+
+fn tag_header(signature: &[u8; 4]) -> Vec<u8> {
+	let mut out = signature.to_vec();
+	out.extend_from_slice(&[0; 4]);
+	out
+}
+
+fn s15_fixed16(f: f32) -> i32 {
+	(f * 65536.0).round() as i32
+}
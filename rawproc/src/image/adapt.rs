@@ -0,0 +1,93 @@
+use nalgebra::{Matrix3, Vector3};
+
+use crate::{
+	colorspace::{xy_to_xyz, XYZ},
+	par::*,
+};
+
+use super::{
+	xyz::{BRADFORD, BRADFORD_INV, XYZ_SCALING},
+	Image,
+};
+
+// From Li et al., "Comprehensive color solutions: CAM16, CAT16, and CAM16-UCS"
+#[rustfmt::skip]
+const CAT16: Matrix3<f32> = Matrix3::new(
+	 0.401288, 0.650173, -0.051461,
+	-0.250268, 1.204414,  0.045854,
+	-0.002079, 0.048952,  0.953127,
+);
+
+/// How to move colours from one white to another. They all scale the
+/// colour in some kind of cone response space, they just disagree on what
+/// the cones are.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChromaticAdaptation {
+	/// What almost everyone uses, and what the ICC profiles use
+	#[default]
+	Bradford,
+	/// From CIECAM16. A little better than Bradford for big changes, like
+	/// tungsten to daylight.
+	Cat16,
+	/// Scale XYZ directly. The simplest and the worst, here for comparing.
+	XyzScaling,
+}
+
+impl ChromaticAdaptation {
+	/// The matrix that takes XYZ colours seen under the `from` white to how
+	/// they'd look under the `to` white. Whites are CIE x and y, like
+	/// [D65](crate::colorspace::D65).
+	pub fn matrix(&self, from: [f32; 2], to: [f32; 2]) -> Matrix3<f32> {
+		self.matrix_xyz(xy_to_xyz(from), xy_to_xyz(to))
+	}
+
+	/// [matrix](Self::matrix) with the whites as XYZ. If they don't have the
+	/// same Y, the brightness is scaled too.
+	pub(crate) fn matrix_xyz(&self, from: Vector3<f32>, to: Vector3<f32>) -> Matrix3<f32> {
+		let (cones, cones_inv) = match self {
+			ChromaticAdaptation::Bradford => (BRADFORD, BRADFORD_INV),
+			ChromaticAdaptation::Cat16 => {
+				(CAT16, CAT16.try_inverse().unwrap_or_else(Matrix3::identity))
+			}
+			ChromaticAdaptation::XyzScaling => (XYZ_SCALING, XYZ_SCALING),
+		};
+
+		let from_cones = cones * from;
+		let to_cones = cones * to;
+
+		cones_inv * Matrix3::from_diagonal(&to_cones.component_div(&from_cones)) * cones
+	}
+}
+
+impl Image<f32, XYZ> {
+	/// The white this image was shot under, as CIE x and y. It's whatever
+	/// the camera calls `(1, 1, 1)`, so after
+	/// [whitebalance](Image::whitebalance) it's the scene's light.
+	pub fn scene_white(&self) -> [f32; 2] {
+		let white = self.metadata.cam_to_xyz * Vector3::new(1.0, 1.0, 1.0);
+		let sum = white.sum();
+		[white[0] / sum, white[1] / sum]
+	}
+
+	/// Move every colour from the `from` white to the `to` white, so
+	/// something white under one light looks white under the other. Usually
+	/// `from` is the [scene_white](Self::scene_white).
+	///
+	/// The camera matrix is adapted along with the data, so the conversions
+	/// out of XYZ see `to` as the white and don't adapt a second time.
+	pub fn adapt_white(&mut self, from: [f32; 2], to: [f32; 2], method: ChromaticAdaptation) {
+		let matrix = method.matrix(from, to);
+		self.data.par_chunks_exact_mut(3).for_each(|px| {
+			let res = matrix * Vector3::new(px[0], px[1], px[2]);
+			px.copy_from_slice(res.as_slice());
+		});
+
+		self.metadata.cam_to_xyz = matrix * self.metadata.cam_to_xyz;
+		self.metadata.xyz_to_cam = self
+			.metadata
+			.cam_to_xyz
+			.try_inverse()
+			.unwrap_or_else(Matrix3::identity);
+	}
+}
@@ -0,0 +1,56 @@
+//! The basic adjustments a raw developer needs, done on linear camera RGB
+//! so they happen before anything's been clipped or curved.
+
+use crate::{algorithms, colorspace::LinRgb, par::*};
+
+use super::{Image, Mask};
+
+// Contrast pivots around middle grey so it doesn't change the overall
+// brightness, just how far things are from it
+const MIDDLE_GREY: f32 = 0.18;
+
+impl Image<f32, LinRgb> {
+	/// Brighten, or darken with a negative number, by `stops`. Give it a
+	/// [Mask] to only change part of the image.
+	///
+	/// # Panics
+	/// If the mask isn't the same size as the image.
+	pub fn exposure(&mut self, stops: f32, mask: Option<&Mask>) {
+		let gain = stops.exp2();
+		self.adjust_masked(mask, |rgb| rgb.iter_mut().for_each(|v| *v *= gain));
+	}
+
+	/// Stretch, or squash with less than 1.0, the tones around middle grey.
+	/// 1.0 leaves the image alone.
+	///
+	/// Linear light doesn't look linear to us, so this works on stops, not
+	/// values: at 2.0, something a stop over middle grey ends up two stops
+	/// over. Each pixel is scaled as a whole so the colour stays the same.
+	pub fn contrast(&mut self, amount: f32) {
+		self.data.par_chunks_exact_mut(3).for_each(|rgb| {
+			let luminance = algorithms::luminance(rgb);
+			if luminance <= 0.0 {
+				return;
+			}
+
+			let contrasted = MIDDLE_GREY * (luminance / MIDDLE_GREY).powf(amount);
+			let scale = contrasted / luminance;
+			rgb.iter_mut().for_each(|v| *v *= scale);
+		});
+	}
+
+	/// Push colours away from, or with less than 1.0 pull them towards, the
+	/// grey of the same brightness. 0.0 is black and white and 1.0 leaves
+	/// the image alone.
+	///
+	/// The luminance is Rec. 709's, which is only close for camera RGB, so
+	/// big changes can shift the brightness of very saturated colours a
+	/// little.
+	pub fn saturation(&mut self, amount: f32) {
+		self.data.par_chunks_exact_mut(3).for_each(|rgb| {
+			let luminance = algorithms::luminance(rgb);
+			rgb.iter_mut()
+				.for_each(|v| *v = luminance + (*v - luminance) * amount);
+		});
+	}
+}
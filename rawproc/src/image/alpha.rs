@@ -0,0 +1,123 @@
+use crate::colorspace::Colorspace;
+
+use super::Image;
+
+/// An image with an alpha plane next to it. Alpha is kept apart from the
+/// colour data so every operation on [Image] still works on the colour, and
+/// it's straight, not premultiplied, alpha: 0.0 is transparent and 1.0, or
+/// the type's maximum, is opaque.
+///
+/// Things that move pixels around, like rotating, should move the alpha
+/// with them so the corners they uncover end up transparent.
+#[derive(Clone, Debug)]
+pub struct AlphaImage<T: Copy + Clone, C: Colorspace> {
+	pub image: Image<T, C>,
+	/// One value per pixel, `width * height` long
+	pub alpha: Vec<T>,
+}
+
+impl<T: Copy + Clone, C: Colorspace> Image<T, C> {
+	/// Pair the image with an alpha plane.
+	///
+	/// # Panics
+	/// If `alpha` isn't one value per pixel.
+	pub fn with_alpha(self, alpha: Vec<T>) -> AlphaImage<T, C> {
+		assert_eq!(
+			alpha.len(),
+			self.width * self.height,
+			"alpha needs one value per pixel"
+		);
+
+		AlphaImage { image: self, alpha }
+	}
+}
+
+impl<C: Colorspace> Image<f32, C> {
+	/// Pair the image with an alpha plane that's opaque everywhere
+	pub fn opaque(self) -> AlphaImage<f32, C> {
+		let alpha = vec![1.0; self.width * self.height];
+		self.with_alpha(alpha)
+	}
+}
+
+impl<T: Copy + Clone, C: Colorspace> AlphaImage<T, C> {
+	pub fn width(&self) -> usize {
+		self.image.width
+	}
+
+	pub fn height(&self) -> usize {
+		self.image.height
+	}
+
+	pub fn into_parts(self) -> (Image<T, C>, Vec<T>) {
+		(self.image, self.alpha)
+	}
+
+	/// The colour and alpha interleaved, so RGBA for an RGB image. This is
+	/// the layout most encoders want.
+	pub fn interleaved(&self) -> Vec<T> {
+		let components = C::COMPONENTS;
+		let mut out = Vec::with_capacity(self.alpha.len() * (components + 1));
+		for (px, alpha) in self.image.data.chunks_exact(components).zip(&self.alpha) {
+			out.extend_from_slice(px);
+			out.push(*alpha);
+		}
+
+		out
+	}
+}
+
+impl<C: Colorspace> AlphaImage<f32, C> {
+	/// Put the image over `background`, which has to be the same size, and
+	/// get back an image with no transparency left in it.
+	///
+	/// # Panics
+	/// If the background isn't the same size.
+	pub fn over(self, background: &Image<f32, C>) -> Image<f32, C> {
+		assert_eq!(
+			(self.image.width, self.image.height),
+			(background.width, background.height),
+			"the background needs to be the same size"
+		);
+
+		let (mut image, alpha) = self.into_parts();
+		let components = C::COMPONENTS;
+		for ((px, bg), a) in image
+			.data
+			.chunks_exact_mut(components)
+			.zip(background.data.chunks_exact(components))
+			.zip(alpha)
+		{
+			for (v, b) in px.iter_mut().zip(bg) {
+				*v = *v * a + b * (1.0 - a);
+			}
+		}
+
+		image
+	}
+
+	/// Put the image over a solid colour, like a white page
+	pub fn flatten(self, color: &[f32]) -> Image<f32, C> {
+		let (mut image, alpha) = self.into_parts();
+		for (px, a) in image.data.chunks_exact_mut(C::COMPONENTS).zip(alpha) {
+			for (v, b) in px.iter_mut().zip(color) {
+				*v = *v * a + b * (1.0 - a);
+			}
+		}
+
+		image
+	}
+
+	/// Eight bit colour and alpha, the same way [Image::bytes] does it
+	pub fn bytes(self) -> AlphaImage<u8, C>
+	where
+		Image<f32, C>: Into<Image<u8, C>>,
+	{
+		let (image, alpha) = self.into_parts();
+
+		AlphaImage {
+			image: image.into(),
+			alpha: alpha.into_iter().map(|a| (a * 255.0) as u8).collect(),
+		}
+	}
+}
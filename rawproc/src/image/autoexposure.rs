@@ -0,0 +1,93 @@
+//! dcraw's auto-brighten: find the value that only the brightest few percent
+//! of the image are over and scale that up to white. It's not clever, but it
+//! means a batch of underexposed frames comes out looking like something
+//! without anyone having to look at them.
+
+use crate::{colorspace::LinRgb, par::*};
+
+use super::Image;
+
+// How finely the values are sorted to find the percentile
+const BINS: usize = 4096;
+
+impl Image<f32, LinRgb> {
+	/// The multiplier that would put the brightest `clip_percent` percent of
+	/// the image at or over white, 1.0. Like dcraw each channel is looked at
+	/// on its own and the brightest one decides, so the rest of the image
+	/// only clips where that channel would. dcraw uses 1.0.
+	///
+	/// Run it after whitebalancing, or the channel the camera's most
+	/// sensitive in decides for the rest. If there's nothing brighter than
+	/// black to go on it's 1.0.
+	pub fn analyze(&self, clip_percent: f32) -> f32 {
+		match self.brightest(clip_percent) {
+			Some(white) => 1.0 / white.into_iter().fold(0.0, f32::max),
+			None => 1.0,
+		}
+	}
+
+	/// Scale the image by what [analyze](Self::analyze) suggests, and give
+	/// back the multiplier in case you want to use it on something else, like
+	/// the full size render of a preview you measured.
+	pub fn apply_auto_exposure(&mut self, clip_percent: f32) -> f32 {
+		let gain = self.analyze(clip_percent);
+		self.data.par_iter_mut().for_each(|v| *v *= gain);
+		gain
+	}
+
+	/// The value of each channel that only the brightest `percent` percent
+	/// of the pixels are over. None if the image is all black.
+	pub(crate) fn brightest(&self, percent: f32) -> Option<[f32; 3]> {
+		let clip = (percent / 100.0).clamp(0.0, 1.0) as f64;
+
+		let brightest = self
+			.data
+			.par_iter()
+			.copied()
+			.filter(|v| v.is_finite())
+			.reduce(|| 0.0, f32::max);
+		if brightest <= 0.0 {
+			return None;
+		}
+
+		let bin = |v: f32| ((v / brightest) * BINS as f32).clamp(0.0, BINS as f32 - 1.0) as usize;
+		let histogram = self
+			.data
+			.par_chunks_exact(3)
+			.fold(
+				|| vec![[0u64; 3]; BINS],
+				|mut hist, rgb| {
+					for (c, v) in rgb.iter().enumerate() {
+						// NaN goes to the first bin, like it does for histogram
+						hist[bin(*v)][c] += 1;
+					}
+					hist
+				},
+			)
+			.reduce(
+				|| vec![[0u64; 3]; BINS],
+				|mut a, b| {
+					for (a, b) in a.iter_mut().zip(b) {
+						*a = [0, 1, 2].map(|c| a[c] + b[c]);
+					}
+					a
+				},
+			);
+
+		// Walk down from the top until we've passed the clipped ones
+		let pixels = (self.data.len() / 3) as f64;
+		let limit = (pixels * clip) as u64;
+		Some([0, 1, 2].map(|c| {
+			let mut seen = 0;
+			let white_bin = histogram
+				.iter()
+				.rposition(|h| {
+					seen += h[c];
+					seen > limit
+				})
+				.unwrap_or(0);
+
+			(white_bin + 1) as f32 / BINS as f32 * brightest
+		}))
+	}
+}
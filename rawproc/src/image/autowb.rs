@@ -0,0 +1,158 @@
+use crate::{colorspace::BayerRgb, par::*};
+
+use super::{CfaColors, Image, Sample};
+
+// Anything this close to the brightest value in its channel is probably
+// clipped, and clipped pixels have lost their colour
+const CLIP: f32 = 0.98;
+
+// How finely Retinex sorts the values to find the bright ones
+const BINS: usize = 4096;
+
+// Which values Retinex calls white. Not the very brightest, so a few hot
+// pixels don't get to decide.
+const WHITE_PERCENTILE: f64 = 0.995;
+
+/// How [auto_whitebalance](Image::auto_whitebalance) guesses at the light.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WhitebalanceMethod {
+	/// Assume everything averages out to grey. Good for busy scenes, fooled
+	/// by big areas of one colour like a sunset or a lawn.
+	#[default]
+	GrayWorld,
+	/// Assume the brightest things are white, the white patch part of
+	/// Retinex. Good when there's something white or shiny in the frame,
+	/// fooled when there isn't.
+	Retinex,
+}
+
+impl<T: Sample> Image<T, BayerRgb> {
+	/// Work out whitebalance multipliers from the image itself, for when the
+	/// camera got it wrong. They're green normalized, like the as-shot ones,
+	/// and go into `metadata.whitebalance` so the next
+	/// [whitebalance](Image::whitebalance) uses them. Do this before that,
+	/// while the data is still as the sensor saw it.
+	///
+	/// Black levels are taken off before anything's measured and clipped
+	/// pixels are left out. If a channel has nothing to go on it keeps a
+	/// multiplier of 1.0.
+	pub fn auto_whitebalance(&mut self, method: WhitebalanceMethod) -> [f32; 3] {
+		let (width, cfa) = (self.width, &self.metadata.cfa);
+		let black = self.metadata.blacklevels.map(|b| b as f32);
+
+		// The channel and the light at a sensor position. Emerald's close
+		// enough to green but it's not green, so it doesn't get a say. On an
+		// RYYB sensor yellow's all there is in the middle, so it does.
+		let fourth = CfaColors::of(cfa) == CfaColors::Ryb;
+		let light = |(idx, value): (usize, &T)| {
+			let c = cfa.color_at(idx / width, idx % width);
+			let c = match c {
+				3 if fourth => 1,
+				c => c,
+			};
+			(c < 3).then(|| (c, (value.to_f32() - black[c]).max(0.0)))
+		};
+
+		let brightest = self
+			.data
+			.par_iter()
+			.enumerate()
+			.filter_map(light)
+			.fold(
+				|| [0.0f32; 3],
+				|mut max, (c, v)| {
+					max[c] = max[c].max(v);
+					max
+				},
+			)
+			.reduce(|| [0.0; 3], |a, b| [0, 1, 2].map(|c| a[c].max(b[c])));
+		let clip = brightest.map(|b| b * CLIP);
+		let unclipped = |&(c, v): &(usize, f32)| v < clip[c];
+
+		let estimate = match method {
+			WhitebalanceMethod::GrayWorld => {
+				let (sums, counts) = self
+					.data
+					.par_iter()
+					.enumerate()
+					.filter_map(light)
+					.filter(unclipped)
+					.fold(
+						|| ([0.0f64; 3], [0u64; 3]),
+						|(mut sums, mut counts), (c, v)| {
+							sums[c] += v as f64;
+							counts[c] += 1;
+							(sums, counts)
+						},
+					)
+					.reduce(
+						|| ([0.0; 3], [0; 3]),
+						|a, b| {
+							(
+								[0, 1, 2].map(|c| a.0[c] + b.0[c]),
+								[0, 1, 2].map(|c| a.1[c] + b.1[c]),
+							)
+						},
+					);
+
+				[0, 1, 2].map(|c| (sums[c] / counts[c].max(1) as f64) as f32)
+			}
+			WhitebalanceMethod::Retinex => {
+				let bin = |c: usize, v: f32| {
+					((v / clip[c]) * BINS as f32).min(BINS as f32 - 1.0) as usize
+				};
+				let histogram = self
+					.data
+					.par_iter()
+					.enumerate()
+					.filter_map(light)
+					.filter(unclipped)
+					.fold(
+						|| vec![[0u64; 3]; BINS],
+						|mut hist, (c, v)| {
+							hist[bin(c, v)][c] += 1;
+							hist
+						},
+					)
+					.reduce(
+						|| vec![[0u64; 3]; BINS],
+						|mut a, b| {
+							for (a, b) in a.iter_mut().zip(b) {
+								*a = [0, 1, 2].map(|c| a[c] + b[c]);
+							}
+							a
+						},
+					);
+
+				[0, 1, 2].map(|c| {
+					let total: u64 = histogram.iter().map(|h| h[c]).sum();
+					let target = (total as f64 * WHITE_PERCENTILE) as u64;
+
+					let mut seen = 0;
+					let white_bin = histogram
+						.iter()
+						.position(|h| {
+							seen += h[c];
+							seen > target
+						})
+						.unwrap_or(0);
+
+					(white_bin as f32 + 0.5) / BINS as f32 * clip[c]
+				})
+			}
+		};
+
+		let green = estimate[1];
+		let wb = estimate.map(|e| {
+			if e > 0.0 && green > 0.0 {
+				green / e
+			} else {
+				1.0
+			}
+		});
+
+		self.metadata.whitebalance = wb;
+		wb
+	}
+}
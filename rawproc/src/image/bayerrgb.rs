@@ -1,58 +1,106 @@
-use std::ops::Range;
-
 use rawloader::CFA;
 
 use crate::{
-	colorspace::{BayerRgb, Colorspace, LinRgb},
-	RollingRandom,
+	colorspace::{BayerRgb, LinRgb},
+	par::*,
+	Error, RollingRandom,
 };
 
-use super::Image;
+use super::{cfa, demosaic, simd, Crop, Demosaic, Image, Region, Sample};
+
+// How far past its edge debayer_region looks. AHD's homogeneity window is
+// the widest reach any of the demosaics have, and this covers it.
+const REGION_MARGIN: usize = 8;
 
 impl<T: Copy + Clone> Image<T, BayerRgb> {
-	/// Crops the raw image down, removing parts we're supposed to.
+	/// Crops the raw image down to the active area, removing the parts of
+	/// the sensor that didn't see any light.
 	///
 	/// A camera may cover part of a sensor to gather black level information
 	/// or noise information, and this function removes those parts so we can
 	/// get just the image itself
 	pub fn crop(&mut self) {
-		let crop = if let Some(crop) = self.metadata.crop.as_ref() {
-			*crop
-		} else {
-			return;
-		};
+		if let Some(area) = self.metadata.active_area.take() {
+			self.crop_edges(area);
+		}
+	}
 
-		let new_width = self.width - (crop.left + crop.right);
-		let new_height = self.height - (crop.top + crop.bottom);
-		let new_size = new_width * new_height;
+	/// Crops to the manufacturer's default crop. If the active area hasn't
+	/// been cropped to yet it will be first.
+	pub fn crop_default(&mut self) {
+		self.crop();
 
-		//TODO: gen- we do not need to allocate again here. We, in theory, can
-		// do this all in the already existing vec
-		let mut image = Vec::with_capacity(new_size);
-		for row in 0..new_height {
-			let row_x = row + crop.top;
+		if let Some(crop) = self.metadata.default_crop.take() {
+			self.crop_edges(crop);
+		}
+	}
 
-			let start = row_x * self.width + crop.left;
-			let end = start + new_width;
-			image.extend_from_slice(&self.data[start..end]);
+	/// Crop the mosaic to `region`, shifting the CFA pattern so the colours
+	/// still line up. Like the other crops it happens in the buffer the
+	/// image already has, nothing is allocated.
+	///
+	/// # Panics
+	/// If the region doesn't fit in the image.
+	pub fn crop_to(&mut self, region: Region) {
+		self.crop_region(region);
+		self.metadata.cfa = self.metadata.cfa.shift(region.x, region.y);
+	}
+
+	/// A copy of just `region`, with the CFA pattern shifted to match. For
+	/// when you want to work on part of the sensor, like a zoomed in view,
+	/// and keep the whole thing around.
+	///
+	/// # Panics
+	/// If the region doesn't fit in the image.
+	pub fn view(&self, region: Region) -> Image<T, BayerRgb> {
+		assert!(
+			region.x + region.width <= self.width && region.y + region.height <= self.height,
+			"the view needs to be inside the image"
+		);
+
+		let mut data = Vec::with_capacity(region.width * region.height);
+		for y in region.y..region.y + region.height {
+			let start = y * self.width + region.x;
+			data.extend_from_slice(&self.data[start..start + region.width]);
 		}
 
-		self.width = new_width;
-		self.height = new_height;
-		self.data = image;
-		self.metadata.crop = None;
-		self.metadata.cfa = self.metadata.cfa.shift(crop.left, crop.top);
+		let mut metadata = self.metadata.clone();
+		metadata.cfa = metadata.cfa.shift(region.x, region.y);
+		Image {
+			width: region.width,
+			height: region.height,
+			metadata,
+			data,
+			phantom: Default::default(),
+		}
 	}
 
-	fn color_at_i(&self, i: usize) -> CfaColor {
-		CfaColor::from(self.metadata.cfa.color_at(i % self.width, i / self.width))
+	/// A crop bigger than the image is metadata we can't make sense of, so
+	/// it's skipped
+	fn crop_edges(&mut self, crop: Crop) {
+		if let Some(region) = crop.region(self.width, self.height) {
+			self.crop_to(region);
+		}
 	}
 
-	pub fn debayer(self) -> Image<T, LinRgb> {
-		let mut rgb = vec![self.data[0]; self.width * self.height * 3];
+	fn color_at_i(&self, i: usize) -> CfaColor {
+		CfaColor::from(self.metadata.cfa.color_at(i / self.width, i % self.width))
+	}
 
-		let cfa = self.metadata.cfa.clone();
-		let mut rr = RollingRandom::new();
+	/// Debayer with NearestRandom into a buffer you provide. `rgb` is
+	/// resized to fit.
+	fn debayer_random(
+		width: usize,
+		height: usize,
+		cfa: &CFA,
+		data: &[T],
+		seed: u64,
+		rgb: &mut Vec<T>,
+	) where
+		T: Send + Sync,
+	{
+		rgb.clear();
+		rgb.resize(width * height * 3, data[0]);
 
 		#[rustfmt::skip]
 		let top_options = [
@@ -92,180 +140,358 @@ impl<T: Copy + Clone> Image<T, BayerRgb> {
 		let bottomleft_options = [(0, -1), (1, -1), (1, 0)];
 		let bottomright_options = [(-1, -1), (0, -1), (-1, 0)];
 
+		// The edges are done after the middle, on one thread, with the
+		// numbers after the last row's
+		let mut rr = RollingRandom::new(seed, height as u64);
+
 		// These used to be closures but rustc was mad about two mutable refs on rgb
 		macro_rules! row {
 			($range:expr, $opt:expr) => {
-				Self::debayer_meat(
-					self.width,
-					&mut rgb,
-					&cfa,
-					&mut rr,
-					self.data.as_slice(),
-					$range,
-					$opt,
-				);
+				for idx in $range {
+					pixel!(idx, $opt);
+				}
 			};
 		}
 
 		macro_rules! pixel {
 			($idx:expr, $opt:expr) => {
-				Self::debayer_inner(
-					self.width,
-					&mut rgb,
-					&cfa,
-					&mut rr,
-					self.data.as_slice(),
-					$idx,
-					$opt,
-				)
+				let (x, y) = ($idx % width, $idx / width);
+				let px = Self::debayer_pixel(width, cfa, &mut rr, data, x, y, $opt);
+				rgb[$idx * 3..$idx * 3 + 3].copy_from_slice(&px);
 			};
 		}
 
 		//TODO: gen- care about the edges of the image
-		// We're staying away from the borders for now so we can handle them special later
-		for y in 1..self.height - 1 {
-			let range_start = self.width * y + 1;
-			let range_end = self.width * y + (self.width - 1);
-			let range = range_start..range_end;
-			row!(range, &center_options);
-		}
+		// We're staying away from the borders for now so we can handle them special later.
+		// The middle is nearly all of it, so that's done a row at a time on
+		// rayon, each row with its own random numbers. They're picked by the
+		// row, not by whichever thread gets to it, so it comes out the same.
+		rgb.par_chunks_exact_mut(width * 3)
+			.enumerate()
+			.take(height - 1)
+			.skip(1)
+			.for_each(|(y, row)| {
+				let mut rr = RollingRandom::new(seed, y as u64);
+				for x in 1..width - 1 {
+					let px = Self::debayer_pixel(width, cfa, &mut rr, data, x, y, &center_options);
+					row[x * 3..x * 3 + 3].copy_from_slice(&px);
+				}
+			});
 
 		// Top
-		row!(1..self.width - 1, &top_options);
+		row!(1..width - 1, &top_options);
 
 		// Bottom
 		row!(
-			(self.width * (self.height - 1)) + 1..(self.width * self.height) - 1,
+			(width * (height - 1)) + 1..(width * height) - 1,
 			&bottom_options
 		);
 
-		for y in 1..self.height - 1 {
+		for y in 1..height - 1 {
 			//left
-			pixel!(self.width * y, &left_options);
+			pixel!(width * y, &left_options);
 
 			//Right
-			pixel!(self.width * (y + 1) - 1, &right_options);
+			pixel!(width * (y + 1) - 1, &right_options);
 		}
 
 		pixel!(0, &topleft_options);
-		pixel!(self.width - 1, &topright_options);
-		pixel!(self.width * (self.height - 1), &bottomleft_options);
-		pixel!(self.width * self.height - 1, &bottomright_options);
-
-		Image {
-			width: self.width,
-			height: self.height,
-			metadata: self.metadata,
-			data: rgb,
-			phantom: Default::default(),
-		}
-	}
-
-	/// This is a poorly named function.
-	#[inline]
-	fn debayer_meat(
-		width: usize,
-		rgb: &mut Vec<T>,
-		cfa: &CFA,
-		rr: &mut RollingRandom,
-		bayer: &[T],
-		range: Range<usize>,
-		options: &[(isize, isize)],
-	) {
-		for idx in range {
-			Self::debayer_inner(width, rgb, cfa, rr, bayer, idx, options)
-		}
+		pixel!(width - 1, &topright_options);
+		pixel!(width * (height - 1), &bottomleft_options);
+		pixel!(width * height - 1, &bottomright_options);
 	}
 
+	/// Pick the colours for the pixel at `(x, y)` from a random neighbour
+	/// in `options` of each colour
 	#[inline]
-	fn debayer_inner(
+	fn debayer_pixel(
 		width: usize,
-		rgb: &mut Vec<T>,
 		cfa: &CFA,
 		rr: &mut RollingRandom,
 		bayer: &[T],
-		idx: usize,
+		x: usize,
+		y: usize,
 		options: &[(isize, isize)],
-	) {
+	) -> [T; 3] {
 		let get = |p: (usize, usize)| -> T { bayer[width * p.1 + p.0] };
-		let mut set = |x: usize, y: usize, clr: CfaColor, v: T| {
-			rgb[(width * y + x) * LinRgb::COMPONENTS + clr.rgb_index()] = v;
-		};
-
-		let y = idx / width;
-		let x = idx % width;
+		let mut rgb = [bayer[width * y + x]; 3];
+		let mut set = |clr: CfaColor, v: T| rgb[clr.rgb_index()] = v;
 
-		let options = options.clone().into_iter().map(|(x_off, y_off)| {
+		let options = options.iter().map(|(x_off, y_off)| {
 			let x = (x as isize + x_off) as usize;
 			let y = (y as isize + y_off) as usize;
-			(CfaColor::from(cfa.color_at(x, y)), x, y)
+			(CfaColor::from(cfa.color_at(y, x)), x, y)
 		});
 
-		match CfaColor::from(cfa.color_at(x, y)) {
+		match CfaColor::from(cfa.color_at(y, x)) {
 			#[rustfmt::skip]
 				CfaColor::Red => {
-					set(x, y, CfaColor::Red, get((x, y)));
-					set(x, y, CfaColor::Green, get(pick_color(rr, options.clone(), CfaColor::Green)));
-					set(x, y, CfaColor::Blue, get(pick_color(rr, options.clone(), CfaColor::Blue)));
+					set(CfaColor::Green, get(pick_color(rr, options.clone(), CfaColor::Green)));
+					set(CfaColor::Blue, get(pick_color(rr, options.clone(), CfaColor::Blue)));
 				}
 			#[rustfmt::skip]
 				CfaColor::Blue => {
-					set(x, y, CfaColor::Red, get(pick_color(rr, options.clone(), CfaColor::Red)));
-					set(x, y, CfaColor::Blue, get((x, y)));
-					set(x, y, CfaColor::Green, get(pick_color(rr, options.clone(), CfaColor::Green)));
+					set(CfaColor::Red, get(pick_color(rr, options.clone(), CfaColor::Red)));
+					set(CfaColor::Green, get(pick_color(rr, options.clone(), CfaColor::Green)));
 				}
 			#[rustfmt::skip]
-				CfaColor::Green => {
-					set(x, y, CfaColor::Red, get(pick_color(rr, options.clone(), CfaColor::Red)));
-					set(x, y, CfaColor::Blue, get(pick_color(rr, options.clone(), CfaColor::Blue)));
-					set(x, y, CfaColor::Green, get((x, y)));
+				CfaColor::Green | CfaColor::Emerald => {
+					set(CfaColor::Red, get(pick_color(rr, options.clone(), CfaColor::Red)));
+					set(CfaColor::Blue, get(pick_color(rr, options.clone(), CfaColor::Blue)));
 				}
-			CfaColor::Emerald => unreachable!(),
+		}
+
+		rgb
+	}
+}
+
+impl<T: Sample> Image<T, BayerRgb> {
+	/// Debayer with the default, [Demosaic::Bilinear].
+	pub fn debayer(self) -> Image<T, LinRgb> {
+		self.debayer_with(Demosaic::default())
+	}
+
+	/// Debayer with whichever [Demosaic] you like, trading speed for quality.
+	pub fn debayer_with(self, demosaic: Demosaic) -> Image<T, LinRgb> {
+		self.debayer_with_into(demosaic, vec![])
+	}
+
+	/// Debayer into a buffer you provide, like one from a
+	/// [BufferPool](crate::pool::BufferPool). It's resized as needed so it
+	/// doesn't matter what's in it, but if it's already big enough we don't
+	/// allocate for the output. Bilinear and AHD still need float scratch
+	/// space to work in.
+	pub fn debayer_into(self, rgb: Vec<T>) -> Image<T, LinRgb> {
+		self.debayer_with_into(Demosaic::default(), rgb)
+	}
+
+	/// [debayer_with](Self::debayer_with) and
+	/// [debayer_into](Self::debayer_into) at once.
+	pub fn debayer_with_into(self, demosaic: Demosaic, mut rgb: Vec<T>) -> Image<T, LinRgb> {
+		Self::debayer_data(
+			self.width,
+			self.height,
+			&self.metadata.cfa,
+			&self.data,
+			demosaic,
+			&mut rgb,
+		);
+
+		Image {
+			width: self.width,
+			height: self.height,
+			metadata: self.metadata,
+			data: rgb,
+			phantom: Default::default(),
 		}
 	}
+
+	/// Debayer just `region`, for when that's all you're going to look at.
+	/// A few pixels around it are debayered too, and then cut off, so the
+	/// edges come out the same as they would in a full debayer.
+	///
+	/// Errors with [Error::RegionOutOfBounds] if the region doesn't fit in
+	/// the image.
+	pub fn debayer_region(
+		&self,
+		region: Region,
+		demosaic: Demosaic,
+	) -> Result<Image<T, LinRgb>, Error> {
+		if !region.fits(self.width, self.height) {
+			return Err(Error::RegionOutOfBounds {
+				width: self.width,
+				height: self.height,
+			});
+		}
+
+		let x = region.x.saturating_sub(REGION_MARGIN);
+		let y = region.y.saturating_sub(REGION_MARGIN);
+		let padded = Region {
+			x,
+			y,
+			width: (region.x + region.width + REGION_MARGIN).min(self.width) - x,
+			height: (region.y + region.height + REGION_MARGIN).min(self.height) - y,
+		};
+
+		let mut rgb = self.view(padded).debayer_with(demosaic);
+		rgb.crop_region(Region {
+			x: region.x - x,
+			y: region.y - y,
+			..region
+		});
+		Ok(rgb)
+	}
+
+	/// A quick, half size, debayer. Every 2x2 square of the mosaic becomes one
+	/// pixel, its red and blue as they are and its greens averaged, like
+	/// dcraw's `-h`. Nothing's interpolated so it's much faster than a full
+	/// debayer, and a quarter of the pixels, which makes it great for
+	/// previews and thumbnails. An odd row or column on the edge is dropped.
+	///
+	/// X-Trans doesn't have every colour in every 2x2 square, so those get a
+	/// bilinear debayer and are then shrunk.
+	pub fn debayer_half(self) -> Image<T, LinRgb> {
+		let (width, height) = (self.width / 2, self.height / 2);
+		let mut metadata = self.metadata;
+		let halve = |crop: Crop| Crop {
+			top: crop.top / 2,
+			right: crop.right / 2,
+			bottom: crop.bottom / 2,
+			left: crop.left / 2,
+		};
+		metadata.active_area = metadata.active_area.map(halve);
+		metadata.default_crop = metadata.default_crop.map(halve);
+
+		// A mosaic one pixel across has no 2x2 squares at all
+		if width == 0 || height == 0 {
+			return Image {
+				width,
+				height,
+				metadata,
+				data: vec![],
+				phantom: Default::default(),
+			};
+		}
+
+		let cfa = &metadata.cfa;
+		let mut data = vec![T::from_f32(0.0); width * height * 3];
+		if demosaic::is_bayer(cfa) {
+			let src = &self.data;
+			data.par_chunks_exact_mut(width * 3)
+				.enumerate()
+				.for_each(|(y, row)| {
+					for (x, rgb) in row.chunks_exact_mut(3).enumerate() {
+						let mut sums = [0.0f32; 3];
+						let mut counts = [0.0f32; 3];
+						for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+							let (sx, sy) = (x * 2 + dx, y * 2 + dy);
+							let c = cfa::channel(cfa.color_at(sy, sx));
+							sums[c] += src[sy * self.width + sx].to_f32();
+							counts[c] += 1.0;
+						}
+
+						for c in 0..3 {
+							rgb[c] = T::from_f32(sums[c] / counts[c].max(1.0));
+						}
+					}
+				});
+		} else {
+			let full_width = self.width;
+			let mut full = vec![];
+			Self::debayer_data(
+				self.width,
+				self.height,
+				cfa,
+				&self.data,
+				Demosaic::Bilinear,
+				&mut full,
+			);
+
+			data.par_chunks_exact_mut(width * 3)
+				.enumerate()
+				.for_each(|(y, row)| {
+					for (x, rgb) in row.chunks_exact_mut(3).enumerate() {
+						for (c, v) in rgb.iter_mut().enumerate() {
+							let at = |dx: usize, dy: usize| {
+								full[((y * 2 + dy) * full_width + x * 2 + dx) * 3 + c].to_f32()
+							};
+							*v = T::from_f32((at(0, 0) + at(1, 0) + at(0, 1) + at(1, 1)) / 4.0);
+						}
+					}
+				});
+		}
+
+		Image {
+			width,
+			height,
+			metadata,
+			data,
+			phantom: Default::default(),
+		}
+	}
+
+	/// Debayer without needing an Image to own the data. This is what lets
+	/// a [SharedImage](super::SharedImage) debayer without copying first.
+	pub(crate) fn debayer_data(
+		width: usize,
+		height: usize,
+		cfa: &CFA,
+		data: &[T],
+		demosaic: Demosaic,
+		rgb: &mut Vec<T>,
+	) {
+		// AHD and NearestRandom only know Bayer. Bilinear takes anything.
+		if demosaic != Demosaic::Bilinear {
+			if let Some((bayer, mosaic)) = cfa::remosaic_data(width, height, cfa, data) {
+				return Self::debayer_data(width, height, &bayer, &mosaic, demosaic, rgb);
+			}
+		}
+
+		let bayer = demosaic::is_bayer(cfa);
+		let algorithm = match demosaic {
+			Demosaic::NearestRandom { seed } if bayer => {
+				return Self::debayer_random(width, height, cfa, data, seed, rgb);
+			}
+			Demosaic::Ahd if bayer => demosaic::ahd,
+			Demosaic::Ahd => demosaic::xtrans,
+			Demosaic::Bilinear | Demosaic::NearestRandom { .. } => demosaic::bilinear,
+		};
+
+		let floats: Vec<f32> = data.iter().map(|v| v.to_f32()).collect();
+		let interpolated = algorithm(width, height, cfa, &floats);
+
+		rgb.clear();
+		rgb.extend(interpolated.into_iter().map(T::from_f32));
+	}
 }
 
 impl Image<f32, BayerRgb> {
 	pub fn whitebalance(&mut self) {
 		let wb = self.metadata.whitebalance;
-		for (i, light) in self.data.iter_mut().enumerate() {
-			match CfaColor::from(self.metadata.cfa.color_at(i % self.width, i / self.width)) {
-				CfaColor::Red => *light = *light as f32 * wb[0],
-				CfaColor::Green => *light = *light as f32 * wb[1],
-				CfaColor::Blue => *light = *light as f32 * wb[2],
-				CfaColor::Emerald => unreachable!(),
-			}
-		}
+		let cfa = &self.metadata.cfa;
+		// The CFA repeats, so there's only one pattern for each of its rows
+		let patterns: Vec<Vec<f32>> = (0..cfa.height.max(1))
+			.map(|y| {
+				(0..cfa.width)
+					.map(|x| wb[CfaColor::from(cfa.color_at(y, x)).rgb_index()])
+					.collect()
+			})
+			.collect();
+
+		self.data
+			.par_chunks_mut(self.width.max(1))
+			.enumerate()
+			.for_each(|(y, row)| simd::scale_row(row, &patterns[y % patterns.len()]));
 	}
 }
 
 impl Image<u16, BayerRgb> {
 	pub fn whitebalance(&mut self) {
 		let wb = self.metadata.whitebalance;
-		for (i, light) in self.data.iter_mut().enumerate() {
-			/*match CfaColor::from(self.metadata.cfa.color_at(i % self.width, i / self.width)) {
+		let (width, cfa) = (self.width, &self.metadata.cfa);
+		self.data.par_iter_mut().enumerate().for_each(|(i, light)| {
+			/*match CfaColor::from(cfa.color_at(i / width, i % width)) {
 				CfaColor::Red => *light = (*light as f32 * wb[0]) as u16,
 				CfaColor::Green => *light = (*light as f32 * wb[1]) as u16,
 				CfaColor::Blue => *light = (*light as f32 * wb[2]) as u16,
 				CfaColor::Emerald => unreachable!(),
 			}*/
-			*light = (*light as f32
-				* wb[self.metadata.cfa.color_at(i % self.width, i / self.width)]) as u16;
-		}
+			*light = (*light as f32 * wb[cfa::channel(cfa.color_at(i / width, i % width))]) as u16;
+		});
 	}
 }
 
 impl Image<u8, BayerRgb> {
 	pub fn whitebalance(&mut self) {
 		let wb = self.metadata.whitebalance;
-		for (i, light) in self.data.iter_mut().enumerate() {
-			match CfaColor::from(self.metadata.cfa.color_at(i % self.width, i / self.width)) {
+		let (width, cfa) = (self.width, &self.metadata.cfa);
+		self.data.par_iter_mut().enumerate().for_each(|(i, light)| {
+			match CfaColor::from(cfa.color_at(i / width, i % width)) {
 				CfaColor::Red => *light = (*light as f32 * wb[0]) as u8,
-				CfaColor::Green => *light = (*light as f32 * wb[1]) as u8,
+				CfaColor::Green | CfaColor::Emerald => *light = (*light as f32 * wb[1]) as u8,
 				CfaColor::Blue => *light = (*light as f32 * wb[2]) as u8,
-				CfaColor::Emerald => unreachable!(),
 			}
-		}
+		});
 	}
 }
 
@@ -274,8 +500,9 @@ fn pick_color<I>(roll: &mut RollingRandom, options: I, color: CfaColor) -> (usiz
 where
 	I: Iterator<Item = (CfaColor, usize, usize)>,
 {
-	let colors: Vec<(CfaColor, usize, usize)> =
-		options.filter(|(clr, _, _)| *clr == color).collect();
+	let colors: Vec<(CfaColor, usize, usize)> = options
+		.filter(|(clr, _, _)| clr.rgb_index() == color.rgb_index())
+		.collect();
 	let random = roll.random_u8() % colors.len() as u8;
 	let red = &colors[random as usize];
 
@@ -283,7 +510,7 @@ where
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
-enum CfaColor {
+pub(super) enum CfaColor {
 	Red,
 	Green,
 	Blue,
@@ -291,12 +518,13 @@ enum CfaColor {
 }
 
 impl CfaColor {
+	/// Emerald is rawloader's fourth colour, which is yellow on an RYYB
+	/// sensor. It goes in green either way, see [CfaColors](super::CfaColors).
 	pub fn rgb_index(&self) -> usize {
 		match self {
 			CfaColor::Red => 0,
-			CfaColor::Green => 1,
+			CfaColor::Green | CfaColor::Emerald => 1,
 			CfaColor::Blue => 2,
-			CfaColor::Emerald => unreachable!(),
 		}
 	}
 }
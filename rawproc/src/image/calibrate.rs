@@ -0,0 +1,132 @@
+//! Calibration frames, for astrophotography and anything else where the
+//! sensor's own quirks matter. A dark frame is the noise and glow the sensor
+//! makes on its own, shot with the cap on. A flat frame is how unevenly light
+//! reaches it, vignetting and dust, shot of something evenly lit. Both happen
+//! before demosaicing, while every value is still one photosite.
+
+use crate::{colorspace::BayerRgb, par::*};
+
+use super::{cfa, Image, Sample};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CalibrationError {
+	#[error("The calibration frame is {found:?} but the image is {expected:?}")]
+	SizeMismatch {
+		expected: (usize, usize),
+		found: (usize, usize),
+	},
+	#[error("The calibration frame's CFA is {found} but the image's is {expected}")]
+	CfaMismatch { expected: String, found: String },
+}
+
+impl<T: Sample> Image<T, BayerRgb> {
+	/// Take a master dark away from this image. What's taken away is the
+	/// dark's signal above its own black level, so this image keeps its
+	/// black level and nothing from a dark that read under black gets added
+	/// back. Integer values stop at 0.
+	///
+	/// The dark should be shot at the same exposure time, ISO, and sensor
+	/// temperature, and cropped the same, or it won't line up.
+	pub fn subtract_dark(&mut self, dark: &Image<T, BayerRgb>) -> Result<(), CalibrationError> {
+		self.check_frame(dark)?;
+
+		let dark_black = dark.metadata.blacklevels.map(|b| b as f32);
+		let (width, cfa) = (self.width, &dark.metadata.cfa);
+		self.data
+			.par_iter_mut()
+			.zip(dark.data.par_iter())
+			.enumerate()
+			.for_each(|(idx, (value, dark))| {
+				let c = cfa::channel(cfa.color_at(idx / width, idx % width));
+				let signal = (dark.to_f32() - dark_black[c]).max(0.0);
+				*value = T::from_f32(value.to_f32() - signal);
+			});
+
+		Ok(())
+	}
+
+	/// Divide this image by a master flat, evening out vignetting and dust.
+	/// The flat is scaled so each of its colours averages to 1.0, which
+	/// keeps the image about as bright as it was. Photosites where the flat
+	/// saw nothing are left alone instead of being blown up to infinity.
+	///
+	/// Subtract the dark first. The flat should already have had its own
+	/// dark, or bias, taken away.
+	pub fn divide_flat(&mut self, flat: &Image<T, BayerRgb>) -> Result<(), CalibrationError> {
+		self.check_frame(flat)?;
+
+		let black = self.metadata.blacklevels.map(|b| b as f32);
+		let flat_black = flat.metadata.blacklevels.map(|b| b as f32);
+		let (width, cfa) = (self.width, &flat.metadata.cfa);
+		let signal = |idx: usize, value: &T| {
+			let c = cfa::channel(cfa.color_at(idx / width, idx % width));
+			(c, value.to_f32() - flat_black[c])
+		};
+
+		let (sums, counts) = flat
+			.data
+			.par_iter()
+			.enumerate()
+			.map(|(idx, value)| signal(idx, value))
+			.fold(
+				|| ([0.0f64; 3], [0u64; 3]),
+				|(mut sums, mut counts), (c, v)| {
+					sums[c] += v as f64;
+					counts[c] += 1;
+					(sums, counts)
+				},
+			)
+			.reduce(
+				|| ([0.0; 3], [0; 3]),
+				|a, b| {
+					(
+						[0, 1, 2].map(|c| a.0[c] + b.0[c]),
+						[0, 1, 2].map(|c| a.1[c] + b.1[c]),
+					)
+				},
+			);
+		let means = [0, 1, 2].map(|c| (sums[c] / counts[c].max(1) as f64) as f32);
+
+		self.data
+			.par_iter_mut()
+			.zip(flat.data.par_iter())
+			.enumerate()
+			.for_each(|(idx, (value, flat))| {
+				let (c, flat) = signal(idx, flat);
+				if flat <= 0.0 || means[c] <= 0.0 {
+					return;
+				}
+
+				let gain = means[c] / flat;
+				*value = T::from_f32(black[c] + (value.to_f32() - black[c]) * gain);
+			});
+
+		Ok(())
+	}
+
+	/// A calibration frame has to be the same size as we are, with the same
+	/// colours in the same places
+	fn check_frame(&self, frame: &Image<T, BayerRgb>) -> Result<(), CalibrationError> {
+		if (self.width, self.height) != (frame.width, frame.height) {
+			return Err(CalibrationError::SizeMismatch {
+				expected: (self.width, self.height),
+				found: (frame.width, frame.height),
+			});
+		}
+
+		let (ours, theirs) = (&self.metadata.cfa, &frame.metadata.cfa);
+		let same = ours.width == theirs.width
+			&& ours.height == theirs.height
+			&& (0..ours.height).all(|row| {
+				(0..ours.width).all(|col| ours.color_at(row, col) == theirs.color_at(row, col))
+			});
+		if !same {
+			return Err(CalibrationError::CfaMismatch {
+				expected: ours.name.clone(),
+				found: theirs.name.clone(),
+			});
+		}
+
+		Ok(())
+	}
+}
@@ -0,0 +1,268 @@
+//! Colour filter arrays past plain Bayer. Quad Bayer sensors, in a lot of
+//! phones and drones, have a Bayer pattern of 2x2 blocks that are each one
+//! colour, so they can bin down to a cleaner half size image in the dark.
+//! Huawei's RYYB sensors swap green for yellow, which lets more light in.
+//!
+//! rawloader's CFA is what everything uses, and it works its size out from
+//! the length of the pattern, so a 4x4 Quad Bayer pattern is tiled up to
+//! 12x12, which it does know, by [cfa_from_pattern]. It also only has four
+//! colours, and yellow is the same fourth colour as emerald.
+
+use rawloader::CFA;
+
+use crate::{colorspace::BayerRgb, par::*, Error};
+
+use super::{demosaic, Crop, Image, Sample};
+
+/// What shape of pattern a CFA is
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CfaLayout {
+	/// 2x2, the one nearly every camera has
+	Bayer,
+	/// A Bayer pattern of 2x2 blocks of the same colour
+	QuadBayer,
+	/// Fujifilm's 6x6
+	XTrans,
+	/// Anything else. Bilinear works on any pattern, so it gets that.
+	Other,
+}
+
+impl CfaLayout {
+	pub fn of(cfa: &CFA) -> Self {
+		match (cfa.width, cfa.height) {
+			(2, 2) => CfaLayout::Bayer,
+			(6, 6) => CfaLayout::XTrans,
+			_ if Quad::of(cfa).is_some() => CfaLayout::QuadBayer,
+			_ => CfaLayout::Other,
+		}
+	}
+}
+
+/// Which colours the filters are. We only ever have three channels, so a
+/// fourth colour has to go in one of them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CfaColors {
+	/// Red, green, and blue
+	Rgb,
+	/// Red, green, blue, and emerald, like Sony's F828. Emerald is close
+	/// enough to green that it goes in with it.
+	Rgbe,
+	/// Red, yellow, and blue, like Huawei's RYYB sensors. Yellow goes in the
+	/// middle channel, where green would be. That's camera RGB, the camera's
+	/// own primaries, and the camera matrix turns it into real colour like it
+	/// does any other camera's.
+	Ryb,
+}
+
+impl CfaColors {
+	pub fn of(cfa: &CFA) -> Self {
+		let colors: Vec<usize> = (0..cfa.height)
+			.flat_map(|row| (0..cfa.width).map(move |col| cfa.color_at(row, col)))
+			.collect();
+
+		match (colors.contains(&1), colors.contains(&3)) {
+			(_, false) => CfaColors::Rgb,
+			(true, true) => CfaColors::Rgbe,
+			(false, true) => CfaColors::Ryb,
+		}
+	}
+}
+
+/// Build a CFA from its pattern, row by row, in the letters rawloader uses:
+/// R, G, B, E or Y for the fourth colour, and M, which it takes as green.
+/// Sizes rawloader doesn't know are tiled up to 12x12, so any pattern that
+/// fits into that evenly works. None if it doesn't, or has a colour we don't
+/// know.
+pub fn cfa_from_pattern(pattern: &str, width: usize, height: usize) -> Option<CFA> {
+	if width == 0 || height == 0 || pattern.len() != width * height {
+		return None;
+	}
+	if !pattern.bytes().all(|c| b"RGBEYM".contains(&c)) {
+		return None;
+	}
+
+	// What rawloader decides the size is from the length
+	let known = match pattern.len() {
+		4 => (2, 2),
+		36 => (6, 6),
+		16 => (2, 8),
+		144 => (12, 12),
+		_ => (0, 0),
+	};
+	if known == (width, height) {
+		return Some(CFA::new(pattern));
+	}
+
+	if 12 % width != 0 || 12 % height != 0 {
+		return None;
+	}
+	let tiled: String = (0..12)
+		.map(|row| &pattern[(row % height) * width..][..width])
+		.flat_map(|row| std::iter::repeat_n(row, 12 / width))
+		.collect();
+	Some(CFA::new(&tiled))
+}
+
+/// Which of red, green, or blue a CFA colour goes in. The fourth colour,
+/// emerald or yellow, goes in green. See [CfaColors].
+pub(crate) fn channel(color: usize) -> usize {
+	match color {
+		0..=2 => color,
+		_ => 1,
+	}
+}
+
+/// Where a Quad Bayer pattern's blocks start, and the Bayer pattern they
+/// make. After an odd crop the blocks don't start at the corner.
+#[derive(Copy, Clone, Debug)]
+struct Quad {
+	x: usize,
+	y: usize,
+	colors: [usize; 4],
+}
+
+impl Quad {
+	fn of(cfa: &CFA) -> Option<Self> {
+		if !cfa.width.is_multiple_of(4) || !cfa.height.is_multiple_of(4) {
+			return None;
+		}
+
+		// It has to repeat every four pixels, not just every twelve
+		let repeats = (0..cfa.height).all(|row| {
+			(0..cfa.width).all(|col| cfa.color_at(row, col) == cfa.color_at(row % 4, col % 4))
+		});
+		if !repeats {
+			return None;
+		}
+
+		[(0, 0), (1, 0), (0, 1), (1, 1)]
+			.into_iter()
+			.map(|(x, y)| Quad {
+				x,
+				y,
+				colors: [(0, 0), (2, 0), (0, 2), (2, 2)]
+					.map(|(bx, by)| cfa.color_at(y + by, x + bx)),
+			})
+			.find(|quad| quad.blocks(cfa) && quad.is_bayer())
+	}
+
+	/// Whether every block is all one colour
+	fn blocks(&self, cfa: &CFA) -> bool {
+		(0..4).all(|row| {
+			(0..4).all(|col| {
+				let block = (row / 2) * 2 + col / 2;
+				cfa.color_at(self.y + row, self.x + col) == self.colors[block]
+			})
+		})
+	}
+
+	/// Whether the blocks have a red, a blue, and a green on each diagonal
+	fn is_bayer(&self) -> bool {
+		let [a, b, c, d] = self.colors.map(channel);
+		let mut sorted = [a, b, c, d];
+		sorted.sort();
+
+		sorted == [0, 1, 1, 2] && (a == d || b == c)
+	}
+
+	/// The Bayer pattern of the blocks, as if each were one pixel
+	fn bayer(&self) -> CFA {
+		let name: String = self
+			.colors
+			.iter()
+			.map(|c| ['R', 'G', 'B', 'E'][*c])
+			.collect();
+		CFA::new(&name)
+	}
+}
+
+impl<T: Sample> Image<T, BayerRgb> {
+	/// Average each 2x2 block of a Quad Bayer mosaic into one pixel, which is
+	/// what the camera does itself in low light. The result is an ordinary
+	/// Bayer mosaic half as wide and tall, with a quarter of the noise, to
+	/// whitebalance and debayer like any other. An odd row or column left
+	/// over on the edge is dropped.
+	///
+	/// Errors with [Error::NotQuadBayer] if it's any other pattern.
+	pub fn bin_quad(self) -> Result<Image<T, BayerRgb>, Error> {
+		let quad = Quad::of(&self.metadata.cfa)
+			.ok_or_else(|| Error::NotQuadBayer(self.metadata.cfa.name.clone()))?;
+
+		let (width, height) = ((self.width - quad.x) / 2, (self.height - quad.y) / 2);
+		let mut data = vec![T::from_f32(0.0); width * height];
+		data.par_chunks_exact_mut(width.max(1))
+			.enumerate()
+			.for_each(|(y, row)| {
+				for (x, out) in row.iter_mut().enumerate() {
+					let (sx, sy) = (quad.x + x * 2, quad.y + y * 2);
+					let at =
+						|dx: usize, dy: usize| self.data[(sy + dy) * self.width + sx + dx].to_f32();
+					*out = T::from_f32((at(0, 0) + at(1, 0) + at(0, 1) + at(1, 1)) / 4.0);
+				}
+			});
+
+		let mut metadata = self.metadata;
+		let halve = |crop: Crop| Crop {
+			top: crop.top.saturating_sub(quad.y) / 2,
+			right: crop.right / 2,
+			bottom: crop.bottom / 2,
+			left: crop.left.saturating_sub(quad.x) / 2,
+		};
+		metadata.active_area = metadata.active_area.map(halve);
+		metadata.default_crop = metadata.default_crop.map(halve);
+		metadata.cfa = quad.bayer();
+
+		Ok(Image::from_raw_parts(width, height, metadata, data))
+	}
+
+	/// Rearrange a Quad Bayer mosaic into an ordinary Bayer one the same
+	/// size, so it can go through demosaics that only know Bayer, like
+	/// [Ahd](super::Demosaic::Ahd). Each pixel keeps its own sample where the
+	/// colours line up and gets a bilinear guess where they don't, so it's
+	/// not as sharp as a real remosaic, but it's a lot better than feeding a
+	/// Quad Bayer pattern to a Bayer demosaic.
+	///
+	/// [debayer](Image::debayer) does this itself when it needs to. Errors
+	/// with [Error::NotQuadBayer] if it's any other pattern.
+	pub fn remosaic(self) -> Result<Image<T, BayerRgb>, Error> {
+		let (cfa, data) = remosaic_data(self.width, self.height, &self.metadata.cfa, &self.data)
+			.ok_or_else(|| Error::NotQuadBayer(self.metadata.cfa.name.clone()))?;
+
+		let mut metadata = self.metadata;
+		metadata.cfa = cfa;
+		Ok(Image::from_raw_parts(
+			self.width,
+			self.height,
+			metadata,
+			data,
+		))
+	}
+}
+
+/// [remosaic](Image::remosaic) on bare data, for
+/// [debayer_data](Image::debayer_data). None if it isn't Quad Bayer.
+pub(super) fn remosaic_data<T: Sample>(
+	width: usize,
+	height: usize,
+	cfa: &CFA,
+	data: &[T],
+) -> Option<(CFA, Vec<T>)> {
+	let bayer = Quad::of(cfa)?.bayer();
+
+	let floats: Vec<f32> = data.iter().map(|v| v.to_f32()).collect();
+	let rgb = demosaic::bilinear(width, height, cfa, &floats);
+
+	let mut mosaic = vec![T::from_f32(0.0); width * height];
+	mosaic
+		.par_chunks_exact_mut(width.max(1))
+		.enumerate()
+		.for_each(|(y, row)| {
+			for (x, out) in row.iter_mut().enumerate() {
+				let c = channel(bayer.color_at(y, x));
+				*out = T::from_f32(rgb[(y * width + x) * 3 + c]);
+			}
+		});
+
+	Some((bayer, mosaic))
+}
@@ -0,0 +1,193 @@
+use crate::{colorspace::LinRgb, par::*, transfer::TransferFunction};
+
+use super::Image;
+
+/// A tone curve, a smooth line through control points, like the curves
+/// tool in an editor.
+///
+/// The points are in sRGB encoded values, not linear ones, so they line up
+/// with what you see and what other software shows. The line between them
+/// is a monotone cubic spline, which is smooth but never overshoots a point:
+/// a curve that only goes up won't dip anywhere.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(try_from = "Vec<(f32, f32)>", into = "Vec<(f32, f32)>")
+)]
+pub struct ToneCurve {
+	points: Vec<(f32, f32)>,
+	tangents: Vec<f32>,
+}
+
+impl ToneCurve {
+	/// A curve through `points`, each an input and an output between 0.0 and
+	/// 1.0. They're sorted by input, and if two share an input the last one
+	/// wins. Inputs before the first point or after the last get that
+	/// point's output.
+	///
+	/// # Panics
+	/// If there are fewer than two points with different inputs.
+	pub fn new(points: &[(f32, f32)]) -> Self {
+		let mut sorted = points.to_vec();
+		sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+		let mut points: Vec<(f32, f32)> = Vec::with_capacity(sorted.len());
+		for point in sorted {
+			match points.last_mut() {
+				Some(last) if last.0 == point.0 => *last = point,
+				_ => points.push(point),
+			}
+		}
+		assert!(
+			points.len() >= 2,
+			"a tone curve needs at least two points with different inputs"
+		);
+
+		let tangents = monotone_tangents(&points);
+		Self { points, tangents }
+	}
+
+	/// The control points, sorted by input
+	pub fn points(&self) -> &[(f32, f32)] {
+		&self.points
+	}
+
+	/// The straight line. Nothing changes.
+	pub fn linear() -> Self {
+		Self::new(&[(0.0, 0.0), (1.0, 1.0)])
+	}
+
+	/// A gentle S, close to the standard picture style most cameras start
+	/// with. Darker shadows and brighter highlights than a plain sRGB
+	/// encode, which is most of why camera JPEGs look punchier.
+	pub fn camera_standard() -> Self {
+		Self::new(&[
+			(0.0, 0.0),
+			(0.25, 0.21),
+			(0.5, 0.53),
+			(0.75, 0.81),
+			(1.0, 1.0),
+		])
+	}
+
+	/// A long toe and a soft shoulder, like film. The shadows sink in slowly
+	/// and the highlights roll off instead of running into white.
+	pub fn filmic() -> Self {
+		Self::new(&[
+			(0.0, 0.0),
+			(0.1, 0.05),
+			(0.3, 0.27),
+			(0.6, 0.66),
+			(0.85, 0.9),
+			(1.0, 0.97),
+		])
+	}
+
+	/// Where `x`, an encoded value, ends up
+	pub fn eval(&self, x: f32) -> f32 {
+		let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+		if x <= first.0 {
+			return first.1;
+		} else if x >= last.0 {
+			return last.1;
+		}
+
+		// The segment x is in, so points[k].0 <= x < points[k + 1].0
+		let k = self.points.partition_point(|p| p.0 <= x) - 1;
+		let ((x0, y0), (x1, y1)) = (self.points[k], self.points[k + 1]);
+		let h = x1 - x0;
+		let t = (x - x0) / h;
+
+		// Cubic Hermite basis
+		let t2 = t * t;
+		let t3 = t2 * t;
+		let h00 = 2.0 * t3 - 3.0 * t2 + 1.0;
+		let h10 = t3 - 2.0 * t2 + t;
+		let h01 = -2.0 * t3 + 3.0 * t2;
+		let h11 = t3 - t2;
+
+		h00 * y0 + h10 * h * self.tangents[k] + h01 * y1 + h11 * h * self.tangents[k + 1]
+	}
+}
+
+impl Default for ToneCurve {
+	fn default() -> Self {
+		Self::linear()
+	}
+}
+
+// serde goes through the points, and checks them first so a bad file is an
+// error instead of a panic
+#[cfg(feature = "serde")]
+impl TryFrom<Vec<(f32, f32)>> for ToneCurve {
+	type Error = &'static str;
+
+	fn try_from(points: Vec<(f32, f32)>) -> Result<Self, Self::Error> {
+		let first = points.first().map(|p| p.0);
+		if points.iter().any(|p| Some(p.0) != first) {
+			Ok(Self::new(&points))
+		} else {
+			Err("a tone curve needs at least two points with different inputs")
+		}
+	}
+}
+
+#[cfg(feature = "serde")]
+impl From<ToneCurve> for Vec<(f32, f32)> {
+	fn from(curve: ToneCurve) -> Self {
+		curve.points
+	}
+}
+
+/// Tangents for a monotone cubic, from Fritsch and Carlson. Where the points
+/// turn around the tangent is flat, and elsewhere they're pulled in enough
+/// that the curve can't overshoot.
+fn monotone_tangents(points: &[(f32, f32)]) -> Vec<f32> {
+	let secants: Vec<f32> = points
+		.windows(2)
+		.map(|w| (w[1].1 - w[0].1) / (w[1].0 - w[0].0))
+		.collect();
+
+	let mut tangents = Vec::with_capacity(points.len());
+	tangents.push(secants[0]);
+	for w in secants.windows(2) {
+		if w[0] * w[1] <= 0.0 {
+			tangents.push(0.0);
+		} else {
+			tangents.push((w[0] + w[1]) / 2.0);
+		}
+	}
+	tangents.push(secants[secants.len() - 1]);
+
+	for (k, secant) in secants.iter().enumerate() {
+		if *secant == 0.0 {
+			tangents[k] = 0.0;
+			tangents[k + 1] = 0.0;
+			continue;
+		}
+
+		let a = tangents[k] / secant;
+		let b = tangents[k + 1] / secant;
+		let length = (a * a + b * b).sqrt();
+		if length > 3.0 {
+			let scale = 3.0 / length;
+			tangents[k] = scale * a * secant;
+			tangents[k + 1] = scale * b * secant;
+		}
+	}
+
+	tangents
+}
+
+impl Image<f32, LinRgb> {
+	/// Put every channel of every pixel through the curve. The values are
+	/// sRGB encoded for the curve and linear again after, so the image
+	/// stays linear. Anything over 1.0 is treated as 1.0, so set the
+	/// exposure first.
+	pub fn apply_curve(&mut self, curve: &ToneCurve) {
+		let tf = TransferFunction::Srgb;
+		self.data
+			.par_iter_mut()
+			.for_each(|v| *v = tf.decode(curve.eval(tf.encode(*v))));
+	}
+}
@@ -0,0 +1,396 @@
+//! Demosaicing that looks at more than one neighbour. Everything in here
+//! works on floats and hands back interleaved RGB, the conversion to and from
+//! whatever the image is stored as happens in [debayer_with].
+//!
+//! [debayer_with]: super::Image::debayer_with
+
+use rawloader::CFA;
+
+use crate::par::*;
+
+use super::{bayerrgb::CfaColor, cfa};
+
+/// How to fill in the two colours each pixel of a mosaic didn't see.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Demosaic {
+	/// Average the nearest pixels of each colour. Fast, and the same every
+	/// time, but it softens the image and leaves coloured fringes on sharp
+	/// edges.
+	#[default]
+	Bilinear,
+	/// Adaptive Homogeneity-Directed. Green is filled in both across and down,
+	/// and at every pixel it keeps whichever looks more like its neighbours,
+	/// so it follows edges instead of blurring over them. A few times slower
+	/// than bilinear, but much cleaner.
+	///
+	/// AHD is only for Bayer mosaics; an X-Trans raw gets its own
+	/// edge-following demosaic instead.
+	Ahd,
+	/// Copy a random neighbour of each colour. The fastest, but it leaves
+	/// speckles. It's what `debayer` used to do. X-Trans raws get Bilinear
+	/// instead.
+	///
+	/// The neighbours come from `seed`, so the same seed gets the same image
+	/// every time, on any number of threads. Recipes and the command line
+	/// always use 0.
+	NearestRandom { seed: u64 },
+}
+
+impl Demosaic {
+	/// What recipes and the command line call it
+	pub fn name(&self) -> &'static str {
+		match self {
+			Demosaic::Bilinear => "bilinear",
+			Demosaic::Ahd => "ahd",
+			Demosaic::NearestRandom { .. } => "nearest_random",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"bilinear" => Some(Demosaic::Bilinear),
+			"ahd" => Some(Demosaic::Ahd),
+			"nearest_random" => Some(Demosaic::NearestRandom { seed: 0 }),
+			_ => None,
+		}
+	}
+}
+
+/// The rgb index of the colour at `(x, y)`. rawloader wants the row first.
+#[inline]
+fn colour(cfa: &CFA, x: usize, y: usize) -> usize {
+	CfaColor::from(cfa.color_at(y, x)).rgb_index()
+}
+
+/// Whether the pattern is a 2x2 Bayer one, a red, a blue, and two greens
+/// across a diagonal. Anything else, like X-Trans, has to go through
+/// [xtrans].
+pub(crate) fn is_bayer(cfa: &CFA) -> bool {
+	if cfa.width != 2 || cfa.height != 2 {
+		return false;
+	}
+
+	let [a, b, c, d] =
+		[(0, 0), (0, 1), (1, 0), (1, 1)].map(|(row, col)| cfa::channel(cfa.color_at(row, col)));
+	let mut sorted = [a, b, c, d];
+	sorted.sort();
+
+	sorted == [0, 1, 1, 2] && (a == d || b == c)
+}
+
+/// Reflect a coordinate off the edges without repeating the edge itself, so
+/// one step past the edge is the same colour as one step in would have been.
+#[inline]
+fn mirror(v: isize, len: usize) -> usize {
+	let last = len as isize - 1;
+	if v < 0 {
+		(-v).min(last) as usize
+	} else if v > last {
+		(2 * last - v).max(0) as usize
+	} else {
+		v as usize
+	}
+}
+
+/// Run `f` for every pixel, a row at a time on rayon, and collect the results
+fn per_pixel<T, F>(width: usize, height: usize, f: F) -> Vec<T>
+where
+	T: Copy + Default + Send,
+	F: Fn(usize, usize) -> T + Sync,
+{
+	let mut out = vec![T::default(); width * height];
+	out.par_chunks_exact_mut(width)
+		.enumerate()
+		.for_each(|(y, row)| {
+			for (x, v) in row.iter_mut().enumerate() {
+				*v = f(x, y);
+			}
+		});
+
+	out
+}
+
+/// Every missing colour is the average of the pixels of that colour in the
+/// 3x3 around it. For a Bayer mosaic that's the two or four nearest. Bigger
+/// patterns, like X-Trans, don't always have every colour that close, so for
+/// those we look out to the 5x5.
+pub(super) fn bilinear(width: usize, height: usize, cfa: &CFA, raw: &[f32]) -> Vec<f32> {
+	let window = |x: usize, y: usize, reach: usize| {
+		let mut sums = [0.0; 3];
+		let mut counts = [0.0; 3];
+		for ny in y.saturating_sub(reach)..(y + reach + 1).min(height) {
+			for nx in x.saturating_sub(reach)..(x + reach + 1).min(width) {
+				let c = colour(cfa, nx, ny);
+				sums[c] += raw[ny * width + nx];
+				counts[c] += 1.0;
+			}
+		}
+
+		(sums, counts)
+	};
+
+	let pixels = per_pixel(width, height, |x, y| {
+		let (mut sums, mut counts) = window(x, y, 1);
+		if counts.contains(&0.0) {
+			(sums, counts) = window(x, y, 2);
+		}
+
+		let own = colour(cfa, x, y);
+		let mut rgb = [0.0f32; 3];
+		for c in 0..3 {
+			rgb[c] = if c == own {
+				raw[y * width + x]
+			} else if counts[c] > 0.0 {
+				sums[c] / counts[c]
+			} else {
+				0.0
+			};
+		}
+
+		rgb
+	});
+
+	pixels.into_iter().flatten().collect()
+}
+
+/// Adaptive Homogeneity-Directed demosaicing, after Hirakawa and Parks.
+///
+/// It builds two full images, one with green filled in across the rows and
+/// one down the columns, and the red and blue of each from its own green.
+/// Both are taken to CIELab and at every pixel we count how many of its
+/// neighbours are close enough in lightness and colour to be the same
+/// surface. The image with more of those, summed over the 3x3, wins; a tie
+/// is the average of the two.
+pub(super) fn ahd(width: usize, height: usize, cfa: &CFA, raw: &[f32]) -> Vec<f32> {
+	// The green interpolation reaches two pixels out
+	if width < 5 || height < 5 {
+		return bilinear(width, height, cfa, raw);
+	}
+
+	let at = |x: isize, y: isize| mirror(y, height) * width + mirror(x, width);
+	let colour_at = |x: isize, y: isize| colour(cfa, mirror(x, width), mirror(y, height));
+
+	// Green in each direction. The estimate is the average of the two greens
+	// on either side, corrected by how the pixel's own colour curves, and
+	// kept between those two greens so it can't overshoot.
+	let green = [(1, 0), (0, 1)].map(|(dx, dy)| {
+		per_pixel(width, height, |x, y| {
+			let (x, y) = (x as isize, y as isize);
+			let here = raw[at(x, y)];
+			if colour_at(x, y) == 1 {
+				return here;
+			}
+
+			let g1 = raw[at(x - dx, y - dy)];
+			let g2 = raw[at(x + dx, y + dy)];
+			let c1 = raw[at(x - 2 * dx, y - 2 * dy)];
+			let c2 = raw[at(x + 2 * dx, y + 2 * dy)];
+
+			let estimate = (g1 + g2) / 2.0 + (2.0 * here - c1 - c2) / 4.0;
+			estimate.clamp(g1.min(g2), g1.max(g2))
+		})
+	});
+
+	// Red and blue follow green. A missing colour is this pixel's green plus
+	// the average difference between that colour and green around it, which
+	// keeps the colour smooth even where the brightness isn't.
+	let rgb = green.each_ref().map(|green| {
+		per_pixel(width, height, |x, y| {
+			let (x, y) = (x as isize, y as isize);
+			let own = colour_at(x, y);
+			let g = green[at(x, y)];
+
+			let mut rgb = [0.0f32; 3];
+			rgb[1] = g;
+			for c in [0, 2] {
+				if c == own {
+					rgb[c] = raw[at(x, y)];
+					continue;
+				}
+
+				let (mut sum, mut count) = (0.0, 0.0);
+				for ny in y - 1..=y + 1 {
+					for nx in x - 1..=x + 1 {
+						if colour_at(nx, ny) == c {
+							sum += raw[at(nx, ny)] - green[at(nx, ny)];
+							count += 1.0;
+						}
+					}
+				}
+
+				rgb[c] = if count > 0.0 { g + sum / count } else { g };
+			}
+
+			rgb
+		})
+	});
+
+	let white = raw.iter().copied().fold(f32::EPSILON, f32::max);
+	let lab = rgb.each_ref().map(|rgb| {
+		rgb.par_iter()
+			.map(|px| to_lab(px.map(|v| v / white)))
+			.collect::<Vec<_>>()
+	});
+
+	// How many of the four neighbours are the same surface. The tolerance is
+	// the smaller of the two directions' biggest differences, so it's tight
+	// across an edge and loose along it.
+	const NEIGHBOURS: [(isize, isize); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+	let homogeneity = per_pixel(width, height, |x, y| {
+		let (x, y) = (x as isize, y as isize);
+		let here = at(x, y);
+
+		let differences = lab.each_ref().map(|lab| {
+			NEIGHBOURS.map(|(dx, dy)| {
+				let (a, b) = (lab[here], lab[at(x + dx, y + dy)]);
+				let lightness = (a[0] - b[0]).abs();
+				let chroma = (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2);
+				(lightness, chroma)
+			})
+		});
+
+		let [across, down] = &differences;
+		let epsilon_l = across[0].0.max(across[1].0).min(down[2].0.max(down[3].0));
+		let epsilon_c = across[0].1.max(across[1].1).min(down[2].1.max(down[3].1));
+
+		differences.map(|direction| {
+			direction
+				.iter()
+				.filter(|(l, c)| *l <= epsilon_l && *c <= epsilon_c)
+				.count() as u8
+		})
+	});
+
+	let pixels = per_pixel(width, height, |x, y| {
+		let (x, y) = (x as isize, y as isize);
+		let mut score = [0u32; 2];
+		for ny in y - 1..=y + 1 {
+			for nx in x - 1..=x + 1 {
+				let [across, down] = homogeneity[at(nx, ny)];
+				score[0] += across as u32;
+				score[1] += down as u32;
+			}
+		}
+
+		let (across, down) = (rgb[0][at(x, y)], rgb[1][at(x, y)]);
+		match score[0].cmp(&score[1]) {
+			std::cmp::Ordering::Greater => across,
+			std::cmp::Ordering::Less => down,
+			std::cmp::Ordering::Equal => [0, 1, 2].map(|c| (across[c] + down[c]) / 2.0),
+		}
+	});
+
+	pixels.into_iter().flatten().collect()
+}
+
+/// Fuji's X-Trans. The pattern is 6x6 and more than half of it is green, so
+/// every red and blue pixel has green right next to it, but the greens
+/// aren't always on both sides of it like in a Bayer mosaic.
+///
+/// Green comes from whichever line through the pixel, across, down, or one
+/// of the diagonals, has green on both ends that agree the most, so it
+/// follows edges. Red and blue are then filled in from the colour difference
+/// to green of their pixels in the 5x5, weighted by how close they are.
+pub(super) fn xtrans(width: usize, height: usize, cfa: &CFA, raw: &[f32]) -> Vec<f32> {
+	if width < 6 || height < 6 {
+		return bilinear(width, height, cfa, raw);
+	}
+
+	let at = |x: isize, y: isize| mirror(y, height) * width + mirror(x, width);
+	let colour_at = |x: isize, y: isize| colour(cfa, mirror(x, width), mirror(y, height));
+
+	let green = per_pixel(width, height, |x, y| {
+		let (x, y) = (x as isize, y as isize);
+		if colour_at(x, y) == 1 {
+			return raw[at(x, y)];
+		}
+
+		let mut best: Option<(f32, f32)> = None;
+		let (mut sum, mut count) = (0.0, 0.0);
+		for (dx, dy) in [(1, 0), (0, 1), (1, 1), (1, -1)] {
+			let ends = [(x - dx, y - dy), (x + dx, y + dy)];
+			let greens: Vec<f32> = ends
+				.iter()
+				.filter(|(nx, ny)| colour_at(*nx, *ny) == 1)
+				.map(|(nx, ny)| raw[at(*nx, *ny)])
+				.collect();
+
+			sum += greens.iter().sum::<f32>();
+			count += greens.len() as f32;
+			if let [a, b] = greens[..] {
+				let difference = (a - b).abs();
+				if best.is_none_or(|(d, _)| difference < d) {
+					best = Some((difference, (a + b) / 2.0));
+				}
+			}
+		}
+
+		match best {
+			Some((_, green)) => green,
+			None if count > 0.0 => sum / count,
+			None => 0.0,
+		}
+	});
+
+	let pixels = per_pixel(width, height, |x, y| {
+		let (x, y) = (x as isize, y as isize);
+		let own = colour_at(x, y);
+		let g = green[at(x, y)];
+
+		let mut rgb = [0.0f32; 3];
+		rgb[1] = g;
+		for c in [0, 2] {
+			if c == own {
+				rgb[c] = raw[at(x, y)];
+				continue;
+			}
+
+			let (mut sum, mut weights) = (0.0, 0.0);
+			for dy in -2..=2isize {
+				for dx in -2..=2isize {
+					let (nx, ny) = (x + dx, y + dy);
+					if colour_at(nx, ny) == c {
+						let weight = 1.0 / (dx * dx + dy * dy) as f32;
+						sum += (raw[at(nx, ny)] - green[at(nx, ny)]) * weight;
+						weights += weight;
+					}
+				}
+			}
+
+			rgb[c] = if weights > 0.0 { g + sum / weights } else { g };
+		}
+
+		rgb
+	});
+
+	pixels.into_iter().flatten().collect()
+}
+
+/// Camera RGB to CIELab, treating it as if it were linear sRGB. It isn't,
+/// but AHD only compares nearby pixels to each other so it doesn't have to
+/// be exact, just perceptual.
+fn to_lab(rgb: [f32; 3]) -> [f32; 3] {
+	#[rustfmt::skip]
+	const RGB_TO_XYZ: [[f32; 3]; 3] = [
+		[0.4124, 0.3576, 0.1805],
+		[0.2126, 0.7152, 0.0722],
+		[0.0193, 0.1192, 0.9505],
+	];
+	const WHITE: [f32; 3] = [0.9505, 1.0, 1.089];
+
+	let f = |t: f32| {
+		if t > 0.008856 {
+			t.cbrt()
+		} else {
+			7.787 * t + 16.0 / 116.0
+		}
+	};
+
+	let [fx, fy, fz] = [0, 1, 2].map(|i| {
+		let row = RGB_TO_XYZ[i];
+		f((row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2]) / WHITE[i])
+	});
+
+	[116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
@@ -0,0 +1,185 @@
+//! Noise reduction for high ISO files. Noise is two problems: grain in the
+//! brightness, and blotches of colour. They're handled apart because colour
+//! can be blurred a lot before anyone notices, but brightness is where all
+//! the detail is.
+
+use crate::{algorithms, colorspace::LinRgb, par::*};
+
+use super::{
+	sharpen::{gaussian_blur, gaussian_reach},
+	tile::TILE_SIZE,
+	Image,
+};
+
+// How different two pixels can be, in square rooted luminance, before the
+// bilateral filter stops averaging them together at full strength
+const RANGE_SIGMA: f32 = 0.15;
+
+// How far the luminance filter reaches at full strength, in pixels
+const SPATIAL_SIGMA: f32 = 3.0;
+
+// How wide the chroma blur is at full strength, in pixels
+const CHROMA_SIGMA: f32 = 8.0;
+
+impl Image<f32, LinRgb> {
+	/// Both kinds of noise reduction, [luminance](Self::denoise_luminance)
+	/// then [chroma](Self::denoise_chroma). Strengths go from 0.0, nothing,
+	/// to 1.0, a lot.
+	pub fn denoise(&mut self, luminance: f32, chroma: f32) {
+		self.denoise_luminance(luminance);
+		self.denoise_chroma(chroma);
+	}
+
+	/// Smooth out grain with a bilateral filter: each pixel becomes an
+	/// average of the ones near it, but only the ones about as bright, so
+	/// edges stay where they are. `strength` goes from 0.0 to 1.0 and sets
+	/// both how far it looks and how different a pixel can be and still
+	/// count. Too much and skin and foliage go plastic.
+	///
+	/// Only the brightness changes. Each pixel is scaled as a whole, so its
+	/// colour is the same as it was.
+	pub fn denoise_luminance(&mut self, strength: f32) {
+		let strength = strength.clamp(0.0, 1.0);
+		if strength <= 0.0 {
+			return;
+		}
+
+		let reach = bilateral_reach(SPATIAL_SIGMA * strength) as usize;
+		self.process_tiled(TILE_SIZE, reach, |tile| {
+			tile.denoise_luminance_tile(strength)
+		});
+	}
+
+	fn denoise_luminance_tile(&mut self, strength: f32) {
+		if self.width == 0 || self.height == 0 {
+			return;
+		}
+
+		// Shot noise grows with the square root of the light, so this evens
+		// out how much noise there is between the shadows and the highlights
+		let luminance: Vec<f32> = self
+			.data
+			.par_chunks_exact(3)
+			.map(|rgb| algorithms::luminance(rgb).max(0.0).sqrt())
+			.collect();
+
+		let smoothed = bilateral(
+			&luminance,
+			self.width,
+			SPATIAL_SIGMA * strength,
+			RANGE_SIGMA * strength,
+		);
+
+		self.data
+			.par_chunks_exact_mut(3)
+			.zip(luminance.par_iter().zip(smoothed.par_iter()))
+			.for_each(|(rgb, (before, after))| {
+				let (before, after) = (before * before, after * after);
+				if before > 0.0 {
+					rgb.iter_mut().for_each(|v| *v *= after / before);
+				} else {
+					rgb.iter_mut().for_each(|v| *v += after - before);
+				}
+			});
+	}
+
+	/// Blur away colour blotches without touching the brightness. Each
+	/// pixel's difference from its own luminance is blurred and then put
+	/// back on it. `strength` goes from 0.0 to 1.0; the eye is much worse at
+	/// colour detail than brightness detail, so even 1.0 rarely shows.
+	pub fn denoise_chroma(&mut self, strength: f32) {
+		let strength = strength.clamp(0.0, 1.0);
+		if strength <= 0.0 {
+			return;
+		}
+
+		let reach = gaussian_reach(CHROMA_SIGMA * strength);
+		self.process_tiled(TILE_SIZE, reach, |tile| tile.denoise_chroma_tile(strength));
+	}
+
+	fn denoise_chroma_tile(&mut self, strength: f32) {
+		if self.width == 0 || self.height == 0 {
+			return;
+		}
+
+		let luminance: Vec<f32> = self
+			.data
+			.par_chunks_exact(3)
+			.map(algorithms::luminance)
+			.collect();
+
+		let chroma: Vec<f32> = self
+			.data
+			.par_chunks_exact(3)
+			.zip(luminance.par_iter())
+			.flat_map_iter(|(rgb, y)| rgb.iter().map(move |v| v - y))
+			.collect();
+		let blurred = gaussian_blur(&chroma, self.width, 3, CHROMA_SIGMA * strength);
+
+		self.data
+			.par_chunks_exact_mut(3)
+			.zip(blurred.par_chunks_exact(3).zip(luminance.par_iter()))
+			.for_each(|(rgb, (chroma, y))| {
+				for (v, c) in rgb.iter_mut().zip(chroma) {
+					*v = y + c;
+				}
+			});
+	}
+}
+
+/// Bilateral filter one channel. Neighbours are weighted by a gaussian of
+/// how far away they are and another of how different they are.
+fn bilateral(data: &[f32], width: usize, spatial_sigma: f32, range_sigma: f32) -> Vec<f32> {
+	let height = data.len() / width;
+	let reach = bilateral_reach(spatial_sigma);
+	let spatial: Vec<f32> = (-reach..=reach)
+		.flat_map(|dy| (-reach..=reach).map(move |dx| (dx, dy)))
+		.map(|(dx, dy)| {
+			(-((dx * dx + dy * dy) as f32) / (2.0 * spatial_sigma * spatial_sigma)).exp()
+		})
+		.collect();
+	let range_denominator = 2.0 * range_sigma * range_sigma;
+
+	let mut out = vec![0.0; data.len()];
+	out.par_chunks_exact_mut(width)
+		.enumerate()
+		.for_each(|(y, row)| {
+			for (x, out) in row.iter_mut().enumerate() {
+				let center = data[y * width + x];
+				let (mut sum, mut weights) = (0.0, 0.0);
+
+				let mut k = 0;
+				for dy in -reach..=reach {
+					for dx in -reach..=reach {
+						let weight_spatial = spatial[k];
+						k += 1;
+
+						let (Some(sx), Some(sy)) =
+							(x.checked_add_signed(dx), y.checked_add_signed(dy))
+						else {
+							continue;
+						};
+						if sx >= width || sy >= height {
+							continue;
+						}
+
+						let value = data[sy * width + sx];
+						let difference = value - center;
+						let weight =
+							weight_spatial * (-(difference * difference) / range_denominator).exp();
+						sum += value * weight;
+						weights += weight;
+					}
+				}
+
+				*out = sum / weights;
+			}
+		});
+
+	out
+}
+
+/// How many pixels away [bilateral] looks
+fn bilateral_reach(spatial_sigma: f32) -> isize {
+	(spatial_sigma * 2.0).ceil().max(1.0) as isize
+}
@@ -0,0 +1,233 @@
+//! Saving an image partway through, exactly as it is, to pick back up later.
+//! It's a short header and then the samples, little endian, so it's quick
+//! to write and read and loses nothing. It's not for sharing, the format
+//! is only ours.
+//!
+//! The metadata isn't in it. It's big, and it's the same for every step of
+//! one raw, so keep it once on its own, with the `serde` feature or by
+//! decoding the raw again, and give it back when you load.
+
+use std::io::{ErrorKind, Read, Write};
+
+use crate::{
+	colorspace::{Colorspace, ColorspaceKind},
+	Error,
+};
+
+use super::{DynImage, Image, RawMetadata, Sample, SampleKind};
+
+const MAGIC: &[u8; 8] = b"rawproc\0";
+const VERSION: u8 = 1;
+
+// How many samples we convert at a time, so we never need a second copy of
+// the whole image as bytes
+const CHUNK: usize = 64 * 1024;
+
+/// What's in a dump, ahead of the samples
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImageHeader {
+	pub width: usize,
+	pub height: usize,
+	pub colorspace: ColorspaceKind,
+	pub sample: SampleKind,
+}
+
+impl ImageHeader {
+	/// How many samples follow the header. None if it's more than fits in
+	/// memory, which only a corrupt header would say.
+	pub fn samples(&self) -> Option<usize> {
+		self.width
+			.checked_mul(self.height)?
+			.checked_mul(self.colorspace.components())?
+			.checked_mul(self.sample.size())
+			.map(|bytes| bytes / self.sample.size())
+	}
+
+	/// Read the header off the front of a dump, leaving `reader` at the
+	/// samples
+	pub fn read<R: Read>(reader: &mut R) -> Result<Self, Error> {
+		let mut fixed = [0; 8 + 1 + 1 + 1 + 8 + 8];
+		read_exact(reader, &mut fixed)?;
+
+		if &fixed[..8] != MAGIC {
+			return Err(Error::CorruptFile("it isn't an image dump".into()));
+		}
+		if fixed[8] != VERSION {
+			return Err(Error::CorruptFile(format!(
+				"it's a version {} image dump, we only know {VERSION}",
+				fixed[8]
+			)));
+		}
+
+		let sample = match fixed[9] {
+			0 => SampleKind::U8,
+			1 => SampleKind::U16,
+			2 => SampleKind::F32,
+			_ => {
+				return Err(Error::CorruptFile(
+					"the sample type isn't one we know".into(),
+				))
+			}
+		};
+		let colorspace = colorspace_from_id(fixed[10])
+			.ok_or_else(|| Error::CorruptFile("the colorspace isn't one we know".into()))?;
+		let number = |at: usize| u64::from_le_bytes(fixed[at..at + 8].try_into().unwrap());
+
+		Ok(Self {
+			width: number(11) as usize,
+			height: number(19) as usize,
+			colorspace,
+			sample,
+		})
+	}
+
+	/// Write the header, for when you're writing the samples yourself
+	pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+		let sample = match self.sample {
+			SampleKind::U8 => 0,
+			SampleKind::U16 => 1,
+			SampleKind::F32 => 2,
+		};
+
+		writer.write_all(MAGIC)?;
+		writer.write_all(&[VERSION, sample, colorspace_id(self.colorspace)])?;
+		writer.write_all(&(self.width as u64).to_le_bytes())?;
+		writer.write_all(&(self.height as u64).to_le_bytes())?;
+		Ok(())
+	}
+}
+
+impl<T: Sample, C: Colorspace> Image<T, C> {
+	/// What [dump](Self::dump) writes ahead of the samples
+	pub fn header(&self) -> ImageHeader {
+		ImageHeader {
+			width: self.width,
+			height: self.height,
+			colorspace: C::KIND,
+			sample: T::KIND,
+		}
+	}
+
+	/// Write the image, less its metadata, so [load](Self::load) can give
+	/// it back exactly. It's the [header](ImageHeader) and then the samples,
+	/// little endian.
+	pub fn dump<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+		self.header().write(writer)?;
+
+		let mut bytes = Vec::with_capacity(CHUNK * T::KIND.size());
+		for chunk in self.data.chunks(CHUNK) {
+			bytes.clear();
+			for v in chunk {
+				match T::KIND {
+					SampleKind::U8 => bytes.push(v.to_f32() as u8),
+					SampleKind::U16 => bytes.extend((v.to_f32() as u16).to_le_bytes()),
+					SampleKind::F32 => bytes.extend(v.to_f32().to_le_bytes()),
+				}
+			}
+			writer.write_all(&bytes)?;
+		}
+
+		Ok(())
+	}
+
+	/// Read back an image [dump](Self::dump)ed earlier, with the metadata it
+	/// had. Errors with [Error::ColorspaceMismatch] or
+	/// [Error::SampleMismatch] if it isn't this kind of image.
+	pub fn load<R: Read>(reader: &mut R, metadata: RawMetadata) -> Result<Self, Error> {
+		DynImage::load(reader, metadata)?.try_into()
+	}
+}
+
+impl<T: Sample> DynImage<T> {
+	/// [Image::load] for when you don't know the colorspace ahead of time.
+	/// The samples still have to be `T`.
+	pub fn load<R: Read>(reader: &mut R, metadata: RawMetadata) -> Result<Self, Error> {
+		let header = ImageHeader::read(reader)?;
+		if header.sample != T::KIND {
+			return Err(Error::SampleMismatch {
+				expected: T::KIND,
+				found: header.sample,
+			});
+		}
+
+		let len = header
+			.samples()
+			.ok_or_else(|| Error::CorruptFile("the image is impossibly big".into()))?;
+
+		// Grown as it's read instead of all at once, so a corrupt header
+		// can't have us allocate more than the file holds
+		let size = T::KIND.size();
+		let mut data = vec![];
+		let mut bytes = vec![0; CHUNK * size];
+		while data.len() < len {
+			let count = (len - data.len()).min(CHUNK);
+			data.reserve(count);
+			let bytes = &mut bytes[..count * size];
+			read_exact(reader, bytes)?;
+
+			data.extend(bytes.chunks_exact(size).map(|b| {
+				T::from_f32(match T::KIND {
+					SampleKind::U8 => b[0] as f32,
+					SampleKind::U16 => u16::from_le_bytes([b[0], b[1]]) as f32,
+					SampleKind::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+				})
+			}));
+		}
+
+		Ok(DynImage {
+			width: header.width,
+			height: header.height,
+			metadata,
+			colorspace: header.colorspace,
+			data,
+		})
+	}
+}
+
+/// read_exact, but running out is a [TruncatedFile](Error::TruncatedFile)
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), Error> {
+	reader.read_exact(buf).map_err(|e| match e.kind() {
+		ErrorKind::UnexpectedEof => Error::TruncatedFile,
+		_ => e.into(),
+	})
+}
+
+// These are written into dumps, so they can't change. New colorspaces get
+// the next number.
+fn colorspace_id(kind: ColorspaceKind) -> u8 {
+	match kind {
+		ColorspaceKind::BayerRgb => 0,
+		ColorspaceKind::Monochrome => 1,
+		ColorspaceKind::LinRgb => 2,
+		ColorspaceKind::XYZ => 3,
+		ColorspaceKind::LinSrgb => 4,
+		ColorspaceKind::Srgb => 5,
+		ColorspaceKind::Hsv => 6,
+		ColorspaceKind::Lab => 7,
+		ColorspaceKind::Oklab => 8,
+		ColorspaceKind::AdobeRgb => 9,
+		ColorspaceKind::ProPhotoRgb => 10,
+		ColorspaceKind::Rec2020 => 11,
+		ColorspaceKind::DisplayP3 => 12,
+	}
+}
+
+fn colorspace_from_id(id: u8) -> Option<ColorspaceKind> {
+	Some(match id {
+		0 => ColorspaceKind::BayerRgb,
+		1 => ColorspaceKind::Monochrome,
+		2 => ColorspaceKind::LinRgb,
+		3 => ColorspaceKind::XYZ,
+		4 => ColorspaceKind::LinSrgb,
+		5 => ColorspaceKind::Srgb,
+		6 => ColorspaceKind::Hsv,
+		7 => ColorspaceKind::Lab,
+		8 => ColorspaceKind::Oklab,
+		9 => ColorspaceKind::AdobeRgb,
+		10 => ColorspaceKind::ProPhotoRgb,
+		11 => ColorspaceKind::Rec2020,
+		12 => ColorspaceKind::DisplayP3,
+		_ => return None,
+	})
+}
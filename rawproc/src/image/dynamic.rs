@@ -0,0 +1,94 @@
+use crate::{
+	colorspace::{Colorspace, ColorspaceKind, Hsv, Lab, LinSrgb, Oklab, Srgb, XYZ},
+	Error,
+};
+
+use super::{Image, RawMetadata};
+
+/// An image where the colorspace is a value instead of a type, for when you
+/// don't know which one you want until runtime. Typed images go in with
+/// `into()` and come back out with `try_into()`, which checks that the
+/// colorspace is the one you asked for.
+#[derive(Clone, Debug)]
+pub struct DynImage<T: Copy + Clone> {
+	pub width: usize,
+	pub height: usize,
+	pub metadata: RawMetadata,
+	pub colorspace: ColorspaceKind,
+
+	pub data: Vec<T>,
+}
+
+impl<T: Copy + Clone, C: Colorspace> From<Image<T, C>> for DynImage<T> {
+	fn from(img: Image<T, C>) -> Self {
+		DynImage {
+			width: img.width,
+			height: img.height,
+			metadata: img.metadata,
+			colorspace: C::KIND,
+			data: img.data,
+		}
+	}
+}
+
+impl<T: Copy + Clone, C: Colorspace> TryFrom<DynImage<T>> for Image<T, C> {
+	type Error = Error;
+
+	fn try_from(img: DynImage<T>) -> Result<Self, Self::Error> {
+		if img.colorspace != C::KIND {
+			return Err(Error::ColorspaceMismatch {
+				expected: C::KIND,
+				found: img.colorspace,
+			});
+		}
+
+		Ok(img.typed())
+	}
+}
+
+impl<T: Copy + Clone> DynImage<T> {
+	// Only call this after checking the colorspace!
+	fn typed<C: Colorspace>(self) -> Image<T, C> {
+		Image {
+			width: self.width,
+			height: self.height,
+			metadata: self.metadata,
+			data: self.data,
+			phantom: Default::default(),
+		}
+	}
+}
+
+impl DynImage<f32> {
+	/// Convert to another colorspace, going through the same typed
+	/// conversions you'd use yourself. Errors if there's no way to get there.
+	pub fn convert(self, to: ColorspaceKind) -> Result<DynImage<f32>, Error> {
+		use ColorspaceKind as Kind;
+
+		let from = self.colorspace;
+		let converted: DynImage<f32> = match (from, to) {
+			(from, to) if from == to => self,
+			(Kind::LinSrgb, Kind::Srgb) => self.typed::<LinSrgb>().gamma().into(),
+			(Kind::Srgb, Kind::LinSrgb) => self.typed::<Srgb>().linearize().into(),
+			(Kind::Srgb, Kind::Hsv) => Image::<f32, Hsv>::from(self.typed::<Srgb>()).into(),
+			(Kind::Hsv, Kind::Srgb) => Image::<f32, Srgb>::from(self.typed::<Hsv>()).into(),
+			(Kind::LinSrgb, Kind::Hsv) => {
+				Image::<f32, Hsv>::from(self.typed::<LinSrgb>().gamma()).into()
+			}
+			(Kind::Hsv, Kind::LinSrgb) => Image::<f32, Srgb>::from(self.typed::<Hsv>())
+				.linearize()
+				.into(),
+			(Kind::XYZ, Kind::Lab) => self.typed::<XYZ>().to_lab().into(),
+			(Kind::Lab, Kind::XYZ) => self.typed::<Lab>().to_xyz().into(),
+			(Kind::LinSrgb, Kind::Oklab) => self.typed::<LinSrgb>().to_oklab().into(),
+			(Kind::Oklab, Kind::LinSrgb) => self.typed::<Oklab>().to_linsrgb().into(),
+			(Kind::XYZ, Kind::AdobeRgb) => self.typed::<XYZ>().to_adobe_rgb().into(),
+			(Kind::XYZ, Kind::ProPhotoRgb) => self.typed::<XYZ>().to_prophoto_rgb().into(),
+			(Kind::XYZ, Kind::Rec2020) => self.typed::<XYZ>().to_rec2020().into(),
+			(Kind::XYZ, Kind::DisplayP3) => self.typed::<XYZ>().to_display_p3().into(),
+			(from, to) => return Err(Error::UnsupportedConversion { from, to }),
+		};
+
+		Ok(converted)
+	}
+}
@@ -0,0 +1,378 @@
+use crate::{algorithms::luminance, colorspace::Colorspace};
+
+use super::{AlphaImage, Image, Region};
+
+// How far off level we look for a horizon, in degrees. Anything more
+// crooked than this was probably on purpose.
+const HORIZON_RANGE: f32 = 15.0;
+const HORIZON_STEP: f32 = 0.1;
+
+// The horizon is found on a copy of the image that's no bigger than this
+const ANALYSIS_SIZE: usize = 1024;
+
+impl<T: Copy + Clone, C: Colorspace> Image<T, C> {
+	/// Cut the image down to `region`. The rows are moved up in the buffer
+	/// the image already has, so this doesn't allocate.
+	///
+	/// On a mosaic use [crop_to](Image::crop_to) instead, it keeps the CFA
+	/// pattern lined up.
+	///
+	/// # Panics
+	/// If the region doesn't fit in the image.
+	pub fn crop_region(&mut self, region: Region) {
+		assert!(
+			region.x + region.width <= self.width && region.y + region.height <= self.height,
+			"the crop needs to be inside the image"
+		);
+
+		crop_in_place(&mut self.data, self.width, C::COMPONENTS, region);
+		self.width = region.width;
+		self.height = region.height;
+	}
+}
+
+impl<C: Colorspace> Image<f32, C> {
+	/// Rotate by `degrees` around the middle, clockwise for a positive angle.
+	/// The image stays the same size, so the corners get cut off and the
+	/// bits the rotation uncovers are black. Use [AlphaImage::rotate] if you
+	/// need to know where those are.
+	pub fn rotate(&self, degrees: f32) -> Image<f32, C> {
+		let (data, _) = rotate_planes(self, None, degrees);
+		Image {
+			width: self.width,
+			height: self.height,
+			metadata: self.metadata.clone(),
+			data,
+			phantom: Default::default(),
+		}
+	}
+
+	/// How far the horizon is off level, in degrees, clockwise positive. It's
+	/// the angle of the strongest straight, nearly horizontal, lines in the
+	/// image, found with a Hough transform, so it works on horizons and
+	/// anything else that should be level, like a shelf or a roofline.
+	///
+	/// None if nothing looks like a horizon, or if it's already level.
+	pub fn horizon_angle(&self) -> Option<f32> {
+		let (lum, width, height) = analysis_luminance(self);
+		if width < 3 || height < 3 {
+			return None;
+		}
+
+		// Sobel gradients, keeping the edges that run close to horizontal
+		let at = |x: usize, y: usize| lum[y * width + x];
+		let limit = HORIZON_RANGE.to_radians().tan();
+		let mut edges = vec![];
+		let mut total = 0.0;
+		for y in 1..height - 1 {
+			for x in 1..width - 1 {
+				let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+					- at(x - 1, y - 1)
+					- 2.0 * at(x - 1, y)
+					- at(x - 1, y + 1);
+				let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+					- at(x - 1, y - 1)
+					- 2.0 * at(x, y - 1)
+					- at(x + 1, y - 1);
+				let magnitude = (gx * gx + gy * gy).sqrt();
+				total += magnitude;
+
+				if gy.abs() > 0.0 && (gx / gy).abs() <= limit {
+					edges.push((x as f32, y as f32, magnitude));
+				}
+			}
+		}
+
+		// Only the strong edges vote, or texture like grass drowns it out
+		let mean = total / ((width - 2) * (height - 2)) as f32;
+		edges.retain(|(_, _, m)| *m > mean * 4.0);
+		if edges.is_empty() {
+			return None;
+		}
+
+		let steps = (HORIZON_RANGE * 2.0 / HORIZON_STEP) as usize + 1;
+		let diagonal = ((width * width + height * height) as f32).sqrt();
+		let rhos = diagonal.ceil() as usize * 2 + 1;
+		let mut votes = vec![0.0f32; steps * rhos];
+		let angles: Vec<(f32, f32)> = (0..steps)
+			.map(|step| {
+				let angle = (step as f32 * HORIZON_STEP - HORIZON_RANGE).to_radians();
+				angle.sin_cos()
+			})
+			.collect();
+
+		// A line at angle a is every point where y cos a - x sin a is the same
+		for (x, y, magnitude) in edges {
+			for (step, (sin, cos)) in angles.iter().enumerate() {
+				let rho = (y * cos - x * sin + diagonal).round() as usize;
+				votes[step * rhos + rho] += magnitude;
+			}
+		}
+
+		let (best, _) = votes
+			.iter()
+			.enumerate()
+			.max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+		let angle = (best / rhos) as f32 * HORIZON_STEP - HORIZON_RANGE;
+
+		if angle.abs() < HORIZON_STEP / 2.0 {
+			None
+		} else {
+			Some(angle)
+		}
+	}
+
+	/// Level the horizon and crop off the corners the rotation uncovered,
+	/// keeping the aspect ratio. Returns the angle it rotated by, or None if
+	/// it left the image alone because [horizon_angle](Self::horizon_angle)
+	/// didn't find anything to fix.
+	pub fn auto_straighten(&mut self) -> Option<f32> {
+		let tilt = self.horizon_angle()?;
+
+		*self = self.rotate(-tilt);
+		self.crop_region(rotated_crop(self.width, self.height, -tilt));
+
+		Some(-tilt)
+	}
+}
+
+impl<C: Colorspace> AlphaImage<f32, C> {
+	/// [Image::rotate], but the alpha goes along with it and the corners the
+	/// rotation uncovers are transparent.
+	pub fn rotate(&self, degrees: f32) -> AlphaImage<f32, C> {
+		let (data, alpha) = rotate_planes(&self.image, Some(&self.alpha), degrees);
+
+		AlphaImage {
+			image: Image {
+				width: self.image.width,
+				height: self.image.height,
+				metadata: self.image.metadata.clone(),
+				data,
+				phantom: Default::default(),
+			},
+			alpha,
+		}
+	}
+}
+
+impl<T: Copy + Clone, C: Colorspace> AlphaImage<T, C> {
+	/// [Image::crop_region] for the colour and alpha both.
+	///
+	/// # Panics
+	/// If the region doesn't fit in the image.
+	pub fn crop_region(&mut self, region: Region) {
+		let width = self.image.width;
+		self.image.crop_region(region);
+		crop_in_place(&mut self.alpha, width, 1, region);
+	}
+}
+
+impl<C: Colorspace> AlphaImage<f32, C> {
+	/// Crop away the transparent parts around the edges, like the corners
+	/// after a [rotate](Self::rotate), leaving the biggest rectangle that's
+	/// fully opaque. With `keep_aspect` the rectangle has the same aspect
+	/// ratio the image has now.
+	///
+	/// Returns the crop, or None, leaving the image alone, if nothing's
+	/// opaque.
+	pub fn auto_crop(&mut self, keep_aspect: bool) -> Option<Region> {
+		let (width, height) = (self.width(), self.height());
+		let aspect = keep_aspect.then(|| width as f32 / height as f32);
+
+		let opaque: Vec<bool> = self.alpha.iter().map(|a| *a >= OPAQUE).collect();
+		let region = valid_region(&opaque, width, height, aspect)?;
+		self.crop_region(region);
+
+		Some(region)
+	}
+}
+
+// Bilinear sampling leaves a sliver of transparency along the edge, so
+// nearly opaque is opaque enough
+const OPAQUE: f32 = 0.999;
+
+/// The biggest rectangle where every pixel is `valid`, like the part of an
+/// image that's left after correcting distortion or perspective. `valid` is
+/// one bool per pixel, row by row. Give an `aspect`, width over height, to
+/// get the biggest rectangle of that shape instead.
+///
+/// None if no pixel is valid.
+pub fn valid_region(
+	valid: &[bool],
+	width: usize,
+	height: usize,
+	aspect: Option<f32>,
+) -> Option<Region> {
+	assert_eq!(valid.len(), width * height, "need one bool per pixel");
+
+	// Go down the rows keeping how many valid pixels are stacked up in each
+	// column, and find the biggest rectangle under that like a histogram.
+	// Every rectangle that can't grow is one of the ones we look at.
+	let mut heights = vec![0; width];
+	let mut stack: Vec<(usize, usize)> = vec![];
+	let mut best: Option<(f32, Region)> = None;
+
+	for y in 0..height {
+		for (x, h) in heights.iter_mut().enumerate() {
+			*h = if valid[y * width + x] { *h + 1 } else { 0 };
+		}
+
+		stack.clear();
+		// The zero on the end closes off whatever's still open
+		for (x, h) in heights.iter().copied().chain([0]).enumerate() {
+			let mut start = x;
+
+			while let Some(&(left, top)) = stack.last() {
+				if top < h {
+					break;
+				}
+				stack.pop();
+
+				let found = Region::new(left, y + 1 - top, x - left, top);
+				let fitted = match aspect {
+					Some(aspect) => fit_aspect(found, aspect),
+					None => found,
+				};
+				let area = (fitted.width * fitted.height) as f32;
+				if area > 0.0 && best.map(|(a, _)| area > a).unwrap_or(true) {
+					best = Some((area, fitted));
+				}
+
+				start = left;
+			}
+
+			if h > 0 {
+				stack.push((start, h));
+			}
+		}
+	}
+
+	best.map(|(_, region)| region)
+}
+
+/// The biggest rectangle of `aspect` that fits in `region`, in the middle of
+/// it
+fn fit_aspect(region: Region, aspect: f32) -> Region {
+	let width = (region.width as f32).min(region.height as f32 * aspect);
+	let height = width / aspect;
+	let (width, height) = (width.floor() as usize, height.floor() as usize);
+
+	Region::new(
+		region.x + (region.width - width) / 2,
+		region.y + (region.height - height) / 2,
+		width,
+		height,
+	)
+}
+
+/// The biggest rectangle with the image's aspect ratio that's still all
+/// image after rotating a `width` by `height` one by `degrees`, centered.
+pub fn rotated_crop(width: usize, height: usize, degrees: f32) -> Region {
+	let (sin, cos) = degrees.to_radians().sin_cos();
+	let (sin, cos) = (sin.abs(), cos.abs());
+	let (w, h) = (width as f32, height as f32);
+
+	// The crop's corners, turned back, have to land inside the original
+	let scale = (w / (w * cos + h * sin)).min(h / (w * sin + h * cos));
+	let crop_width = ((w * scale).floor() as usize).clamp(1, width);
+	let crop_height = ((h * scale).floor() as usize).clamp(1, height);
+
+	Region::new(
+		(width - crop_width) / 2,
+		(height - crop_height) / 2,
+		crop_width,
+		crop_height,
+	)
+}
+
+/// Rotate the colour, and the alpha if there is any, with bilinear
+/// sampling. Where the rotation uncovers the canvas the colour is 0 and the
+/// alpha is too. The alpha that comes back is empty if there wasn't any.
+fn rotate_planes<C: Colorspace>(
+	image: &Image<f32, C>,
+	alpha: Option<&[f32]>,
+	degrees: f32,
+) -> (Vec<f32>, Vec<f32>) {
+	let (width, height) = (image.width, image.height);
+	let components = C::COMPONENTS;
+	let (sin, cos) = degrees.to_radians().sin_cos();
+	let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+
+	let mut data = vec![0.0; image.data.len()];
+	let mut rotated_alpha = if alpha.is_some() {
+		vec![0.0; width * height]
+	} else {
+		vec![]
+	};
+
+	for y in 0..height {
+		for x in 0..width {
+			// Turn the output pixel back to find where it came from
+			let (ox, oy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+			let sx = cos * ox + sin * oy + cx - 0.5;
+			let sy = -sin * ox + cos * oy + cy - 0.5;
+
+			if sx < -0.5 || sy < -0.5 || sx > width as f32 - 0.5 || sy > height as f32 - 0.5 {
+				continue;
+			}
+
+			let x0 = (sx.floor().max(0.0) as usize).min(width - 1);
+			let y0 = (sy.floor().max(0.0) as usize).min(height - 1);
+			let x1 = (x0 + 1).min(width - 1);
+			let y1 = (y0 + 1).min(height - 1);
+			let fx = (sx - x0 as f32).clamp(0.0, 1.0);
+			let fy = (sy - y0 as f32).clamp(0.0, 1.0);
+
+			let sample = |plane: &[f32], stride: usize, c: usize| {
+				let at = |x: usize, y: usize| plane[(y * width + x) * stride + c];
+				let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * fx;
+				let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * fx;
+				top + (bottom - top) * fy
+			};
+
+			let out = (y * width + x) * components;
+			for c in 0..components {
+				data[out + c] = sample(&image.data, components, c);
+			}
+			if let Some(alpha) = alpha {
+				rotated_alpha[y * width + x] = sample(alpha, 1, 0);
+			}
+		}
+	}
+
+	(data, rotated_alpha)
+}
+
+/// The luminance of the image, box averaged down so the longest side is no
+/// more than [ANALYSIS_SIZE]. Returns it with its width and height.
+fn analysis_luminance<C: Colorspace>(image: &Image<f32, C>) -> (Vec<f32>, usize, usize) {
+	let factor = image.width.max(image.height).div_ceil(ANALYSIS_SIZE).max(1);
+	let width = image.width / factor;
+	let height = image.height / factor;
+
+	let mut lum = vec![0.0; width * height];
+	for y in 0..height * factor {
+		for x in 0..width * factor {
+			let at = (y * image.width + x) * C::COMPONENTS;
+			lum[(y / factor) * width + x / factor] +=
+				luminance(&image.data[at..at + C::COMPONENTS]);
+		}
+	}
+
+	let count = (factor * factor) as f32;
+	lum.iter_mut().for_each(|l| *l /= count);
+	(lum, width, height)
+}
+
+/// Move the rows of `region` to the front of `data` and cut off the rest.
+/// Every row moves to somewhere at or before where it was, so going top to
+/// bottom never writes over a row we haven't moved yet.
+fn crop_in_place<T: Copy>(data: &mut Vec<T>, width: usize, components: usize, region: Region) {
+	let stride = region.width * components;
+	for (row, y) in (region.y..region.y + region.height).enumerate() {
+		let start = (y * width + region.x) * components;
+		data.copy_within(start..start + stride, row * stride);
+	}
+
+	data.truncate(stride * region.height);
+}
@@ -0,0 +1,182 @@
+use crate::colorspace::Colorspace;
+
+use super::{mask::smoothstep, Image};
+
+/// A rectangle of pixels. The spot being fixed is the ellipse that fits
+/// inside it, with its edge feathered so the fix blends in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Region {
+	pub x: usize,
+	pub y: usize,
+	pub width: usize,
+	pub height: usize,
+}
+
+impl Region {
+	pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+		Self {
+			x,
+			y,
+			width,
+			height,
+		}
+	}
+
+	/// The square around a circular spot, like a dust spot. It's cut off at
+	/// zero if the spot hangs over the top or left edge.
+	pub fn around(x: usize, y: usize, radius: usize) -> Self {
+		let left = x.saturating_sub(radius);
+		let top = y.saturating_sub(radius);
+
+		Self {
+			x: left,
+			y: top,
+			width: x + radius + 1 - left,
+			height: y + radius + 1 - top,
+		}
+	}
+
+	/// Whether the region is inside a `width` by `height` image. One so big
+	/// it wraps around isn't.
+	pub(crate) fn fits(&self, width: usize, height: usize) -> bool {
+		let right = self.x.checked_add(self.width);
+		let bottom = self.y.checked_add(self.height);
+		right.is_some_and(|r| r <= width) && bottom.is_some_and(|b| b <= height)
+	}
+
+	fn contains(&self, x: usize, y: usize) -> bool {
+		(self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+	}
+
+	/// How much of the fix a pixel in the region gets. All of it in the
+	/// middle, fading out over the outer third to nothing at the edge.
+	fn weight(&self, dx: usize, dy: usize) -> f32 {
+		let rx = (dx as f32 + 0.5) / self.width as f32 * 2.0 - 1.0;
+		let ry = (dy as f32 + 0.5) / self.height as f32 * 2.0 - 1.0;
+		let distance = (rx * rx + ry * ry).sqrt();
+
+		1.0 - smoothstep((distance - 2.0 / 3.0) * 3.0)
+	}
+
+	/// Every pixel just outside the region, as `(x, y)`
+	fn border(&self, width: usize, height: usize) -> Vec<(usize, usize)> {
+		let left = self.x.saturating_sub(1);
+		let top = self.y.saturating_sub(1);
+		let right = (self.x + self.width + 1).min(width);
+		let bottom = (self.y + self.height + 1).min(height);
+
+		(top..bottom)
+			.flat_map(|y| (left..right).map(move |x| (x, y)))
+			.filter(|(x, y)| !self.contains(*x, *y))
+			.collect()
+	}
+}
+
+impl<C: Colorspace> Image<f32, C> {
+	/// Cover `dst` with the pixels from `src`, like a clone stamp, but
+	/// shifted so the average colour along `src`'s border matches `dst`'s.
+	/// That takes the texture from `src` and the tone from around `dst`, so
+	/// a clean patch of sky can go over a dust spot in a darker patch of it.
+	///
+	/// Heal before sharpening. Sharpening puts halos around spots and those
+	/// are harder to hide.
+	///
+	/// # Panics
+	/// If the regions aren't the same size or don't fit in the image.
+	pub fn heal(&mut self, src: Region, dst: Region) {
+		self.check_region(&src);
+		self.check_region(&dst);
+		assert_eq!(
+			(src.width, src.height),
+			(dst.width, dst.height),
+			"heal needs the source and destination to be the same size"
+		);
+
+		let components = C::COMPONENTS;
+		let src_mean = self.mean(&src.border(self.width, self.height));
+		let dst_mean = self.mean(&dst.border(self.width, self.height));
+
+		// Copy the source first so overlapping regions don't read pixels we
+		// already changed
+		let mut patch = Vec::with_capacity(src.width * src.height * components);
+		for y in src.y..src.y + src.height {
+			let start = (y * self.width + src.x) * components;
+			patch.extend_from_slice(&self.data[start..start + src.width * components]);
+		}
+
+		for dy in 0..dst.height {
+			for dx in 0..dst.width {
+				let weight = dst.weight(dx, dy);
+				let from = (dy * dst.width + dx) * components;
+				let to = ((dst.y + dy) * self.width + dst.x + dx) * components;
+
+				for c in 0..components {
+					let healed = patch[from + c] - src_mean[c] + dst_mean[c];
+					let v = &mut self.data[to + c];
+					*v += (healed - *v) * weight;
+				}
+			}
+		}
+	}
+
+	/// Fill `region` in from what's around it, using the median of the
+	/// pixels on its border. It's meant for small spots, like dust and stuck
+	/// pixels, where there's no texture to speak of. Like
+	/// [heal](Self::heal), do it before sharpening.
+	///
+	/// # Panics
+	/// If the region doesn't fit in the image.
+	pub fn fill(&mut self, region: Region) {
+		self.check_region(&region);
+
+		let components = C::COMPONENTS;
+		let border = region.border(self.width, self.height);
+		if border.is_empty() {
+			return;
+		}
+
+		let median: Vec<f32> = (0..components)
+			.map(|c| {
+				let mut values: Vec<f32> = border
+					.iter()
+					.map(|(x, y)| self.data[(y * self.width + x) * components + c])
+					.collect();
+				values.sort_by(|a, b| a.total_cmp(b));
+				values[values.len() / 2]
+			})
+			.collect();
+
+		for dy in 0..region.height {
+			for dx in 0..region.width {
+				let weight = region.weight(dx, dy);
+				let at = ((region.y + dy) * self.width + region.x + dx) * components;
+
+				for (v, m) in self.data[at..at + components].iter_mut().zip(&median) {
+					*v += (m - *v) * weight;
+				}
+			}
+		}
+	}
+
+	fn check_region(&self, region: &Region) {
+		assert!(
+			region.fits(self.width, self.height),
+			"the region needs to be inside the image"
+		);
+	}
+
+	fn mean(&self, pixels: &[(usize, usize)]) -> Vec<f32> {
+		let components = C::COMPONENTS;
+		let mut sum = vec![0.0; components];
+		for (x, y) in pixels {
+			let at = (y * self.width + x) * components;
+			for (s, v) in sum.iter_mut().zip(&self.data[at..at + components]) {
+				*s += v;
+			}
+		}
+
+		let count = pixels.len().max(1) as f32;
+		sum.into_iter().map(|s| s / count).collect()
+	}
+}
@@ -0,0 +1,205 @@
+//! Histograms and clipping, for the things an editor shows next to the image.
+//! It's one pass over the data, split over rayon by rows. That's quick, but a
+//! full size raw is still a lot of samples, so for something that updates as
+//! you drag a slider take it of the preview you're showing instead.
+
+use crate::{
+	colorspace::{Colorspace, ColorspaceKind},
+	par::*,
+};
+
+use super::{cfa, Image, Sample};
+
+// A sample this close to white is clipped. Normalizing doesn't leave clipped
+// samples at exactly 1.0, and neither does whitebalancing and converting
+// them after.
+const CLIPPED: f32 = 0.99;
+
+// Rec. 709's luminance, for camera RGB which doesn't have primaries of its own
+const REC709_LUMINANCE: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// How many samples of each channel landed in each bin. The bins go from
+/// black to white evenly, and anything past either end is counted in the
+/// first or last bin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+	/// Red, green, and blue. A mosaic's are its CFA colours, and a
+	/// monochrome image only has red.
+	pub channels: [Vec<u32>; 3],
+	/// The luminance of every pixel. None for a mosaic, which doesn't have
+	/// whole pixels, and for colorspaces that aren't RGB.
+	pub luminance: Option<Vec<u32>>,
+	clipped: [u32; 3],
+	crushed: [u32; 3],
+}
+
+impl Histogram {
+	fn new(bins: usize, luminance: bool) -> Self {
+		Self {
+			channels: [vec![0; bins], vec![0; bins], vec![0; bins]],
+			luminance: luminance.then(|| vec![0; bins]),
+			clipped: [0; 3],
+			crushed: [0; 3],
+		}
+	}
+
+	pub fn bins(&self) -> usize {
+		self.channels[0].len()
+	}
+
+	/// How much of each channel is blown out, from 0.0 to 1.0
+	pub fn clipped(&self) -> [f32; 3] {
+		[0, 1, 2].map(|c| self.clipped[c] as f32 / self.total(c).max(1) as f32)
+	}
+
+	/// How much of each channel is at or below black, from 0.0 to 1.0
+	pub fn crushed(&self) -> [f32; 3] {
+		[0, 1, 2].map(|c| self.crushed[c] as f32 / self.total(c).max(1) as f32)
+	}
+
+	fn total(&self, channel: usize) -> u32 {
+		self.channels[channel].iter().sum()
+	}
+
+	/// Count a sample that's `position` of the way from black to white
+	#[inline]
+	fn add(&mut self, channel: usize, position: f32, bins: usize) {
+		self.channels[channel][bin(position, bins)] += 1;
+		self.clipped[channel] += (position >= CLIPPED) as u32;
+		self.crushed[channel] += (position <= 0.0) as u32;
+	}
+
+	fn merge(mut self, other: Self) -> Self {
+		for c in 0..3 {
+			for (a, b) in self.channels[c].iter_mut().zip(&other.channels[c]) {
+				*a += b;
+			}
+			self.clipped[c] += other.clipped[c];
+			self.crushed[c] += other.crushed[c];
+		}
+
+		if let (Some(a), Some(b)) = (&mut self.luminance, &other.luminance) {
+			for (a, b) in a.iter_mut().zip(b) {
+				*a += b;
+			}
+		}
+
+		self
+	}
+}
+
+#[inline]
+fn bin(position: f32, bins: usize) -> usize {
+	// NaN goes to the first bin, as usize casts it to 0
+	((position * bins as f32) as usize).min(bins - 1)
+}
+
+impl<T: Sample, C: Colorspace> Image<T, C> {
+	/// Count the samples into `bins` bins per channel. Where black and white
+	/// are depends on what the image is:
+	///
+	/// - floats go from 0.0 to 1.0, like a [normalized](Image::normalize) raw
+	/// - integer raws, a mosaic or camera RGB straight from the file, go from
+	///   the black level to the white level
+	/// - other integers go from 0 to as high as the type goes
+	///
+	/// Luminance is taken on the values as they are, so on an sRGB image it's
+	/// luma, like most editors show.
+	///
+	/// # Panics
+	/// If `bins` is 0.
+	pub fn histogram(&self, bins: usize) -> Histogram {
+		assert!(bins > 0, "a histogram needs at least one bin");
+
+		let (black, white) = self.range();
+		let scale = [0, 1, 2].map(|c| 1.0 / (white[c] - black[c]).max(f32::EPSILON));
+		let position = |c: usize, v: T| (v.to_f32() - black[c]) * scale[c];
+
+		let weights = luminance_weights(C::KIND);
+		let row_len = match C::COMPONENTS {
+			1 => self.width,
+			n => self.width * n,
+		};
+		let cfa = &self.metadata.cfa;
+		let mono = C::KIND == ColorspaceKind::Monochrome;
+
+		self.data
+			.par_chunks(row_len.max(1))
+			.enumerate()
+			.fold(
+				|| Histogram::new(bins, weights.is_some()),
+				|mut hist, (y, row)| {
+					if C::COMPONENTS == 1 {
+						for (x, v) in row.iter().enumerate() {
+							// Monochrome is all red, like it is for levels
+							let c = if mono {
+								0
+							} else {
+								cfa::channel(cfa.color_at(y, x))
+							};
+							let p = position(c, *v);
+							hist.add(c, p, bins);
+							if let Some(lum) = &mut hist.luminance {
+								lum[bin(p, bins)] += 1;
+							}
+						}
+						return hist;
+					}
+
+					for px in row.chunks_exact(C::COMPONENTS) {
+						let rgb = [0, 1, 2].map(|c| position(c, px[c]));
+						for (c, p) in rgb.iter().enumerate() {
+							hist.add(c, *p, bins);
+						}
+
+						if let (Some(w), Some(lum)) = (weights, &mut hist.luminance) {
+							let l = w[0] * rgb[0] + w[1] * rgb[1] + w[2] * rgb[2];
+							lum[bin(l, bins)] += 1;
+						}
+					}
+					hist
+				},
+			)
+			.reduce(|| Histogram::new(bins, weights.is_some()), Histogram::merge)
+	}
+
+	/// How much of each channel is blown out, from 0.0 to 1.0. The same as
+	/// [Histogram::clipped] without the bins.
+	pub fn clipped(&self) -> [f32; 3] {
+		self.histogram(1).clipped()
+	}
+
+	/// Black and white for each channel. See [histogram](Self::histogram).
+	fn range(&self) -> ([f32; 3], [f32; 3]) {
+		// Clamped to what the type holds, so infinity for floats
+		let max = T::from_f32(f32::INFINITY).to_f32();
+
+		let raw = matches!(
+			C::KIND,
+			ColorspaceKind::BayerRgb | ColorspaceKind::Monochrome | ColorspaceKind::LinRgb
+		);
+		if max.is_infinite() {
+			([0.0; 3], [1.0; 3])
+		} else if raw {
+			let black = self.metadata.blacklevels.map(|b| b as f32);
+			let white = self.metadata.whitelevels.map(|w| w as f32);
+			(black, white)
+		} else {
+			([0.0; 3], [max; 3])
+		}
+	}
+}
+
+/// How much each channel counts towards luminance, the middle row of the
+/// colorspace's RGB to XYZ matrix. None if it isn't RGB.
+fn luminance_weights(kind: ColorspaceKind) -> Option<[f32; 3]> {
+	match kind {
+		ColorspaceKind::LinRgb => Some(REC709_LUMINANCE),
+		// It's its own luminance
+		ColorspaceKind::Monochrome => Some([1.0, 0.0, 0.0]),
+		kind => kind.color_tag().map(|tag| {
+			let m = tag.rgb_to_xyz();
+			[m[(1, 0)], m[(1, 1)], m[(1, 2)]]
+		}),
+	}
+}
@@ -1,21 +1,30 @@
 use crate::{
 	algorithms,
 	colorspace::{Hsv, Srgb},
+	par::*,
 };
 
-use super::Image;
+use super::{Image, Mask};
 
 impl Image<f32, Hsv> {
 	pub fn saturation(&mut self, scalar: f32) {
-		for hsv in self.data.chunks_mut(3) {
-			hsv[1] = hsv[1] * scalar;
-		}
+		self.data
+			.par_chunks_exact_mut(3)
+			.for_each(|hsv| hsv[1] = hsv[1] * scalar);
+	}
+
+	/// [saturation](Self::saturation), but only where the mask is.
+	///
+	/// # Panics
+	/// If the mask isn't the same size as the image.
+	pub fn saturation_masked(&mut self, scalar: f32, mask: Option<&Mask>) {
+		self.adjust_masked(mask, |hsv| hsv[1] *= scalar);
 	}
 }
 
 impl From<Image<f32, Srgb>> for Image<f32, Hsv> {
 	fn from(mut value: Image<f32, Srgb>) -> Self {
-		value.data.chunks_mut(3).for_each(|rgb| {
+		value.data.par_chunks_exact_mut(3).for_each(|rgb| {
 			let (r, g, b) = (rgb[0], rgb[1], rgb[2]);
 			let (h, s, v) = algorithms::pixel_rgb_to_hsv(r, g, b);
 			rgb[0] = h;
@@ -29,7 +38,7 @@ impl From<Image<f32, Srgb>> for Image<f32, Hsv> {
 
 impl From<Image<f32, Hsv>> for Image<f32, Srgb> {
 	fn from(mut value: Image<f32, Hsv>) -> Self {
-		value.data.chunks_mut(3).for_each(|hsv| {
+		value.data.par_chunks_exact_mut(3).for_each(|hsv| {
 			let (h, s, v) = (hsv[0], hsv[1], hsv[2]);
 			let (r, g, b) = algorithms::pixel_hsv_to_rgb(h, s, v);
 			hsv[0] = r;
@@ -0,0 +1,70 @@
+//! Going to and from the [image](image_rs) crate's buffers, which is what
+//! most Rust graphics code takes. Both keep their pixels as one Vec of
+//! interleaved RGB with no padding between rows, so the Vec moves across and
+//! nothing's copied.
+//!
+//! Only gamma'd sRGB crosses over, in u8 and u16. That's the only thing an
+//! `RgbImage` ever is to anyone, and a linear image there would just look
+//! dark. The metadata stays behind; the image crate has nowhere to keep it.
+
+use image_rs::{DynamicImage, ImageBuffer, Rgb};
+
+use crate::{colorspace::Srgb, Error};
+
+use super::{Image, RawMetadata};
+
+macro_rules! interop {
+	($sample:ty, $variant:ident, $into:ident) => {
+		impl TryFrom<Image<$sample, Srgb>> for ImageBuffer<Rgb<$sample>, Vec<$sample>> {
+			type Error = Error;
+
+			/// Errors with [TooBigForImageCrate](Error::TooBigForImageCrate)
+			/// if the image is wider or taller than a u32 can say
+			fn try_from(img: Image<$sample, Srgb>) -> Result<Self, Error> {
+				let too_big = || Error::TooBigForImageCrate {
+					width: img.width,
+					height: img.height,
+				};
+				let width = u32::try_from(img.width).map_err(|_| too_big())?;
+				let height = u32::try_from(img.height).map_err(|_| too_big())?;
+
+				// It's three samples to a pixel on both sides, so it's always
+				// exactly the right length
+				Ok(ImageBuffer::from_raw(width, height, img.data).unwrap())
+			}
+		}
+
+		impl TryFrom<Image<$sample, Srgb>> for DynamicImage {
+			type Error = Error;
+
+			/// Like the [ImageBuffer] conversion, this errors if the image is
+			/// too big for the image crate
+			fn try_from(img: Image<$sample, Srgb>) -> Result<Self, Error> {
+				Ok(DynamicImage::$variant(img.try_into()?))
+			}
+		}
+
+		impl Image<$sample, Srgb> {
+			/// Take an image crate buffer back, with the metadata to give it.
+			/// Usually that's what it had before you converted it over.
+			pub fn from_image_buffer(
+				buffer: ImageBuffer<Rgb<$sample>, Vec<$sample>>,
+				metadata: RawMetadata,
+			) -> Self {
+				let (width, height) = buffer.dimensions();
+				Image::from_raw_parts(width as usize, height as usize, metadata, buffer.into_raw())
+			}
+
+			/// [from_image_buffer](Self::from_image_buffer) for any image the
+			/// image crate has. If it isn't already RGB in this sample size
+			/// the image crate converts it; alpha is dropped and grey is
+			/// spread to all three channels.
+			pub fn from_dynamic_image(image: DynamicImage, metadata: RawMetadata) -> Self {
+				Self::from_image_buffer(image.$into(), metadata)
+			}
+		}
+	};
+}
+
+interop!(u8, ImageRgb8, into_rgb8);
+interop!(u16, ImageRgb16, into_rgb16);
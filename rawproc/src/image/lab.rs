@@ -0,0 +1,127 @@
+use nalgebra::{Matrix3, Vector3};
+
+use crate::{
+	colorspace::{Lab, LinSrgb, Oklab, XYZ},
+	par::*,
+};
+
+use super::{Image, RawMetadata};
+
+// Where the cube root in Lab's curve switches to a straight line
+const DELTA: f32 = 6.0 / 29.0;
+
+impl Image<f32, XYZ> {
+	/// To CIE L\*a\*b\*. The reference white is whatever the camera's
+	/// `(1, 1, 1)` became in XYZ, which after
+	/// [whitebalance](Image::whitebalance) is the scene's white, so greys
+	/// come out with no colour.
+	pub fn to_lab(mut self) -> Image<f32, Lab> {
+		let white = reference_white(&self.metadata);
+		self.data.par_chunks_exact_mut(3).for_each(|px| {
+			let fx = lab_f(px[0] / white[0]);
+			let fy = lab_f(px[1] / white[1]);
+			let fz = lab_f(px[2] / white[2]);
+
+			px[0] = 116.0 * fy - 16.0;
+			px[1] = 500.0 * (fx - fy);
+			px[2] = 200.0 * (fy - fz);
+		});
+
+		self.change_colorspace(None)
+	}
+}
+
+impl Image<f32, Lab> {
+	/// Back to XYZ, using the same white [to_lab](Image::to_lab) did
+	pub fn to_xyz(mut self) -> Image<f32, XYZ> {
+		let white = reference_white(&self.metadata);
+		self.data.par_chunks_exact_mut(3).for_each(|px| {
+			let fy = (px[0] + 16.0) / 116.0;
+			let fx = fy + px[1] / 500.0;
+			let fz = fy - px[2] / 200.0;
+
+			px[0] = lab_f_inverse(fx) * white[0];
+			px[1] = lab_f_inverse(fy) * white[1];
+			px[2] = lab_f_inverse(fz) * white[2];
+		});
+
+		self.change_colorspace(None)
+	}
+}
+
+impl Image<f32, LinSrgb> {
+	/// To Oklab. Colours outside of sRGB, with negative values, go through
+	/// fine.
+	pub fn to_oklab(mut self) -> Image<f32, Oklab> {
+		self.data.par_chunks_exact_mut(3).for_each(|px| {
+			let lms = LINSRGB_TO_LMS * Vector3::new(px[0], px[1], px[2]);
+			let lab = LMS_TO_OKLAB * lms.map(f32::cbrt);
+			px.copy_from_slice(lab.as_slice());
+		});
+
+		self.change_colorspace(None)
+	}
+}
+
+impl Image<f32, Oklab> {
+	pub fn to_linsrgb(mut self) -> Image<f32, LinSrgb> {
+		self.data.par_chunks_exact_mut(3).for_each(|px| {
+			let lms = OKLAB_TO_LMS * Vector3::new(px[0], px[1], px[2]);
+			let rgb = LMS_TO_LINSRGB * lms.map(|v| v * v * v);
+			px.copy_from_slice(rgb.as_slice());
+		});
+
+		self.change_colorspace(None)
+	}
+}
+
+/// What the camera calls white, `(1, 1, 1)`, in XYZ
+fn reference_white(metadata: &RawMetadata) -> Vector3<f32> {
+	metadata.cam_to_xyz * Vector3::new(1.0, 1.0, 1.0)
+}
+
+fn lab_f(t: f32) -> f32 {
+	if t > DELTA * DELTA * DELTA {
+		t.cbrt()
+	} else {
+		t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+	}
+}
+
+fn lab_f_inverse(t: f32) -> f32 {
+	if t > DELTA {
+		t * t * t
+	} else {
+		3.0 * DELTA * DELTA * (t - 4.0 / 29.0)
+	}
+}
+
+// The Oklab matrices, from https://bottosson.github.io/posts/oklab/, rounded
+// to what fits in an f32
+#[rustfmt::skip]
+const LINSRGB_TO_LMS: Matrix3<f32> = Matrix3::new(
+	0.41222146, 0.53633255, 0.051445995,
+	0.2119035,  0.6806995,  0.10739696,
+	0.08830246, 0.28171885, 0.6299787,
+);
+
+#[rustfmt::skip]
+const LMS_TO_OKLAB: Matrix3<f32> = Matrix3::new(
+	0.21045426,  0.7936178, -0.004072047,
+	1.9779985,  -2.4285922,  0.4505937,
+	0.025904037, 0.78277177, -0.80867577,
+);
+
+#[rustfmt::skip]
+const OKLAB_TO_LMS: Matrix3<f32> = Matrix3::new(
+	1.0,  0.39633778,  0.21580376,
+	1.0, -0.105561346, -0.06385417,
+	1.0, -0.08948418,  -1.2914855,
+);
+
+#[rustfmt::skip]
+const LMS_TO_LINSRGB: Matrix3<f32> = Matrix3::new(
+	 4.0767417,   -3.3077116,  0.23096994,
+	-1.268438,     2.6097574, -0.34131938,
+	-0.0041960863, -0.7034186,  1.7076147,
+);
@@ -0,0 +1,243 @@
+use rawloader::CFA;
+
+use crate::{
+	colorspace::{Colorspace, ColorspaceKind},
+	par::*,
+};
+
+use super::{cfa, Image};
+
+impl<C: Colorspace> Image<u16, C> {
+	/// Convert to floats where 0.0 is the blacklevel and 1.0 is the
+	/// whitelevel, using the levels for each channel.
+	///
+	/// Values below black come out negative and values above white come out
+	/// above 1.0. Nothing is clamped. The levels in the metadata become 0
+	/// and 1 to match.
+	pub fn normalize(self) -> Image<f32, C> {
+		self.normalize_into(vec![])
+	}
+
+	/// [normalize](Self::normalize), but the floats go in a buffer you give
+	/// us instead of a new one. Whatever's in it is overwritten, and it's
+	/// grown if it's too small.
+	///
+	/// This image is left alone, so you can keep a decoded raw around and
+	/// normalize it again every time you render without copying it first.
+	pub fn normalize_into(&self, mut floats: Vec<f32>) -> Image<f32, C> {
+		let mut metadata = self.metadata.clone();
+		let width = self.width;
+
+		let black = metadata.blacklevels.map(|b| b as f32);
+		let range = [0, 1, 2].map(|c| (metadata.whitelevels[c] as f32 - black[c]).max(1.0));
+
+		floats.clear();
+		floats.par_extend(self.data.par_iter().enumerate().map(|(idx, &sixteen)| {
+			let c = channel_of::<C>(&metadata.cfa, width, idx);
+			(sixteen as f32 - black[c]) / range[c]
+		}));
+
+		// The same levels the float DNG reader gives, so anything reading
+		// them off normalized data gets the range it's really in
+		metadata.blacklevels = [0; 3];
+		metadata.whitelevels = [1; 3];
+
+		Image {
+			width,
+			height: self.height,
+			metadata,
+			data: floats,
+			phantom: Default::default(),
+		}
+	}
+}
+
+impl<C: Colorspace> Image<u16, C> {
+	/// [normalize](Self::normalize), but saturating: anything below black is
+	/// 0.0 and anything above white is 1.0.
+	pub fn to_f32_normalized(self) -> Image<f32, C> {
+		let mut floats = self.normalize();
+		floats
+			.data
+			.par_iter_mut()
+			.for_each(|float| *float = float.clamp(0.0, 1.0));
+		floats
+	}
+
+	/// Scale to bytes, black at 0 and white at 255, using the levels for each
+	/// channel. Values outside of the levels saturate. The levels in the
+	/// metadata are updated to match.
+	pub fn to_u8_scaled(self) -> Image<u8, C> {
+		let Image {
+			width,
+			height,
+			mut metadata,
+			data,
+			phantom: _phantom,
+		} = self;
+
+		let black = metadata.blacklevels.map(|b| b as f32);
+		let range = [0, 1, 2].map(|c| (metadata.whitelevels[c] as f32 - black[c]).max(1.0));
+
+		let data = data
+			.into_par_iter()
+			.enumerate()
+			.map(|(idx, sixteen)| {
+				let c = channel_of::<C>(&metadata.cfa, width, idx);
+				let scaled = (sixteen as f32 - black[c]) / range[c] * 255.0;
+				scaled.round().clamp(0.0, 255.0) as u8
+			})
+			.collect();
+
+		metadata.whitelevels = [u8::MAX as u16; 3];
+		metadata.blacklevels = [0; 3];
+
+		Image {
+			width,
+			height,
+			metadata,
+			data,
+			phantom: Default::default(),
+		}
+	}
+}
+
+impl<C: Colorspace> Image<u16, C> {
+	/// Subtract each channel's black level, clamping at zero, so black is
+	/// really 0. Do this before the whitebalance: the multipliers are meant
+	/// for light, and scaling the black level with it lifts the shadows and
+	/// tints them.
+	///
+	/// The levels in the metadata are moved down to match, black to 0 and
+	/// white to what's left of the range, so [normalize](Self::normalize)
+	/// still gives the same answer afterwards.
+	pub fn black_levels(&mut self) {
+		let black = self.metadata.blacklevels;
+		let (width, cfa) = (self.width, &self.metadata.cfa);
+		self.data
+			.par_iter_mut()
+			.enumerate()
+			.for_each(|(idx, value)| {
+				let c = channel_of::<C>(cfa, width, idx);
+				*value = value.saturating_sub(black[c]);
+			});
+
+		for (white, black) in self.metadata.whitelevels.iter_mut().zip(black) {
+			*white = white.saturating_sub(black);
+		}
+		self.metadata.blacklevels = [0; 3];
+	}
+}
+
+impl<C: Colorspace> Image<f32, C> {
+	/// [black_levels](Image::black_levels) for floats that are still in the
+	/// sensor's units, like from [from_raw_parts](Image::from_raw_parts).
+	/// Anything that's been [normalized](Image::normalize) already has black
+	/// at 0, and this does nothing to it.
+	pub fn black_levels(&mut self) {
+		let black = self.metadata.blacklevels.map(|b| b as f32);
+		let (width, cfa) = (self.width, &self.metadata.cfa);
+		self.data
+			.par_iter_mut()
+			.enumerate()
+			.for_each(|(idx, value)| {
+				let c = channel_of::<C>(cfa, width, idx);
+				*value = (*value - black[c]).max(0.0);
+			});
+
+		let metadata = &mut self.metadata;
+		for (white, black) in metadata.whitelevels.iter_mut().zip(metadata.blacklevels) {
+			*white = white.saturating_sub(black);
+		}
+		metadata.blacklevels = [0; 3];
+	}
+
+	/// Normalized floats to the full 16 bits, saturating. The same as
+	/// [rescale_to_bitdepth(16)](Self::rescale_to_bitdepth).
+	pub fn to_u16(self) -> Image<u16, C> {
+		self.rescale_to_bitdepth(16)
+	}
+
+	/// Normalized floats to bytes, rounding to the nearest and saturating.
+	/// The levels in the metadata are updated to match.
+	pub fn to_u8(self) -> Image<u8, C> {
+		self.to_u8_into(vec![])
+	}
+
+	/// [to_u8](Self::to_u8) into a buffer you give us. Like
+	/// [normalize_into](Image::normalize_into) it's overwritten and grown if
+	/// it has to be, and this image is left alone so its floats can be
+	/// reused too.
+	pub fn to_u8_into(&self, mut bytes: Vec<u8>) -> Image<u8, C> {
+		let mut metadata = self.metadata.clone();
+
+		bytes.clear();
+		bytes.par_extend(
+			self.data
+				.par_iter()
+				.map(|float| (float * 255.0).round().clamp(0.0, 255.0) as u8),
+		);
+
+		metadata.whitelevels = [u8::MAX as u16; 3];
+		metadata.blacklevels = [0; 3];
+
+		Image {
+			width: self.width,
+			height: self.height,
+			metadata,
+			data: bytes,
+			phantom: Default::default(),
+		}
+	}
+
+	/// Scale normalized floats up to integers that are `bits` wide, clamping
+	/// to the range. The levels in the metadata are updated to match so the
+	/// u16 operations know what they're working with.
+	///
+	/// # Panics
+	/// If `bits` isn't between 1 and 16
+	pub fn rescale_to_bitdepth(self, bits: u8) -> Image<u16, C> {
+		assert!(
+			(1..=16).contains(&bits),
+			"bitdepth needs to be between 1 and 16, inclusive"
+		);
+
+		let Image {
+			width,
+			height,
+			mut metadata,
+			data,
+			phantom: _phantom,
+		} = self;
+
+		let max = ((1u32 << bits) - 1) as f32;
+		let data = data
+			.into_par_iter()
+			.map(|float| (float.clamp(0.0, 1.0) * max).round() as u16)
+			.collect();
+
+		metadata.whitelevels = [max as u16; 3];
+		metadata.blacklevels = [0; 3];
+
+		Image {
+			width,
+			height,
+			metadata,
+			data,
+			phantom: Default::default(),
+		}
+	}
+}
+
+/// Which of red, green, or blue the value at `idx` of the data is.
+/// Monochrome has the same levels for all three, so it's always red.
+fn channel_of<C: Colorspace>(cfa: &CFA, width: usize, idx: usize) -> usize {
+	if C::KIND == ColorspaceKind::Monochrome {
+		0
+	} else if C::COMPONENTS == 1 {
+		// Bayer data
+		cfa::channel(cfa.color_at(idx / width, idx % width))
+	} else {
+		idx % C::COMPONENTS
+	}
+}
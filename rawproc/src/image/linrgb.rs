@@ -1,23 +1,199 @@
 use nalgebra::Matrix3x1;
 
-use crate::colorspace::{LinRgb, XYZ};
+use crate::{
+	colorspace::{LinRgb, Srgb, XYZ},
+	par::*,
+};
 
-use super::Image;
+use super::{noise::median, simd, Image};
 
 impl Image<u16, LinRgb> {
+	/// Whitebalance an image that didn't need debayering, like a LinearRaw
+	/// DNG. Anything that was debayered was already balanced as BayerRgb.
+	pub fn whitebalance(&mut self) {
+		let wb = self.metadata.whitebalance;
+		self.data.par_chunks_exact_mut(3).for_each(|px| {
+			for (c, light) in px.iter_mut().enumerate() {
+				*light = (*light as f32 * wb[c]) as u16;
+			}
+		});
+	}
+
 	pub fn to_xyz(mut self) -> Image<u16, XYZ> {
-		for px in self.data.chunks_mut(3) {
+		let (white, cam_to_xyz) = (self.metadata.whitelevels, self.metadata.cam_to_xyz);
+		self.data.par_chunks_exact_mut(3).for_each(|px| {
 			let m = Matrix3x1::new(
-				px[0] as f32 / self.metadata.whitelevels[0] as f32,
-				px[1] as f32 / self.metadata.whitelevels[1] as f32,
-				px[2] as f32 / self.metadata.whitelevels[2] as f32,
+				px[0] as f32 / white[0] as f32,
+				px[1] as f32 / white[1] as f32,
+				px[2] as f32 / white[2] as f32,
 			);
-			let res = self.metadata.cam_to_xyz * m;
-			px[0] = (res[0] * self.metadata.whitelevels[0] as f32) as u16;
-			px[1] = (res[1] * self.metadata.whitelevels[1] as f32) as u16;
-			px[2] = (res[2] * self.metadata.whitelevels[2] as f32) as u16;
-		}
+			let res = cam_to_xyz * m;
+			px[0] = (res[0] * white[0] as f32) as u16;
+			px[1] = (res[1] * white[1] as f32) as u16;
+			px[2] = (res[2] * white[2] as f32) as u16;
+		});
+
+		self.change_colorspace(None)
+	}
+}
+
+impl Image<f32, LinRgb> {
+	/// Whitebalance an image that didn't need debayering, like a LinearRaw
+	/// DNG. Anything that was debayered was already balanced as BayerRgb.
+	pub fn whitebalance(&mut self) {
+		let wb = self.metadata.whitebalance;
+		self.data
+			.par_chunks_mut(simd::CHUNK)
+			.for_each(|chunk| simd::scale_row(chunk, &wb));
+	}
+}
+
+impl Image<f32, LinRgb> {
+	/// Camera RGB to XYZ with the camera's colour matrix. Do this after the
+	/// whitebalance and debayer, on values normalized so 1.0 is white.
+	pub fn to_xyz(mut self) -> Image<f32, XYZ> {
+		let cam_to_xyz = self.metadata.cam_to_xyz;
+		self.data.par_chunks_exact_mut(3).for_each(|px| {
+			let res = cam_to_xyz * Matrix3x1::new(px[0], px[1], px[2]);
+			px.copy_from_slice(res.as_slice());
+		});
 
 		self.change_colorspace(None)
 	}
+
+	/// The whole way from camera RGB to something you can look at: XYZ, then
+	/// linear sRGB, then the sRGB curve. There's no tone curve in here; if
+	/// you want one, stop at [LinSrgb](crate::colorspace::LinSrgb) and put
+	/// it on before [gamma](Image::gamma).
+	pub fn to_srgb(self) -> Image<f32, Srgb> {
+		self.to_xyz().to_linsrgb().gamma()
+	}
+}
+
+// How finely histograms are binned for matching. The lookup interpolates
+// between bins, so this is plenty even for 16-bit data.
+const HISTOGRAM_BINS: usize = 4096;
+
+impl Image<f32, LinRgb> {
+	/// Reshape every channel's histogram to look like the same channel of
+	/// `reference`. Frames of a catalog or time series shot as the light
+	/// drifted come out looking like they were shot under the reference's.
+	///
+	/// The images don't have to be the same size.
+	pub fn match_histogram(&mut self, reference: &Image<f32, LinRgb>) {
+		for c in 0..3 {
+			let max = channel_max(&self.data, c).max(channel_max(&reference.data, c));
+			if max <= 0.0 {
+				continue;
+			}
+
+			let ours = cdf(&self.data, c, max);
+			let theirs = cdf(&reference.data, c, max);
+			let lut: Vec<f32> = ours
+				.iter()
+				.map(|p| inverse_cdf(&theirs, *p) * max)
+				.collect();
+
+			for px in self.data.chunks_exact_mut(3) {
+				let pos = (px[c] / max).clamp(0.0, 1.0) * (HISTOGRAM_BINS - 1) as f32;
+				let low = pos.floor() as usize;
+				let high = (low + 1).min(HISTOGRAM_BINS - 1);
+				px[c] = lut[low] + (lut[high] - lut[low]) * pos.fract();
+			}
+		}
+	}
+}
+
+fn channel_max(data: &[f32], c: usize) -> f32 {
+	data.chunks_exact(3).map(|px| px[c]).fold(0.0, f32::max)
+}
+
+/// The cumulative histogram of channel `c`, binned over 0 to `max` and
+/// normalized so the last bin is 1.0
+fn cdf(data: &[f32], c: usize, max: f32) -> Vec<f32> {
+	let mut bins = vec![0.0f32; HISTOGRAM_BINS];
+	for px in data.chunks_exact(3) {
+		let pos = (px[c] / max).clamp(0.0, 1.0) * (HISTOGRAM_BINS - 1) as f32;
+		bins[pos.round() as usize] += 1.0;
+	}
+
+	let total = (data.len() / 3).max(1) as f32;
+	let mut sum = 0.0;
+	for bin in bins.iter_mut() {
+		sum += *bin;
+		*bin = sum / total;
+	}
+
+	bins
+}
+
+/// Where, from 0 to 1, the cumulative histogram reaches `p`
+fn inverse_cdf(cdf: &[f32], p: f32) -> f32 {
+	let idx = cdf.partition_point(|v| *v < p);
+	if idx == 0 {
+		return 0.0;
+	}
+	if idx >= cdf.len() {
+		return 1.0;
+	}
+
+	// Interpolate between the bin before and the one that got there
+	let (before, after) = (cdf[idx - 1], cdf[idx]);
+	let fract = if after > before {
+		(p - before) / (after - before)
+	} else {
+		0.0
+	};
+
+	(idx as f32 - 1.0 + fract) / (cdf.len() - 1) as f32
+}
+
+impl Image<f32, LinRgb> {
+	/// Clean up the speckles of false colour that demosaicing leaves in
+	/// noisy, high ISO, frames. Red and blue are each kept as a difference
+	/// from green and those differences are replaced with their median over
+	/// the pixels within `radius`. Green, which carries most of the detail,
+	/// is left alone, so edges stay sharp while the rainbow noise goes.
+	///
+	/// Do this straight after the debayer. A radius of 1 is usually enough
+	/// and 2 catches the blotchier noise at the highest ISOs.
+	pub fn suppress_false_color(&mut self, radius: usize) {
+		let (width, height) = (self.width, self.height);
+		if radius == 0 || width == 0 {
+			return;
+		}
+		let data = &self.data;
+
+		let mut out = vec![0.0; data.len()];
+		out.par_chunks_exact_mut(width * 3)
+			.enumerate()
+			.for_each(|(y, row)| {
+				let top = y.saturating_sub(radius);
+				let bottom = (y + radius + 1).min(height);
+				let mut red = Vec::with_capacity((radius * 2 + 1).pow(2));
+				let mut blue = Vec::with_capacity((radius * 2 + 1).pow(2));
+
+				for (x, px) in row.chunks_exact_mut(3).enumerate() {
+					let left = x.saturating_sub(radius);
+					let right = (x + radius + 1).min(width);
+
+					red.clear();
+					blue.clear();
+					for ny in top..bottom {
+						for nx in left..right {
+							let at = (ny * width + nx) * 3;
+							red.push(data[at] - data[at + 1]);
+							blue.push(data[at + 2] - data[at + 1]);
+						}
+					}
+
+					let green = data[(y * width + x) * 3 + 1];
+					px[0] = green + median(&mut red);
+					px[1] = green;
+					px[2] = green + median(&mut blue);
+				}
+			});
+
+		self.data = out;
+	}
 }
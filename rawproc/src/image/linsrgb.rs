@@ -1,9 +1,11 @@
 use crate::{
 	algorithms,
 	colorspace::{LinSrgb, Srgb},
+	par::*,
+	transfer::TransferFunction,
 };
 
-use super::Image;
+use super::{Image, Mask};
 
 // ascii art u16
 //
@@ -16,12 +18,13 @@ use super::Image;
 
 impl Image<u16, LinSrgb> {
 	pub fn gamma(mut self) -> Image<u16, Srgb> {
-		for px in self.data.iter_mut() {
-			//TOOD: use correct whitelevel
-			let mut float = *px as f32 / self.metadata.whitelevels[0] as f32;
-			float = algorithms::srgb_gamma(float);
-			*px = (float.max(0.0).min(1.0) * self.metadata.whitelevels[0] as f32) as u16;
-		}
+		//TOOD: use correct whitelevel
+		let white = self.metadata.whitelevels[0] as f32;
+		self.data.par_iter_mut().for_each(|px| {
+			let float = *px as f32 / white;
+			let encoded = TransferFunction::Srgb.encode(float);
+			*px = (encoded * white) as u16;
+		});
 
 		self.change_colorspace(None)
 	}
@@ -38,16 +41,24 @@ impl Image<u16, LinSrgb> {
 
 impl Image<f32, LinSrgb> {
 	pub fn gamma(mut self) -> Image<f32, Srgb> {
-		for float in self.data.iter_mut() {
-			*float = algorithms::srgb_gamma(*float);
-		}
-
+		self.encode_transfer(TransferFunction::Srgb);
 		self.change_colorspace(None)
 	}
 
 	pub fn contrast(&mut self, value: f32) {
-		for px in self.data.iter_mut() {
-			*px = algorithms::contrast(*px, value);
-		}
+		self.data
+			.par_iter_mut()
+			.for_each(|px| *px = algorithms::contrast(*px, value));
+	}
+
+	/// Brighten, or darken with a negative number, by `stops`. Give it a
+	/// [Mask] to only change part of the image, like a graduated filter to
+	/// hold back a sky.
+	///
+	/// # Panics
+	/// If the mask isn't the same size as the image.
+	pub fn exposure(&mut self, stops: f32, mask: Option<&Mask>) {
+		let gain = stops.exp2();
+		self.adjust_masked(mask, |rgb| rgb.iter_mut().for_each(|v| *v *= gain));
 	}
 }
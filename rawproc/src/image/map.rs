@@ -0,0 +1,224 @@
+use std::sync::Mutex;
+
+use crate::{colorspace::Colorspace, par::*};
+
+use super::Image;
+
+impl<T: Copy + Clone, C: Colorspace> Image<T, C> {
+	/// Run `f` on every pixel, replacing it with what `f` returns. The pixel
+	/// is given as an array in the colorspace's component order, so for RGB
+	/// images that's `[r, g, b]` and for HSV it's `[h, s, v]`.
+	///
+	/// `N` has to be the colorspace's number of components. It's usually
+	/// inferred from the closure, but if it's not you can annotate the
+	/// argument, like `|[r, g, b]: [f32; 3]|`.
+	///
+	/// # Panics
+	/// If `N` isn't the number of components in the colorspace.
+	pub fn map_pixels<const N: usize, F>(&mut self, f: F)
+	where
+		F: Fn([T; N]) -> [T; N],
+	{
+		Self::check_components::<N>();
+
+		for px in self.data.chunks_exact_mut(N) {
+			let mapped = f(px.try_into().unwrap());
+			px.copy_from_slice(&mapped);
+		}
+	}
+
+	/// [map_pixels](Self::map_pixels), but spread over rayon's thread pool.
+	pub fn par_map_pixels<const N: usize, F>(&mut self, f: F)
+	where
+		T: Send + Sync,
+		F: Fn([T; N]) -> [T; N] + Send + Sync,
+	{
+		Self::check_components::<N>();
+
+		self.data.par_chunks_exact_mut(N).for_each(|px| {
+			let mapped = f(px.try_into().unwrap());
+			px.copy_from_slice(&mapped);
+		});
+	}
+
+	/// Every pixel as an array, in the colorspace's component order, without
+	/// copying anything. `N` has to be the colorspace's number of components.
+	/// Matching on the pixel, like `for [r, g, b] in img.pixels()`, is
+	/// enough to say what it is, otherwise it's `img.pixels::<3>()`.
+	///
+	/// # Panics
+	/// If `N` isn't the number of components in the colorspace.
+	pub fn pixels<const N: usize>(&self) -> impl Iterator<Item = &[T; N]> {
+		Self::check_components::<N>();
+		self.data
+			.chunks_exact(N)
+			.map(|px| <&[T; N]>::try_from(px).unwrap())
+	}
+
+	/// [pixels](Self::pixels) that you can change in place
+	///
+	/// # Panics
+	/// If `N` isn't the number of components in the colorspace.
+	pub fn pixels_mut<const N: usize>(&mut self) -> impl Iterator<Item = &mut [T; N]> {
+		Self::check_components::<N>();
+		self.data
+			.chunks_exact_mut(N)
+			.map(|px| <&mut [T; N]>::try_from(px).unwrap())
+	}
+
+	/// One component of every pixel, left to right and top to bottom, like
+	/// all the reds of an RGB image. If you're going to go over the same
+	/// channel more than once, [into_planar](Self::into_planar) first.
+	///
+	/// # Panics
+	/// If there aren't that many components in the colorspace.
+	pub fn channel(&self, component: usize) -> impl Iterator<Item = T> + '_ {
+		Self::check_component(component);
+		self.data
+			.iter()
+			.skip(component)
+			.step_by(C::COMPONENTS)
+			.copied()
+	}
+
+	/// [channel](Self::channel) that you can change in place
+	///
+	/// # Panics
+	/// If there aren't that many components in the colorspace.
+	pub fn channel_mut(&mut self, component: usize) -> impl Iterator<Item = &mut T> {
+		Self::check_component(component);
+		self.data.iter_mut().skip(component).step_by(C::COMPONENTS)
+	}
+
+	fn check_component(component: usize) {
+		assert!(
+			component < C::COMPONENTS,
+			"there are only {} components",
+			C::COMPONENTS
+		);
+	}
+
+	fn check_components<const N: usize>() {
+		assert_eq!(
+			N,
+			C::COMPONENTS,
+			"pixel arrays need to be the same length as the colorspace has components"
+		);
+	}
+}
+
+/// A run of whole rows out of an image, for [par_rows_mut]. `data` is the
+/// rows one after the other, `width * COMPONENTS` values each.
+///
+/// [par_rows_mut]: Image::par_rows_mut
+pub struct Band<'a, T> {
+	/// The row of the image the band starts at
+	pub y: usize,
+	pub width: usize,
+	/// How many rows are in the band. The last band can be shorter.
+	pub height: usize,
+	pub data: &'a mut [T],
+}
+
+/// [Band], but you can only look
+pub struct BandRef<'a, T> {
+	pub y: usize,
+	pub width: usize,
+	pub height: usize,
+	pub data: &'a [T],
+}
+
+impl<T: Copy + Clone, C: Colorspace> Image<T, C> {
+	/// Split the image into bands of `band_height` rows and run `f` on them
+	/// on as many threads as there are cores. The bands don't overlap, so
+	/// you can write your own parallel filters without splitting the data
+	/// yourself. If a filter needs to read the rows around it, copy the
+	/// image first and read from the copy.
+	///
+	/// It uses scoped threads rather than rayon's pool, so it's fine to call
+	/// from inside a rayon task.
+	///
+	/// # Panics
+	/// If `band_height` is 0, or if `f` panics.
+	pub fn par_rows_mut<F>(&mut self, band_height: usize, f: F)
+	where
+		T: Send,
+		F: Fn(Band<T>) + Sync,
+	{
+		assert!(band_height > 0, "bands need at least one row");
+		let (width, stride) = (self.width, self.width * C::COMPONENTS);
+		if stride == 0 {
+			return;
+		}
+
+		let bands = Mutex::new(self.data.chunks_mut(stride * band_height).enumerate());
+		run_scoped(|| loop {
+			let next = bands.lock().unwrap().next();
+			let Some((idx, data)) = next else { break };
+
+			f(Band {
+				y: idx * band_height,
+				width,
+				height: data.len() / stride,
+				data,
+			});
+		});
+	}
+
+	/// [par_rows_mut](Self::par_rows_mut) for when you only need to read,
+	/// like to gather statistics. What `f` returns for each band comes back
+	/// in order, top to bottom.
+	///
+	/// # Panics
+	/// If `band_height` is 0, or if `f` panics.
+	pub fn par_rows<R, F>(&self, band_height: usize, f: F) -> Vec<R>
+	where
+		T: Sync,
+		R: Send,
+		F: Fn(BandRef<T>) -> R + Sync,
+	{
+		assert!(band_height > 0, "bands need at least one row");
+		let (width, stride) = (self.width, self.width * C::COMPONENTS);
+		if stride == 0 {
+			return vec![];
+		}
+
+		let bands = Mutex::new(self.data.chunks(stride * band_height).enumerate());
+		let results = Mutex::new(vec![]);
+		run_scoped(|| loop {
+			let next = bands.lock().unwrap().next();
+			let Some((idx, data)) = next else { break };
+
+			let result = f(BandRef {
+				y: idx * band_height,
+				width,
+				height: data.len() / stride,
+				data,
+			});
+			results.lock().unwrap().push((idx, result));
+		});
+
+		let mut results = results.into_inner().unwrap();
+		results.sort_by_key(|(idx, _)| *idx);
+		results.into_iter().map(|(_, r)| r).collect()
+	}
+}
+
+/// Run `work` on one scoped thread per core and wait for them all. With one
+/// core, or where we can't tell, like wasm32-unknown-unknown which can't
+/// spawn threads at all, it runs right here instead.
+fn run_scoped<F: Fn() + Sync>(work: F) {
+	let threads = std::thread::available_parallelism()
+		.map(|n| n.get())
+		.unwrap_or(1);
+	if threads == 1 {
+		work();
+		return;
+	}
+
+	std::thread::scope(|scope| {
+		for _ in 0..threads {
+			scope.spawn(&work);
+		}
+	});
+}
@@ -0,0 +1,320 @@
+use crate::{algorithms, colorspace::Colorspace, transfer::TransferFunction};
+
+use super::Image;
+
+/// The part of the tonal range a luminosity mask picks out
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ToneRange {
+	/// The darkest third
+	Shadows,
+	/// The middle third
+	Midtones,
+	/// The brightest third
+	Highlights,
+}
+
+impl ToneRange {
+	/// Where the range starts and ends in sRGB encoded lightness
+	fn bounds(&self) -> (f32, f32) {
+		match self {
+			ToneRange::Shadows => (f32::NEG_INFINITY, 1.0 / 3.0),
+			ToneRange::Midtones => (1.0 / 3.0, 2.0 / 3.0),
+			ToneRange::Highlights => (2.0 / 3.0, f32::INFINITY),
+		}
+	}
+}
+
+/// How much of an adjustment each pixel gets, from 0.0 for none of it to
+/// 1.0 for all of it. A mask is the same size as the image it's used on,
+/// with one weight per pixel, row by row.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mask {
+	pub width: usize,
+	pub height: usize,
+	pub weights: Vec<f32>,
+}
+
+impl Mask {
+	/// A mask with the same weight everywhere
+	pub fn new(width: usize, height: usize, weight: f32) -> Self {
+		Self {
+			width,
+			height,
+			weights: vec![weight; width * height],
+		}
+	}
+
+	/// # Panics
+	/// If there isn't one weight per pixel.
+	pub fn from_weights(width: usize, height: usize, weights: Vec<f32>) -> Self {
+		assert_eq!(
+			weights.len(),
+			width * height,
+			"a mask needs one weight per pixel"
+		);
+
+		Self {
+			width,
+			height,
+			weights,
+		}
+	}
+
+	/// A graduated filter. The weight is 1.0 on the `start` side of the line
+	/// through `start`, 0.0 past the line through `end`, and eases from one
+	/// to the other in between. Both lines are at right angles to the one
+	/// from `start` to `end`, and the points are in pixels, `(x, y)`.
+	///
+	/// For a sky, start at the top of the frame and end at the horizon.
+	pub fn linear(width: usize, height: usize, start: (f32, f32), end: (f32, f32)) -> Self {
+		let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+		let length_squared = dx * dx + dy * dy;
+
+		Self::from_fn(width, height, |x, y| {
+			if length_squared == 0.0 {
+				return 1.0;
+			}
+
+			// How far along from start to end the pixel is
+			let t = ((x - start.0) * dx + (y - start.1) * dy) / length_squared;
+			1.0 - smoothstep(t)
+		})
+	}
+
+	/// An ellipse that's 1.0 inside and 0.0 outside, centered on `center`
+	/// with a `radius` of `(horizontal, vertical)` pixels. `feather` is how
+	/// much of the radius the edge is softened over, from 0.0 for a hard
+	/// edge to 1.0 for fading out all the way from the middle.
+	pub fn radial(
+		width: usize,
+		height: usize,
+		center: (f32, f32),
+		radius: (f32, f32),
+		feather: f32,
+	) -> Self {
+		let feather = feather.clamp(0.0, 1.0);
+		let inner = 1.0 - feather;
+
+		Self::from_fn(width, height, |x, y| {
+			let rx = (x - center.0) / radius.0.max(f32::EPSILON);
+			let ry = (y - center.1) / radius.1.max(f32::EPSILON);
+			let distance = (rx * rx + ry * ry).sqrt();
+
+			if feather == 0.0 {
+				return if distance <= 1.0 { 1.0 } else { 0.0 };
+			}
+			1.0 - smoothstep((distance - inner) / feather)
+		})
+	}
+
+	/// Select the pixels of a linear image whose lightness is between `low`
+	/// and `high`. Lightness is the luminance with the sRGB curve on it, so
+	/// 0.5 looks like it's halfway, and the weight fades out over `feather`
+	/// on either side of the range instead of cutting off.
+	pub fn luminosity<C: Colorspace>(
+		image: &Image<f32, C>,
+		low: f32,
+		high: f32,
+		feather: f32,
+	) -> Self {
+		let weights = image
+			.data
+			.chunks_exact(C::COMPONENTS)
+			.map(|px| {
+				let lightness = TransferFunction::Srgb.encode(algorithms::luminance(px));
+				let distance = (low - lightness).max(lightness - high);
+
+				if distance <= 0.0 {
+					1.0
+				} else if feather <= 0.0 {
+					0.0
+				} else {
+					1.0 - smoothstep(distance / feather)
+				}
+			})
+			.collect();
+
+		Self {
+			width: image.width,
+			height: image.height,
+			weights,
+		}
+	}
+
+	/// A [luminosity](Self::luminosity) mask for the shadows, midtones, or
+	/// highlights. Intersect it with a gradient to, say, only pull down the
+	/// highlights in the sky.
+	pub fn tone_range<C: Colorspace>(
+		image: &Image<f32, C>,
+		range: ToneRange,
+		feather: f32,
+	) -> Self {
+		let (low, high) = range.bounds();
+		Self::luminosity(image, low, high, feather)
+	}
+
+	/// Select the pixels of a linear image that are close in colour to
+	/// `reference`, which is linear too. Close is a hue within
+	/// `hue_tolerance` degrees of the reference's and a chroma within
+	/// `chroma_tolerance` of its chroma, both measured after the sRGB curve
+	/// so they line up with what you see. Past the tolerances the weight fades
+	/// out over another `feather` times the tolerance.
+	///
+	/// Greys don't have a hue to speak of, so pick a reference with some
+	/// colour in it.
+	pub fn color_range<C: Colorspace>(
+		image: &Image<f32, C>,
+		reference: [f32; 3],
+		hue_tolerance: f32,
+		chroma_tolerance: f32,
+		feather: f32,
+	) -> Self {
+		let hue_chroma = |px: &[f32]| {
+			let [r, g, b] = [px[0], px[1], px[2]].map(|c| TransferFunction::Srgb.encode(c));
+			let (hue, saturation, value) = algorithms::pixel_rgb_to_hsv(r, g, b);
+			(hue, saturation * value)
+		};
+		let falloff = |distance: f32, tolerance: f32| {
+			let fade = tolerance * feather;
+			if distance <= tolerance {
+				1.0
+			} else if fade <= 0.0 {
+				0.0
+			} else {
+				1.0 - smoothstep((distance - tolerance) / fade)
+			}
+		};
+
+		let (ref_hue, ref_chroma) = hue_chroma(&reference);
+		let weights = image
+			.data
+			.chunks_exact(C::COMPONENTS)
+			.map(|px| {
+				if px.len() < 3 {
+					return 0.0;
+				}
+
+				let (hue, chroma) = hue_chroma(px);
+				let hue_distance = (hue - ref_hue).abs();
+				let hue_distance = hue_distance.min(360.0 - hue_distance);
+
+				falloff(hue_distance, hue_tolerance)
+					* falloff((chroma - ref_chroma).abs(), chroma_tolerance)
+			})
+			.collect();
+
+		Self {
+			width: image.width,
+			height: image.height,
+			weights,
+		}
+	}
+
+	/// Each weight is `f` of the middle of its pixel
+	fn from_fn<F: Fn(f32, f32) -> f32>(width: usize, height: usize, f: F) -> Self {
+		let mut weights = Vec::with_capacity(width * height);
+		for y in 0..height {
+			for x in 0..width {
+				weights.push(f(x as f32 + 0.5, y as f32 + 0.5));
+			}
+		}
+
+		Self {
+			width,
+			height,
+			weights,
+		}
+	}
+
+	pub fn weight(&self, x: usize, y: usize) -> f32 {
+		self.weights[y * self.width + x]
+	}
+
+	/// Swap what is and isn't masked
+	pub fn invert(&mut self) {
+		self.weights.iter_mut().for_each(|w| *w = 1.0 - *w);
+	}
+
+	/// Only keep what's in both masks, like a radial inside a graduated
+	/// filter.
+	///
+	/// # Panics
+	/// If the masks aren't the same size.
+	pub fn intersect(&mut self, other: &Mask) {
+		self.check_size(other.width, other.height);
+		for (w, o) in self.weights.iter_mut().zip(&other.weights) {
+			*w *= o;
+		}
+	}
+
+	/// Keep what's in either mask.
+	///
+	/// # Panics
+	/// If the masks aren't the same size.
+	pub fn union(&mut self, other: &Mask) {
+		self.check_size(other.width, other.height);
+		for (w, o) in self.weights.iter_mut().zip(&other.weights) {
+			*w = *w + o - *w * o;
+		}
+	}
+
+	pub(crate) fn check_size(&self, width: usize, height: usize) {
+		assert_eq!(
+			(self.width, self.height),
+			(width, height),
+			"the mask needs to be the same size as what it's used with"
+		);
+	}
+}
+
+/// Ease from 0.0 to 1.0 as `t` goes from 0.0 to 1.0 so the mask doesn't
+/// have a visible edge where it starts or stops
+pub(super) fn smoothstep(t: f32) -> f32 {
+	let t = t.clamp(0.0, 1.0);
+	t * t * (3.0 - 2.0 * t)
+}
+
+impl<C: Colorspace> Image<f32, C> {
+	/// Run `f` on every pixel and keep as much of the change as the mask
+	/// says to. Without a mask every pixel gets all of it.
+	///
+	/// # Panics
+	/// If the mask isn't the same size as the image.
+	pub(crate) fn adjust_masked<F>(&mut self, mask: Option<&Mask>, f: F)
+	where
+		F: Fn(&mut [f32]),
+	{
+		let components = C::COMPONENTS;
+		let mask = match mask {
+			None => {
+				self.data.chunks_exact_mut(components).for_each(f);
+				return;
+			}
+			Some(mask) => mask,
+		};
+		mask.check_size(self.width, self.height);
+
+		let mut adjusted = vec![0.0; components];
+		for (px, weight) in self.data.chunks_exact_mut(components).zip(&mask.weights) {
+			if *weight <= 0.0 {
+				continue;
+			}
+
+			adjusted.copy_from_slice(px);
+			f(&mut adjusted);
+			for (v, a) in px.iter_mut().zip(&adjusted) {
+				*v += (a - *v) * weight;
+			}
+		}
+	}
+
+	/// Put every sample through `curve`, like a tone curve. With a mask, only
+	/// the masked part of the image is changed.
+	///
+	/// # Panics
+	/// If the mask isn't the same size as the image.
+	pub fn curve<F: Fn(f32) -> f32>(&mut self, curve: F, mask: Option<&Mask>) {
+		self.adjust_masked(mask, |px| px.iter_mut().for_each(|v| *v = curve(*v)));
+	}
+}
@@ -1,10 +1,63 @@
+mod adapt;
+mod adjust;
+mod alpha;
+mod autoexposure;
+mod autowb;
 mod bayerrgb;
+mod calibrate;
+pub(crate) mod cfa;
+mod curve;
+pub(crate) mod demosaic;
+mod denoise;
+mod dump;
+mod dynamic;
+mod geometry;
+mod heal;
+mod histogram;
 mod hsv;
+#[cfg(feature = "image")]
+mod interop;
+mod lab;
+mod levels;
 mod linrgb;
 mod linsrgb;
+mod map;
+mod mask;
+mod monochrome;
+pub(crate) mod noise;
+mod orientation;
+mod planar;
+mod resize;
+mod sample;
+mod shared;
+mod sharpen;
+mod simd;
 mod srgb;
+mod tile;
+mod transfer;
 mod xyz;
 
+pub use adapt::ChromaticAdaptation;
+pub use alpha::AlphaImage;
+pub use autowb::WhitebalanceMethod;
+pub use calibrate::CalibrationError;
+pub use cfa::{cfa_from_pattern, CfaColors, CfaLayout};
+pub use curve::ToneCurve;
+pub use demosaic::Demosaic;
+pub use dump::ImageHeader;
+pub use dynamic::DynImage;
+pub use geometry::{rotated_crop, valid_region};
+pub use heal::Region;
+pub use histogram::Histogram;
+pub use map::{Band, BandRef};
+pub use mask::{Mask, ToneRange};
+pub use orientation::Orientation;
+pub use planar::PlanarImage;
+pub use resize::{Filter, PrintSize};
+pub use sample::{Sample, SampleKind};
+pub use shared::SharedImage;
+pub use sharpen::OutputMedium;
+pub use srgb::SplitTone;
 pub use xyz::XYZ_TO_SRGB;
 
 use std::marker::PhantomData;
@@ -12,20 +65,232 @@ use std::marker::PhantomData;
 use nalgebra::Matrix3;
 use rawloader::CFA;
 
-use crate::colorspace::{Colorspace, Hsv, LinSrgb, Srgb};
+use crate::{
+	colormatrix,
+	colorspace::{ColorTag, Colorspace, Hsv, LinSrgb, Srgb},
+	exif::Exif,
+	lens::LensCorrection,
+	makernote::{FineTune, Makernote, PresetKind, WhitebalancePreset},
+};
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RawMetadata {
-	/// Whitebalance coefficients. Red, green, blue
+	/// Whitebalance coefficients. Red, green, blue. This is what gets used
+	/// when you call `whitebalance()` and starts out as the as-shot values.
 	pub whitebalance: [f32; 3],
+	/// The whitebalance the camera recorded when the picture was taken
+	pub as_shot_whitebalance: [f32; 3],
+	/// The whitebalance that neutralizes the camera matrix. It's the camera's
+	/// idea of daylight and is what you'd compare against to get a temperature
+	pub daylight_whitebalance: [f32; 3],
+	/// The whitebalance presets from the makernotes. Which ones are here
+	/// depends a lot on the camera, and it might be none of them.
+	pub whitebalance_presets: Vec<WhitebalancePreset>,
+	/// The preset the camera was set to when the photo was taken
+	pub whitebalance_selected: Option<PresetKind>,
+	/// The whitebalance shift set in camera
+	pub whitebalance_fine_tune: Option<FineTune>,
 	/// Whitelevel values; the highest per channel value
 	pub whitelevels: [u16; 3],
-	pub crop: Option<Crop>,
+	/// Blacklevel values; what the sensor reads when it saw no light
+	pub blacklevels: [u16; 3],
+	/// The part of the sensor that saw light. Outside of it are the masked
+	/// pixels used for black level and noise measurements.
+	pub active_area: Option<Crop>,
+	/// The crop the manufacturer thinks looks best, relative to the active
+	/// area. This is often a few pixels in from the edges to hide demosaicing
+	/// artifacts, and it's fine to keep them.
+	pub default_crop: Option<Crop>,
+	#[cfg_attr(feature = "serde", serde(with = "crate::serialize::cfa"))]
 	pub cfa: CFA,
+	/// The camera's colour matrix as the camera, or rawloader, gives it to us.
+	/// This is the same as a DNG's ColorMatrix1.
+	#[cfg_attr(feature = "serde", serde(with = "crate::serialize::matrix"))]
+	pub xyz_to_cam: Matrix3<f32>,
+	#[cfg_attr(feature = "serde", serde(with = "crate::serialize::matrix"))]
 	pub cam_to_xyz: Matrix3<f32>,
+	/// Manufacturer, cleaned up so it's the same across models. Like "Nikon"
+	pub make: String,
+	pub model: String,
+	/// The camera body's serial number, if the file has it
+	pub serial: Option<String>,
+	/// The parts of the makernote we understood, and the parts we didn't.
+	/// None if the camera isn't one we know how to read.
+	pub makernote: Option<Makernote>,
+	/// Every raw image in the file, when there's more than the one we
+	/// decoded. Pick one with [decode_sub_image](crate::decode_sub_image).
+	/// Empty for files that only have the one, or that we can't look into.
+	pub sub_images: Vec<SubImage>,
+	/// The lens corrections the file came with. Only DNGs carry these, see
+	/// [correct_lens_embedded](Image::correct_lens_embedded).
+	pub lens_correction: Option<LensCorrection>,
+	/// Which way up the camera was, see
+	/// [apply_orientation](Image::apply_orientation)
+	pub orientation: Orientation,
+	/// How the photo was taken
+	pub exif: Exif,
+}
+
+impl RawMetadata {
+	/// The XYZ to camera matrix. The one the file gave us if we can use it,
+	/// otherwise the one from our [table](crate::colormatrix) for this make
+	/// and model. None if we have neither.
+	pub fn color_matrix(&self) -> Option<Matrix3<f32>> {
+		Some(self.xyz_to_cam)
+			.filter(colormatrix::is_usable)
+			.or_else(|| colormatrix::lookup(&self.make, &self.model))
+	}
+
+	/// Get the coefficients for a whitebalance source. Presets are normalized
+	/// so that green is 1.0. Returns None if the camera didn't give us that
+	/// preset.
+	pub fn whitebalance_for(&self, source: WhitebalanceSource) -> Option<[f32; 3]> {
+		match source {
+			WhitebalanceSource::AsShot => Some(self.as_shot_whitebalance),
+			WhitebalanceSource::Daylight => Some(self.daylight_whitebalance),
+			WhitebalanceSource::Preset(kind) => self
+				.whitebalance_presets
+				.iter()
+				.find(|p| p.kind == kind)
+				.map(|p| p.coefficients),
+		}
+	}
+
+	/// Set the whitebalance that'll be applied by `whitebalance()`. Returns
+	/// false, leaving the whitebalance alone, if we don't have that source.
+	pub fn use_whitebalance(&mut self, source: WhitebalanceSource) -> bool {
+		match self.whitebalance_for(source) {
+			Some(wb) => {
+				self.whitebalance = wb;
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Shift the whitebalance along the amber-blue and green-magenta axes,
+	/// like the fine tune grid in a camera's whitebalance menu. Shifts add
+	/// up, so do this after you pick the whitebalance.
+	pub fn fine_tune_whitebalance(&mut self, shift: FineTune) {
+		let multipliers = shift.multipliers();
+		for (wb, m) in self.whitebalance.iter_mut().zip(multipliers) {
+			*wb *= m;
+		}
+	}
+
+	/// Apply the fine tune set in camera, so the raw comes out with the same
+	/// shift as the camera's JPEG. Returns false if the camera didn't tell us
+	/// about one.
+	pub fn use_camera_fine_tune(&mut self) -> bool {
+		match self.whitebalance_fine_tune {
+			Some(shift) => {
+				self.fine_tune_whitebalance(shift);
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// How the current whitebalance compares to daylight, per channel, with
+	/// green normalized to 1.0. Red above 1 and blue below 1 means we're
+	/// warming the image compared to daylight.
+	pub fn whitebalance_scale(&self) -> [f32; 3] {
+		let wb = green_normalize(self.whitebalance);
+		let day = green_normalize(self.daylight_whitebalance);
+
+		[wb[0] / day[0], wb[1] / day[1], wb[2] / day[2]]
+	}
+}
+
+fn green_normalize(wb: [f32; 3]) -> [f32; 3] {
+	[wb[0] / wb[1], 1.0, wb[2] / wb[1]]
+}
+
+/// The whitebalance sets we know about from a raw file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WhitebalanceSource {
+	/// What the camera used when the photo was taken
+	AsShot,
+	/// The camera's daylight reference
+	Daylight,
+	/// One of the presets from the makernotes
+	Preset(PresetKind),
+}
+
+/// What metadata an export is allowed to carry. Writers that store
+/// metadata take one of these, and you can [apply](Self::apply) it to an
+/// image's metadata yourself before handing it to anything else.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MetadataPolicy {
+	/// Where the photo was taken
+	pub keep_gps: bool,
+	/// Serial numbers of the body and lens, and the makernote, which is
+	/// where cameras tend to put them
+	pub keep_serial: bool,
+	/// Everything else that isn't needed to show the image right, like the
+	/// make and model
+	pub keep_camera: bool,
+}
+
+impl MetadataPolicy {
+	pub const KEEP_ALL: MetadataPolicy = MetadataPolicy {
+		keep_gps: true,
+		keep_serial: true,
+		keep_camera: true,
+	};
+	pub const STRIP_GPS: MetadataPolicy = MetadataPolicy {
+		keep_gps: false,
+		..Self::KEEP_ALL
+	};
+	pub const STRIP_SERIAL: MetadataPolicy = MetadataPolicy {
+		keep_serial: false,
+		..Self::KEEP_ALL
+	};
+	pub const STRIP_ALL: MetadataPolicy = MetadataPolicy {
+		keep_gps: false,
+		keep_serial: false,
+		keep_camera: false,
+	};
+
+	/// Does this keep everything?
+	pub fn keeps_all(&self) -> bool {
+		*self == Self::KEEP_ALL
+	}
+
+	/// Take out of `metadata` what this policy doesn't keep
+	pub fn apply(&self, metadata: &mut RawMetadata) {
+		if !self.keep_gps {
+			metadata.exif.gps = None;
+		}
+
+		if !self.keep_serial || !self.keep_camera {
+			metadata.serial = None;
+			metadata.makernote = None;
+			metadata.exif.lens_serial = None;
+		}
+
+		if !self.keep_camera {
+			metadata.make.clear();
+			metadata.model.clear();
+			metadata.exif = Exif {
+				gps: metadata.exif.gps,
+				..Default::default()
+			};
+		}
+	}
+}
+
+impl Default for MetadataPolicy {
+	fn default() -> Self {
+		Self::KEEP_ALL
+	}
 }
 
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Crop {
 	pub top: usize,
 	pub right: usize,
@@ -47,6 +312,39 @@ impl Crop {
 			None
 		}
 	}
+
+	/// The region left after cutting this off the edges of a `width` by
+	/// `height` image, or None if the crop is bigger than the image.
+	/// Metadata can't always be trusted.
+	pub(crate) fn region(self, width: usize, height: usize) -> Option<Region> {
+		let horizontal = self.left.checked_add(self.right)?;
+		let vertical = self.top.checked_add(self.bottom)?;
+
+		Some(Region {
+			x: self.left,
+			y: self.top,
+			width: width.checked_sub(horizontal)?,
+			height: height.checked_sub(vertical)?,
+		})
+	}
+}
+
+/// One of the raw images in a file that has more than one, like the other
+/// half of an HDR pair or the reduced resolution copy next to the full one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SubImage {
+	pub width: usize,
+	pub height: usize,
+	/// The TIFF NewSubFileType. 0 is a full resolution image, 1 is a reduced
+	/// resolution one, and DNG has a few more for things like depth maps
+	pub subfile_type: u32,
+	/// Is this the image a plain decode gives you?
+	pub primary: bool,
+	/// Was it already demosaiced? These decode as LinRgb.
+	pub linear: bool,
+	/// Are the samples floats? These need [decode_float](crate::decode_float).
+	pub float: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -76,6 +374,18 @@ impl<T: Copy + Clone, C: Colorspace> Image<T, C> {
 		}
 	}
 
+	/// How to tag a file of this image so viewers know its colorspace. See
+	/// [ColorspaceKind::color_tag](crate::colorspace::ColorspaceKind::color_tag).
+	pub fn color_tag(&self) -> Option<ColorTag> {
+		C::KIND.color_tag()
+	}
+
+	/// The ICC profile to embed with this image, if it's in a colorspace we
+	/// can describe
+	pub fn icc_profile(&self) -> Option<Vec<u8>> {
+		self.color_tag().map(|tag| tag.icc_profile())
+	}
+
 	pub(crate) fn change_colorspace<N: Colorspace>(self, data: Option<Vec<T>>) -> Image<T, N> {
 		Image {
 			width: self.width,
@@ -118,31 +428,7 @@ macro_rules! impl_u16_to_f32 {
 	($colorspace:path) => {
 		impl From<Image<u16, $colorspace>> for Image<f32, $colorspace> {
 			fn from(img: Image<u16, $colorspace>) -> Self {
-				let Image {
-					width,
-					height,
-					metadata,
-					data: data_u16,
-					phantom: _phantom,
-				} = img;
-				let levels = metadata.whitelevels;
-
-				let data = data_u16
-					.into_iter()
-					.enumerate()
-					.map(|(idx, sixteen)| {
-						let color_index = idx % 3;
-						sixteen as f32 / levels[color_index] as f32
-					})
-					.collect();
-
-				Image {
-					width,
-					height,
-					metadata,
-					data,
-					phantom: Default::default(),
-				}
+				img.normalize()
 			}
 		}
 	};
@@ -155,26 +441,7 @@ macro_rules! impl_f32_to_u8 {
 	($colorspace:path) => {
 		impl From<Image<f32, $colorspace>> for Image<u8, $colorspace> {
 			fn from(img: Image<f32, $colorspace>) -> Self {
-				let Image {
-					width,
-					height,
-					metadata,
-					data: data_u8,
-					phantom: _phantom,
-				} = img;
-
-				let data = data_u8
-					.into_iter()
-					.map(|float| (float * 255.0) as u8)
-					.collect();
-
-				Image {
-					width,
-					height,
-					metadata,
-					data,
-					phantom: Default::default(),
-				}
+				img.to_u8()
 			}
 		}
 	};
@@ -188,26 +455,7 @@ macro_rules! impl_f32_to_u16 {
 	($colorspace:path) => {
 		impl From<Image<f32, $colorspace>> for Image<u16, $colorspace> {
 			fn from(img: Image<f32, $colorspace>) -> Self {
-				let Image {
-					width,
-					height,
-					metadata,
-					data: data_u8,
-					phantom: _phantom,
-				} = img;
-
-				let data = data_u8
-					.into_iter()
-					.map(|float| (float * u16::MAX as f32) as u16)
-					.collect();
-
-				Image {
-					width,
-					height,
-					metadata,
-					data,
-					phantom: Default::default(),
-				}
+				img.to_u16()
 			}
 		}
 	};
@@ -0,0 +1,65 @@
+use crate::{
+	colorspace::{Colorspace, LinRgb, LinSrgb, Monochrome},
+	par::*,
+};
+
+use super::{xyz::BRUCE_XYZ_SRGB, Crop, Image};
+
+impl<T: Copy + Clone> Image<T, Monochrome> {
+	/// Crops down to the active area, like [BayerRgb's](Image::crop). There's
+	/// no pattern to keep lined up so any edge is fine.
+	pub fn crop(&mut self) {
+		if let Some(area) = self.metadata.active_area.take() {
+			self.crop_edges(area);
+		}
+	}
+
+	/// Crops to the manufacturer's default crop, and the active area first
+	/// if that hasn't happened yet.
+	pub fn crop_default(&mut self) {
+		self.crop();
+
+		if let Some(crop) = self.metadata.default_crop.take() {
+			self.crop_edges(crop);
+		}
+	}
+
+	/// A crop bigger than the image is metadata we can't make sense of, so
+	/// it's skipped
+	fn crop_edges(&mut self, crop: Crop) {
+		if let Some(region) = crop.region(self.width, self.height) {
+			self.crop_region(region);
+		}
+	}
+}
+
+impl Image<f32, Monochrome> {
+	/// Grey, in RGB. The camera matrix is swapped for one that maps RGB
+	/// white straight to sRGB's, so going on through
+	/// [to_xyz](Image::to_xyz) and [to_linsrgb](Image::to_linsrgb) like any
+	/// other raw leaves it grey. A monochrome camera's matrix, if it even has
+	/// one, doesn't mean anything for a single channel.
+	pub fn to_linrgb(self) -> Image<f32, LinRgb> {
+		let mut rgb: Image<f32, LinRgb> = self.expand();
+
+		let srgb_to_xyz = BRUCE_XYZ_SRGB.try_inverse().unwrap();
+		rgb.metadata.cam_to_xyz = srgb_to_xyz;
+		rgb.metadata.xyz_to_cam = BRUCE_XYZ_SRGB;
+		rgb
+	}
+
+	/// Grey, in linear sRGB, for when there's nothing to do in camera RGB
+	pub fn to_linsrgb(self) -> Image<f32, LinSrgb> {
+		self.expand()
+	}
+
+	/// Copy each sample into all three channels
+	fn expand<C: Colorspace>(self) -> Image<f32, C> {
+		let mut rgb = vec![0.0; self.data.len() * 3];
+		rgb.par_chunks_exact_mut(3)
+			.zip(self.data.par_iter())
+			.for_each(|(px, grey)| px.fill(*grey));
+
+		Image::from_raw_parts(self.width, self.height, self.metadata, rgb)
+	}
+}
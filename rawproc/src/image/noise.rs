@@ -0,0 +1,68 @@
+use crate::{colorspace::Colorspace, par::*};
+
+use super::Image;
+
+impl<C: Colorspace> Image<f32, C> {
+	/// Get rid of salt and pepper noise: single pixels that are much
+	/// brighter, or darker, than everything around them, like the odd hot
+	/// pixel that slipped through or a cosmic ray hit. A sample is an impulse
+	/// if it's more than `threshold` past the brightest, or darkest, of its
+	/// eight neighbours in that channel, and it's replaced with their median.
+	///
+	/// Real detail is never a single pixel on its own, so this leaves it
+	/// alone, and it's a lot cheaper than a full denoise. Run it after the
+	/// debayer, when every pixel has all of its channels.
+	pub fn remove_impulses(&mut self, threshold: f32) {
+		let (width, height) = (self.width, self.height);
+		let components = C::COMPONENTS;
+		if width < 3 || height < 3 {
+			return;
+		}
+		let data = &self.data;
+
+		let mut out = data.clone();
+		out.par_chunks_exact_mut(width * components)
+			.enumerate()
+			.skip(1)
+			.take(height - 2)
+			.for_each(|(y, row)| {
+				for x in 1..width - 1 {
+					for c in 0..components {
+						let at = |x: usize, y: usize| data[(y * width + x) * components + c];
+						let mut neighbours = [
+							at(x - 1, y - 1),
+							at(x, y - 1),
+							at(x + 1, y - 1),
+							at(x - 1, y),
+							at(x + 1, y),
+							at(x - 1, y + 1),
+							at(x, y + 1),
+							at(x + 1, y + 1),
+						];
+
+						let value = at(x, y);
+						let (low, high) = neighbours
+							.iter()
+							.fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), n| {
+								(lo.min(*n), hi.max(*n))
+							});
+
+						if value > high + threshold || value < low - threshold {
+							row[x * components + c] = median(&mut neighbours);
+						}
+					}
+				}
+			});
+
+		self.data = out;
+	}
+}
+
+/// The middle value, reordering `values` to find it. With an even count it's
+/// the higher of the two middle values.
+pub(crate) fn median(values: &mut [f32]) -> f32 {
+	let middle = values.len() / 2;
+	*values
+		.select_nth_unstable_by(middle, |a, b| a.total_cmp(b))
+		.1
+}
@@ -0,0 +1,147 @@
+use crate::colorspace::Colorspace;
+
+use super::Image;
+
+/// Which way up the camera was held, as the EXIF Orientation tag has it.
+/// Sensors always read out the same way, so a portrait shot comes out on its
+/// side until this is applied. Each is what has to be done to the data to
+/// make it upright.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Orientation {
+	#[default]
+	Normal,
+	FlipHorizontal,
+	Rotate180,
+	FlipVertical,
+	/// Mirrored across the top-left to bottom-right diagonal
+	Transpose,
+	/// A quarter turn clockwise
+	Rotate90,
+	/// Mirrored across the top-right to bottom-left diagonal
+	Transverse,
+	/// A quarter turn counterclockwise
+	Rotate270,
+}
+
+impl Orientation {
+	/// From the value of the Orientation tag. Anything that isn't 1 through
+	/// 8 is taken as Normal.
+	pub fn from_exif(value: u16) -> Self {
+		match value {
+			2 => Orientation::FlipHorizontal,
+			3 => Orientation::Rotate180,
+			4 => Orientation::FlipVertical,
+			5 => Orientation::Transpose,
+			6 => Orientation::Rotate90,
+			7 => Orientation::Transverse,
+			8 => Orientation::Rotate270,
+			_ => Orientation::Normal,
+		}
+	}
+
+	pub fn exif(&self) -> u16 {
+		match self {
+			Orientation::Normal => 1,
+			Orientation::FlipHorizontal => 2,
+			Orientation::Rotate180 => 3,
+			Orientation::FlipVertical => 4,
+			Orientation::Transpose => 5,
+			Orientation::Rotate90 => 6,
+			Orientation::Transverse => 7,
+			Orientation::Rotate270 => 8,
+		}
+	}
+}
+
+impl<T: Copy + Clone, C: Colorspace> Image<T, C> {
+	/// A quarter turn clockwise. The width and height swap.
+	///
+	/// None of these move the CFA pattern, so on a mosaic do them after the
+	/// debayer.
+	pub fn rotate90(&mut self) {
+		let (width, height) = (self.width, self.height);
+		self.remap(height, width, |x, y| (y, height - 1 - x));
+	}
+
+	pub fn rotate180(&mut self) {
+		let (width, height) = (self.width, self.height);
+		self.remap(width, height, |x, y| (width - 1 - x, height - 1 - y));
+	}
+
+	/// A quarter turn counterclockwise. The width and height swap.
+	pub fn rotate270(&mut self) {
+		let (width, height) = (self.width, self.height);
+		self.remap(height, width, |x, y| (width - 1 - y, x));
+	}
+
+	/// Mirror left to right
+	pub fn flip_h(&mut self) {
+		let (components, stride) = (C::COMPONENTS, self.width * C::COMPONENTS);
+		for row in self.data.chunks_exact_mut(stride) {
+			for x in 0..self.width / 2 {
+				let mirror = self.width - 1 - x;
+				for c in 0..components {
+					row.swap(x * components + c, mirror * components + c);
+				}
+			}
+		}
+	}
+
+	/// Mirror top to bottom
+	pub fn flip_v(&mut self) {
+		let stride = self.width * C::COMPONENTS;
+		for y in 0..self.height / 2 {
+			let mirror = self.height - 1 - y;
+			let (top, bottom) = self.data.split_at_mut(mirror * stride);
+			top[y * stride..(y + 1) * stride].swap_with_slice(&mut bottom[..stride]);
+		}
+	}
+
+	/// Make the image upright, as the orientation in the metadata says, and
+	/// then set that to Normal so it isn't done twice. Crop first, the crops
+	/// in the metadata are for the sensor the way it was.
+	pub fn apply_orientation(&mut self) {
+		match self.metadata.orientation {
+			Orientation::Normal => (),
+			Orientation::FlipHorizontal => self.flip_h(),
+			Orientation::Rotate180 => self.rotate180(),
+			Orientation::FlipVertical => self.flip_v(),
+			Orientation::Transpose => {
+				self.rotate90();
+				self.flip_h();
+			}
+			Orientation::Rotate90 => self.rotate90(),
+			Orientation::Transverse => {
+				self.rotate90();
+				self.flip_v();
+			}
+			Orientation::Rotate270 => self.rotate270(),
+		}
+
+		self.metadata.orientation = Orientation::Normal;
+	}
+
+	/// Rebuild the image at `width` by `height`, each pixel coming from the
+	/// `(x, y)` that `from` gives for it
+	fn remap<F: Fn(usize, usize) -> (usize, usize)>(
+		&mut self,
+		width: usize,
+		height: usize,
+		from: F,
+	) {
+		let components = C::COMPONENTS;
+		let mut data = Vec::with_capacity(self.data.len());
+		for y in 0..height {
+			for x in 0..width {
+				let (sx, sy) = from(x, y);
+				let at = (sy * self.width + sx) * components;
+				data.extend_from_slice(&self.data[at..at + components]);
+			}
+		}
+
+		self.data = data;
+		self.width = width;
+		self.height = height;
+	}
+}
@@ -0,0 +1,145 @@
+use std::marker::PhantomData;
+
+use crate::{colorspace::Colorspace, par::*};
+
+use super::{Image, RawMetadata};
+
+/// An image with each component in a plane of its own, so for RGB all the
+/// red, then all the green, then all the blue. [Image] keeps them
+/// interleaved, which is what nearly everything wants, but a filter that
+/// works on one channel at a time, like blurring only the chroma, can walk
+/// a plane front to back instead of striding over the other channels.
+#[derive(Clone, Debug)]
+pub struct PlanarImage<T: Copy + Clone, C: Colorspace> {
+	pub width: usize,
+	pub height: usize,
+	pub metadata: RawMetadata,
+
+	/// Every plane one after the other, `width * height` values each
+	pub data: Vec<T>,
+	phantom: PhantomData<C>,
+}
+
+impl<T: Copy + Clone + Send + Sync, C: Colorspace> Image<T, C> {
+	/// Split the components out into planes. It's one pass over the image,
+	/// each plane filled on its own thread, and nothing at all for one
+	/// component images, which are already a single plane.
+	pub fn into_planar(self) -> PlanarImage<T, C> {
+		let data = if C::COMPONENTS == 1 {
+			self.data
+		} else {
+			deinterleave(&self.data, C::COMPONENTS)
+		};
+
+		PlanarImage {
+			width: self.width,
+			height: self.height,
+			metadata: self.metadata,
+			data,
+			phantom: PhantomData,
+		}
+	}
+}
+
+impl<T: Copy + Clone, C: Colorspace> PlanarImage<T, C> {
+	/// One plane, `width * height` long.
+	///
+	/// # Panics
+	/// If there aren't that many components in the colorspace.
+	pub fn plane(&self, component: usize) -> &[T] {
+		let len = self.plane_len(component);
+		&self.data[component * len..][..len]
+	}
+
+	/// # Panics
+	/// If there aren't that many components in the colorspace.
+	pub fn plane_mut(&mut self, component: usize) -> &mut [T] {
+		let len = self.plane_len(component);
+		&mut self.data[component * len..][..len]
+	}
+
+	/// Every plane, in component order
+	pub fn planes(&self) -> impl Iterator<Item = &[T]> {
+		self.data.chunks_exact((self.width * self.height).max(1))
+	}
+
+	/// Every plane, in component order. They don't overlap, so they can go
+	/// to different threads.
+	pub fn planes_mut(&mut self) -> impl Iterator<Item = &mut [T]> {
+		self.data
+			.chunks_exact_mut((self.width * self.height).max(1))
+	}
+
+	fn plane_len(&self, component: usize) -> usize {
+		assert!(
+			component < C::COMPONENTS,
+			"there are only {} components",
+			C::COMPONENTS
+		);
+		self.width * self.height
+	}
+}
+
+impl<T: Copy + Clone + Send + Sync, C: Colorspace> PlanarImage<T, C> {
+	/// Put the components back together, the other way from
+	/// [into_planar](Image::into_planar). Just as cheap.
+	pub fn interleave(self) -> Image<T, C> {
+		let data = if C::COMPONENTS == 1 {
+			self.data
+		} else {
+			interleave(&self.data, self.width, C::COMPONENTS)
+		};
+
+		Image::from_raw_parts(self.width, self.height, self.metadata, data)
+	}
+}
+
+impl<T: Copy + Clone + Send + Sync, C: Colorspace> From<Image<T, C>> for PlanarImage<T, C> {
+	fn from(img: Image<T, C>) -> Self {
+		img.into_planar()
+	}
+}
+
+impl<T: Copy + Clone + Send + Sync, C: Colorspace> From<PlanarImage<T, C>> for Image<T, C> {
+	fn from(planar: PlanarImage<T, C>) -> Self {
+		planar.interleave()
+	}
+}
+
+// Both of these start from a copy so every value is already something and
+// we only have to move them around
+
+fn deinterleave<T: Copy + Send + Sync>(data: &[T], components: usize) -> Vec<T> {
+	let len = data.len() / components;
+	let mut planes = data.to_vec();
+
+	// Filled a plane at a time, so a plane's writes stay on one thread
+	planes
+		.par_chunks_mut(len.max(1))
+		.enumerate()
+		.for_each(|(c, plane)| {
+			for (out, px) in plane.iter_mut().zip(data.chunks_exact(components)) {
+				*out = px[c];
+			}
+		});
+
+	planes
+}
+
+fn interleave<T: Copy + Send + Sync>(planes: &[T], width: usize, components: usize) -> Vec<T> {
+	let len = planes.len() / components;
+	let mut data = planes.to_vec();
+
+	// A row at a time, so each thread reads its bit of every plane in order
+	data.par_chunks_mut((width * components).max(1))
+		.enumerate()
+		.for_each(|(y, row)| {
+			for (x, px) in row.chunks_exact_mut(components).enumerate() {
+				for (c, out) in px.iter_mut().enumerate() {
+					*out = planes[c * len + y * width + x];
+				}
+			}
+		});
+
+	data
+}
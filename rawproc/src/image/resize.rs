@@ -0,0 +1,344 @@
+use std::borrow::Cow;
+
+use crate::{colorspace::Colorspace, par::*, transfer::TransferFunction};
+
+use super::{AlphaImage, Image};
+
+const MM_PER_INCH: f32 = 25.4;
+
+/// How big a print is going to be, and at what resolution
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PrintSize {
+	/// Width and height in millimetres
+	pub width_mm: f32,
+	pub height_mm: f32,
+	/// Dots, or pixels, per inch. 300 is what most labs want.
+	pub dpi: f32,
+}
+
+impl PrintSize {
+	pub fn from_cm(width: f32, height: f32, dpi: f32) -> Self {
+		Self {
+			width_mm: width * 10.0,
+			height_mm: height * 10.0,
+			dpi,
+		}
+	}
+
+	pub fn from_inches(width: f32, height: f32, dpi: f32) -> Self {
+		Self {
+			width_mm: width * MM_PER_INCH,
+			height_mm: height * MM_PER_INCH,
+			dpi,
+		}
+	}
+
+	/// The size in pixels of the whole print
+	pub fn pixels(&self) -> (usize, usize) {
+		let to_pixels = |mm: f32| (mm / MM_PER_INCH * self.dpi).round().max(1.0) as usize;
+		(to_pixels(self.width_mm), to_pixels(self.height_mm))
+	}
+
+	/// The biggest size a `width` by `height` image can be and still fit on
+	/// the print without changing its shape. The print is turned to match
+	/// the image, so a portrait image on a 30x20cm print is 20x30cm.
+	pub fn fit(&self, width: usize, height: usize) -> (usize, usize) {
+		let (mut print_width, mut print_height) = self.pixels();
+		if (width > height) != (print_width > print_height) {
+			std::mem::swap(&mut print_width, &mut print_height);
+		}
+
+		let scale = (print_width as f32 / width as f32).min(print_height as f32 / height as f32);
+		(
+			((width as f32 * scale).round() as usize).clamp(1, print_width),
+			((height as f32 * scale).round() as usize).clamp(1, print_height),
+		)
+	}
+}
+
+/// How [resize](Image::resize) weighs the pixels it samples from.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Filter {
+	/// The closest pixel, unchanged. Blocky and aliases when shrinking, but
+	/// no new values are made up, which is what you want for pixel art or
+	/// for looking at the pixels themselves.
+	Nearest,
+	/// A triangle, so bilinear when growing. Soft, but it never rings.
+	Triangle,
+	/// Catmull-Rom. Sharper than the triangle with only a little ringing.
+	CatmullRom,
+	/// Lanczos with three lobes. The sharpest, with a touch more ringing
+	/// around hard edges.
+	#[default]
+	Lanczos3,
+}
+
+impl Filter {
+	/// How far out the filter reaches, in source pixels, when it isn't
+	/// being stretched
+	fn radius(&self) -> f32 {
+		match self {
+			Filter::Nearest => 0.5,
+			Filter::Triangle => 1.0,
+			Filter::CatmullRom => 2.0,
+			Filter::Lanczos3 => 3.0,
+		}
+	}
+
+	fn weight(&self, t: f32) -> f32 {
+		let t = t.abs();
+		match self {
+			Filter::Nearest => (t <= 0.5) as u8 as f32,
+			Filter::Triangle => (1.0 - t).max(0.0),
+			Filter::CatmullRom => {
+				if t < 1.0 {
+					1.5 * t * t * t - 2.5 * t * t + 1.0
+				} else if t < 2.0 {
+					-0.5 * t * t * t + 2.5 * t * t - 4.0 * t + 2.0
+				} else {
+					0.0
+				}
+			}
+			Filter::Lanczos3 => {
+				if t < 3.0 {
+					sinc(t) * sinc(t / 3.0)
+				} else {
+					0.0
+				}
+			}
+		}
+	}
+}
+
+fn sinc(t: f32) -> f32 {
+	if t == 0.0 {
+		1.0
+	} else {
+		let pt = std::f32::consts::PI * t;
+		pt.sin() / pt
+	}
+}
+
+impl<C: Colorspace> Image<f32, C> {
+	/// Scale the image to `width` by `height` with `filter`. When shrinking,
+	/// the filter is stretched over every input pixel that lands in an
+	/// output pixel, so it doesn't alias.
+	///
+	/// Light adds up linearly, so that's where the averaging has to happen.
+	/// Images with a curve, like sRGB, are taken back to linear for it and
+	/// encoded again after. Lanczos and Catmull-Rom can undershoot at hard
+	/// edges, which the encode clamps.
+	pub fn resize(&self, width: usize, height: usize, filter: Filter) -> Image<f32, C> {
+		self.resize_into(width, height, filter, vec![])
+	}
+
+	/// [resize](Self::resize), but the resized image goes in a buffer you
+	/// give us, overwriting what's there. The passes in between still need
+	/// scratch space of their own.
+	pub fn resize_into(
+		&self,
+		width: usize,
+		height: usize,
+		filter: Filter,
+		pixels: Vec<f32>,
+	) -> Image<f32, C> {
+		let curve = curve::<C>(filter);
+		let source: Cow<[f32]> = match curve {
+			Some(tf) => Cow::Owned(self.data.par_iter().map(|v| tf.decode(*v)).collect()),
+			None => Cow::Borrowed(&self.data),
+		};
+
+		let size = (self.width, self.height, width, height);
+		let mut data = resample(&source, size, C::COMPONENTS, filter, pixels);
+
+		if let Some(tf) = curve {
+			data.par_iter_mut().for_each(|v| *v = tf.encode(*v));
+		}
+
+		Image {
+			width,
+			height,
+			metadata: self.metadata.clone(),
+			data,
+			phantom: Default::default(),
+		}
+	}
+
+	/// Resize to fit on a print of `size`, see [PrintSize::fit], with
+	/// [Lanczos3](Filter::Lanczos3). Sharpen for print after this, and give
+	/// the encoder the same dpi so the lab prints it at the size you meant.
+	pub fn resize_for_print(&self, size: &PrintSize) -> Image<f32, C> {
+		let (width, height) = size.fit(self.width, self.height);
+		self.resize(width, height, Filter::Lanczos3)
+	}
+}
+
+impl<C: Colorspace> AlphaImage<f32, C> {
+	/// [Image::resize], with the alpha resized along with it. The colour is
+	/// weighted by its alpha while it's averaged, so transparent pixels,
+	/// whatever colour they are, don't bleed into the edges next to them.
+	pub fn resize(&self, width: usize, height: usize, filter: Filter) -> AlphaImage<f32, C> {
+		let components = C::COMPONENTS;
+		let image = &self.image;
+		let curve = curve::<C>(filter);
+
+		let premultiplied: Vec<f32> = image
+			.data
+			.par_chunks_exact(components)
+			.zip(self.alpha.par_iter())
+			.flat_map_iter(|(px, a)| {
+				px.iter().map(move |v| match curve {
+					Some(tf) => tf.decode(*v) * a,
+					None => v * a,
+				})
+			})
+			.collect();
+
+		let size = (image.width, image.height, width, height);
+		let mut data = resample(&premultiplied, size, components, filter, vec![]);
+		let mut alpha = resample(&self.alpha, size, 1, filter, vec![]);
+
+		data.par_chunks_exact_mut(components)
+			.zip(alpha.par_iter_mut())
+			.for_each(|(px, a)| {
+				for v in px {
+					*v = if *a > 0.0 { *v / *a } else { 0.0 };
+					if let Some(tf) = curve {
+						*v = tf.encode(*v);
+					}
+				}
+				// Lanczos and Catmull-Rom overshoot at hard edges
+				*a = a.clamp(0.0, 1.0);
+			});
+
+		AlphaImage {
+			image: Image {
+				width,
+				height,
+				metadata: image.metadata.clone(),
+				data,
+				phantom: Default::default(),
+			},
+			alpha,
+		}
+	}
+}
+
+/// The curve to take an image in `C` off of before averaging. Nearest doesn't
+/// average anything, so it can stay as it is.
+fn curve<C: Colorspace>(filter: Filter) -> Option<TransferFunction> {
+	C::KIND
+		.color_tag()
+		.map(|tag| tag.transfer)
+		.filter(|tf| *tf != TransferFunction::Linear && filter != Filter::Nearest)
+}
+
+/// Resample a `from_width` by `from_height` image to `width` by `height`,
+/// into `out`. `size` is those four in that order.
+fn resample(
+	data: &[f32],
+	size: (usize, usize, usize, usize),
+	components: usize,
+	filter: Filter,
+	out: Vec<f32>,
+) -> Vec<f32> {
+	let (from_width, from_height, width, height) = size;
+	let across = resample_rows(data, from_width, from_height, width, components, filter);
+
+	// Resample the columns by turning the image on its side, doing the rows,
+	// and turning it back
+	let turned = transpose(&across, width, from_height, components, vec![]);
+	let down = resample_rows(&turned, from_height, width, height, components, filter);
+	transpose(&down, height, width, components, out)
+}
+
+/// Resample every row from `from` pixels wide to `to` pixels wide
+fn resample_rows(
+	data: &[f32],
+	from: usize,
+	rows: usize,
+	to: usize,
+	components: usize,
+	filter: Filter,
+) -> Vec<f32> {
+	if from == to {
+		return data.to_vec();
+	}
+
+	// When shrinking, the filter is stretched out over every input pixel
+	// that lands in one output pixel
+	let scale = from as f32 / to as f32;
+	let stretch = if filter == Filter::Nearest {
+		1.0
+	} else {
+		scale.max(1.0)
+	};
+	let support = filter.radius() * stretch;
+	let taps: Vec<(usize, Vec<f32>)> = (0..to)
+		.map(|x| {
+			let center = (x as f32 + 0.5) * scale - 0.5;
+			if filter == Filter::Nearest {
+				let nearest = (center.round().max(0.0) as usize).min(from - 1);
+				return (nearest, vec![1.0]);
+			}
+
+			let start = (center - support).ceil().max(0.0) as usize;
+			let end = ((center + support).floor().max(0.0) as usize).min(from - 1);
+
+			let mut weights: Vec<f32> = (start..=end)
+				.map(|sx| filter.weight((sx as f32 - center) / stretch))
+				.collect();
+			let sum: f32 = weights.iter().sum();
+			if sum > 0.0 {
+				weights.iter_mut().for_each(|w| *w /= sum);
+			} else {
+				// Exactly between pixels with nothing under the filter, which
+				// can only happen right at the edge
+				weights
+					.iter_mut()
+					.for_each(|w| *w = 1.0 / (end - start + 1) as f32);
+			}
+
+			(start, weights)
+		})
+		.collect();
+
+	let mut out = vec![0.0; to * rows * components];
+	out.par_chunks_exact_mut(to * components)
+		.zip(data.par_chunks_exact(from * components))
+		.for_each(|(out, row)| {
+			for (x, (start, weights)) in taps.iter().enumerate() {
+				for (i, weight) in weights.iter().enumerate() {
+					let sx = (start + i) * components;
+					for c in 0..components {
+						out[x * components + c] += row[sx + c] * weight;
+					}
+				}
+			}
+		});
+
+	out
+}
+
+/// Swap rows for columns, into `out`
+fn transpose(
+	data: &[f32],
+	width: usize,
+	height: usize,
+	components: usize,
+	mut out: Vec<f32>,
+) -> Vec<f32> {
+	out.clear();
+	out.resize(data.len(), 0.0);
+	for y in 0..height {
+		for x in 0..width {
+			let from = (y * width + x) * components;
+			let to = (x * height + y) * components;
+			out[to..to + components].copy_from_slice(&data[from..from + components]);
+		}
+	}
+
+	out
+}
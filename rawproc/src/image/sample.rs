@@ -0,0 +1,72 @@
+/// Every [Sample] type as a value, like
+/// [ColorspaceKind](crate::colorspace::ColorspaceKind) is for colorspaces
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SampleKind {
+	U8,
+	U16,
+	F32,
+}
+
+impl SampleKind {
+	/// How many bytes one sample takes
+	pub fn size(&self) -> usize {
+		match self {
+			SampleKind::U8 => 1,
+			SampleKind::U16 => 2,
+			SampleKind::F32 => 4,
+		}
+	}
+}
+
+/// Something a pixel's values can be stored as. Lets algorithms that need to
+/// do real maths work in f32 and hand back the type they were given.
+pub trait Sample: Copy + Clone + Send + Sync {
+	const KIND: SampleKind;
+
+	fn to_f32(self) -> f32;
+	/// Integers are rounded and clamped to what fits
+	fn from_f32(value: f32) -> Self;
+}
+
+impl Sample for u8 {
+	const KIND: SampleKind = SampleKind::U8;
+
+	#[inline]
+	fn to_f32(self) -> f32 {
+		self as f32
+	}
+
+	#[inline]
+	fn from_f32(value: f32) -> Self {
+		value.round().clamp(0.0, u8::MAX as f32) as u8
+	}
+}
+
+impl Sample for u16 {
+	const KIND: SampleKind = SampleKind::U16;
+
+	#[inline]
+	fn to_f32(self) -> f32 {
+		self as f32
+	}
+
+	#[inline]
+	fn from_f32(value: f32) -> Self {
+		value.round().clamp(0.0, u16::MAX as f32) as u16
+	}
+}
+
+impl Sample for f32 {
+	const KIND: SampleKind = SampleKind::F32;
+
+	#[inline]
+	fn to_f32(self) -> f32 {
+		self
+	}
+
+	#[inline]
+	fn from_f32(value: f32) -> Self {
+		value
+	}
+}
@@ -0,0 +1,113 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use crate::colorspace::{BayerRgb, Colorspace, LinRgb};
+
+use super::{Demosaic, Image, RawMetadata, Sample};
+
+/// An image whose data lives behind an [Arc] so it can be handed to as many
+/// threads as you like without copying it. Cloning a SharedImage is cheap.
+///
+/// Processing happens on an [Image], so operations here read from the shared
+/// data and produce a new, owned, image. A decoded mosaic can be shared and
+/// then debayered a few different ways at once without ever being copied.
+#[derive(Clone, Debug)]
+pub struct SharedImage<T: Copy + Clone, C: Colorspace> {
+	pub width: usize,
+	pub height: usize,
+	pub metadata: RawMetadata,
+
+	pub data: Arc<[T]>,
+	phantom: PhantomData<C>,
+}
+
+impl<T: Copy + Clone, C: Colorspace> Image<T, C> {
+	/// Move the image data into an Arc so it can be shared.
+	pub fn into_shared(self) -> SharedImage<T, C> {
+		SharedImage {
+			width: self.width,
+			height: self.height,
+			metadata: self.metadata,
+			data: self.data.into(),
+			phantom: Default::default(),
+		}
+	}
+}
+
+impl<T: Copy + Clone, C: Colorspace> SharedImage<T, C> {
+	/// Copy the data out into an image you can process. If you're the only
+	/// one holding on to the data it's still copied, as `Arc<[T]>` can't
+	/// become a Vec without one.
+	pub fn to_image(&self) -> Image<T, C> {
+		Image {
+			width: self.width,
+			height: self.height,
+			metadata: self.metadata.clone(),
+			data: self.data.to_vec(),
+			phantom: Default::default(),
+		}
+	}
+
+	/// How many SharedImages are pointing at this data
+	pub fn share_count(&self) -> usize {
+		Arc::strong_count(&self.data)
+	}
+}
+
+impl<T: Sample> SharedImage<T, BayerRgb> {
+	/// Debayer straight from the shared data with the default demosaic.
+	pub fn debayer(&self) -> Image<T, LinRgb> {
+		self.debayer_with_into(Demosaic::default(), vec![])
+	}
+
+	/// Debayer straight from the shared data with whichever [Demosaic] you
+	/// like, so one frame can be tried a few ways at once.
+	pub fn debayer_with(&self, demosaic: Demosaic) -> Image<T, LinRgb> {
+		self.debayer_with_into(demosaic, vec![])
+	}
+
+	/// Debayer straight from the shared data into a buffer you provide.
+	pub fn debayer_into(&self, data: Vec<T>) -> Image<T, LinRgb> {
+		self.debayer_with_into(Demosaic::default(), data)
+	}
+
+	/// [debayer_with](Self::debayer_with) into a buffer you provide.
+	pub fn debayer_with_into(&self, demosaic: Demosaic, mut data: Vec<T>) -> Image<T, LinRgb> {
+		Image::<T, BayerRgb>::debayer_data(
+			self.width,
+			self.height,
+			&self.metadata.cfa,
+			&self.data,
+			demosaic,
+			&mut data,
+		);
+
+		Image {
+			width: self.width,
+			height: self.height,
+			metadata: self.metadata.clone(),
+			data,
+			phantom: Default::default(),
+		}
+	}
+}
+
+// Images are plain data and are meant to be moved between, and shared
+// across, threads. If something that isn't Send or Sync sneaks into one of
+// them this stops compiling and we find out here instead of downstream.
+#[allow(dead_code)]
+fn assert_send_sync() {
+	use crate::colorspace::{Hsv, LinSrgb, Srgb, XYZ};
+
+	fn is<T: Send + Sync>() {}
+
+	is::<RawMetadata>();
+	is::<Image<u16, BayerRgb>>();
+	is::<Image<f32, BayerRgb>>();
+	is::<Image<u16, LinRgb>>();
+	is::<Image<u16, XYZ>>();
+	is::<Image<f32, LinSrgb>>();
+	is::<Image<f32, Srgb>>();
+	is::<Image<f32, Hsv>>();
+	is::<SharedImage<u16, BayerRgb>>();
+	is::<SharedImage<f32, LinSrgb>>();
+}
@@ -0,0 +1,133 @@
+use crate::{colorspace::Colorspace, par::*};
+
+use super::{tile::TILE_SIZE, Image};
+
+/// Where the image is going to be seen. Screens show every pixel as it is,
+/// but ink spreads into the paper, and a matte paper soaks up more of it than
+/// a glossy one, so prints need more sharpening to look as crisp.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutputMedium {
+	Screen,
+	MattePrint,
+	GlossyPrint,
+}
+
+impl OutputMedium {
+	/// The radius and amount to sharpen an image with a `long_edge` this many
+	/// pixels long. Bigger images have their detail spread over more pixels
+	/// so they get a wider radius.
+	pub fn sharpening(&self, long_edge: usize) -> (f32, f32) {
+		let scale = long_edge as f32;
+		match self {
+			OutputMedium::Screen => ((scale / 4000.0).clamp(0.5, 1.0), 0.5),
+			OutputMedium::MattePrint => ((scale / 3000.0).clamp(0.8, 2.0), 1.0),
+			OutputMedium::GlossyPrint => ((scale / 4000.0).clamp(0.6, 1.5), 0.8),
+		}
+	}
+}
+
+impl<C: Colorspace> Image<f32, C> {
+	/// Sharpen for where the image is going. Do it last, after resizing to
+	/// the size you're exporting at: capture sharpening was for the sensor's
+	/// pixels, and downscaling a lot leaves the image soft again, or worse,
+	/// halos that are the wrong size.
+	pub fn sharpen_for_output(&mut self, medium: OutputMedium) {
+		let (radius, amount) = medium.sharpening(self.width.max(self.height));
+		self.unsharp_mask(radius, amount, 0.0);
+	}
+
+	/// Sharpen by adding back the difference between the image and a blurred
+	/// copy of it. `radius` is the blur's standard deviation in pixels and
+	/// `amount` how much of the difference is added, 1.0 for all of it.
+	/// Differences smaller than `threshold` are left alone so noise in flat
+	/// areas isn't sharpened with everything else.
+	///
+	/// The blur is separable, across and then down, so a wider radius costs
+	/// a little more instead of a lot more. On linear RGB a threshold around
+	/// 0.01 keeps the shadows quiet.
+	pub fn unsharp_mask(&mut self, radius: f32, amount: f32, threshold: f32) {
+		if radius <= 0.0 {
+			return;
+		}
+
+		self.process_tiled(TILE_SIZE, gaussian_reach(radius), |tile| {
+			tile.unsharp_mask_tile(radius, amount, threshold)
+		});
+	}
+
+	fn unsharp_mask_tile(&mut self, radius: f32, amount: f32, threshold: f32) {
+		if self.width == 0 || self.height == 0 {
+			return;
+		}
+
+		let blurred = gaussian_blur(&self.data, self.width, C::COMPONENTS, radius);
+		self.data
+			.par_iter_mut()
+			.zip(blurred.par_iter())
+			.for_each(|(v, b)| {
+				let detail = *v - b;
+				if detail.abs() > threshold {
+					*v += detail * amount;
+				}
+			});
+	}
+}
+
+/// Blur every channel with a gaussian of standard deviation `sigma`. It's
+/// separable, so it's a blur across the rows and then down the columns.
+/// The edges are extended outward.
+pub(super) fn gaussian_blur(data: &[f32], width: usize, components: usize, sigma: f32) -> Vec<f32> {
+	let height = data.len() / (width * components);
+	let kernel = gaussian_kernel(sigma);
+	let reach = kernel.len() / 2;
+	let stride = width * components;
+
+	let mut across = vec![0.0; data.len()];
+	across
+		.par_chunks_exact_mut(stride)
+		.zip(data.par_chunks_exact(stride))
+		.for_each(|(out, row)| {
+			for x in 0..width {
+				for c in 0..components {
+					let mut sum = 0.0;
+					for (k, weight) in kernel.iter().enumerate() {
+						let sx = (x + k).saturating_sub(reach).min(width - 1);
+						sum += row[sx * components + c] * weight;
+					}
+					out[x * components + c] = sum;
+				}
+			}
+		});
+
+	let mut down = vec![0.0; data.len()];
+	down.par_chunks_exact_mut(stride)
+		.enumerate()
+		.for_each(|(y, out)| {
+			for (k, weight) in kernel.iter().enumerate() {
+				let sy = (y + k).saturating_sub(reach).min(height - 1);
+				let row = &across[sy * stride..(sy + 1) * stride];
+				for (o, v) in out.iter_mut().zip(row) {
+					*o += v * weight;
+				}
+			}
+		});
+
+	down
+}
+
+/// How many pixels away [gaussian_blur] looks
+pub(super) fn gaussian_reach(sigma: f32) -> usize {
+	(sigma * 3.0).ceil().max(1.0) as usize
+}
+
+/// A normalized gaussian kernel out to three standard deviations
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+	let reach = gaussian_reach(sigma) as isize;
+	let kernel: Vec<f32> = (-reach..=reach)
+		.map(|x| (-((x * x) as f32) / (2.0 * sigma * sigma)).exp())
+		.collect();
+
+	let sum: f32 = kernel.iter().sum();
+	kernel.into_iter().map(|k| k / sum).collect()
+}
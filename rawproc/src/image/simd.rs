@@ -0,0 +1,233 @@
+//! The per pixel loops that run over every sample: whitebalance and the
+//! sRGB curve. With the `simd` feature on x86_64 they do four samples at a
+//! time with SSE, which every x86_64 has, and everywhere else they're the
+//! plain loops. `benches/pixel.rs` times them.
+//!
+//! The 3x3 colour matrices aren't here. RGB is packed, so SSE spends more
+//! shuffling pixels into and out of vectors than it saves, and what the
+//! compiler makes of the plain loop was as fast on a full size image and
+//! faster on small ones.
+//!
+//! The SSE curve computes the power with polynomials instead of `powf`, so
+//! it can differ from the scalar one in the seventh decimal place.
+
+use crate::transfer::TransferFunction;
+
+/// How many samples to hand each thread at once. A multiple of twelve, so
+/// the pieces are whole pixels and line up with [scale_row]'s vectors.
+pub(super) const CHUNK: usize = 12 * 1024;
+
+/// Multiply a row of samples by `pattern`, which repeats across it. For a
+/// mosaic that's the whitebalance for each column of the CFA along the row,
+/// and for RGB it's just the whitebalance.
+pub(super) fn scale_row(row: &mut [f32], pattern: &[f32]) {
+	#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+	if 12 % pattern.len() == 0 {
+		// SAFETY: SSE2 is part of x86_64, so every x86_64 has it
+		return unsafe { sse::scale_row(row, pattern) };
+	}
+
+	scalar_scale_row(row, pattern)
+}
+
+/// Encode every value with the sRGB curve
+pub(super) fn srgb_encode(data: &mut [f32]) {
+	#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+	// SAFETY: SSE2 is part of x86_64, so every x86_64 has it
+	return unsafe { sse::srgb_encode(data) };
+
+	#[allow(unreachable_code)]
+	data.iter_mut()
+		.for_each(|v| *v = TransferFunction::Srgb.encode(*v))
+}
+
+fn scalar_scale_row(row: &mut [f32], pattern: &[f32]) {
+	for (light, wb) in row.iter_mut().zip(pattern.iter().cycle()) {
+		*light *= wb;
+	}
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod sse {
+	use std::arch::x86_64::*;
+
+	use crate::transfer::TransferFunction;
+
+	// Below this the sRGB curve is a straight line
+	const SRGB_KNEE: f32 = 0.0031308;
+
+	// ln(2), split in two so multiplying it by an exponent doesn't lose
+	// precision. The high half has few enough bits that it's exact.
+	const LN2_HI: f32 = 0.693_359_4;
+	const LN2_LO: f32 = -2.121_944_4e-4;
+
+	#[target_feature(enable = "sse2")]
+	pub fn scale_row(row: &mut [f32], pattern: &[f32]) {
+		// Twelve samples at a time, so any pattern that fits in twelve lines
+		// up with the vectors: Bayer, X-Trans, and RGB pixels
+		let p = |i: usize| pattern[i % pattern.len()];
+		let wb = [0, 4, 8].map(|i| _mm_setr_ps(p(i), p(i + 1), p(i + 2), p(i + 3)));
+
+		let mut chunks = row.chunks_exact_mut(12);
+		for chunk in &mut chunks {
+			for (quad, wb) in chunk.chunks_exact_mut(4).zip(wb) {
+				// SAFETY: the quad is four floats, and loadu/storeu don't
+				// need them aligned
+				unsafe {
+					let v = _mm_loadu_ps(quad.as_ptr());
+					_mm_storeu_ps(quad.as_mut_ptr(), _mm_mul_ps(v, wb));
+				}
+			}
+		}
+
+		// The remainder starts on a multiple of twelve, where the pattern
+		// starts over too
+		super::scalar_scale_row(chunks.into_remainder(), pattern);
+	}
+
+	#[target_feature(enable = "sse2")]
+	pub fn srgb_encode(data: &mut [f32]) {
+		let mut chunks = data.chunks_exact_mut(4);
+		for chunk in &mut chunks {
+			// SAFETY: the chunk is four floats
+			unsafe {
+				let v = _mm_loadu_ps(chunk.as_ptr());
+				_mm_storeu_ps(chunk.as_mut_ptr(), srgb(v));
+			}
+		}
+
+		chunks
+			.into_remainder()
+			.iter_mut()
+			.for_each(|v| *v = TransferFunction::Srgb.encode(*v));
+	}
+
+	#[inline]
+	#[target_feature(enable = "sse2")]
+	fn srgb(v: __m128) -> __m128 {
+		// Clamped to 0..1 like TransferFunction::encode
+		let original = v;
+		let v = _mm_min_ps(_mm_max_ps(v, _mm_setzero_ps()), _mm_set1_ps(1.0));
+		let line = _mm_mul_ps(v, _mm_set1_ps(12.92));
+		let curve = _mm_sub_ps(
+			_mm_mul_ps(_mm_set1_ps(1.055), pow(v, 1.0 / 2.4)),
+			_mm_set1_ps(0.055),
+		);
+
+		let linear = _mm_cmple_ps(v, _mm_set1_ps(SRGB_KNEE));
+		let encoded = select(linear, line, curve);
+		// NaN goes through as NaN, like it does with powf
+		select(_mm_cmpunord_ps(original, original), original, encoded)
+	}
+
+	/// `a` where the mask is set, `b` where it isn't
+	#[inline]
+	#[target_feature(enable = "sse2")]
+	fn select(mask: __m128, a: __m128, b: __m128) -> __m128 {
+		_mm_or_ps(_mm_and_ps(mask, a), _mm_andnot_ps(mask, b))
+	}
+
+	/// `v` to the `power`, for positive `v`. Anything else is garbage, so
+	/// mask it out after.
+	#[inline]
+	#[target_feature(enable = "sse2")]
+	fn pow(v: __m128, power: f32) -> __m128 {
+		exp(_mm_mul_ps(ln(v), _mm_set1_ps(power)))
+	}
+
+	// ln and exp are Cephes' logf and expf, which are good to a couple of
+	// ULPs over the whole float range
+
+	#[inline]
+	#[target_feature(enable = "sse2")]
+	fn ln(v: __m128) -> __m128 {
+		const SQRT_HALF: f32 = std::f32::consts::FRAC_1_SQRT_2;
+		const P: [f32; 9] = [
+			7.037_683_6e-2,
+			-1.151_461e-1,
+			1.167_699_9e-1,
+			-1.242_014_1e-1,
+			1.424_932_3e-1,
+			-1.666_805_8e-1,
+			2.000_071_5e-1,
+			-2.499_999_4e-1,
+			3.333_333e-1,
+		];
+
+		// Split into an exponent and a mantissa in [0.5, 1)
+		let v = _mm_max_ps(v, _mm_set1_ps(f32::MIN_POSITIVE));
+		let bits = _mm_castps_si128(v);
+		let exponent = _mm_sub_epi32(_mm_srli_epi32(bits, 23), _mm_set1_epi32(126));
+		let mut e = _mm_cvtepi32_ps(exponent);
+		let mantissa = _mm_or_ps(
+			_mm_and_ps(v, _mm_castsi128_ps(_mm_set1_epi32(!0x7f80_0000))),
+			_mm_set1_ps(0.5),
+		);
+
+		// Move the mantissa to [sqrt(0.5), sqrt(2)) where the polynomial is
+		// good, and take one off to get x
+		let small = _mm_cmplt_ps(mantissa, _mm_set1_ps(SQRT_HALF));
+		e = _mm_sub_ps(e, _mm_and_ps(small, _mm_set1_ps(1.0)));
+		let x = _mm_add_ps(
+			_mm_sub_ps(mantissa, _mm_set1_ps(1.0)),
+			_mm_and_ps(small, mantissa),
+		);
+
+		let z = _mm_mul_ps(x, x);
+		let mut y = _mm_set1_ps(P[0]);
+		for p in &P[1..] {
+			y = _mm_add_ps(_mm_mul_ps(y, x), _mm_set1_ps(*p));
+		}
+		y = _mm_mul_ps(_mm_mul_ps(y, x), z);
+
+		y = _mm_add_ps(y, _mm_mul_ps(e, _mm_set1_ps(LN2_LO)));
+		y = _mm_sub_ps(y, _mm_mul_ps(z, _mm_set1_ps(0.5)));
+		let x = _mm_add_ps(x, y);
+		_mm_add_ps(x, _mm_mul_ps(e, _mm_set1_ps(LN2_HI)))
+	}
+
+	#[inline]
+	#[target_feature(enable = "sse2")]
+	fn exp(v: __m128) -> __m128 {
+		const P: [f32; 6] = [
+			1.987_569_1e-4,
+			1.398_199_9e-3,
+			8.333_452e-3,
+			4.166_579_6e-2,
+			1.666_666_5e-1,
+			0.5,
+		];
+
+		let v = _mm_min_ps(_mm_max_ps(v, _mm_set1_ps(-87.3)), _mm_set1_ps(88.3));
+
+		// v = n ln(2) + x, with x small enough for the polynomial
+		let n = _mm_add_ps(
+			_mm_mul_ps(v, _mm_set1_ps(std::f32::consts::LOG2_E)),
+			_mm_set1_ps(0.5),
+		);
+		let n = floor(n);
+		let x = _mm_sub_ps(v, _mm_mul_ps(n, _mm_set1_ps(LN2_HI)));
+		let x = _mm_sub_ps(x, _mm_mul_ps(n, _mm_set1_ps(LN2_LO)));
+
+		let z = _mm_mul_ps(x, x);
+		let mut y = _mm_set1_ps(P[0]);
+		for p in &P[1..] {
+			y = _mm_add_ps(_mm_mul_ps(y, x), _mm_set1_ps(*p));
+		}
+		y = _mm_add_ps(_mm_add_ps(_mm_mul_ps(y, z), x), _mm_set1_ps(1.0));
+
+		// Times 2^n, by building it in the exponent bits
+		let n = _mm_add_epi32(_mm_cvttps_epi32(n), _mm_set1_epi32(127));
+		_mm_mul_ps(y, _mm_castsi128_ps(_mm_slli_epi32(n, 23)))
+	}
+
+	/// SSE2 doesn't have a floor. Truncate, and take one off where that
+	/// went up.
+	#[inline]
+	#[target_feature(enable = "sse2")]
+	fn floor(v: __m128) -> __m128 {
+		let truncated = _mm_cvtepi32_ps(_mm_cvttps_epi32(v));
+		let over = _mm_cmpgt_ps(truncated, v);
+		_mm_sub_ps(truncated, _mm_and_ps(over, _mm_set1_ps(1.0)))
+	}
+}
@@ -1,12 +1,34 @@
-use crate::{algorithms, colorspace::Srgb};
+use crate::{
+	algorithms,
+	colorspace::Srgb,
+	makernote::{AfPoint, PictureStyle},
+	par::*,
+};
 
 use super::Image;
 
+/// Tint the shadows one colour and the highlights another, like a toned
+/// black and white print or the teal and orange of a film grade.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SplitTone {
+	/// Hue of the shadow tint in degrees, 0 is red
+	pub shadow_hue: f32,
+	/// How strong the shadow tint is, from 0.0 for none to 1.0
+	pub shadow_saturation: f32,
+	pub highlight_hue: f32,
+	pub highlight_saturation: f32,
+	/// Where shadows stop and highlights start, from -1.0 to 1.0. Negative
+	/// gives more of the image to the highlight tint, positive to the shadow
+	/// tint, and 0.0 splits it in the middle.
+	pub balance: f32,
+}
+
 impl Image<f32, Srgb> {
 	pub fn contrast(&mut self, value: f32) {
-		for px in self.data.iter_mut() {
-			*px = algorithms::contrast(*px, value);
-		}
+		self.data
+			.par_iter_mut()
+			.for_each(|px| *px = algorithms::contrast(*px, value));
 	}
 
 	//TODO: gen- What do we name this, really?
@@ -24,4 +46,89 @@ impl Image<f32, Srgb> {
 		let mult = 1.0 / large;
 		self.data.iter_mut().for_each(|f| *f = *f * mult)
 	}
+
+	/// Split tone the image. It goes on after the tone curve, when the
+	/// image looks the way it's going to, and only moves colour: a pixel's
+	/// luminance is the same after as before.
+	pub fn split_tone(&mut self, tone: &SplitTone) {
+		// The tints with their luminance taken out, so all that's left is the
+		// push toward their hue
+		let tint = |hue: f32, saturation: f32| {
+			let (r, g, b) = algorithms::pixel_hsv_to_rgb(hue.rem_euclid(360.0), 1.0, 1.0);
+			let lum = algorithms::luminance(&[r, g, b]);
+			[r - lum, g - lum, b - lum].map(|c| c * saturation.clamp(0.0, 1.0))
+		};
+		let shadow = tint(tone.shadow_hue, tone.shadow_saturation);
+		let highlight = tint(tone.highlight_hue, tone.highlight_saturation);
+		let pivot = (0.5 + tone.balance.clamp(-1.0, 1.0) / 2.0).clamp(0.05, 0.95);
+
+		for rgb in self.data.chunks_exact_mut(3) {
+			let lum = algorithms::luminance(rgb).clamp(0.0, 1.0);
+
+			// Fade from all shadow tint at black to all highlight tint at white,
+			// crossing over at the pivot
+			let t = if lum < pivot {
+				lum / pivot * 0.5
+			} else {
+				0.5 + (lum - pivot) / (1.0 - pivot) * 0.5
+			};
+			let shadow_weight = (1.0 - t) * (1.0 - t);
+			let highlight_weight = t * t;
+
+			for c in 0..3 {
+				let shifted = rgb[c] + shadow[c] * shadow_weight + highlight[c] * highlight_weight;
+				rgb[c] = shifted.clamp(0.0, 1.0);
+			}
+		}
+	}
+
+	/// Apply a rough approximation of the camera's picture style so the
+	/// render starts out looking like the back-of-camera JPEG.
+	pub fn picture_style(&mut self, style: &PictureStyle) {
+		let (contrast, saturation) = style.baseline();
+
+		self.data.par_chunks_exact_mut(3).for_each(|rgb| {
+			let (h, s, v) = algorithms::pixel_rgb_to_hsv(rgb[0], rgb[1], rgb[2]);
+			let (r, g, b) = algorithms::pixel_hsv_to_rgb(h, (s * saturation).clamp(0.0, 1.0), v);
+
+			rgb[0] = algorithms::contrast(r, contrast);
+			rgb[1] = algorithms::contrast(g, contrast);
+			rgb[2] = algorithms::contrast(b, contrast);
+		});
+	}
+}
+
+impl Image<u8, Srgb> {
+	/// Draw the outline of AF points over the image, like the camera does in
+	/// playback. Points in focus are red, ones that were selected but didn't
+	/// get focus are white, and the rest are a dim grey.
+	pub fn overlay_af_points(&mut self, points: &[AfPoint]) {
+		for point in points {
+			let colour = match (point.in_focus, point.selected) {
+				(true, _) => [255, 0, 0],
+				(false, true) => [255, 255, 255],
+				(false, false) => [96, 96, 96],
+			};
+
+			let (left, top, right, bottom) = point.area.to_pixels(self.width, self.height);
+			if left >= right || top >= bottom {
+				continue;
+			}
+
+			let mut set = |x: usize, y: usize| {
+				let idx = (y * self.width + x) * 3;
+				self.data[idx..idx + 3].copy_from_slice(&colour);
+			};
+
+			for x in left..right {
+				set(x, top);
+				set(x, bottom - 1);
+			}
+
+			for y in top..bottom {
+				set(left, y);
+				set(right - 1, y);
+			}
+		}
+	}
 }
@@ -0,0 +1,107 @@
+//! Working on a big image a piece at a time. Filters that look at the
+//! pixels around each one, like denoising and sharpening, need scratch
+//! buffers the size of what they're working on, and on a whole 100MP frame
+//! that's a lot of memory. On tiles it's only ever a tile's worth.
+
+use std::sync::Mutex;
+
+use crate::{colorspace::Colorspace, par::*};
+
+use super::Image;
+
+/// How big the tiles are when the built in filters tile themselves. Small
+/// enough that a tile's scratch buffers are a few megabytes, big enough that
+/// the overlap is only a little extra work.
+pub(super) const TILE_SIZE: usize = 512;
+
+impl<T: Copy + Clone, C: Colorspace> Image<T, C> {
+	/// Cut the image into tiles `tile_size` pixels square and run `f` on
+	/// each one, a row of tiles at a time with the tiles of a row spread over
+	/// rayon's pool. Each tile has `overlap` more pixels of the image around
+	/// it, where there are any, so a filter reaching that far sees the same
+	/// neighbours it would on the whole image. Only the middle of each tile
+	/// is kept, so as long as the overlap is at least as far as `f` reaches
+	/// the result is the same as running `f` on the whole image.
+	///
+	/// The tiles are copies and `f` always sees the image as it was, never
+	/// what a tile next to it has already done. The copies, and whatever `f`
+	/// allocates, are only ever a row of tiles worth, so memory grows with
+	/// how wide the image is but not how tall, and never to a whole frame's
+	/// worth. If the image fits in one tile `f` gets the image itself.
+	///
+	/// # Panics
+	/// If `tile_size` is 0, or if `f` changes the size of a tile.
+	pub fn process_tiled<F>(&mut self, tile_size: usize, overlap: usize, f: F)
+	where
+		T: Send + Sync,
+		F: Fn(&mut Image<T, C>) + Sync,
+	{
+		assert!(tile_size > 0, "tiles need to be at least one pixel");
+		let (width, height) = (self.width, self.height);
+		let stride = width * C::COMPONENTS;
+		if stride == 0 || height == 0 {
+			return;
+		}
+
+		if width <= tile_size && height <= tile_size {
+			f(self);
+			assert!(
+				self.width == width && self.height == height,
+				"process_tiled can't change the size of a tile"
+			);
+			return;
+		}
+
+		// The rows just over the band we're on, as they were before the
+		// last band wrote over them
+		let mut above = vec![];
+
+		for y0 in (0..height).step_by(tile_size) {
+			let y1 = (y0 + tile_size).min(height);
+			let top = y0.saturating_sub(overlap);
+			let bottom = (y1 + overlap).min(height);
+
+			// Every row the tiles need, top to bottom, untouched
+			let mut band = std::mem::take(&mut above);
+			band.extend_from_slice(&self.data[y0 * stride..bottom * stride]);
+
+			let output = Mutex::new(&mut self.data);
+			let metadata = &self.metadata;
+			(0..width.div_ceil(tile_size))
+				.into_par_iter()
+				.for_each(|tx| {
+					let x0 = tx * tile_size;
+					let x1 = (x0 + tile_size).min(width);
+					let left = x0.saturating_sub(overlap);
+					let right = (x1 + overlap).min(width);
+
+					let (tile_width, tile_height) = (right - left, bottom - top);
+					let tile_stride = tile_width * C::COMPONENTS;
+					let mut data = Vec::with_capacity(tile_stride * tile_height);
+					for row in band.chunks_exact(stride) {
+						data.extend_from_slice(&row[left * C::COMPONENTS..right * C::COMPONENTS]);
+					}
+
+					let mut tile =
+						Image::from_raw_parts(tile_width, tile_height, metadata.clone(), data);
+					f(&mut tile);
+					assert!(
+						tile.width == tile_width && tile.height == tile_height,
+						"process_tiled can't change the size of a tile"
+					);
+
+					// Put the middle back, leaving the overlap
+					let mut output = output.lock().unwrap();
+					for y in y0..y1 {
+						let row = &tile.data[(y - top) * tile_stride..][..tile_stride];
+						let from = &row[(x0 - left) * C::COMPONENTS..][..(x1 - x0) * C::COMPONENTS];
+						output[y * stride + x0 * C::COMPONENTS..][..from.len()]
+							.copy_from_slice(from);
+					}
+				});
+
+			let next_top = y1.saturating_sub(overlap);
+			above = band[(next_top - top) * stride..(y1 - top) * stride].to_vec();
+		}
+	}
+}
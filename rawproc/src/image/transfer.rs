@@ -0,0 +1,39 @@
+use crate::{
+	colorspace::{Colorspace, LinSrgb, Srgb},
+	par::*,
+	transfer::TransferFunction,
+};
+
+use super::{simd, Image};
+
+impl<C: Colorspace> Image<f32, C> {
+	/// Encode every value with the transfer function, in place. This doesn't
+	/// change the colorspace type so it's up to you to keep track of it.
+	pub fn encode_transfer(&mut self, tf: TransferFunction) {
+		if tf == TransferFunction::Srgb {
+			self.data
+				.par_chunks_mut(simd::CHUNK)
+				.for_each(simd::srgb_encode);
+			return;
+		}
+
+		self.data
+			.par_iter_mut()
+			.for_each(|float| *float = tf.encode(*float));
+	}
+
+	/// Decode every value with the transfer function, in place, making it linear.
+	pub fn decode_transfer(&mut self, tf: TransferFunction) {
+		self.data
+			.par_iter_mut()
+			.for_each(|float| *float = tf.decode(*float));
+	}
+}
+
+impl Image<f32, Srgb> {
+	/// Undo the sRGB curve
+	pub fn linearize(mut self) -> Image<f32, LinSrgb> {
+		self.decode_transfer(TransferFunction::Srgb);
+		self.change_colorspace(None)
+	}
+}
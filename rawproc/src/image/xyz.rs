@@ -1,8 +1,15 @@
-use nalgebra::{Matrix3, Matrix3x1};
+use nalgebra::{Matrix3, Matrix3x1, Vector3};
 
-use crate::colorspace::{LinSrgb, XYZ};
+use crate::{
+	colorspace::{
+		AdobeRgb, ColorTag, Colorspace, ColorspaceKind, DisplayP3, LinSrgb, ProPhotoRgb, Rec2020,
+		XYZ,
+	},
+	par::*,
+	Error,
+};
 
-use super::Image;
+use super::{ChromaticAdaptation, Image};
 
 impl Image<u16, XYZ> {
 	//TODO: gen-
@@ -10,38 +17,104 @@ impl Image<u16, XYZ> {
 	//get from the camera I guess? I don't know how to make it D65. I'm already
 	//trying to chromatically-shove it into D65.
 	pub fn to_linsrgb(mut self) -> Image<u16, LinSrgb> {
-		let cam_reference = self.metadata.cam_to_xyz * Matrix3x1::new(1.0, 1.0, 1.0);
-		let srgb_reference = BRUCE_XYZ_SRGB.try_inverse().unwrap() * Matrix3x1::new(1.0, 1.0, 1.0);
+		let premul_trans = xyz_to_linsrgb(self.metadata.cam_to_xyz);
 
-		let cam_cones = BRADFORD * cam_reference;
-		let srgb_cones = BRADFORD * srgb_reference;
-
-		#[rustfmt::skip]
-		let difference_matrix = Matrix3::new(
-			srgb_cones[0] / cam_cones[0], 0.0, 0.0,
-			0.0, srgb_cones[1] / cam_cones[1], 0.0,
-			0.0, 0.0, srgb_cones[2] / cam_cones[2]
-		);
-
-		let chromatic_adaptation_matrix = BRADFORD_INV * difference_matrix * BRADFORD;
-		let premul_trans = BRUCE_XYZ_SRGB * chromatic_adaptation_matrix;
-
-		for px in self.data.chunks_mut(3) {
+		let white = self.metadata.whitelevels;
+		self.data.par_chunks_exact_mut(3).for_each(|px| {
 			let m = Matrix3x1::new(
-				px[0] as f32 / self.metadata.whitelevels[0] as f32,
-				px[1] as f32 / self.metadata.whitelevels[1] as f32,
-				px[2] as f32 / self.metadata.whitelevels[2] as f32,
+				px[0] as f32 / white[0] as f32,
+				px[1] as f32 / white[1] as f32,
+				px[2] as f32 / white[2] as f32,
 			);
 			let res = premul_trans * m;
-			px[0] = (res[0] * self.metadata.whitelevels[0] as f32) as u16;
-			px[1] = (res[1] * self.metadata.whitelevels[1] as f32) as u16;
-			px[2] = (res[2] * self.metadata.whitelevels[2] as f32) as u16;
+			px[0] = (res[0] * white[0] as f32) as u16;
+			px[1] = (res[1] * white[1] as f32) as u16;
+			px[2] = (res[2] * white[2] as f32) as u16;
+		});
+
+		self.change_colorspace(None)
+	}
+}
+
+impl Image<f32, XYZ> {
+	/// XYZ to linear sRGB, adapting the camera's white to D65 the same way
+	/// the u16 version does. Values can come out negative or over 1.0 for
+	/// colours sRGB can't show; they're left alone so nothing's thrown away
+	/// before you decide what to do with them.
+	pub fn to_linsrgb(mut self) -> Image<f32, LinSrgb> {
+		let premul_trans = xyz_to_linsrgb(self.metadata.cam_to_xyz);
+		self.data.par_chunks_exact_mut(3).for_each(|px| {
+			let res = premul_trans * Matrix3x1::new(px[0], px[1], px[2]);
+			px.copy_from_slice(res.as_slice());
+		});
+
+		self.change_colorspace(None)
+	}
+
+	/// Into any RGB colorspace we know the primaries of, encoded with its
+	/// curve, like `to_rgb::<AdobeRgb>()`. The camera's white is adapted to
+	/// the colorspace's, and colours outside of it are clipped. The marker
+	/// says which colorspace it is, so [color_tag](Image::color_tag) and the
+	/// encoders can tag the file.
+	///
+	/// Errors with [Error::UnsupportedConversion] if `C` isn't an RGB space
+	/// with a [ColorTag].
+	pub fn to_rgb<C: Colorspace>(self) -> Result<Image<f32, C>, Error> {
+		match C::KIND.color_tag() {
+			Some(tag) => Ok(self.into_tagged(&tag)),
+			None => Err(Error::UnsupportedConversion {
+				from: ColorspaceKind::XYZ,
+				to: C::KIND,
+			}),
 		}
+	}
+
+	pub fn to_adobe_rgb(self) -> Image<f32, AdobeRgb> {
+		self.into_tagged(&ColorTag::ADOBE_RGB)
+	}
+
+	pub fn to_prophoto_rgb(self) -> Image<f32, ProPhotoRgb> {
+		self.into_tagged(&ColorTag::PROPHOTO_RGB)
+	}
+
+	pub fn to_rec2020(self) -> Image<f32, Rec2020> {
+		self.into_tagged(&ColorTag::REC2020)
+	}
+
+	pub fn to_display_p3(self) -> Image<f32, DisplayP3> {
+		self.into_tagged(&ColorTag::DISPLAY_P3)
+	}
+
+	fn into_tagged<C: Colorspace>(mut self, tag: &ColorTag) -> Image<f32, C> {
+		let cam_reference = self.metadata.cam_to_xyz * Vector3::new(1.0, 1.0, 1.0);
+		let adapt = ChromaticAdaptation::Bradford.matrix_xyz(cam_reference, tag.white_xyz());
+		let premul_trans = tag
+			.rgb_to_xyz()
+			.try_inverse()
+			.unwrap_or_else(Matrix3::identity)
+			* adapt;
+
+		let tf = tag.transfer;
+		self.data.par_chunks_exact_mut(3).for_each(|px| {
+			let res = premul_trans * Vector3::new(px[0], px[1], px[2]);
+			for (out, v) in px.iter_mut().zip(res.iter()) {
+				*out = tf.encode(*v);
+			}
+		});
 
 		self.change_colorspace(None)
 	}
 }
 
+/// The matrix that takes XYZ to linear sRGB, with a Bradford adaptation from
+/// the white the camera saw, what it calls `(1, 1, 1)`, to D65.
+fn xyz_to_linsrgb(cam_to_xyz: Matrix3<f32>) -> Matrix3<f32> {
+	let cam_reference = cam_to_xyz * Matrix3x1::new(1.0, 1.0, 1.0);
+	let srgb_reference = BRUCE_XYZ_SRGB.try_inverse().unwrap() * Matrix3x1::new(1.0, 1.0, 1.0);
+
+	BRUCE_XYZ_SRGB * ChromaticAdaptation::Bradford.matrix_xyz(cam_reference, srgb_reference)
+}
+
 // Assumes D65 white
 #[rustfmt::skip]
 pub const XYZ_TO_SRGB: Matrix3<f32> = Matrix3::new(
@@ -62,18 +135,18 @@ pub const BRUCE_XYZ_SRGB: Matrix3<f32> = Matrix3::new(
 // whitebalance in an image!
 // http://www.brucelindbloom.com/index.html?Eqn_ChromAdapt.html
 #[rustfmt::skip]
-const XYZ_SCALING: Matrix3<f32> = Matrix3::new(
+pub(crate) const XYZ_SCALING: Matrix3<f32> = Matrix3::new(
 	1.0, 0.0, 0.0,
 	0.0, 1.0, 0.0,
 	0.0, 0.0, 1.0
 );
 
-const BRADFORD: Matrix3<f32> = Matrix3::new(
+pub(crate) const BRADFORD: Matrix3<f32> = Matrix3::new(
 	0.8951000, 0.2664000, -0.1614000, -0.7502000, 1.7135000, 0.0367000, 0.0389000, -0.0685000,
 	1.0296000,
 );
 
-const BRADFORD_INV: Matrix3<f32> = Matrix3::new(
+pub(crate) const BRADFORD_INV: Matrix3<f32> = Matrix3::new(
 	0.9869929, -0.1470543, 0.1599627, 0.4323053, 0.5183603, 0.0492912, -0.0085287, 0.0400428,
 	0.9684867,
 );
@@ -0,0 +1,285 @@
+//! Lens corrections. Mirrorless lenses are often designed to be fixed up in
+//! software, so without this wide angles come out barrelled, with dark
+//! corners and coloured fringes towards the edges.
+//!
+//! Everything's measured from the optical center, with the radius scaled so
+//! the farthest corner is at 1.0. It's the model DNG's WarpRectilinear and
+//! FixVignetteRadial opcodes use, and when a DNG has those we read them into
+//! [RawMetadata::lens_correction](crate::image::RawMetadata::lens_correction).
+
+use crate::{
+	colorspace::LinRgb,
+	dng,
+	image::Image,
+	par::*,
+	tiff::{Endian, Tiff},
+};
+
+const TAG_OPCODE_LIST3: u16 = 0xC74E;
+const OPCODE_WARP_RECTILINEAR: u32 = 1;
+const OPCODE_FIX_VIGNETTE_RADIAL: u32 = 3;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LensError {
+	#[error("The opcode list ends in the middle of an opcode")]
+	Truncated,
+	#[error("An opcode says it's bigger than anything could be")]
+	Overflow,
+}
+
+/// Everything we know how to correct about a lens. Leave a part as None to
+/// skip it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LensCorrection {
+	/// The optical center, as a fraction of the width and height
+	pub center: [f32; 2],
+	pub distortion: Option<Distortion>,
+	pub vignetting: Option<Vignetting>,
+	pub chromatic_aberration: Option<ChromaticAberration>,
+}
+
+impl Default for LensCorrection {
+	fn default() -> Self {
+		Self {
+			center: [0.5, 0.5],
+			distortion: None,
+			vignetting: None,
+			chromatic_aberration: None,
+		}
+	}
+}
+
+/// Radial distortion. A pixel at radius `r` in the corrected image comes from
+/// `r * (k[0] + k[1] r² + k[2] r⁴ + k[3] r⁶)` in the original. Barrel
+/// distortion has a negative `k[1]`, pincushion a positive one.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Distortion {
+	pub k: [f32; 4],
+}
+
+impl Distortion {
+	/// The usual three term radial model, with no scaling
+	pub fn new(k1: f32, k2: f32, k3: f32) -> Self {
+		Self {
+			k: [1.0, k1, k2, k3],
+		}
+	}
+
+	fn scale(&self, r2: f32) -> f32 {
+		let [k0, k1, k2, k3] = self.k;
+		k0 + r2 * (k1 + r2 * (k2 + r2 * k3))
+	}
+}
+
+/// How much darker the lens makes things away from the center. A pixel at
+/// radius `r` is brightened by `1 + k[0] r² + k[1] r⁴ + ... + k[4] r¹⁰`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Vignetting {
+	pub k: [f32; 5],
+}
+
+impl Vignetting {
+	fn gain(&self, r2: f32) -> f32 {
+		let polynomial = self.k.iter().rev().fold(0.0, |acc, k| (acc + k) * r2);
+		1.0 + polynomial
+	}
+}
+
+/// Lateral chromatic aberration, where the lens makes red and blue images
+/// that are a slightly different size than the green one. Each is how much
+/// bigger its channel is drawn from than green, 1.0 being the same size.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChromaticAberration {
+	pub red: f32,
+	pub blue: f32,
+}
+
+impl LensCorrection {
+	pub fn is_empty(&self) -> bool {
+		self.distortion.is_none()
+			&& self.vignetting.is_none()
+			&& self.chromatic_aberration.is_none()
+	}
+}
+
+impl Image<f32, LinRgb> {
+	/// Fix the lens. Vignetting is evened out first, on the image as the
+	/// lens drew it, then the image is warped to undo the distortion and
+	/// chromatic aberration. Anything that ends up pulled in from outside
+	/// the frame is black, so you'll want to crop a little after.
+	///
+	/// Do this before any cropping, the center is relative to the frame
+	/// the correction was measured on.
+	pub fn correct_lens(&mut self, correction: &LensCorrection) {
+		let (width, height) = (self.width, self.height);
+		if correction.is_empty() || width == 0 || height == 0 {
+			return;
+		}
+
+		let cx = correction.center[0] * (width - 1) as f32;
+		let cy = correction.center[1] * (height - 1) as f32;
+		// The distance to the farthest corner, so r is 1.0 there
+		let (right, bottom) = ((width - 1) as f32, (height - 1) as f32);
+		let reach = [(0.0, 0.0), (right, 0.0), (0.0, bottom), (right, bottom)]
+			.map(|(x, y)| ((x - cx).powi(2) + (y - cy).powi(2)).sqrt())
+			.into_iter()
+			.fold(1.0f32, f32::max);
+
+		if let Some(vignetting) = correction.vignetting {
+			self.data
+				.par_chunks_exact_mut(width * 3)
+				.enumerate()
+				.for_each(|(y, row)| {
+					let dy = (y as f32 - cy) / reach;
+					for (x, rgb) in row.chunks_exact_mut(3).enumerate() {
+						let dx = (x as f32 - cx) / reach;
+						let gain = vignetting.gain(dx * dx + dy * dy);
+						rgb.iter_mut().for_each(|v| *v *= gain);
+					}
+				});
+		}
+
+		if correction.distortion.is_none() && correction.chromatic_aberration.is_none() {
+			return;
+		}
+
+		let ca = correction
+			.chromatic_aberration
+			.map(|ca| [ca.red, 1.0, ca.blue])
+			.unwrap_or([1.0; 3]);
+		let data = &self.data;
+		let mut out = vec![0.0; data.len()];
+		out.par_chunks_exact_mut(width * 3)
+			.enumerate()
+			.for_each(|(y, row)| {
+				let dy = y as f32 - cy;
+				for (x, rgb) in row.chunks_exact_mut(3).enumerate() {
+					let dx = x as f32 - cx;
+					let r2 = (dx * dx + dy * dy) / (reach * reach);
+					let scale = correction.distortion.map(|d| d.scale(r2)).unwrap_or(1.0);
+
+					for (c, v) in rgb.iter_mut().enumerate() {
+						let s = scale * ca[c];
+						*v = bilinear(data, width, height, c, cx + dx * s, cy + dy * s);
+					}
+				}
+			});
+
+		self.data = out;
+	}
+
+	/// [correct_lens](Self::correct_lens) with what the raw told us about
+	/// its lens, if it told us anything
+	pub fn correct_lens_embedded(&mut self) {
+		if let Some(correction) = self.metadata.lens_correction {
+			self.correct_lens(&correction);
+		}
+	}
+}
+
+/// Channel `c` at `(x, y)`, between pixels. Outside of the image is black.
+fn bilinear(data: &[f32], width: usize, height: usize, c: usize, x: f32, y: f32) -> f32 {
+	if x < 0.0 || y < 0.0 || x > (width - 1) as f32 || y > (height - 1) as f32 {
+		return 0.0;
+	}
+
+	let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+	let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+	let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+	let at = |x: usize, y: usize| data[(y * width + x) * 3 + c];
+
+	let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+	let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+	top * (1.0 - fy) + bottom * fy
+}
+
+/// The corrections in a DNG's OpcodeList3, which is what Adobe writes for
+/// lenses that are meant to be corrected in software. The tangential part of
+/// WarpRectilinear is left out, and when there's a warp for each colour the
+/// green one is the distortion and the others only give us how much bigger
+/// red and blue are.
+///
+/// A broken opcode list isn't worth failing a decode over, so it's None the
+/// same as if there wasn't one.
+pub(crate) fn embedded(data: &[u8]) -> Option<LensCorrection> {
+	let tiff = Tiff::new(data)?;
+	let ifd = dng::raw_ifd(&tiff)?;
+	let list = tiff.bytes(ifd.get(TAG_OPCODE_LIST3)?)?;
+
+	opcodes(list).ok().flatten()
+}
+
+fn opcodes(list: &[u8]) -> Result<Option<LensCorrection>, LensError> {
+	// Opcode lists are always big endian, whatever the file is
+	let be = Tiff::with_endian(list, Endian::Big);
+	let u32_at = |at: usize| be.u32_at(at).ok_or(LensError::Truncated);
+	let double = |at: usize| -> Result<f32, LensError> {
+		let bytes = at
+			.checked_add(8)
+			.and_then(|end| list.get(at..end))
+			.ok_or(LensError::Truncated)?;
+		Ok(f64::from_be_bytes(bytes.try_into().unwrap()) as f32)
+	};
+	let offset = |at: usize, by: usize| at.checked_add(by).ok_or(LensError::Overflow);
+
+	let mut correction = LensCorrection::default();
+	let count = u32_at(0)?;
+	let mut at = 4;
+	for _ in 0..count {
+		let id = u32_at(at)?;
+		let length = u32_at(offset(at, 12)?)? as usize;
+		let params = offset(at, 16)?;
+
+		match id {
+			OPCODE_WARP_RECTILINEAR => {
+				let planes = u32_at(params)? as usize;
+				let plane = |p: usize| -> Result<[f32; 4], LensError> {
+					let start = params + 4 + p * 48;
+					Ok([
+						double(start)?,
+						double(start + 8)?,
+						double(start + 16)?,
+						double(start + 24)?,
+					])
+				};
+				let center = planes
+					.checked_mul(48)
+					.and_then(|size| size.checked_add(params + 4))
+					.ok_or(LensError::Overflow)?;
+				correction.center = [double(center)?, double(offset(center, 8)?)?];
+
+				if planes >= 3 {
+					let (red, green, blue) = (plane(0)?, plane(1)?, plane(2)?);
+					correction.distortion = Some(Distortion { k: green });
+					correction.chromatic_aberration = Some(ChromaticAberration {
+						red: red[0] / green[0],
+						blue: blue[0] / green[0],
+					});
+				} else if planes >= 1 {
+					correction.distortion = Some(Distortion { k: plane(0)? });
+				}
+			}
+			OPCODE_FIX_VIGNETTE_RADIAL => {
+				let k = [0, 1, 2, 3, 4].map(|i| double(params + i * 8));
+				if k.iter().all(Result::is_ok) {
+					correction.vignetting = Some(Vignetting {
+						k: k.map(Result::unwrap),
+					});
+				}
+				// Only take the center from here if there's no warp to give it
+				if correction.distortion.is_none() {
+					correction.center = [double(params + 40)?, double(params + 48)?];
+				}
+			}
+			_ => (),
+		}
+
+		at = offset(params, length)?;
+	}
+
+	Ok((!correction.is_empty()).then_some(correction))
+}
@@ -1,19 +1,411 @@
 pub mod algorithms;
+pub mod batch;
+pub mod budget;
+pub mod cdl;
+pub mod colormatrix;
 pub mod colorspace;
+pub mod cr2;
+pub mod dng;
+pub mod encode;
+pub mod exif;
+pub mod export;
+pub mod exr;
+pub mod hdr;
+pub mod hotpixel;
+mod icc;
 pub mod image;
+pub mod lens;
+pub mod ljpeg;
+pub mod lut;
+pub mod makernote;
+#[cfg(feature = "fs")]
+mod mmap;
+pub mod negative;
+mod par;
+pub mod pixelshift;
+pub mod pool;
+pub mod preview;
+pub mod recipe;
+#[cfg(feature = "fs")]
+pub mod sequence;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod stack;
+pub mod support;
+mod tiff;
+pub mod transfer;
 
-use std::io::Read;
+use std::io::{Cursor, Read};
+#[cfg(feature = "fs")]
+use std::{fs::File, path::Path};
 
-use colorspace::BayerRgb;
-use image::{Image, RawMetadata};
+use colorspace::{BayerRgb, ColorspaceKind, Monochrome};
+use image::{DynImage, Image, Orientation, RawMetadata};
 use nalgebra::Matrix3;
-use rand::{thread_rng, Rng};
+use preview::EmbeddedPreview;
 use rawloader::{RawImageData, RawLoaderError};
+use support::{FormatInfo, Support};
 
-use crate::image::Crop;
+use crate::image::{Crop, Region};
 
 pub fn decode<R: Read>(reader: &mut R) -> Result<Image<u16, BayerRgb>, Error> {
-	let image = rawloader::decode(reader)?;
+	decode_with_buffer(reader, &mut vec![])
+}
+
+/// Decode, reading the file into `bytes` instead of allocating a new buffer
+/// for it. Keep passing the same Vec in and, once it's grown to the size of
+/// your largest file, the file read stops allocating.
+///
+/// rawloader still allocates the buffer the image data is decoded into. Once
+/// you're done with an image you can [recycle](Image::recycle) that buffer,
+/// and for DNGs, [decode_into] can use it again.
+pub fn decode_with_buffer<R: Read>(
+	reader: &mut R,
+	bytes: &mut Vec<u8>,
+) -> Result<Image<u16, BayerRgb>, Error> {
+	bytes.clear();
+	reader.read_to_end(bytes)?;
+
+	decode_slice(bytes)
+}
+
+/// [decode_with_buffer], and decode the samples into `samples` instead of
+/// a new buffer. Take it from a [BufferPool](pool::BufferPool) and
+/// [recycle](Image::recycle) the image when you're done, and a service
+/// working through DNGs stops allocating once the pool's warm.
+///
+/// Only our own DNG decoder can decode into a buffer it's given, so DNGs go
+/// to it first, the way [decode_region] does. Everything else, and any DNG
+/// it can't read, is decoded by rawloader into a buffer of its own and
+/// `samples` is dropped.
+pub fn decode_into<R: Read>(
+	reader: &mut R,
+	bytes: &mut Vec<u8>,
+	samples: Vec<u16>,
+) -> Result<Image<u16, BayerRgb>, Error> {
+	bytes.clear();
+	reader.read_to_end(bytes)?;
+
+	if dng::is_dng(bytes) {
+		match dng::decode_into(bytes, samples).and_then(supported) {
+			Ok(image) if image.colorspace == ColorspaceKind::LinRgb => {
+				return Err(Error::LinearImageData)
+			}
+			Ok(image) => return image.try_into(),
+			Err(Error::Dng { .. } | Error::TruncatedFile) => (),
+			Err(e) => return Err(e),
+		}
+	}
+
+	decode_slice(bytes)
+}
+
+/// Decode a raw that's already in memory, like one a browser handed over
+/// as a `Uint8Array`. [decode] reads into a buffer and then does this.
+pub fn decode_slice(bytes: &[u8]) -> Result<Image<u16, BayerRgb>, Error> {
+	let image = decode_bytes(bytes)?;
+	match image.colorspace {
+		ColorspaceKind::LinRgb => Err(Error::LinearImageData),
+		_ => image.try_into(),
+	}
+}
+
+/// Decode a raw from a camera without a colour filter array. It's already
+/// one colour, so there's no debayer or whitebalance, just
+/// [normalize](Image::normalize) and [crop](Image::crop). Errors with
+/// [Error::ColorspaceMismatch] for anything with colour.
+pub fn decode_monochrome<R: Read>(reader: &mut R) -> Result<Image<u16, Monochrome>, Error> {
+	decode_dyn(reader)?.try_into()
+}
+
+/// Decode without deciding on the colorspace first. Most raws are a mosaic
+/// and come out as [BayerRgb], but LinearRaw DNGs were demosaiced before they
+/// were written and come out as [LinRgb](colorspace::LinRgb). Those skip the
+/// debayer and go straight to the colour matrix. Monochrome cameras come out
+/// as [Monochrome].
+pub fn decode_dyn<R: Read>(reader: &mut R) -> Result<DynImage<u16>, Error> {
+	decode_dyn_with_buffer(reader, &mut vec![])
+}
+
+/// [decode_dyn], but reusing a buffer like [decode_with_buffer] does
+pub fn decode_dyn_with_buffer<R: Read>(
+	reader: &mut R,
+	bytes: &mut Vec<u8>,
+) -> Result<DynImage<u16>, Error> {
+	// We keep the bytes around so we can go looking at the parts of the file
+	// rawloader doesn't care about
+	bytes.clear();
+	reader.read_to_end(bytes)?;
+
+	decode_bytes(bytes)
+}
+
+/// [decode_dyn] for a raw that's already in memory, like [decode_slice]
+pub fn decode_dyn_slice(bytes: &[u8]) -> Result<DynImage<u16>, Error> {
+	decode_bytes(bytes)
+}
+
+/// [decode_dyn], but for a raw we only partly support too. Where the others
+/// error with [Error::PartialSupport], this gives you the image and the
+/// [Support] that says what it's missing, and what's left up to you.
+///
+/// A crop that doesn't fit on the sensor is dropped, so
+/// [crop](Image::crop) won't panic on it.
+pub fn decode_partial<R: Read>(reader: &mut R) -> Result<(DynImage<u16>, Support), Error> {
+	let mut bytes = vec![];
+	reader.read_to_end(&mut bytes)?;
+
+	let mut image = decode_unchecked(&bytes)?;
+	let support = Support::of(&image);
+	if !support.crop.is_usable() {
+		image.metadata.active_area = None;
+		image.metadata.default_crop = None;
+	}
+
+	Ok((image, support))
+}
+
+/// What a raw is, and how much of the pipeline we can run on it. See
+/// [FormatInfo].
+///
+/// This decodes the whole raw, rawloader can't tell us any of it without
+/// doing that. It errors like [decode_dyn] for a raw we can't read at all,
+/// but not for one we only partly support, that's in
+/// [support](FormatInfo::support).
+pub fn probe<R: Read>(reader: &mut R) -> Result<FormatInfo, Error> {
+	let mut bytes = vec![];
+	reader.read_to_end(&mut bytes)?;
+
+	let image = decode_unchecked(&bytes)?;
+	Ok(support::info(&bytes, &image))
+}
+
+/// The JPEG previews embedded in a raw, biggest first, without decoding the
+/// raw itself. Much faster than a decode when all you need is something to
+/// look at, like in a culling UI. Empty if the camera didn't embed any, or
+/// hid them somewhere we don't look.
+pub fn decode_thumbnail<R: Read>(reader: &mut R) -> Result<Vec<EmbeddedPreview>, Error> {
+	let mut bytes = vec![];
+	reader.read_to_end(&mut bytes)?;
+
+	Ok(preview::find(&bytes))
+}
+
+/// [decode], reading the file at `path`.
+///
+/// Needs the `fs` feature, which is on by default.
+#[cfg(feature = "fs")]
+pub fn decode_file<P: AsRef<Path>>(path: P) -> Result<Image<u16, BayerRgb>, Error> {
+	bayer(decode_dyn_file(path)?)
+}
+
+/// [decode_dyn], reading the file at `path`
+#[cfg(feature = "fs")]
+pub fn decode_dyn_file<P: AsRef<Path>>(path: P) -> Result<DynImage<u16>, Error> {
+	decode_bytes(&std::fs::read(path)?)
+}
+
+/// Decode a file on disk, memory mapping it instead of reading it in. Use
+/// this when memory is tight, like a 60MP raw on a Raspberry Pi.
+///
+/// What's alive at the peak, for a file of `F` bytes and a sensor of `P`
+/// pixels:
+/// - [decode]: the file read into a Vec, rawloader's own copy of it, and the
+///   `2P` bytes of samples. That's two copies of the file on the heap.
+/// - this, for anything rawloader reads: rawloader's copy and the samples.
+///   The map is backed by the file, so it doesn't count against you the way
+///   heap does.
+/// - this, for the DNGs we decode ourselves: the samples and one decoded
+///   tile or strip, which is the whole image again for DNGs written as a
+///   single strip. The file is never copied.
+///
+/// The samples are decoded straight into the buffer the image ends up with,
+/// nothing's copied after. On platforms without mmap this reads the file
+/// in, same as [decode_file].
+///
+/// Needs the `fs` feature, which is on by default.
+///
+/// # Safety
+/// The map is the file, not a copy of it. If the file is truncated while
+/// we're decoding, reading the part that's gone is a SIGBUS, and if it's
+/// written to, the bytes change underneath a `&[u8]`, which is undefined
+/// behaviour. Nothing else may change the file until this returns.
+#[cfg(feature = "fs")]
+pub unsafe fn decode_file_mapped<P: AsRef<Path>>(path: P) -> Result<Image<u16, BayerRgb>, Error> {
+	// SAFETY: our caller promises the file won't change
+	bayer(unsafe { decode_dyn_file_mapped(path)? })
+}
+
+/// [decode_dyn], memory mapping the file like [decode_file_mapped]
+///
+/// # Safety
+/// The same as [decode_file_mapped]: nothing may change the file until this
+/// returns.
+#[cfg(feature = "fs")]
+pub unsafe fn decode_dyn_file_mapped<P: AsRef<Path>>(path: P) -> Result<DynImage<u16>, Error> {
+	let file = File::open(path)?;
+	// SAFETY: our caller promises the file won't change
+	let map = unsafe { mmap::Mmap::open(&file)? };
+
+	decode_bytes(&map)
+}
+
+/// A bayer image, or the error for one that isn't
+#[cfg(feature = "fs")]
+fn bayer(image: DynImage<u16>) -> Result<Image<u16, BayerRgb>, Error> {
+	match image.colorspace {
+		ColorspaceKind::LinRgb => Err(Error::LinearImageData),
+		_ => image.try_into(),
+	}
+}
+
+/// Decode just `region` of the sensor, in sensor coordinates before any
+/// crop. The CFA pattern is shifted so it lines up with the region, and the
+/// active area and default crop are dropped, they don't mean anything for
+/// part of a sensor. Pair it with [debayer](Image::debayer) for a loupe view
+/// that never touches the rest of the frame.
+///
+/// DNGs are read by our own decoder, which only decodes the strips or tiles
+/// the region touches. Everything else has to be decoded whole by rawloader
+/// and then cut down, so it saves the debayer but not the decode.
+pub fn decode_region<R: Read>(
+	reader: &mut R,
+	region: Region,
+) -> Result<Image<u16, BayerRgb>, Error> {
+	let mut bytes = vec![];
+	reader.read_to_end(&mut bytes)?;
+
+	// If it's a DNG we can't read, rawloader still might
+	if dng::is_dng(&bytes) {
+		match dng::decode_region(&bytes, region).and_then(supported) {
+			Ok(image) if image.colorspace == ColorspaceKind::LinRgb => {
+				return Err(Error::LinearImageData)
+			}
+			Ok(image) => return image.try_into(),
+			Err(Error::Dng { .. } | Error::TruncatedFile) => (),
+			Err(e) => return Err(e),
+		}
+	}
+
+	let mut image: Image<u16, BayerRgb> = decode_bytes(&bytes)?.try_into()?;
+	if !region.fits(image.width, image.height) {
+		return Err(Error::RegionOutOfBounds {
+			width: image.width,
+			height: image.height,
+		});
+	}
+
+	image.crop_to(region);
+	image.metadata.active_area = None;
+	image.metadata.default_crop = None;
+	Ok(image)
+}
+
+/// Decode one of the images listed in [RawMetadata::sub_images], by its
+/// index in that list. The primary one goes through the same decode as
+/// always, the others are read by our own DNG decoder.
+///
+/// Canon Dual Pixel CR2s list the A+B frame first and the A frame second.
+/// Both share the A+B frame's metadata, so that one is always decoded too.
+///
+/// Other raws only have the one image, index 0. Any other index errors with
+/// [Error::NoSubImage].
+pub fn decode_sub_image<R: Read>(reader: &mut R, index: usize) -> Result<DynImage<u16>, Error> {
+	let mut bytes = vec![];
+	reader.read_to_end(&mut bytes)?;
+
+	if cr2::is_cr2(&bytes) {
+		let mut image = decode_bytes(&bytes)?;
+		if index > 0 {
+			image.data = cr2::decode_frame(&bytes, index, image.width, image.height)?;
+		}
+
+		return Ok(image);
+	}
+
+	// Anything else only has the one image, and its other IFDs aren't raws
+	// our DNG decoder should be guessing at
+	if !dng::is_dng(&bytes) {
+		return match index {
+			0 => decode_bytes(&bytes),
+			_ => Err(Error::NoSubImage(index)),
+		};
+	}
+
+	match dng::sub_images(&bytes).get(index) {
+		Some(sub) if sub.primary => decode_bytes(&bytes),
+		_ => dng::decode_sub_image(&bytes, index),
+	}
+}
+
+fn decode_bytes(bytes: &[u8]) -> Result<DynImage<u16>, Error> {
+	supported(decode_unchecked(bytes)?)
+}
+
+/// Error if the pipeline won't have what it needs for `image`
+fn supported(image: DynImage<u16>) -> Result<DynImage<u16>, Error> {
+	let support = Support::of(&image);
+	if support.is_complete() {
+		Ok(image)
+	} else {
+		Err(Error::PartialSupport {
+			make: image.metadata.make,
+			model: image.metadata.model,
+			support,
+		})
+	}
+}
+
+fn decode_unchecked(bytes: &[u8]) -> Result<DynImage<u16>, Error> {
+	// rawloader would only say it doesn't know the file. We don't know which
+	// Canon it came from either, that's deep in the boxes.
+	if cr2::is_cr3(bytes) {
+		return Err(Error::UnsupportedFormat {
+			make: Some(String::from("Canon")),
+			model: None,
+		});
+	}
+
+	// rawloader doesn't know deflate or tiled DNGs, so we do those ourselves.
+	// It thinks it knows 16 bit lossless JPEG, but it doesn't.
+	if dng::is_dng(bytes) && dng::is_sixteen_bit_ljpeg(bytes) {
+		return dng::decode(bytes);
+	}
+
+	let image = match rawloader::decode(&mut Cursor::new(bytes)) {
+		Ok(image) => image,
+		// If we can't read it either, rawloader's reason is the one you want.
+		// Unless the file's bad, which we're better at saying.
+		Err(e) if dng::is_dng(bytes) => {
+			return dng::decode(bytes).map_err(|ours| match ours {
+				Error::TruncatedFile
+				| Error::Ljpeg { .. }
+				| Error::Dng {
+					source: dng::DngError::Deflate,
+				} => ours,
+				_ => e.into(),
+			})
+		}
+		Err(e) => return Err(e.into()),
+	};
+	let makernote = makernote::parse(bytes);
+	let mut exif = exif::read(bytes);
+	// Older bodies don't have the lens tags, but the makernote might know
+	if exif.lens_model.is_none() {
+		exif.lens_model = makernote
+			.as_ref()
+			.and_then(|mn| mn.lens.as_ref())
+			.and_then(|lens| lens.name.clone());
+	}
+	let sub_images = if cr2::is_cr2(bytes) {
+		cr2::sub_images(bytes, image.width, image.height)
+	} else {
+		dng::sub_images(bytes)
+	};
+	let vendor_whitebalance = makernote
+		.as_ref()
+		.map(|mn| mn.whitebalance.clone())
+		.unwrap_or_default();
 
 	// the whitebalance and a few other values are apparently RGBE, which is RGB
 	// with a shared exponent. It's weird and I don't entirely understand how to
@@ -28,9 +420,6 @@ pub fn decode<R: Read>(reader: &mut R) -> Result<Image<u16, BayerRgb>, Error> {
 	// https://www.snappiness.space/testing-the-only-rgbe-sensor-ever-made/
 	let wb_coeffs = image.wb_coeffs;
 	let whitebalance = [wb_coeffs[0], wb_coeffs[1], wb_coeffs[2]];
-	let wl = image.whitelevels;
-	let whitelevels = [wl[0], wl[1], wl[2]];
-	let crop = Crop::from_css_quad(image.crops);
 
 	let rlm = image.xyz_to_cam;
 	#[rustfmt::skip]
@@ -40,41 +429,346 @@ pub fn decode<R: Read>(reader: &mut R) -> Result<Image<u16, BayerRgb>, Error> {
 		rlm[2][0], rlm[2][1], rlm[2][2],
 	);
 
+	// rawloader can compute the whitebalance that neutralizes the camera
+	// matrix, which is what the camera would use in daylight. It's a good
+	// reference to have around for figuring out how far "as shot" strays.
+	// If rawloader doesn't have a matrix we use ours, and do it ourselves.
+	let ours = if colormatrix::is_usable(&xyz_to_cam) {
+		None
+	} else {
+		colormatrix::lookup(&image.clean_make, &image.clean_model)
+	};
+	let (xyz_to_cam, daylight_whitebalance) = match ours {
+		Some(ours) => (ours, dng::daylight_whitebalance(&ours)),
+		None => {
+			let neutral = image.neutralwb();
+			(xyz_to_cam, [neutral[0], neutral[1], neutral[2]])
+		}
+	};
+
+	// Some cameras don't tell us what they shot with, and rawloader gives us
+	// NaN. Without a matrix, daylight can come out NaN or infinite too. We
+	// want real multipliers either way: the camera's, then daylight, then
+	// leaving the channels as they are.
+	let usable = |wb: &[f32; 3]| wb.iter().all(|c| c.is_finite() && *c > 0.0);
+	let daylight_whitebalance = if usable(&daylight_whitebalance) {
+		daylight_whitebalance
+	} else {
+		[1.0; 3]
+	};
+	let whitebalance = if usable(&whitebalance) {
+		whitebalance
+	} else {
+		daylight_whitebalance
+	};
+	let wl = image.whitelevels;
+	let whitelevels = [wl[0], wl[1], wl[2]];
+	let bl = image.blacklevels;
+	let blacklevels = [bl[0], bl[1], bl[2]];
+	let active_area = Crop::from_css_quad(image.crops);
+	let default_crop = {
+		let (width, height) = match active_area {
+			// Support catches a crop that doesn't fit
+			Some(area) => (
+				image.width.saturating_sub(area.left + area.right),
+				image.height.saturating_sub(area.top + area.bottom),
+			),
+			None => (image.width, image.height),
+		};
+
+		dng::default_crop(bytes, width, height)
+	};
+
 	#[rustfmt::skip]
-	let cam_to_xyz = xyz_to_cam.try_inverse().unwrap().normalize();
+	let cam_to_xyz = xyz_to_cam.try_inverse().unwrap_or_else(Matrix3::identity).normalize();
 
 	let metadata = RawMetadata {
 		whitebalance,
-		crop,
+		as_shot_whitebalance: whitebalance,
+		daylight_whitebalance,
+		whitebalance_presets: vendor_whitebalance.presets,
+		whitebalance_selected: vendor_whitebalance.selected,
+		whitebalance_fine_tune: vendor_whitebalance.fine_tune,
+		makernote,
+		sub_images,
+		lens_correction: lens::embedded(bytes),
+		orientation: Orientation::from_exif(image.orientation.to_u16()),
+		exif,
+		active_area,
+		default_crop,
 		whitelevels,
+		blacklevels,
 		cfa: image.cfa,
+		xyz_to_cam,
 		cam_to_xyz,
+		make: image.clean_make.clone(),
+		model: image.clean_model.clone(),
+		serial: tiff::serial_number(bytes),
 	};
 
 	let data = match image.data {
 		RawImageData::Float(_) => return Err(Error::FloatImageData),
 		RawImageData::Integer(intu16) => intu16,
 	};
+	if data.len() != image.width * image.height * image.cpp {
+		return Err(Error::CorruptFile(String::from(
+			"there isn't the right amount of image data for its size",
+		)));
+	}
+
+	// Three components per pixel means it was demosaiced already, and one
+	// without a pattern means there was never a colour filter to begin with
+	let colorspace = match image.cpp {
+		3 => ColorspaceKind::LinRgb,
+		_ if !metadata.cfa.is_valid() => ColorspaceKind::Monochrome,
+		_ => ColorspaceKind::BayerRgb,
+	};
 
-	Ok(Image {
+	Ok(DynImage {
 		width: image.width,
 		height: image.height,
 		metadata,
-		phantom: Default::default(),
+		colorspace,
 
 		data,
 	})
 }
 
+/// Decode into floats, black at 0.0 and white at 1.0. Floating point DNGs,
+/// like the ones HDR merges write, go straight in without ever being squeezed
+/// through a whitelevel. Everything else is decoded and [normalized].
+///
+/// [normalized]: Image::normalize
+pub fn decode_float<R: Read>(reader: &mut R) -> Result<Image<f32, BayerRgb>, Error> {
+	let mut bytes = vec![];
+	reader.read_to_end(&mut bytes)?;
+
+	if dng::is_dng(&bytes) && dng::is_float(&bytes) {
+		return dng::decode_float(&bytes);
+	}
+
+	let image: Image<u16, BayerRgb> = decode_bytes(&bytes)?.try_into()?;
+	Ok(image.normalize())
+}
+
+/// Everything that can go wrong. The ones a caller is most likely to want to
+/// tell apart are right here: [UnsupportedFormat](Error::UnsupportedFormat)
+/// for a camera or file we can't read,
+/// [PartialSupport](Error::PartialSupport) for one we can only partly read,
+/// [TruncatedFile](Error::TruncatedFile)
+/// and [CorruptFile](Error::CorruptFile) for a bad file, and
+/// [Io](Error::Io). The rest say which part of rawproc had the problem.
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum Error {
 	#[error("{source}")]
-	RawLoaderError {
+	Io {
+		#[from]
+		source: std::io::Error,
+	},
+	#[error("{}", unsupported(.make, .model))]
+	UnsupportedFormat {
+		make: Option<String>,
+		model: Option<String>,
+	},
+	/// We decoded it, but the raw doesn't have something the pipeline
+	/// needs. [decode_partial] gets you the image anyway.
+	#[error("{}", partial(.make, .model, .support))]
+	PartialSupport {
+		make: String,
+		model: String,
+		support: Support,
+	},
+	#[error("The file ended before the image did")]
+	TruncatedFile,
+	#[error("The file is corrupt: {0}")]
+	CorruptFile(String),
+	#[error("{source}")]
+	RawLoaderError { source: RawLoaderError },
+	#[error("{source}")]
+	Ljpeg { source: ljpeg::LjpegError },
+	#[error("{source}")]
+	Cdl {
+		#[from]
+		source: cdl::CdlError,
+	},
+	#[error("{source}")]
+	Lut {
+		#[from]
+		source: lut::LutError,
+	},
+	#[error("{source}")]
+	Cr2 { source: cr2::Cr2Error },
+	#[cfg(feature = "fs")]
+	#[error("{source}")]
+	Sequence { source: sequence::SequenceError },
+	#[error("{source}")]
+	Dng { source: dng::DngError },
+	#[error("{source}")]
+	HotPixel {
+		#[from]
+		source: hotpixel::HotPixelError,
+	},
+	#[error("{source}")]
+	Calibration {
+		#[from]
+		source: image::CalibrationError,
+	},
+	#[error("{source}")]
+	Jpeg {
+		#[from]
+		source: jpeg_encoder::EncodingError,
+	},
+	#[error("{source}")]
+	Stack {
 		#[from]
-		source: RawLoaderError,
+		source: stack::StackError,
 	},
-	#[error("Raw image data was floats. Please talk to gennyble if you want this supported")]
+	#[error("{source}")]
+	PixelShift {
+		#[from]
+		source: pixelshift::PixelShiftError,
+	},
+	#[error("{source}")]
+	Recipe {
+		#[from]
+		source: recipe::RecipeError,
+	},
+	#[error("JPEGs can't be more than 65535 pixels either way, and this is {width}x{height}")]
+	TooBigForJpeg { width: usize, height: usize },
+	#[cfg(feature = "image")]
+	#[error("The image crate can't do more than 4294967295 pixels either way, and this is {width}x{height}")]
+	TooBigForImageCrate { width: usize, height: usize },
+	#[error("The region doesn't fit in the {width}x{height} image")]
+	RegionOutOfBounds { width: usize, height: usize },
+	#[error("Raw image data was floats, decode it with decode_float instead")]
 	FloatImageData,
+	#[error("Raw image data was already demosaiced, decode it with decode_dyn instead")]
+	LinearImageData,
+	#[error("Expected an image in {expected:?} but it was in {found:?}")]
+	ColorspaceMismatch {
+		expected: ColorspaceKind,
+		found: ColorspaceKind,
+	},
+	#[error("Expected {expected:?} samples but they were {found:?}")]
+	SampleMismatch {
+		expected: image::SampleKind,
+		found: image::SampleKind,
+	},
+	#[error("We don't know how to convert from {from:?} to {to:?}")]
+	UnsupportedConversion {
+		from: ColorspaceKind,
+		to: ColorspaceKind,
+	},
+	#[error("There's no sub-image {0}")]
+	NoSubImage(usize),
+	#[error("The CFA pattern {0} isn't Quad Bayer")]
+	NotQuadBayer(String),
+	#[error("Cancelled before it was done")]
+	Cancelled,
+}
+
+fn unsupported(make: &Option<String>, model: &Option<String>) -> String {
+	match (make, model) {
+		// Models usually have the make in them already
+		(Some(make), Some(model)) if model.starts_with(make.as_str()) => {
+			format!("We can't decode raws from the {model} yet")
+		}
+		(Some(make), Some(model)) => format!("We can't decode raws from the {make} {model} yet"),
+		(Some(make), None) => format!("We can't decode raws from {make} yet"),
+		_ => String::from("This isn't a raw format we know"),
+	}
+}
+
+fn partial(make: &str, model: &str, support: &Support) -> String {
+	let camera = match (make, model) {
+		("", "") => String::from("this camera"),
+		(make, "") => make.to_owned(),
+		(make, model) if model.starts_with(make) => format!("the {model}"),
+		(make, model) => format!("the {make} {model}"),
+	};
+
+	format!(
+		"We can only partly decode raws from {camera}, we don't have its {}",
+		support.missing().join(" or ")
+	)
+}
+
+// rawloader's errors are only a message, so we pick the ones we care about
+// out of that
+impl From<RawLoaderError> for Error {
+	fn from(source: RawLoaderError) -> Self {
+		let message = source.to_string();
+		let message = message
+			.strip_prefix("RawLoaderError: \"")
+			.and_then(|m| m.strip_suffix('"'))
+			.unwrap_or(&message);
+		// Names are quoted, so splitting on quotes puts them at the odd indices
+		let quoted: Vec<&str> = message.split('"').collect();
+
+		if message.starts_with("Couldn't find camera") && quoted.len() >= 4 {
+			Error::UnsupportedFormat {
+				make: Some(quoted[1].to_owned()),
+				model: Some(quoted[3].to_owned()),
+			}
+		} else if message.starts_with("Couldn't find a decoder for make") && quoted.len() >= 2 {
+			Error::UnsupportedFormat {
+				make: Some(quoted[1].to_owned()),
+				model: None,
+			}
+		} else if message.starts_with("Couldn't find a decoder") {
+			Error::UnsupportedFormat {
+				make: None,
+				model: None,
+			}
+		} else if message.starts_with("Caught a panic") {
+			// rawloader's decoders don't check what they read, so a panic is
+			// almost always a bad file
+			Error::CorruptFile(String::from("rawloader couldn't make sense of it"))
+		} else {
+			Error::RawLoaderError { source }
+		}
+	}
+}
+
+// Running out of data is the same problem wherever it happens, so those get
+// lifted out to TruncatedFile
+
+impl From<ljpeg::LjpegError> for Error {
+	fn from(source: ljpeg::LjpegError) -> Self {
+		match source {
+			ljpeg::LjpegError::UnexpectedEnd => Error::TruncatedFile,
+			source => Error::Ljpeg { source },
+		}
+	}
+}
+
+impl From<cr2::Cr2Error> for Error {
+	fn from(source: cr2::Cr2Error) -> Self {
+		match source {
+			cr2::Cr2Error::Truncated => Error::TruncatedFile,
+			source => Error::Cr2 { source },
+		}
+	}
+}
+
+#[cfg(feature = "fs")]
+impl From<sequence::SequenceError> for Error {
+	fn from(source: sequence::SequenceError) -> Self {
+		match source {
+			sequence::SequenceError::Truncated => Error::TruncatedFile,
+			source => Error::Sequence { source },
+		}
+	}
+}
+
+impl From<dng::DngError> for Error {
+	fn from(source: dng::DngError) -> Self {
+		match source {
+			dng::DngError::Truncated => Error::TruncatedFile,
+			source => Error::Dng { source },
+		}
+	}
 }
 
 struct RollingRandom {
@@ -85,9 +779,26 @@ struct RollingRandom {
 impl RollingRandom {
 	const BUCKET_SIZE: usize = 1024;
 
-	pub fn new() -> Self {
+	/// Random numbers that only depend on `seed` and `stream`, so the same
+	/// two give the same numbers on every run and every machine. Different
+	/// streams of one seed are different numbers, for handing out one per
+	/// row or per thread.
+	pub fn new(seed: u64, stream: u64) -> Self {
+		// SplitMix64. It's tiny, and being ours means the numbers, and
+		// everything made with them, can't change under us with a dependency
+		let mut state = seed ^ stream.wrapping_mul(0xD1B5_4A32_D192_ED03);
+		let mut next = || {
+			state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+			let mut z = state;
+			z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+			z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+			z ^ (z >> 31)
+		};
+
 		let mut values = [0u8; Self::BUCKET_SIZE];
-		thread_rng().fill(&mut values[..]);
+		for chunk in values.chunks_exact_mut(8) {
+			chunk.copy_from_slice(&next().to_le_bytes());
+		}
 
 		Self { values, index: 0 }
 	}
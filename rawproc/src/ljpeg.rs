@@ -0,0 +1,712 @@
+//! Lossless JPEG, the predictive mode from ITU-T T.81 (SOF3).
+//!
+//! It's what CR2 and compressed DNG, along with a handful of others, use to
+//! squish their raw data. Every sample is predicted from its neighbours and
+//! only the difference is stored, huffman coded.
+//!
+//! We only handle what raw files actually use: a single scan with every
+//! component in it, and no subsampling.
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LjpegError {
+	#[error("Data ended before we were done reading it")]
+	UnexpectedEnd,
+	#[error("Expected a JPEG marker but found {0:#04X}")]
+	ExpectedMarker(u8),
+	#[error("This isn't a lossless JPEG, the frame marker was {0:#06X}")]
+	NotLossless(u16),
+	#[error("Missing the {0} marker")]
+	Missing(&'static str),
+	#[error("Huffman table {0} was used but never defined")]
+	MissingTable(usize),
+	#[error("Ran into a huffman code we don't have")]
+	BadHuffmanCode,
+	#[error("Predictor {0} isn't one of the seven lossless predictors")]
+	BadPredictor(u8),
+	#[error("Subsampled components aren't supported")]
+	Subsampled,
+	#[error("The scan doesn't include every component of the frame")]
+	PartialScan,
+	#[error("Samples can't be {0} bits, lossless JPEG is 2 to 16")]
+	BadPrecision(u8),
+	#[error("A point transform of {0} doesn't leave any bits of the sample")]
+	BadPointTransform(u8),
+	#[error("A difference can't be {0} bits long, 16 is the most")]
+	BadDifference(u8),
+}
+
+/// A decoded lossless JPEG. Components are interleaved, so each row is
+/// `width * components` samples long.
+#[derive(Clone, Debug)]
+pub struct LjpegImage {
+	pub width: usize,
+	pub height: usize,
+	pub components: usize,
+	/// Bits per sample
+	pub precision: u8,
+	pub data: Vec<u16>,
+}
+
+#[derive(Clone, Debug)]
+struct Frame {
+	precision: u8,
+	height: usize,
+	width: usize,
+	component_ids: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+struct Scan {
+	/// Huffman table index, in frame component order
+	tables: Vec<usize>,
+	predictor: u8,
+	point_transform: u8,
+	/// Where the entropy coded data starts
+	start: usize,
+}
+
+pub fn decode(data: &[u8]) -> Result<LjpegImage, LjpegError> {
+	let mut pos = 0;
+	let mut frame = None;
+	let mut tables: [Option<Huffman>; 4] = Default::default();
+	let mut restart_interval = 0;
+
+	if read_u16(data, pos)? != 0xFFD8 {
+		return Err(LjpegError::Missing("SOI"));
+	}
+	pos += 2;
+
+	let scan = loop {
+		let marker = read_u16(data, pos)?;
+		if marker >> 8 != 0xFF {
+			return Err(LjpegError::ExpectedMarker((marker >> 8) as u8));
+		}
+
+		let length = read_u16(data, pos + 2)? as usize;
+		let segment = data
+			.get(pos + 4..pos + 2 + length)
+			.ok_or(LjpegError::UnexpectedEnd)?;
+
+		match marker {
+			0xFFC3 => frame = Some(parse_frame(segment)?),
+			0xFFC0..=0xFFC2 | 0xFFC5..=0xFFC7 | 0xFFC9..=0xFFCB | 0xFFCD..=0xFFCF => {
+				return Err(LjpegError::NotLossless(marker))
+			}
+			0xFFC4 => parse_huffman(segment, &mut tables)?,
+			0xFFDD => restart_interval = read_u16(segment, 0)? as usize,
+			0xFFDA => {
+				let frame = frame.as_ref().ok_or(LjpegError::Missing("SOF3"))?;
+				break parse_scan(segment, frame, pos + 2 + length)?;
+			}
+			_ => (),
+		}
+
+		pos += 2 + length;
+	};
+
+	let frame = frame.ok_or(LjpegError::Missing("SOF3"))?;
+	let huffman = scan
+		.tables
+		.iter()
+		.map(|idx| {
+			tables
+				.get(*idx)
+				.and_then(Option::as_ref)
+				.ok_or(LjpegError::MissingTable(*idx))
+		})
+		.collect::<Result<Vec<&Huffman>, LjpegError>>()?;
+
+	let samples = decode_scan(data, &frame, &scan, &huffman, restart_interval)?;
+
+	Ok(LjpegImage {
+		width: frame.width,
+		height: frame.height,
+		components: frame.component_ids.len(),
+		precision: frame.precision,
+		data: samples,
+	})
+}
+
+fn parse_frame(seg: &[u8]) -> Result<Frame, LjpegError> {
+	let precision = *seg.first().ok_or(LjpegError::UnexpectedEnd)?;
+	if !(2..=16).contains(&precision) {
+		return Err(LjpegError::BadPrecision(precision));
+	}
+	let height = read_u16(seg, 1)? as usize;
+	let width = read_u16(seg, 3)? as usize;
+	let count = *seg.get(5).ok_or(LjpegError::UnexpectedEnd)? as usize;
+
+	let mut component_ids = Vec::with_capacity(count);
+	for idx in 0..count {
+		let comp = seg
+			.get(6 + idx * 3..9 + idx * 3)
+			.ok_or(LjpegError::UnexpectedEnd)?;
+
+		if comp[1] != 0x11 {
+			return Err(LjpegError::Subsampled);
+		}
+		component_ids.push(comp[0]);
+	}
+
+	Ok(Frame {
+		precision,
+		height,
+		width,
+		component_ids,
+	})
+}
+
+fn parse_huffman(mut seg: &[u8], tables: &mut [Option<Huffman>; 4]) -> Result<(), LjpegError> {
+	while !seg.is_empty() {
+		let index = (seg[0] & 0x0F) as usize;
+		let counts: [u8; 16] = seg
+			.get(1..17)
+			.ok_or(LjpegError::UnexpectedEnd)?
+			.try_into()
+			.unwrap();
+		let total: usize = counts.iter().map(|c| *c as usize).sum();
+		let values = seg.get(17..17 + total).ok_or(LjpegError::UnexpectedEnd)?;
+
+		*tables
+			.get_mut(index)
+			.ok_or(LjpegError::MissingTable(index))? = Some(Huffman::new(counts, values.to_vec()));
+		seg = &seg[17 + total..];
+	}
+
+	Ok(())
+}
+
+fn parse_scan(seg: &[u8], frame: &Frame, start: usize) -> Result<Scan, LjpegError> {
+	let count = *seg.first().ok_or(LjpegError::UnexpectedEnd)? as usize;
+	if count != frame.component_ids.len() {
+		return Err(LjpegError::PartialScan);
+	}
+
+	let mut tables = vec![0; count];
+	for idx in 0..count {
+		let comp = seg
+			.get(1 + idx * 2..3 + idx * 2)
+			.ok_or(LjpegError::UnexpectedEnd)?;
+		let frame_idx = frame
+			.component_ids
+			.iter()
+			.position(|id| *id == comp[0])
+			.ok_or(LjpegError::PartialScan)?;
+
+		tables[frame_idx] = (comp[1] >> 4) as usize;
+	}
+
+	let rest = seg
+		.get(1 + count * 2..4 + count * 2)
+		.ok_or(LjpegError::UnexpectedEnd)?;
+	let predictor = rest[0];
+	if !(1..=7).contains(&predictor) {
+		return Err(LjpegError::BadPredictor(predictor));
+	}
+
+	let point_transform = rest[2] & 0x0F;
+	if point_transform >= frame.precision {
+		return Err(LjpegError::BadPointTransform(point_transform));
+	}
+
+	Ok(Scan {
+		tables,
+		predictor,
+		point_transform,
+		start,
+	})
+}
+
+fn decode_scan(
+	data: &[u8],
+	frame: &Frame,
+	scan: &Scan,
+	huffman: &[&Huffman],
+	restart_interval: usize,
+) -> Result<Vec<u16>, LjpegError> {
+	let comps = frame.component_ids.len();
+	let row_len = frame.width * comps;
+	let mut out = vec![0u16; row_len * frame.height];
+	let mut bits = BitReader::new(data, scan.start);
+
+	let pt = scan.point_transform;
+	let initial = 1i32 << (frame.precision - pt - 1);
+	let mask = (1i32 << 16) - 1;
+
+	// Lines that start a restart interval predict like the first line does
+	let mut first_line = true;
+	let mut mcus = 0;
+
+	for y in 0..frame.height {
+		for x in 0..frame.width {
+			if restart_interval != 0 && mcus == restart_interval {
+				bits.restart();
+				mcus = 0;
+				first_line = true;
+			}
+
+			for (c, table) in huffman.iter().enumerate() {
+				let idx = y * row_len + x * comps + c;
+				let diff = read_diff(&mut bits, table)?;
+
+				let left = || (out[idx - comps] >> pt) as i32;
+				let above = || (out[idx - row_len] >> pt) as i32;
+				let diag = || (out[idx - row_len - comps] >> pt) as i32;
+
+				let prediction = if x == 0 && (y == 0 || first_line) {
+					initial
+				} else if first_line {
+					left()
+				} else if x == 0 {
+					above()
+				} else {
+					let (ra, rb, rc) = (left(), above(), diag());
+					match scan.predictor {
+						1 => ra,
+						2 => rb,
+						3 => rc,
+						4 => ra + rb - rc,
+						5 => ra + ((rb - rc) >> 1),
+						6 => rb + ((ra - rc) >> 1),
+						7 => (ra + rb) >> 1,
+						_ => unreachable!(),
+					}
+				};
+
+				out[idx] = (((prediction + diff) & mask) << pt) as u16;
+			}
+
+			mcus += 1;
+		}
+
+		first_line = false;
+	}
+
+	Ok(out)
+}
+
+fn read_diff(bits: &mut BitReader, huffman: &Huffman) -> Result<i32, LjpegError> {
+	let ssss = huffman.decode(bits)?;
+	if ssss > 16 {
+		return Err(LjpegError::BadDifference(ssss));
+	}
+
+	let ssss = ssss as u32;
+	Ok(match ssss {
+		0 => 0,
+		16 => 32768,
+		_ => {
+			let v = bits.bits(ssss) as i32;
+			// Values with a leading 0 are negative
+			if v < 1 << (ssss - 1) {
+				v - (1 << ssss) + 1
+			} else {
+				v
+			}
+		}
+	})
+}
+
+#[derive(Clone, Debug)]
+struct Huffman {
+	maxcode: [i32; 18],
+	valptr: [i32; 17],
+	mincode: [i32; 17],
+	values: Vec<u8>,
+}
+
+impl Huffman {
+	fn new(counts: [u8; 16], values: Vec<u8>) -> Self {
+		let mut maxcode = [-1; 18];
+		let mut valptr = [0; 17];
+		let mut mincode = [0; 17];
+
+		let mut code = 0;
+		let mut k = 0;
+		for len in 1..=16 {
+			let n = counts[len - 1] as i32;
+			if n != 0 {
+				valptr[len] = k;
+				mincode[len] = code;
+				code += n;
+				k += n;
+				maxcode[len] = code - 1;
+			}
+			code <<= 1;
+		}
+		maxcode[17] = i32::MAX;
+
+		Self {
+			maxcode,
+			valptr,
+			mincode,
+			values,
+		}
+	}
+
+	fn decode(&self, bits: &mut BitReader) -> Result<u8, LjpegError> {
+		let mut code = bits.bits(1) as i32;
+		let mut len = 1;
+
+		while len <= 16 && code > self.maxcode[len] {
+			code = (code << 1) | bits.bits(1) as i32;
+			len += 1;
+		}
+
+		if len > 16 {
+			return Err(LjpegError::BadHuffmanCode);
+		}
+
+		let idx = self.valptr[len] + code - self.mincode[len];
+		self.values
+			.get(idx as usize)
+			.copied()
+			.ok_or(LjpegError::BadHuffmanCode)
+	}
+}
+
+/// Reads bits from entropy coded data, undoing the 0xFF00 byte stuffing.
+/// When it hits a marker it stops and reads zeros, like libjpeg does.
+struct BitReader<'a> {
+	data: &'a [u8],
+	pos: usize,
+	acc: u64,
+	count: u32,
+	at_marker: bool,
+}
+
+impl<'a> BitReader<'a> {
+	fn new(data: &'a [u8], pos: usize) -> Self {
+		Self {
+			data,
+			pos,
+			acc: 0,
+			count: 0,
+			at_marker: false,
+		}
+	}
+
+	fn fill(&mut self) {
+		while self.count <= 56 {
+			let byte = if self.at_marker || self.pos >= self.data.len() {
+				0
+			} else if self.data[self.pos] == 0xFF {
+				if self.data.get(self.pos + 1) == Some(&0) {
+					self.pos += 2;
+					0xFF
+				} else {
+					self.at_marker = true;
+					0
+				}
+			} else {
+				self.pos += 1;
+				self.data[self.pos - 1]
+			};
+
+			self.acc |= (byte as u64) << (56 - self.count);
+			self.count += 8;
+		}
+	}
+
+	fn bits(&mut self, n: u32) -> u32 {
+		if n == 0 {
+			return 0;
+		}
+
+		if self.count < n {
+			self.fill();
+		}
+
+		let v = (self.acc >> (64 - n)) as u32;
+		self.acc <<= n;
+		self.count -= n;
+		v
+	}
+
+	/// Skip past the next RST marker and start fresh
+	fn restart(&mut self) {
+		while self.pos + 1 < self.data.len() {
+			if self.data[self.pos] == 0xFF && (0xD0..=0xD7).contains(&self.data[self.pos + 1]) {
+				self.pos += 2;
+				break;
+			}
+			self.pos += 1;
+		}
+
+		self.acc = 0;
+		self.count = 0;
+		self.at_marker = false;
+	}
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16, LjpegError> {
+	data.get(pos..pos + 2)
+		.map(|b| u16::from_be_bytes([b[0], b[1]]))
+		.ok_or(LjpegError::UnexpectedEnd)
+}
+
+/// Encode samples as a lossless JPEG. `data` is interleaved, like
+/// [LjpegImage::data], and every sample has to fit in `precision` bits.
+///
+/// We use predictor 1, the sample to the left, and build a huffman table
+/// fit to the data.
+pub fn encode(
+	data: &[u16],
+	width: usize,
+	height: usize,
+	components: usize,
+	precision: u8,
+) -> Vec<u8> {
+	let row_len = width * components;
+	let initial = 1i32 << (precision - 1);
+
+	// First pass works out the differences so we can fit the huffman table
+	let mut diffs = Vec::with_capacity(data.len());
+	for y in 0..height {
+		for x in 0..width {
+			for c in 0..components {
+				let idx = y * row_len + x * components + c;
+				let prediction = if x == 0 && y == 0 {
+					initial
+				} else if x == 0 {
+					data[idx - row_len] as i32
+				} else {
+					data[idx - components] as i32
+				};
+
+				let mut diff = (data[idx] as i32 - prediction) & 0xFFFF;
+				if diff > 32768 {
+					diff -= 65536;
+				}
+				diffs.push(diff);
+			}
+		}
+	}
+
+	let mut freq = [0u32; 17];
+	for diff in diffs.iter() {
+		freq[category(*diff) as usize] += 1;
+	}
+	let (counts, values) = fit_huffman(&freq);
+	let codes = canonical_codes(&counts, &values);
+
+	let mut out = vec![0xFF, 0xD8];
+
+	// SOF3
+	let mut sof = vec![precision];
+	sof.extend_from_slice(&(height as u16).to_be_bytes());
+	sof.extend_from_slice(&(width as u16).to_be_bytes());
+	sof.push(components as u8);
+	for c in 0..components {
+		sof.extend_from_slice(&[c as u8, 0x11, 0]);
+	}
+	write_segment(&mut out, 0xFFC3, &sof);
+
+	// DHT, one table at index 0 used for everything
+	let mut dht = vec![0];
+	dht.extend_from_slice(&counts);
+	dht.extend_from_slice(&values);
+	write_segment(&mut out, 0xFFC4, &dht);
+
+	// SOS with predictor 1 and no point transform
+	let mut sos = vec![components as u8];
+	for c in 0..components {
+		sos.extend_from_slice(&[c as u8, 0x00]);
+	}
+	sos.extend_from_slice(&[1, 0, 0]);
+	write_segment(&mut out, 0xFFDA, &sos);
+
+	let mut bits = BitWriter::new(out);
+	for diff in diffs {
+		let ssss = category(diff);
+		let (code, len) = codes[ssss as usize];
+		bits.write(code as u32, len as u32);
+
+		if ssss != 0 && ssss != 16 {
+			let extra = if diff < 0 { diff - 1 } else { diff };
+			bits.write(extra as u32 & ((1 << ssss) - 1), ssss);
+		}
+	}
+
+	let mut out = bits.finish();
+	out.extend_from_slice(&[0xFF, 0xD9]);
+	out
+}
+
+/// SSSS, the number of bits it takes to hold the magnitude of the difference
+fn category(diff: i32) -> u32 {
+	if diff == 32768 {
+		16
+	} else {
+		32 - diff.unsigned_abs().leading_zeros()
+	}
+}
+
+fn write_segment(out: &mut Vec<u8>, marker: u16, data: &[u8]) {
+	out.extend_from_slice(&marker.to_be_bytes());
+	out.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+	out.extend_from_slice(data);
+}
+
+// Building an optimal table limited to 16 bit codes, straight out of
+// Annex K.2 and K.3 of the spec. Symbol 256 is reserved so that no code is
+// all ones, and taken out again at the end.
+fn fit_huffman(freq: &[u32; 17]) -> ([u8; 16], Vec<u8>) {
+	let mut freq: Vec<u64> = (0..257)
+		.map(|s| if s < 17 { freq[s] as u64 } else { 0 })
+		.collect();
+	freq[256] = 1;
+
+	let mut codesize = [0usize; 257];
+	let mut others = [usize::MAX; 257];
+
+	loop {
+		// The least frequent, ties going to the larger symbol
+		let least = |exclude: Option<usize>| -> Option<usize> {
+			let mut found: Option<usize> = None;
+			for v in 0..257 {
+				if freq[v] > 0 && Some(v) != exclude {
+					match found {
+						Some(f) if freq[v] > freq[f] => (),
+						_ => found = Some(v),
+					}
+				}
+			}
+			found
+		};
+
+		let v1 = least(None).unwrap();
+		let v2 = match least(Some(v1)) {
+			Some(v2) => v2,
+			None => break,
+		};
+
+		freq[v1] += freq[v2];
+		freq[v2] = 0;
+
+		let mut v = v1;
+		codesize[v] += 1;
+		while others[v] != usize::MAX {
+			v = others[v];
+			codesize[v] += 1;
+		}
+		others[v] = v2;
+
+		let mut v = v2;
+		codesize[v] += 1;
+		while others[v] != usize::MAX {
+			v = others[v];
+			codesize[v] += 1;
+		}
+	}
+
+	let mut bits = [0u32; 33];
+	for size in codesize.iter().filter(|s| **s > 0) {
+		bits[*size] += 1;
+	}
+
+	let mut i = 32;
+	while i > 16 {
+		while bits[i] > 0 {
+			let mut j = i - 2;
+			while bits[j] == 0 {
+				j -= 1;
+			}
+
+			bits[i] -= 2;
+			bits[i - 1] += 1;
+			bits[j + 1] += 2;
+			bits[j] -= 1;
+		}
+		i -= 1;
+	}
+
+	while bits[i] == 0 {
+		i -= 1;
+	}
+	bits[i] -= 1;
+
+	let mut counts = [0u8; 16];
+	for len in 1..=16 {
+		counts[len - 1] = bits[len] as u8;
+	}
+
+	let mut values = vec![];
+	for size in 1..=32 {
+		for (symbol, codesize) in codesize.iter().enumerate().take(256) {
+			if *codesize == size {
+				values.push(symbol as u8);
+			}
+		}
+	}
+
+	(counts, values)
+}
+
+/// Code and length for each of the 17 categories
+fn canonical_codes(counts: &[u8; 16], values: &[u8]) -> [(u16, u8); 17] {
+	let mut codes = [(0, 0); 17];
+	let mut code = 0u16;
+	let mut k = 0;
+
+	for len in 1..=16 {
+		for _ in 0..counts[len - 1] {
+			codes[values[k] as usize] = (code, len as u8);
+			code += 1;
+			k += 1;
+		}
+		code <<= 1;
+	}
+
+	codes
+}
+
+/// Writes bits MSB first, stuffing a 0x00 after every 0xFF.
+struct BitWriter {
+	out: Vec<u8>,
+	acc: u32,
+	count: u32,
+}
+
+impl BitWriter {
+	fn new(out: Vec<u8>) -> Self {
+		Self {
+			out,
+			acc: 0,
+			count: 0,
+		}
+	}
+
+	fn write(&mut self, value: u32, len: u32) {
+		for bit in (0..len).rev() {
+			self.acc = (self.acc << 1) | ((value >> bit) & 1);
+			self.count += 1;
+
+			if self.count == 8 {
+				self.push(self.acc as u8);
+				self.acc = 0;
+				self.count = 0;
+			}
+		}
+	}
+
+	fn push(&mut self, byte: u8) {
+		self.out.push(byte);
+		if byte == 0xFF {
+			self.out.push(0x00);
+		}
+	}
+
+	/// Pad out the last byte with ones, as the spec asks
+	fn finish(mut self) -> Vec<u8> {
+		if self.count > 0 {
+			let pad = 8 - self.count;
+			let byte = (self.acc << pad) | ((1 << pad) - 1);
+			self.push(byte as u8);
+		}
+
+		self.out
+	}
+}
@@ -0,0 +1,211 @@
+//! 3D LUTs, the `.cube` files video tools grade with and sell looks as. A
+//! LUT is a cube of colours: the input picks a spot in the cube and the
+//! output is whatever's stored there, interpolated from the corners around
+//! it.
+//!
+//! We read Adobe's and Resolve's `.cube`. 1D LUTs, and the 1D shaper some
+//! files put in front of the cube, aren't supported.
+
+use crate::{colorspace::Rgb, image::Image, Error};
+
+// Bigger than anything anyone makes. 256 is already 200MB of floats.
+const MAX_SIZE: usize = 256;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LutError {
+	#[error("The cube file is missing its LUT_3D_SIZE")]
+	MissingSize,
+	#[error("The cube file's size is {0}, it should be 2 to 256")]
+	BadSize(usize),
+	#[error("1D LUTs aren't supported, only 3D")]
+	OneDimensional,
+	#[error("Line {0} of the cube file isn't something we understand")]
+	BadLine(usize),
+	#[error("The cube file should have {expected} entries but it has {found}")]
+	WrongCount { expected: usize, found: usize },
+}
+
+/// How to get between the points of the cube
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Interpolation {
+	/// Blend the eight corners around the input. It's what most people mean
+	/// by interpolating a cube, but it can tint greys a little, because it
+	/// mixes in corners that aren't on the grey diagonal.
+	Trilinear,
+	/// Blend the four corners of the tetrahedron the input is in. Greys stay
+	/// grey and it's a bit quicker. Resolve and most hardware do this.
+	#[default]
+	Tetrahedral,
+}
+
+/// A 3D LUT. Each side of the cube is `size` points long and they're
+/// spread evenly from `domain_min` to `domain_max`, which is usually 0..1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut3d {
+	pub title: Option<String>,
+	pub size: usize,
+	pub domain_min: [f32; 3],
+	pub domain_max: [f32; 3],
+	/// The outputs, red changing fastest, then green, then blue, like the
+	/// file has them
+	table: Vec<[f32; 3]>,
+}
+
+impl Lut3d {
+	/// Read a `.cube` file
+	pub fn from_cube(text: &str) -> Result<Self, Error> {
+		let mut title = None;
+		let mut size = None;
+		let mut domain_min = [0.0; 3];
+		let mut domain_max = [1.0; 3];
+		let mut table = vec![];
+
+		for (idx, line) in text.lines().enumerate() {
+			let line = line.split('#').next().unwrap_or_default().trim();
+			if line.is_empty() {
+				continue;
+			}
+
+			let bad = LutError::BadLine(idx + 1);
+			let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+			let rest = rest.trim();
+
+			match keyword {
+				"TITLE" => title = Some(rest.trim_matches('"').to_owned()),
+				"LUT_3D_SIZE" => {
+					let n: usize = rest.parse().map_err(|_| bad)?;
+					if !(2..=MAX_SIZE).contains(&n) {
+						return Err(LutError::BadSize(n).into());
+					}
+					size = Some(n);
+				}
+				"LUT_1D_SIZE" | "LUT_1D_INPUT_RANGE" => return Err(LutError::OneDimensional.into()),
+				"DOMAIN_MIN" => domain_min = numbers(rest).ok_or(bad)?,
+				"DOMAIN_MAX" => domain_max = numbers(rest).ok_or(bad)?,
+				// Resolve's way of saying the domain, the same for all three
+				"LUT_3D_INPUT_RANGE" => {
+					let [min, max] = numbers(rest).ok_or(bad)?;
+					domain_min = [min; 3];
+					domain_max = [max; 3];
+				}
+				_ if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+					// Some other keyword, like LUT_IN_VIDEO_RANGE, that
+					// doesn't change the cube
+				}
+				_ => table.push(numbers(line).ok_or(bad)?),
+			}
+		}
+
+		let size = size.ok_or(LutError::MissingSize)?;
+		let expected = size * size * size;
+		if table.len() != expected {
+			return Err(LutError::WrongCount {
+				expected,
+				found: table.len(),
+			}
+			.into());
+		}
+
+		Ok(Self {
+			title,
+			size,
+			domain_min,
+			domain_max,
+			table,
+		})
+	}
+
+	/// Look one pixel up. Anything outside the domain is clamped to its edge.
+	pub fn apply_pixel(&self, rgb: [f32; 3], interpolation: Interpolation) -> [f32; 3] {
+		let last = (self.size - 1) as f32;
+
+		// Where in the cube we are: which cell, and how far along it
+		let mut cell = [0; 3];
+		let mut frac = [0.0; 3];
+		for c in 0..3 {
+			let range = (self.domain_max[c] - self.domain_min[c]).max(f32::EPSILON);
+			// max before min so NaN ends up at 0
+			let at = ((rgb[c] - self.domain_min[c]) / range * last)
+				.max(0.0)
+				.min(last);
+			cell[c] = (at as usize).min(self.size - 2);
+			frac[c] = at - cell[c] as f32;
+		}
+
+		let corner = |r: usize, g: usize, b: usize| {
+			let n = self.size;
+			self.table[(cell[0] + r) + (cell[1] + g) * n + (cell[2] + b) * n * n]
+		};
+		let [fr, fg, fb] = frac;
+
+		match interpolation {
+			Interpolation::Trilinear => {
+				let lerp =
+					|a: [f32; 3], b: [f32; 3], t: f32| [0, 1, 2].map(|c| a[c] + (b[c] - a[c]) * t);
+
+				let g0 = lerp(
+					lerp(corner(0, 0, 0), corner(1, 0, 0), fr),
+					lerp(corner(0, 1, 0), corner(1, 1, 0), fr),
+					fg,
+				);
+				let g1 = lerp(
+					lerp(corner(0, 0, 1), corner(1, 0, 1), fr),
+					lerp(corner(0, 1, 1), corner(1, 1, 1), fr),
+					fg,
+				);
+				lerp(g0, g1, fb)
+			}
+			Interpolation::Tetrahedral => {
+				// The cell splits into six tetrahedra along the grey
+				// diagonal, and which one we're in depends on which of the
+				// fractions is biggest. Each walks from the black corner to
+				// the white one, one channel at a time.
+				let (first, second, steps) = if fr > fg {
+					if fg > fb {
+						(corner(1, 0, 0), corner(1, 1, 0), [fr, fg, fb])
+					} else if fr > fb {
+						(corner(1, 0, 0), corner(1, 0, 1), [fr, fb, fg])
+					} else {
+						(corner(0, 0, 1), corner(1, 0, 1), [fb, fr, fg])
+					}
+				} else if fb > fg {
+					(corner(0, 0, 1), corner(0, 1, 1), [fb, fg, fr])
+				} else if fb > fr {
+					(corner(0, 1, 0), corner(0, 1, 1), [fg, fb, fr])
+				} else {
+					(corner(0, 1, 0), corner(1, 1, 0), [fg, fr, fb])
+				};
+
+				let (black, white) = (corner(0, 0, 0), corner(1, 1, 1));
+				[0, 1, 2].map(|c| {
+					black[c]
+						+ steps[0] * (first[c] - black[c])
+						+ steps[1] * (second[c] - first[c])
+						+ steps[2] * (white[c] - second[c])
+				})
+			}
+		}
+	}
+}
+
+impl<C: Rgb> Image<f32, C> {
+	/// Put the image through a 3D LUT. A LUT's made for one encoding, sRGB
+	/// for most looks, or a camera's log for the ones that match a film
+	/// stock or a cinema camera, so the image has to be in that first.
+	/// That's usually [Srgb](crate::colorspace::Srgb), or
+	/// [LinRgb](crate::colorspace::LinRgb) for a LUT built on linear data.
+	pub fn apply_lut(&mut self, lut: &Lut3d, interpolation: Interpolation) {
+		self.par_map_pixels(|rgb: [f32; 3]| lut.apply_pixel(rgb, interpolation));
+	}
+}
+
+/// Exactly `N` numbers, split by whitespace
+fn numbers<const N: usize>(text: &str) -> Option<[f32; N]> {
+	let values: Vec<f32> = text
+		.split_whitespace()
+		.map(|v| v.parse::<f32>().ok())
+		.collect::<Option<_>>()?;
+
+	values.try_into().ok()
+}
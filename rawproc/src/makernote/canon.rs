@@ -0,0 +1,275 @@
+use crate::tiff::{Endian, Tiff};
+
+use super::{
+	AfPoint, FineTune, FlashState, LensInfo, Makernote, NormalizedRect, PictureStyle,
+	PictureStyleKind, PresetKind, Vendor, VendorWhitebalance, WhitebalancePreset,
+};
+
+const CAMERA_SETTINGS: u16 = 0x0001;
+const SHOT_INFO: u16 = 0x0004;
+const AF_INFO2: u16 = 0x0026;
+const LENS_MODEL: u16 = 0x0095;
+const PROCESSING: u16 = 0x00A0;
+const COLOR_DATA: u16 = 0x4001;
+
+const KNOWN: &[u16] = &[
+	CAMERA_SETTINGS,
+	SHOT_INFO,
+	AF_INFO2,
+	LENS_MODEL,
+	PROCESSING,
+	COLOR_DATA,
+];
+
+// Canon's makernote is a bare IFD using offsets from the start of the file
+pub(super) fn parse(data: &[u8], endian: Endian, offset: usize) -> Option<Makernote> {
+	let tiff = Tiff::with_endian(data, endian);
+	let ifd = tiff.ifd(offset)?;
+
+	// A lot of Canon's tags are arrays of u16 where the first value is the
+	// length of the array in bytes, so the indices here match exiftool's.
+	let settings = ifd.get(CAMERA_SETTINGS).and_then(|e| tiff.u16s(e));
+	let shot_info = ifd.get(SHOT_INFO).and_then(|e| tiff.u16s(e));
+	let processing = ifd.get(PROCESSING).and_then(|e| tiff.u16s(e));
+
+	let presets = ifd
+		.get(COLOR_DATA)
+		.and_then(|e| tiff.u16s(e))
+		.map(|colordata| presets(&colordata))
+		.unwrap_or_default();
+
+	let fine_tune = processing
+		.as_ref()
+		.filter(|p| p.len() > 13)
+		.map(|p| FineTune {
+			amber_blue: p[12] as i16,
+			green_magenta: p[13] as i16,
+		});
+	let selected = processing
+		.as_ref()
+		.and_then(|p| p.get(8))
+		.and_then(|wb| preset_kind(*wb));
+
+	let name = ifd.get(LENS_MODEL).and_then(|e| tiff.string(e));
+	let lens = settings.as_ref().filter(|s| s.len() > 25).map(|s| {
+		// Focal lengths are in "focal units" per mm, which is almost always 1
+		let units = if s[25] == 0 { 1.0 } else { s[25] as f32 };
+		LensInfo {
+			id: Some(s[22]),
+			name: name.clone().filter(|n| !n.is_empty()),
+			focal_range: Some((s[24] as f32 / units, s[23] as f32 / units)),
+			aperture_range: None,
+		}
+	});
+
+	let flash = settings.as_ref().and_then(|s| s.get(28)).map(|f| match f {
+		0 => FlashState::NotFired,
+		_ => FlashState::Fired,
+	});
+
+	// SubjectDistance is in centimetres, and 0 or 65535 mean "no idea"
+	let focus_distance = shot_info
+		.as_ref()
+		.and_then(|s| s.get(19))
+		.filter(|d| **d != 0 && **d != u16::MAX)
+		.map(|d| *d as f32 / 100.0);
+
+	Some(Makernote {
+		vendor: Vendor::Canon,
+		lens,
+		focus_distance,
+		flash,
+		whitebalance: VendorWhitebalance {
+			presets,
+			selected,
+			fine_tune,
+		},
+		af_points: ifd
+			.get(AF_INFO2)
+			.and_then(|e| tiff.u16s(e))
+			.map(|af| af_points(&af))
+			.unwrap_or_default(),
+		picture_style: picture_style(processing.as_deref(), settings.as_deref()),
+		unknown: super::unknown_tags(&tiff, &ifd, KNOWN),
+	})
+}
+
+// AFInfo2 is a header of 8 values followed by per-point widths, heights, and
+// x/y positions, then the in focus and selected bitfields. Positions are
+// signed and relative to the center of the AF image, with y going up.
+fn af_points(af: &[u16]) -> Vec<AfPoint> {
+	let count = match af.get(2) {
+		Some(count) => *count as usize,
+		None => return vec![],
+	};
+	let mask_len = count.div_ceil(16);
+
+	if af.len() < 8 + count * 4 + mask_len * 2 {
+		return vec![];
+	}
+
+	let (image_width, image_height) = (af[6] as f32, af[7] as f32);
+	if image_width == 0.0 || image_height == 0.0 {
+		return vec![];
+	}
+
+	let widths = &af[8..8 + count];
+	let heights = &af[8 + count..8 + count * 2];
+	let xs = &af[8 + count * 2..8 + count * 3];
+	let ys = &af[8 + count * 3..8 + count * 4];
+	let in_focus = &af[8 + count * 4..8 + count * 4 + mask_len];
+	let selected = &af[8 + count * 4 + mask_len..8 + count * 4 + mask_len * 2];
+
+	let bit = |mask: &[u16], idx: usize| mask[idx / 16] & (1 << (idx % 16)) != 0;
+
+	(0..count)
+		.map(|idx| {
+			let cx = image_width / 2.0 + xs[idx] as i16 as f32;
+			let cy = image_height / 2.0 - ys[idx] as i16 as f32;
+
+			AfPoint {
+				area: NormalizedRect::from_center(
+					cx,
+					cy,
+					widths[idx] as f32,
+					heights[idx] as f32,
+					image_width,
+					image_height,
+				),
+				selected: bit(selected, idx),
+				in_focus: bit(in_focus, idx),
+			}
+		})
+		.collect()
+}
+
+// The style is in Processing, but the contrast, saturation, and sharpness
+// that go with it are in CameraSettings. Those are signed, and 0x7FFF means
+// the style sets it.
+fn picture_style(processing: Option<&[u16]>, settings: Option<&[u16]>) -> Option<PictureStyle> {
+	let (kind, name) = match *processing?.get(10)? {
+		0x01 | 0x81 => (PictureStyleKind::Standard, "Standard"),
+		0x02 | 0x82 => (PictureStyleKind::Portrait, "Portrait"),
+		0x03 => (PictureStyleKind::Vivid, "High Saturation"),
+		0x05 => (PictureStyleKind::Flat, "Low Saturation"),
+		0x83 => (PictureStyleKind::Landscape, "Landscape"),
+		0x84 => (PictureStyleKind::Neutral, "Neutral"),
+		0x85 => (PictureStyleKind::Faithful, "Faithful"),
+		0x86 => (PictureStyleKind::Monochrome, "Monochrome"),
+		0x87 => (PictureStyleKind::Auto, "Auto"),
+		0x21..=0x23 => (PictureStyleKind::Other, "User Defined"),
+		0x00 | 0xFF => return None,
+		_ => (PictureStyleKind::Other, "Unknown"),
+	};
+
+	let adjust = |idx: usize| -> Option<i8> {
+		settings?
+			.get(idx)
+			.map(|v| *v as i16)
+			.filter(|v| *v != 0x7FFF)
+			.map(|v| v as i8)
+	};
+
+	Some(PictureStyle {
+		kind,
+		name: Some(name.to_owned()),
+		contrast: adjust(13),
+		saturation: adjust(14),
+		sharpness: adjust(15),
+		brightness: None,
+	})
+}
+
+fn preset_kind(wb: u16) -> Option<PresetKind> {
+	match wb {
+		0 => Some(PresetKind::Auto),
+		1 => Some(PresetKind::Daylight),
+		2 => Some(PresetKind::Cloudy),
+		3 => Some(PresetKind::Tungsten),
+		4 => Some(PresetKind::Fluorescent),
+		5 => Some(PresetKind::Flash),
+		6 => Some(PresetKind::Custom),
+		8 => Some(PresetKind::Shade),
+		9 => Some(PresetKind::Kelvin),
+		_ => None,
+	}
+}
+
+// The ColorData block changes layout between camera generations and the
+// only way to tell which one we have is its length. Each preset is stored as
+// RGGB levels followed by a colour temperature, five u16 in all, one after the
+// other. None marks slots we skip, like the as-shot values we already have.
+fn presets(colordata: &[u16]) -> Vec<WhitebalancePreset> {
+	use PresetKind::*;
+
+	let (start, layout): (usize, &[Option<PresetKind>]) = match colordata.len() {
+		// ColorData1: 20D, 350D
+		582 => (
+			0x19,
+			&[
+				None,
+				Some(Auto),
+				Some(Daylight),
+				Some(Shade),
+				Some(Cloudy),
+				Some(Tungsten),
+				Some(Fluorescent),
+				Some(Flash),
+			],
+		),
+		// ColorData3: 1DmkIIN, 5D, 30D, 400D
+		796 => (
+			0x3F,
+			&[
+				None,
+				Some(Auto),
+				Some(Measured),
+				Some(Daylight),
+				Some(Shade),
+				Some(Cloudy),
+				Some(Tungsten),
+				Some(Fluorescent),
+				Some(Kelvin),
+				Some(Flash),
+			],
+		),
+		// ColorData4: 1DmkIII, 40D, 450D, 1000D, 50D, 5DmkII and friends
+		674 | 692 | 702 | 1227 | 1250 | 1251 | 1337 | 1338 | 1346 => (
+			0x3F,
+			&[
+				None,
+				Some(Auto),
+				Some(Measured),
+				None,
+				Some(Daylight),
+				Some(Shade),
+				Some(Cloudy),
+				Some(Tungsten),
+				Some(Fluorescent),
+				Some(Kelvin),
+				Some(Flash),
+			],
+		),
+		_ => return vec![],
+	};
+
+	layout
+		.iter()
+		.enumerate()
+		.filter_map(|(idx, kind)| {
+			let at = start + idx * 5;
+			let entry = colordata.get(at..at + 5)?;
+			let green = (entry[1] as f32 + entry[2] as f32) / 2.0;
+
+			if green == 0.0 {
+				return None;
+			}
+
+			Some(WhitebalancePreset {
+				kind: (*kind)?,
+				coefficients: [entry[0] as f32 / green, 1.0, entry[3] as f32 / green],
+				temperature: Some(entry[4]).filter(|t| *t != 0),
+			})
+		})
+		.collect()
+}
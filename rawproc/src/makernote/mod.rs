@@ -0,0 +1,315 @@
+//! Vendor specific bits of a raw file. rawloader gives us the as-shot
+//! whitebalance and that's it, but the cameras write down a lot more than
+//! that in their makernotes.
+//!
+//! Tag numbers and layouts are from exiftool's Nikon and Canon tag tables,
+//! which are the closest thing to documentation that exists.
+
+mod canon;
+mod nikon;
+
+use crate::tiff::{self, Ifd, Tiff};
+
+/// Everything we got out of the makernote.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Makernote {
+	pub vendor: Vendor,
+	pub lens: Option<LensInfo>,
+	/// Distance to the subject in metres, if the camera knew it
+	pub focus_distance: Option<f32>,
+	pub flash: Option<FlashState>,
+	pub whitebalance: VendorWhitebalance,
+	/// Where the camera could focus, and where it did
+	pub af_points: Vec<AfPoint>,
+	/// Nikon's Picture Control or Canon's Picture Style
+	pub picture_style: Option<PictureStyle>,
+	/// Every tag we didn't make sense of, so you can go digging yourself
+	pub unknown: Vec<RawTag>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Vendor {
+	Nikon,
+	Canon,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LensInfo {
+	/// The vendor's lens ID. What it means depends on the vendor.
+	pub id: Option<u16>,
+	pub name: Option<String>,
+	/// Shortest and longest focal length in millimetres
+	pub focal_range: Option<(f32, f32)>,
+	/// Widest and narrowest aperture at the shortest focal length, as f-numbers
+	pub aperture_range: Option<(f32, f32)>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FlashState {
+	Fired,
+	NotFired,
+}
+
+/// The look the camera gives its own JPEGs. The adjustments are the steps
+/// you'd see in the camera menu, 0 being no change from the style's default.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PictureStyle {
+	pub kind: PictureStyleKind,
+	/// What the camera calls it, which is useful for custom styles
+	pub name: Option<String>,
+	pub contrast: Option<i8>,
+	pub saturation: Option<i8>,
+	pub sharpness: Option<i8>,
+	pub brightness: Option<i8>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PictureStyleKind {
+	Standard,
+	Neutral,
+	Vivid,
+	Portrait,
+	Landscape,
+	Faithful,
+	Flat,
+	Monochrome,
+	Auto,
+	/// A user defined style or something we don't have a name for
+	Other,
+}
+
+impl PictureStyleKind {
+	pub fn from_name(name: &str) -> Self {
+		match name.trim().to_ascii_uppercase().as_str() {
+			"STANDARD" => Self::Standard,
+			"NEUTRAL" => Self::Neutral,
+			"VIVID" | "HIGH SATURATION" => Self::Vivid,
+			"PORTRAIT" => Self::Portrait,
+			"LANDSCAPE" => Self::Landscape,
+			"FAITHFUL" => Self::Faithful,
+			"FLAT" => Self::Flat,
+			"MONOCHROME" => Self::Monochrome,
+			"AUTO" => Self::Auto,
+			_ => Self::Other,
+		}
+	}
+}
+
+impl PictureStyle {
+	/// A rough guess at the contrast and saturation multipliers that get you
+	/// close to the camera's rendering of this style. These are eyeballed,
+	/// not measured, so they won't match exactly.
+	pub fn baseline(&self) -> (f32, f32) {
+		let (contrast, saturation) = match self.kind {
+			PictureStyleKind::Standard | PictureStyleKind::Auto => (1.1, 1.1),
+			PictureStyleKind::Neutral => (1.0, 0.95),
+			PictureStyleKind::Vivid => (1.2, 1.3),
+			PictureStyleKind::Portrait => (1.05, 1.0),
+			PictureStyleKind::Landscape => (1.15, 1.2),
+			PictureStyleKind::Faithful | PictureStyleKind::Other => (1.0, 1.0),
+			PictureStyleKind::Flat => (0.85, 0.9),
+			PictureStyleKind::Monochrome => (1.1, 0.0),
+		};
+
+		// Every step in the camera menu nudges it a bit further
+		let step = |v: Option<i8>| 1.0 + v.unwrap_or(0) as f32 * 0.05;
+		let saturation = if self.kind == PictureStyleKind::Monochrome {
+			0.0
+		} else {
+			saturation * step(self.saturation)
+		};
+
+		(contrast * step(self.contrast), saturation)
+	}
+}
+
+/// An autofocus point or area.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AfPoint {
+	pub area: NormalizedRect,
+	/// The point was picked, by you or the camera, to focus with
+	pub selected: bool,
+	/// The camera thinks this point achieved focus
+	pub in_focus: bool,
+}
+
+/// A rectangle where the image is 1.0 by 1.0 with the origin in the top left.
+/// Multiply by the width and height of the image, however it's been scaled,
+/// to get pixels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NormalizedRect {
+	pub x: f32,
+	pub y: f32,
+	pub width: f32,
+	pub height: f32,
+}
+
+impl NormalizedRect {
+	/// Build one from a rect centered at `cx, cy`, in pixels of an image `iw` by `ih`
+	pub(crate) fn from_center(cx: f32, cy: f32, w: f32, h: f32, iw: f32, ih: f32) -> Self {
+		Self {
+			x: (cx - w / 2.0) / iw,
+			y: (cy - h / 2.0) / ih,
+			width: w / iw,
+			height: h / ih,
+		}
+	}
+
+	/// Pixel bounds as `(left, top, right, bottom)` for an image of this size,
+	/// clamped to the image. Right and bottom are exclusive.
+	pub fn to_pixels(&self, width: usize, height: usize) -> (usize, usize, usize, usize) {
+		let px = |v: f32, max: usize| ((v * max as f32).round().max(0.0) as usize).min(max);
+
+		(
+			px(self.x, width),
+			px(self.y, height),
+			px(self.x + self.width, width),
+			px(self.y + self.height, height),
+		)
+	}
+}
+
+/// A makernote tag as it was in the file. `data` is in the byte order of the
+/// makernote, which is in `big_endian`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RawTag {
+	pub tag: u16,
+	/// The TIFF field type
+	pub kind: u16,
+	pub count: u32,
+	pub big_endian: bool,
+	pub data: Vec<u8>,
+}
+
+/// One of the whitebalance presets the camera body offers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WhitebalancePreset {
+	pub kind: PresetKind,
+	/// Red, green, blue. Normalized so green is 1.0
+	pub coefficients: [f32; 3],
+	/// Colour temperature in kelvin, if the camera wrote it down
+	pub temperature: Option<u16>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PresetKind {
+	Auto,
+	Daylight,
+	Shade,
+	Cloudy,
+	Tungsten,
+	Fluorescent,
+	Flash,
+	Kelvin,
+	Measured,
+	Custom,
+}
+
+impl PresetKind {
+	/// Make sense of the names cameras put in their whitebalance strings
+	pub fn from_name(name: &str) -> Option<Self> {
+		let name = name.trim().to_ascii_uppercase();
+		let kind = match name.as_str() {
+			"AUTO" | "AUTO1" | "AUTO2" => PresetKind::Auto,
+			"SUNNY" | "DAYLIGHT" | "DIRECT SUNLIGHT" | "FINE" => PresetKind::Daylight,
+			"SHADE" => PresetKind::Shade,
+			"CLOUDY" | "OVERCAST" => PresetKind::Cloudy,
+			"INCANDESCENT" | "TUNGSTEN" => PresetKind::Tungsten,
+			"FLUORESCENT" => PresetKind::Fluorescent,
+			"FLASH" | "SPEEDLIGHT" => PresetKind::Flash,
+			"KELVIN" | "COLOR TEMP" | "COLOR TEMP." => PresetKind::Kelvin,
+			"PRESET" | "PRESET0" | "PRESET1" | "CUSTOM" => PresetKind::Custom,
+			_ => return None,
+		};
+
+		Some(kind)
+	}
+}
+
+/// The whitebalance shift you set in the camera. Positive amber_blue is
+/// toward blue, positive green_magenta is toward magenta.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FineTune {
+	pub amber_blue: i16,
+	pub green_magenta: i16,
+}
+
+// How far one step of the fine tune grid moves a channel. Cameras don't
+// agree on it, but a step is around five mireds, and that's about this.
+const FINE_TUNE_STEP: f32 = 0.02;
+
+impl FineTune {
+	/// What to multiply the red, green, and blue whitebalance coefficients by
+	/// to shift them like the camera would.
+	pub fn multipliers(&self) -> [f32; 3] {
+		let blue = self.amber_blue as f32 * FINE_TUNE_STEP;
+		let magenta = self.green_magenta as f32 * FINE_TUNE_STEP;
+
+		// Toward magenta is away from green. Red and blue go up half as much
+		// each so the brightness stays put.
+		[
+			(magenta / 2.0 - blue).exp(),
+			(-magenta).exp(),
+			(magenta / 2.0 + blue).exp(),
+		]
+	}
+}
+
+/// Whitebalance information we could dig out of the makernotes.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VendorWhitebalance {
+	pub presets: Vec<WhitebalancePreset>,
+	/// The preset the camera was set to, if it says
+	pub selected: Option<PresetKind>,
+	pub fine_tune: Option<FineTune>,
+}
+
+/// Find the makernote in a TIFF based raw and parse it. Returns None for
+/// anything we don't know how to read.
+pub(crate) fn parse(data: &[u8]) -> Option<Makernote> {
+	let tiff = Tiff::new(data)?;
+	let ifd0 = tiff.first_ifd()?;
+	let make = tiff.string(ifd0.get(tiff::TAG_MAKE)?)?;
+
+	let exif = tiff.sub_ifd(&ifd0, tiff::TAG_EXIF_IFD)?;
+	let makernote = exif.get(tiff::TAG_MAKERNOTE)?;
+	let offset = tiff.value_offset(makernote)?;
+
+	if make.to_ascii_uppercase().starts_with("NIKON") {
+		nikon::parse(data.get(offset..)?)
+	} else if make.starts_with("Canon") {
+		canon::parse(data, tiff.endian(), offset)
+	} else {
+		None
+	}
+}
+
+/// Copy out every tag in the IFD that isn't in `known`.
+fn unknown_tags(tiff: &Tiff, ifd: &Ifd, known: &[u16]) -> Vec<RawTag> {
+	ifd.entries
+		.iter()
+		.filter(|e| !known.contains(&e.tag))
+		.filter_map(|e| {
+			Some(RawTag {
+				tag: e.tag,
+				kind: e.kind,
+				count: e.count,
+				big_endian: tiff.endian() == tiff::Endian::Big,
+				data: tiff.bytes(e)?.to_vec(),
+			})
+		})
+		.collect()
+}
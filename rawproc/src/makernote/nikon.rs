@@ -0,0 +1,196 @@
+use crate::tiff::{Ifd, Tiff};
+
+use super::{
+	AfPoint, FineTune, FlashState, LensInfo, Makernote, NormalizedRect, PictureStyle,
+	PictureStyleKind, PresetKind, Vendor, VendorWhitebalance, WhitebalancePreset,
+};
+
+const WHITEBALANCE: u16 = 0x0005;
+const WB_FINE_TUNE: u16 = 0x000B;
+const WB_RB_LEVELS: u16 = 0x000C;
+const PICTURE_CONTROL: u16 = 0x0023;
+const LENS_TYPE: u16 = 0x0083;
+const LENS: u16 = 0x0084;
+const FLASH_MODE: u16 = 0x0087;
+const LENS_DATA: u16 = 0x0098;
+const AF_INFO2: u16 = 0x00B7;
+
+const KNOWN: &[u16] = &[
+	WHITEBALANCE,
+	WB_FINE_TUNE,
+	WB_RB_LEVELS,
+	PICTURE_CONTROL,
+	LENS_TYPE,
+	LENS,
+	FLASH_MODE,
+	LENS_DATA,
+	AF_INFO2,
+];
+
+// Nikon's type 3 makernote is "Nikon\0", two bytes of version, two bytes of
+// padding, and then an entire TIFF with its own header and offsets.
+pub(super) fn parse(makernote: &[u8]) -> Option<Makernote> {
+	if !makernote.starts_with(b"Nikon\0") {
+		return None;
+	}
+
+	let tiff = Tiff::new(makernote.get(10..)?)?;
+	let ifd = tiff.first_ifd()?;
+
+	let selected = ifd
+		.get(WHITEBALANCE)
+		.and_then(|e| tiff.string(e))
+		.and_then(|name| PresetKind::from_name(&name));
+
+	let fine_tune = ifd
+		.get(WB_FINE_TUNE)
+		.and_then(|e| tiff.u16s(e))
+		.filter(|v| v.len() >= 2)
+		.map(|v| FineTune {
+			amber_blue: v[0] as i16,
+			green_magenta: v[1] as i16,
+		});
+
+	// Nikon only keeps the red and blue levels for the preset in use, the
+	// rest of the table lives in encrypted blocks we don't touch.
+	let mut presets = vec![];
+	if let (Some(kind), Some(levels)) = (
+		selected,
+		ifd.get(WB_RB_LEVELS).and_then(|e| tiff.rationals(e)),
+	) {
+		if levels.len() >= 2 {
+			presets.push(WhitebalancePreset {
+				kind,
+				coefficients: [levels[0], 1.0, levels[1]],
+				temperature: None,
+			});
+		}
+	}
+
+	let lens_range = ifd
+		.get(LENS)
+		.and_then(|e| tiff.rationals(e))
+		.filter(|v| v.len() >= 4);
+	let lens_id = ifd
+		.get(LENS_TYPE)
+		.and_then(|e| tiff.u16s(e))
+		.and_then(|v| v.first().copied());
+	let lens = if lens_range.is_some() || lens_id.is_some() {
+		Some(LensInfo {
+			id: lens_id,
+			name: None,
+			focal_range: lens_range.as_ref().map(|v| (v[0], v[1])),
+			aperture_range: lens_range.as_ref().map(|v| (v[2], v[3])),
+		})
+	} else {
+		None
+	};
+
+	// 0 is "did not fire" and everything else is some flavour of fired
+	let flash = ifd
+		.get(FLASH_MODE)
+		.and_then(|e| tiff.u16s(e))
+		.and_then(|v| v.first().copied())
+		.map(|mode| match mode {
+			0 => FlashState::NotFired,
+			_ => FlashState::Fired,
+		});
+
+	Some(Makernote {
+		vendor: Vendor::Nikon,
+		lens,
+		focus_distance: focus_distance(&tiff, &ifd),
+		flash,
+		whitebalance: VendorWhitebalance {
+			presets,
+			selected,
+			fine_tune,
+		},
+		af_points: af_points(&tiff, &ifd).unwrap_or_default(),
+		picture_style: picture_control(&tiff, &ifd),
+		unknown: super::unknown_tags(&tiff, &ifd, KNOWN),
+	})
+}
+
+// LensData 0100 doesn't have the focus distance at all, and 0201 and later
+// are encrypted, so 0101 is the only version we can read it from. It's at
+// 0x09, on an odd log scale that exiftool converts with 0.01 * 10^(n/40)
+// metres.
+fn focus_distance(tiff: &Tiff, ifd: &Ifd) -> Option<f32> {
+	let data = tiff.bytes(ifd.get(LENS_DATA)?)?;
+	if data.get(0..4)? != b"0101" {
+		return None;
+	}
+
+	let raw = *data.get(0x09)?;
+	Some(0.01 * 10f32.powf(raw as f32 / 40.0))
+}
+
+// Where the phase detect points are depends on which AF module the body has
+// and we don't have tables for those yet. Contrast detect, in live view,
+// tells us the area directly so that's what we do. It's all big endian and
+// the area is given as a center with a size.
+fn af_points(tiff: &Tiff, ifd: &Ifd) -> Option<Vec<AfPoint>> {
+	let data = tiff.bytes(ifd.get(AF_INFO2)?)?;
+	if data.get(0..4)? != b"0100" || *data.get(4)? == 0 {
+		return Some(vec![]);
+	}
+
+	let be = |at: usize| -> Option<f32> {
+		let b = data.get(at..at + 2)?;
+		Some(u16::from_be_bytes([b[0], b[1]]) as f32)
+	};
+
+	let (image_width, image_height) = (be(0x10)?, be(0x12)?);
+	if image_width == 0.0 || image_height == 0.0 {
+		return Some(vec![]);
+	}
+
+	let area = NormalizedRect::from_center(
+		be(0x14)?,
+		be(0x16)?,
+		be(0x18)?,
+		be(0x1A)?,
+		image_width,
+		image_height,
+	);
+
+	Some(vec![AfPoint {
+		area,
+		selected: true,
+		in_focus: data.get(0x1C).map(|b| *b != 0).unwrap_or(false),
+	}])
+}
+
+// PictureControlData is a 4 byte version, the name and the name of the style
+// it's based on as 20 byte strings, and then the adjustments. Adjustments are
+// stored with 0x80 as zero, and 0xFF means it's set to "auto" or n/a.
+fn picture_control(tiff: &Tiff, ifd: &Ifd) -> Option<PictureStyle> {
+	let data = tiff.bytes(ifd.get(PICTURE_CONTROL)?)?;
+	if !data.starts_with(b"01") && !data.starts_with(b"02") {
+		return None;
+	}
+
+	let string = |range: std::ops::Range<usize>| -> Option<String> {
+		let bytes = data.get(range)?;
+		let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+		Some(String::from_utf8_lossy(&bytes[..end]).trim().to_owned())
+	};
+	let adjust = |at: usize| -> Option<i8> {
+		data.get(at)
+			.filter(|v| **v != 0xFF)
+			.map(|v| (*v as i16 - 0x80) as i8)
+	};
+
+	let name = string(4..24)?;
+	let base = string(24..44).unwrap_or_default();
+
+	Some(PictureStyle {
+		kind: PictureStyleKind::from_name(&base),
+		name: Some(name).filter(|n| !n.is_empty()),
+		sharpness: adjust(0x32),
+		contrast: adjust(0x33),
+		brightness: adjust(0x34),
+		saturation: adjust(0x35),
+	})
+}
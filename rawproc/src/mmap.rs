@@ -0,0 +1,113 @@
+//! Read only memory mapped files, so decoding from disk doesn't need a copy
+//! of the whole file on the heap. The kernel pages the file in as it's read
+//! and, because the pages are backed by the file, can drop them again when
+//! memory gets tight instead of swapping or killing us.
+//!
+//! Where we don't have mmap the file is read into a Vec, which works the
+//! same just without the savings.
+
+use std::{fs::File, io, ops::Deref};
+
+#[cfg(unix)]
+pub(crate) struct Mmap {
+	ptr: *mut libc::c_void,
+	len: usize,
+}
+
+// It's read only and never moves, so sharing it is as safe as sharing a &[u8]
+#[cfg(unix)]
+unsafe impl Send for Mmap {}
+#[cfg(unix)]
+unsafe impl Sync for Mmap {}
+
+#[cfg(unix)]
+impl Mmap {
+	/// Map all of `file`.
+	///
+	/// # Safety
+	/// If the file is truncated while it's mapped, reading the part that's
+	/// gone is a SIGBUS, and if it's written to, the bytes we hand out as a
+	/// `&[u8]` change underneath it. Nothing may change the file while the
+	/// map is alive.
+	pub unsafe fn open(file: &File) -> io::Result<Self> {
+		use std::os::unix::io::AsRawFd;
+
+		let len = usize::try_from(file.metadata()?.len())
+			.map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too big to map"))?;
+		// mmap won't do zero bytes
+		if len == 0 {
+			return Ok(Self {
+				ptr: std::ptr::null_mut(),
+				len,
+			});
+		}
+
+		// SAFETY: we ask for a fresh, private, read only mapping and check
+		// that we got one
+		let ptr = unsafe {
+			libc::mmap(
+				std::ptr::null_mut(),
+				len,
+				libc::PROT_READ,
+				libc::MAP_PRIVATE,
+				file.as_raw_fd(),
+				0,
+			)
+		};
+		if ptr == libc::MAP_FAILED {
+			return Err(io::Error::last_os_error());
+		}
+
+		Ok(Self { ptr, len })
+	}
+}
+
+#[cfg(unix)]
+impl Deref for Mmap {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		if self.len == 0 {
+			return &[];
+		}
+
+		// SAFETY: the mapping is len bytes and lives until we're dropped
+		unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+	}
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+	fn drop(&mut self) {
+		if self.len > 0 {
+			// SAFETY: this is the mapping we made, and nothing borrows it now
+			unsafe {
+				libc::munmap(self.ptr, self.len);
+			}
+		}
+	}
+}
+
+#[cfg(not(unix))]
+pub(crate) struct Mmap(Vec<u8>);
+
+#[cfg(not(unix))]
+impl Mmap {
+	/// Read all of `file`. It's unsafe to match the real one.
+	pub unsafe fn open(mut file: &File) -> io::Result<Self> {
+		use std::io::Read;
+
+		let mut bytes = vec![];
+		file.read_to_end(&mut bytes)?;
+		Ok(Self(bytes))
+	}
+}
+
+#[cfg(not(unix))]
+impl Deref for Mmap {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		&self.0
+	}
+}
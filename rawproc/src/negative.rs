@@ -0,0 +1,129 @@
+//! Colour negatives, scanned with a camera. A negative is dark where the
+//! scene was bright, and all of it sits behind the orange of the film base,
+//! the mask, which is there to correct for the dyes and is different for
+//! every stock. To get a positive out we divide the mask back out, take the
+//! density of what's left, which goes up with the log of the light the film
+//! saw, and then spread each channel out on its own, like an enlarger's
+//! colour head would. The dyes don't build up density at the same rate, so
+//! leaving that last part out gets you greys that change colour as they get
+//! brighter.
+//!
+//! This all happens on linear camera RGB, straight out of the debayer and
+//! before whitebalancing, which the mask takes the place of.
+
+use crate::{
+	colorspace::LinRgb,
+	image::{Image, Region},
+	par::*,
+};
+
+// How much of the negative can be brighter than the base and still count
+// as it, for the dust and scratches that let the backlight through
+const BASE_PERCENT: f32 = 0.1;
+
+// How much of the positive clips when it's spread out to white
+const CLIP_PERCENT: f32 = 0.1;
+
+// How many stops the positive spans from black to white, like the range of
+// a print. More is flatter.
+const PRINT_STOPS: f32 = 8.0;
+
+/// Where to get the colour of the film base from
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FilmBase {
+	/// The brightest part of the negative. That's the base if some of the
+	/// border between frames is in the scan, so leave a bit of it in and
+	/// crop after. If there isn't, it's the deepest shadows, which are only
+	/// a little denser and work nearly as well.
+	Auto,
+	/// The average of a patch of bare film, like the border or the gap
+	/// between frames
+	Region(Region),
+	/// One you already know, like one measured on an earlier frame of the
+	/// same roll
+	Rgb([f32; 3]),
+}
+
+impl Image<f32, LinRgb> {
+	/// The colour of the unexposed film, the orange mask, as it was scanned.
+	///
+	/// # Panics
+	/// If it's a [FilmBase::Region] that doesn't fit in the image.
+	pub fn film_base(&self, base: FilmBase) -> [f32; 3] {
+		match base {
+			FilmBase::Auto => self.brightest(BASE_PERCENT).unwrap_or([1.0; 3]),
+			FilmBase::Region(region) => {
+				assert!(
+					region.x + region.width <= self.width
+						&& region.y + region.height <= self.height,
+					"the film base region doesn't fit in the image"
+				);
+
+				let mut sum = [0.0f64; 3];
+				for y in region.y..region.y + region.height {
+					let row = &self.data[(y * self.width + region.x) * 3..][..region.width * 3];
+					for rgb in row.chunks_exact(3) {
+						for c in 0..3 {
+							sum[c] += rgb[c] as f64;
+						}
+					}
+				}
+
+				let count = (region.width * region.height).max(1) as f64;
+				sum.map(|s| (s / count) as f32)
+			}
+			FilmBase::Rgb(rgb) => rgb,
+		}
+	}
+
+	/// Turn the negative into a positive, each channel on its own, as
+	/// densities over the base. The base goes to black and the brightest
+	/// things in the scene go to about 2.0, but that changes with the stock
+	/// and the channel, so [normalize_negative](Self::normalize_negative) it
+	/// after to get light back out.
+	///
+	/// Anything brighter than the base, like a hole or the backlight past
+	/// the edge of the film, is black.
+	pub fn invert_negative(&mut self, base: [f32; 3]) {
+		self.data.par_chunks_exact_mut(3).for_each(|rgb| {
+			for c in 0..3 {
+				let transmitted = rgb[c].max(f32::MIN_POSITIVE);
+				rgb[c] = (base[c] / transmitted).log10().max(0.0);
+			}
+		});
+	}
+
+	/// Scale each channel of an [inverted](Self::invert_negative) negative
+	/// so its densest `clip_percent` percent is at white, and turn the
+	/// densities back into linear light. Evening the channels out at the top
+	/// is what takes out the last of the cast. Returns the multipliers the
+	/// densities were given.
+	pub fn normalize_negative(&mut self, clip_percent: f32) -> [f32; 3] {
+		let gains = match self.brightest(clip_percent) {
+			Some(white) => white.map(|w| 1.0 / w),
+			None => [1.0; 3],
+		};
+
+		let range = PRINT_STOPS.exp2() - 1.0;
+		self.data.par_chunks_exact_mut(3).for_each(|rgb| {
+			for c in 0..3 {
+				rgb[c] = ((rgb[c] * gains[c] * PRINT_STOPS).exp2() - 1.0) / range;
+			}
+		});
+		gains
+	}
+
+	/// All of it: find the base, invert, and normalize, clipping 0.1%. The
+	/// base is returned so you can give it to the rest of the roll as a
+	/// [FilmBase::Rgb], which is steadier than finding it again every frame.
+	///
+	/// # Panics
+	/// If it's a [FilmBase::Region] that doesn't fit in the image.
+	pub fn develop_negative(&mut self, base: FilmBase) -> [f32; 3] {
+		let base = self.film_base(base);
+		self.invert_negative(base);
+		self.normalize_negative(CLIP_PERCENT);
+		base
+	}
+}
@@ -0,0 +1,155 @@
+//! What we run in parallel. With the `rayon` feature, which is on by
+//! default, this is rayon. Without it, it's the same methods on plain
+//! iterators that go through everything one at a time, in order, on the
+//! thread that called them. That's for places threads aren't any use, or
+//! aren't there at all, like wasm.
+//!
+//! Everywhere that would `use rayon::prelude::*` uses this instead.
+
+#[cfg(feature = "rayon")]
+pub(crate) use rayon::prelude::*;
+
+#[cfg(not(feature = "rayon"))]
+pub(crate) use sequential::*;
+
+#[cfg(not(feature = "rayon"))]
+mod sequential {
+	use std::iter::{Copied, Enumerate, Filter, FilterMap, FlatMap, Map, Once, Zip};
+
+	/// An iterator that stands in for one of rayon's. It is one, so
+	/// everything that ends a chain, like `for_each`, `sum` and `collect`,
+	/// is the iterator's own. The adaptors are here so the chain stays one
+	/// of these, and `fold` and `reduce` take an identity like rayon's do.
+	pub(crate) struct Seq<I>(I);
+
+	impl<I: Iterator> Iterator for Seq<I> {
+		type Item = I::Item;
+
+		fn next(&mut self) -> Option<I::Item> {
+			self.0.next()
+		}
+
+		fn size_hint(&self) -> (usize, Option<usize>) {
+			self.0.size_hint()
+		}
+	}
+
+	impl<I: Iterator> Seq<I> {
+		pub(crate) fn map<B, F: FnMut(I::Item) -> B>(self, f: F) -> Seq<Map<I, F>> {
+			Seq(self.0.map(f))
+		}
+
+		pub(crate) fn zip<J: IntoIterator>(self, other: J) -> Seq<Zip<I, J::IntoIter>> {
+			Seq(self.0.zip(other))
+		}
+
+		pub(crate) fn enumerate(self) -> Seq<Enumerate<I>> {
+			Seq(self.0.enumerate())
+		}
+
+		pub(crate) fn filter<P>(self, predicate: P) -> Seq<Filter<I, P>>
+		where
+			P: FnMut(&I::Item) -> bool,
+		{
+			Seq(self.0.filter(predicate))
+		}
+
+		pub(crate) fn filter_map<B, F>(self, f: F) -> Seq<FilterMap<I, F>>
+		where
+			F: FnMut(I::Item) -> Option<B>,
+		{
+			Seq(self.0.filter_map(f))
+		}
+
+		pub(crate) fn flat_map_iter<U, F>(self, f: F) -> Seq<FlatMap<I, U, F>>
+		where
+			U: IntoIterator,
+			F: FnMut(I::Item) -> U,
+		{
+			Seq(self.0.flat_map(f))
+		}
+
+		pub(crate) fn copied<'a, T>(self) -> Seq<Copied<I>>
+		where
+			T: 'a + Copy,
+			I: Iterator<Item = &'a T>,
+		{
+			Seq(self.0.copied())
+		}
+
+		/// rayon folds each of its pieces of the work separately and gives
+		/// you every one, so `identity` is where each starts. There's only
+		/// the one piece here.
+		pub(crate) fn fold<T, ID, F>(self, identity: ID, op: F) -> Seq<Once<T>>
+		where
+			ID: Fn() -> T,
+			F: FnMut(T, I::Item) -> T,
+		{
+			Seq(std::iter::once(self.0.fold(identity(), op)))
+		}
+
+		pub(crate) fn reduce<ID, F>(self, identity: ID, op: F) -> I::Item
+		where
+			ID: Fn() -> I::Item,
+			F: FnMut(I::Item, I::Item) -> I::Item,
+		{
+			self.0.fold(identity(), op)
+		}
+	}
+
+	/// `into_par_iter` for anything that can be iterated over
+	pub(crate) trait IntoSeq: IntoIterator + Sized {
+		fn into_par_iter(self) -> Seq<Self::IntoIter> {
+			Seq(self.into_iter())
+		}
+	}
+
+	impl<I: IntoIterator> IntoSeq for I {}
+
+	/// The slice methods rayon adds
+	pub(crate) trait SliceSeq<T> {
+		fn par_iter(&self) -> Seq<std::slice::Iter<'_, T>>;
+		fn par_iter_mut(&mut self) -> Seq<std::slice::IterMut<'_, T>>;
+		fn par_chunks(&self, size: usize) -> Seq<std::slice::Chunks<'_, T>>;
+		fn par_chunks_mut(&mut self, size: usize) -> Seq<std::slice::ChunksMut<'_, T>>;
+		fn par_chunks_exact(&self, size: usize) -> Seq<std::slice::ChunksExact<'_, T>>;
+		fn par_chunks_exact_mut(&mut self, size: usize) -> Seq<std::slice::ChunksExactMut<'_, T>>;
+	}
+
+	impl<T> SliceSeq<T> for [T] {
+		fn par_iter(&self) -> Seq<std::slice::Iter<'_, T>> {
+			Seq(self.iter())
+		}
+
+		fn par_iter_mut(&mut self) -> Seq<std::slice::IterMut<'_, T>> {
+			Seq(self.iter_mut())
+		}
+
+		fn par_chunks(&self, size: usize) -> Seq<std::slice::Chunks<'_, T>> {
+			Seq(self.chunks(size))
+		}
+
+		fn par_chunks_mut(&mut self, size: usize) -> Seq<std::slice::ChunksMut<'_, T>> {
+			Seq(self.chunks_mut(size))
+		}
+
+		fn par_chunks_exact(&self, size: usize) -> Seq<std::slice::ChunksExact<'_, T>> {
+			Seq(self.chunks_exact(size))
+		}
+
+		fn par_chunks_exact_mut(&mut self, size: usize) -> Seq<std::slice::ChunksExactMut<'_, T>> {
+			Seq(self.chunks_exact_mut(size))
+		}
+	}
+
+	/// `par_extend`, which is only `extend`
+	pub(crate) trait ExtendSeq<T> {
+		fn par_extend<I: IntoIterator<Item = T>>(&mut self, iter: I);
+	}
+
+	impl<T> ExtendSeq<T> for Vec<T> {
+		fn par_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+			self.extend(iter)
+		}
+	}
+}
@@ -0,0 +1,256 @@
+//! Pixel shift, where the camera moves the sensor a pixel at a time between
+//! frames so every spot is seen through a red, a blue, and both green
+//! filters. Put together, that's full colour at every pixel and we never
+//! have to guess with a debayer. Sony and Pentax take four frames. Sony's
+//! sixteen are four of those, each group half a pixel over from the last,
+//! which gets twice the resolution each way.
+//!
+//! Anything that moved between frames comes out as colour fringes, so we
+//! check for that at each pixel and debayer the first frame there instead.
+
+use crate::{
+	colorspace::{BayerRgb, LinRgb},
+	image::{demosaic, Crop, Demosaic, Image},
+	par::*,
+	Error,
+};
+
+/// The usual four frame order: the sensor goes down a pixel, right a pixel,
+/// and back up. See [PixelShift::shifts].
+pub const FOUR_SHOT: [(usize, usize); 4] = [(0, 0), (0, 1), (1, 1), (1, 0)];
+
+/// The usual order of the groups in a sixteen frame sequence: half a pixel
+/// right, then down, then back left. See [PixelShift::groups].
+pub const SIXTEEN_SHOT: [(usize, usize); 4] = [(0, 0), (1, 0), (1, 1), (0, 1)];
+
+// The two green samples at a pixel can always differ by this much of the
+// sensor's range, so noise in the shadows isn't taken for motion
+const NOISE_FLOOR: f32 = 0.02;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PixelShiftError {
+	#[error("Pixel shift takes 4 or 16 frames, not {0}")]
+	FrameCount(usize),
+	#[error("Frame {index} is {width}x{height} but the first is {first_width}x{first_height}")]
+	SizeMismatch {
+		index: usize,
+		width: usize,
+		height: usize,
+		first_width: usize,
+		first_height: usize,
+	},
+	#[error("Pixel shift only works with a 2x2 Bayer pattern, and this is {0}")]
+	NotBayer(String),
+	#[error("Frame {0} has a different CFA pattern than the first")]
+	CfaMismatch(usize),
+	#[error("The shifts have to be the four corners of a 2x2 square, each once")]
+	BadShifts,
+}
+
+/// Composites a pixel shift sequence. Frames go in the order the camera
+/// took them, and if your camera's order isn't the usual one, say what it
+/// is with [shifts](Self::shifts) and [groups](Self::groups).
+#[derive(Clone, Debug)]
+pub struct PixelShift {
+	shifts: [(usize, usize); 4],
+	groups: [(usize, usize); 4],
+	threshold: f32,
+	fallback: Demosaic,
+}
+
+impl Default for PixelShift {
+	fn default() -> Self {
+		Self {
+			shifts: FOUR_SHOT,
+			groups: SIXTEEN_SHOT,
+			threshold: 0.1,
+			fallback: Demosaic::default(),
+		}
+	}
+}
+
+impl PixelShift {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Where the sensor was for each frame of four, as `(x, y)` in pixels.
+	/// What the first frame saw at a pixel, a frame with the shift `(1, 0)`
+	/// saw one pixel to the right. Defaults to [FOUR_SHOT].
+	pub fn shifts(mut self, shifts: [(usize, usize); 4]) -> Self {
+		self.shifts = shifts;
+		self
+	}
+
+	/// Where each group of four frames was, in half pixels, for sixteen
+	/// frame sequences. Same idea as [shifts](Self::shifts). Defaults to
+	/// [SIXTEEN_SHOT].
+	pub fn groups(mut self, groups: [(usize, usize); 4]) -> Self {
+		self.groups = groups;
+		self
+	}
+
+	/// How far apart the two green samples at a pixel can be, as a fraction
+	/// of their brightness, before we decide something moved there. Lower
+	/// catches more motion but debayers more of the image. Defaults to 0.1.
+	///
+	/// Only the greens are compared, so something that moved while just the
+	/// red or blue frame was being taken slips through. Things that move
+	/// rarely hold still for the other three.
+	pub fn motion_threshold(mut self, threshold: f32) -> Self {
+		self.threshold = threshold;
+		self
+	}
+
+	/// How to debayer the pixels where something moved. Defaults to
+	/// [Demosaic::default].
+	pub fn fallback(mut self, demosaic: Demosaic) -> Self {
+		self.fallback = demosaic;
+		self
+	}
+
+	/// Put the frames together. Four frames come out the same size as each
+	/// frame, sixteen twice as wide and tall. Either way it has the first
+	/// frame's metadata and is ready for [whitebalance](Image::whitebalance).
+	///
+	/// The last row and column are only seen by some of the frames, so
+	/// they're always debayered.
+	pub fn composite(&self, frames: &[Image<u16, BayerRgb>]) -> Result<Image<u16, LinRgb>, Error> {
+		let first = match frames.len() {
+			4 | 16 => &frames[0],
+			count => return Err(PixelShiftError::FrameCount(count).into()),
+		};
+		if !is_square(&self.shifts) || (frames.len() == 16 && !is_square(&self.groups)) {
+			return Err(PixelShiftError::BadShifts.into());
+		}
+
+		let cfa = &first.metadata.cfa;
+		if !demosaic::is_bayer(cfa) {
+			return Err(PixelShiftError::NotBayer(cfa.name.clone()).into());
+		}
+
+		for (index, frame) in frames.iter().enumerate() {
+			if frame.width != first.width || frame.height != first.height {
+				return Err(PixelShiftError::SizeMismatch {
+					index,
+					width: frame.width,
+					height: frame.height,
+					first_width: first.width,
+					first_height: first.height,
+				}
+				.into());
+			}
+			if frame.metadata.cfa.name != cfa.name {
+				return Err(PixelShiftError::CfaMismatch(index).into());
+			}
+		}
+
+		if frames.len() == 4 {
+			let data = self.four(frames);
+			return Ok(Image::from_raw_parts(
+				first.width,
+				first.height,
+				first.metadata.clone(),
+				data,
+			));
+		}
+
+		// Each group fills every other pixel of every other row, starting
+		// from where it sits in the half pixel grid
+		let width = first.width * 2;
+		let mut data = vec![0; width * first.height * 2 * 3];
+		for (group, (gx, gy)) in frames.chunks_exact(4).zip(self.groups) {
+			let composite = self.four(group);
+			for (y, row) in composite.chunks_exact(first.width * 3).enumerate() {
+				for (x, px) in row.chunks_exact(3).enumerate() {
+					let idx = ((y * 2 + gy) * width + x * 2 + gx) * 3;
+					data[idx..idx + 3].copy_from_slice(px);
+				}
+			}
+		}
+
+		let mut metadata = first.metadata.clone();
+		let double = |crop: Crop| Crop {
+			top: crop.top * 2,
+			right: crop.right * 2,
+			bottom: crop.bottom * 2,
+			left: crop.left * 2,
+		};
+		metadata.active_area = metadata.active_area.map(double);
+		metadata.default_crop = metadata.default_crop.map(double);
+
+		Ok(Image::from_raw_parts(
+			width,
+			first.height * 2,
+			metadata,
+			data,
+		))
+	}
+
+	/// Composite four frames, already checked, into RGB data the size of one
+	fn four(&self, frames: &[Image<u16, BayerRgb>]) -> Vec<u16> {
+		let first = &frames[0];
+		let (width, height) = (first.width, first.height);
+		let cfa = &first.metadata.cfa;
+		let fallback = first.clone().debayer_with(self.fallback);
+
+		let black = first.metadata.blacklevels[1] as f32;
+		let range = first.metadata.whitelevels[1] as f32 - black;
+		let floor = range * NOISE_FLOOR;
+
+		let mut data = vec![0; width * height * 3];
+		data.par_chunks_mut(width * 3)
+			.enumerate()
+			.for_each(|(y, row)| {
+				for (x, out) in row.chunks_exact_mut(3).enumerate() {
+					let mut rgb = [0u32; 3];
+					let mut greens = [0u16; 2];
+					let mut green_count = 0;
+					let mut seen = true;
+
+					for (frame, (dx, dy)) in frames.iter().zip(self.shifts) {
+						let (fx, fy) = (x + dx, y + dy);
+						if fx >= width || fy >= height {
+							seen = false;
+							break;
+						}
+
+						let value = frame.data[fy * width + fx];
+						match cfa.color_at(fy, fx) {
+							1 | 3 => {
+								greens[green_count] = value;
+								green_count += 1;
+							}
+							color => rgb[color] = value as u32,
+						}
+					}
+
+					let moved = seen && {
+						let [a, b] = greens.map(|g| g as f32);
+						let brightness = ((a + b) / 2.0 - black).max(floor);
+						(a - b).abs() > brightness * self.threshold
+					};
+
+					if !seen || moved {
+						let idx = (y * width + x) * 3;
+						out.copy_from_slice(&fallback.data[idx..idx + 3]);
+					} else {
+						rgb[1] = (greens[0] as u32 + greens[1] as u32).div_ceil(2);
+						out[0] = rgb[0] as u16;
+						out[1] = rgb[1] as u16;
+						out[2] = rgb[2] as u16;
+					}
+				}
+			});
+
+		data
+	}
+}
+
+/// Whether these are each corner of a 2x2 square once, so every pixel gets
+/// seen through every filter
+fn is_square(shifts: &[(usize, usize); 4]) -> bool {
+	[(0, 0), (1, 0), (0, 1), (1, 1)]
+		.iter()
+		.all(|corner| shifts.contains(corner))
+}
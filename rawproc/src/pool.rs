@@ -0,0 +1,69 @@
+//! Reusing big buffers instead of allocating new ones for every frame.
+//!
+//! A 45MP raw is ~90MB as u16 and three times that once it's debayered. If
+//! you're working through thousands of frames, allocating and freeing those
+//! over and over fragments the heap and wastes time. Hand the buffers you're
+//! done with to a [BufferPool] and take them back out when you need one.
+
+use std::sync::Mutex;
+
+use crate::{colorspace::Colorspace, image::Image};
+
+/// A pool of buffers that can be shared between threads.
+#[derive(Debug)]
+pub struct BufferPool<T> {
+	buffers: Mutex<Vec<Vec<T>>>,
+	max_buffers: usize,
+}
+
+impl<T: Copy> BufferPool<T> {
+	/// Make a new, empty, pool that will hold on to at most `max_buffers`.
+	/// Buffers given back when the pool is full are dropped.
+	pub fn new(max_buffers: usize) -> Self {
+		Self {
+			buffers: Mutex::new(vec![]),
+			max_buffers,
+		}
+	}
+
+	/// Take a buffer that can hold at least `capacity` elements without
+	/// reallocating. It's empty, so resize it to what you need. If there's
+	/// nothing big enough in the pool a new one is allocated.
+	pub fn take(&self, capacity: usize) -> Vec<T> {
+		let mut buffers = self.buffers.lock().unwrap();
+
+		match buffers.iter().position(|buf| buf.capacity() >= capacity) {
+			Some(idx) => {
+				let mut buf = buffers.swap_remove(idx);
+				buf.clear();
+				buf
+			}
+			None => Vec::with_capacity(capacity),
+		}
+	}
+
+	/// Put a buffer back in the pool for later
+	pub fn give(&self, buffer: Vec<T>) {
+		let mut buffers = self.buffers.lock().unwrap();
+
+		if buffers.len() < self.max_buffers {
+			buffers.push(buffer);
+		}
+	}
+
+	/// How many buffers are sitting in the pool right now
+	pub fn len(&self) -> usize {
+		self.buffers.lock().unwrap().len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+impl<T: Copy + Clone, C: Colorspace> Image<T, C> {
+	/// Give the image's buffer to a pool when you're done with the image.
+	pub fn recycle(self, pool: &BufferPool<T>) {
+		pool.give(self.data);
+	}
+}
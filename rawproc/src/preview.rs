@@ -0,0 +1,143 @@
+//! The JPEGs cameras tuck in next to the raw data. There's usually a small
+//! thumbnail and a bigger one for the camera's screen, and sometimes one the
+//! full size of the sensor. They're what the camera made of the shot, so
+//! they're nowhere near what we'd make, but they're right there and you don't
+//! have to debayer anything to get them.
+
+use crate::{
+	dng,
+	image::Orientation,
+	tiff::{Ifd, Tiff, TAG_COMPRESSION, TAG_ORIENTATION},
+};
+
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+
+// Old style and new style JPEG compression
+const COMPRESSION_OJPEG: u32 = 6;
+const COMPRESSION_JPEG: u32 = 7;
+
+const RAF_MAGIC: &[u8] = b"FUJIFILMCCD-RAW ";
+
+/// A JPEG from inside a raw, as it was stored. Hand the bytes to any JPEG
+/// decoder.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmbeddedPreview {
+	pub width: usize,
+	pub height: usize,
+	/// Which way up the photo was. Cameras store the previews the way the
+	/// sensor was, same as the raw.
+	pub orientation: Orientation,
+	pub data: Vec<u8>,
+}
+
+/// Every preview we can find, biggest first. TIFF based raws keep them in
+/// their IFDs and Fuji's RAF points to one in its header. Anything else,
+/// like CR3, we don't know how to look in and get none.
+pub(crate) fn find(data: &[u8]) -> Vec<EmbeddedPreview> {
+	let mut spans = vec![];
+	let mut orientation = Orientation::Normal;
+
+	if data.starts_with(RAF_MAGIC) {
+		let be = |at: usize| -> Option<usize> {
+			Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as usize)
+		};
+		if let (Some(offset), Some(length)) = (be(84), be(88)) {
+			spans.push((offset, length));
+		}
+	} else if let Some(tiff) = Tiff::new(data) {
+		orientation = tiff
+			.first_ifd()
+			.and_then(|ifd| tiff.u16s(ifd.get(TAG_ORIENTATION)?))
+			.and_then(|v| v.first().copied())
+			.map(Orientation::from_exif)
+			.unwrap_or_default();
+
+		for ifd in dng::image_ifds(&tiff) {
+			spans.extend(jpeg_spans(&tiff, &ifd));
+		}
+	}
+
+	spans.sort_unstable();
+	spans.dedup_by_key(|span| span.0);
+
+	let mut previews: Vec<EmbeddedPreview> = spans
+		.into_iter()
+		.filter_map(|(offset, length)| {
+			let jpeg = data.get(offset..offset.checked_add(length)?)?;
+			let (width, height) = jpeg_size(jpeg)?;
+			Some(EmbeddedPreview {
+				width,
+				height,
+				orientation,
+				data: jpeg.to_vec(),
+			})
+		})
+		.collect();
+
+	previews.sort_by_key(|p| std::cmp::Reverse(p.width * p.height));
+	previews
+}
+
+/// Where an IFD says it has a JPEG. Either it points right at one, which is
+/// how EXIF does thumbnails, or its one strip is JPEG compressed. Raw data
+/// can be JPEG compressed too, but that's lossless JPEG, and
+/// [jpeg_size] turns those away.
+fn jpeg_spans(tiff: &Tiff, ifd: &Ifd) -> Vec<(usize, usize)> {
+	let first = |tag| tiff.u32s(ifd.get(tag)?)?.first().map(|v| *v as usize);
+	let mut spans = vec![];
+
+	if let (Some(offset), Some(length)) = (first(TAG_JPEG_OFFSET), first(TAG_JPEG_LENGTH)) {
+		spans.push((offset, length));
+	}
+
+	let compression = first(TAG_COMPRESSION).unwrap_or(1) as u32;
+	if compression == COMPRESSION_OJPEG || compression == COMPRESSION_JPEG {
+		let offsets = ifd.get(TAG_STRIP_OFFSETS).and_then(|e| tiff.u32s(e));
+		let lengths = ifd.get(TAG_STRIP_BYTE_COUNTS).and_then(|e| tiff.u32s(e));
+		if let (Some([offset]), Some([length])) = (offsets.as_deref(), lengths.as_deref()) {
+			spans.push((*offset as usize, *length as usize));
+		}
+	}
+
+	spans
+}
+
+/// The width and height of a JPEG that an ordinary decoder can open, from its
+/// start of frame. None if it isn't a JPEG, or is one of the kinds nobody
+/// supports, like the lossless ones raw data is stored in.
+fn jpeg_size(jpeg: &[u8]) -> Option<(usize, usize)> {
+	if !jpeg.starts_with(&[0xFF, 0xD8]) {
+		return None;
+	}
+
+	let mut at = 2;
+	loop {
+		// Markers can be padded with any number of 0xFF
+		while *jpeg.get(at)? == 0xFF && *jpeg.get(at + 1)? == 0xFF {
+			at += 1;
+		}
+		if *jpeg.get(at)? != 0xFF {
+			return None;
+		}
+
+		let marker = *jpeg.get(at + 1)?;
+		let length = u16::from_be_bytes([*jpeg.get(at + 2)?, *jpeg.get(at + 3)?]) as usize;
+		match marker {
+			// Baseline, extended, and progressive. The rest of the start of
+			// frames are lossless, arithmetic coded, or hierarchical.
+			0xC0..=0xC2 => {
+				let sof = jpeg.get(at + 4..at + 9)?;
+				let height = u16::from_be_bytes([sof[1], sof[2]]) as usize;
+				let width = u16::from_be_bytes([sof[3], sof[4]]) as usize;
+				return (width > 0 && height > 0).then_some((width, height));
+			}
+			0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => return None,
+			// Start of scan without a frame
+			0xDA => return None,
+			_ => at += 2 + length,
+		}
+	}
+}
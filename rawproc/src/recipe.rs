@@ -0,0 +1,491 @@
+//! Everything it takes to develop a raw, written down once. A [Recipe] has
+//! the settings for each step and does them in the right order, which is
+//! easy to get wrong by hand: the whitebalance has to happen before the
+//! debayer, the lens correction before the default crop, and the colour
+//! matrix after all of the adjustments that are meant for camera RGB.
+//!
+//! Recipes save to and load from TOML, so the edits for a photo can sit
+//! next to it as a sidecar and be done again later, or to a whole shoot.
+//!
+//! ```toml
+//! version = 1
+//! crop = "default"
+//! whitebalance = "as_shot"
+//! demosaic = "ahd"
+//! false_color = 1
+//! exposure = 0.5
+//! curve = [[0.0, 0.0], [0.5, 0.55], [1.0, 1.0]]
+//!
+//! [sharpen]
+//! radius = 1.0
+//! amount = 0.5
+//! threshold = 0.01
+//! ```
+
+use std::fmt;
+
+use toml::{value::Table, Value};
+
+use crate::{
+	colorspace::{BayerRgb, ColorspaceKind, LinRgb, LinSrgb, Monochrome},
+	image::{Demosaic, DynImage, Image, RawMetadata, ToneCurve, WhitebalanceSource},
+	makernote::PresetKind,
+	Error,
+};
+
+/// The newest recipe format we know. Files with a newer one might have
+/// steps we'd skip without saying, so we turn them away.
+const VERSION: i64 = 1;
+
+const PRESETS: [(&str, PresetKind); 10] = [
+	("auto", PresetKind::Auto),
+	("daylight", PresetKind::Daylight),
+	("shade", PresetKind::Shade),
+	("cloudy", PresetKind::Cloudy),
+	("tungsten", PresetKind::Tungsten),
+	("fluorescent", PresetKind::Fluorescent),
+	("flash", PresetKind::Flash),
+	("kelvin", PresetKind::Kelvin),
+	("measured", PresetKind::Measured),
+	("custom", PresetKind::Custom),
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum RecipeError {
+	#[error("The recipe isn't valid TOML: {source}")]
+	Toml {
+		#[from]
+		source: toml::de::Error,
+	},
+	#[error("Recipe version {0} is newer than we can read")]
+	UnsupportedVersion(i64),
+	#[error("The recipe has a {0}, which we don't know")]
+	UnknownKey(String),
+	#[error("The recipe's {0} isn't something we understand")]
+	BadValue(&'static str),
+}
+
+/// How far to crop the mosaic before anything else
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CropMode {
+	/// Keep the whole sensor, masked pixels and all
+	None,
+	/// Just the part of the sensor that saw light
+	ActiveArea,
+	/// The manufacturer's crop, which is what the camera's JPEG shows
+	#[default]
+	Default,
+}
+
+/// Which whitebalance to develop with
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WhitebalanceMode {
+	#[default]
+	AsShot,
+	Daylight,
+	/// One of the camera's presets. If the camera didn't give us this one
+	/// we stay with as shot.
+	Preset(PresetKind),
+	/// Red, green, and blue coefficients of your own
+	Custom([f32; 3]),
+}
+
+impl WhitebalanceMode {
+	/// The other way from [Display](fmt::Display): `as_shot`, `daylight`,
+	/// `preset:` and one of the preset names, like `preset:shade`, or three
+	/// coefficients split by commas
+	pub fn parse(s: &str) -> Option<Self> {
+		match s {
+			"as_shot" => return Some(WhitebalanceMode::AsShot),
+			"daylight" => return Some(WhitebalanceMode::Daylight),
+			_ => (),
+		}
+
+		if let Some(name) = s.strip_prefix("preset:") {
+			return PRESETS
+				.iter()
+				.find(|(n, _)| *n == name)
+				.map(|(_, kind)| WhitebalanceMode::Preset(*kind));
+		}
+
+		let wb: Vec<f32> = s
+			.split(',')
+			.map(|c| c.trim().parse().ok())
+			.collect::<Option<_>>()?;
+		wb.try_into().ok().map(WhitebalanceMode::Custom)
+	}
+}
+
+impl fmt::Display for WhitebalanceMode {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			WhitebalanceMode::AsShot => write!(f, "as_shot"),
+			WhitebalanceMode::Daylight => write!(f, "daylight"),
+			WhitebalanceMode::Preset(kind) => {
+				let name = PRESETS.iter().find(|(_, k)| k == kind).map(|(n, _)| *n);
+				write!(f, "preset:{}", name.unwrap_or("custom"))
+			}
+			WhitebalanceMode::Custom([r, g, b]) => write!(f, "{r},{g},{b}"),
+		}
+	}
+}
+
+/// The settings for [unsharp_mask](Image::unsharp_mask)
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sharpen {
+	pub radius: f32,
+	pub amount: f32,
+	pub threshold: f32,
+}
+
+/// Every step of a develop and its settings. The [Default] is a plain
+/// develop that changes nothing the camera didn't ask for.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Recipe {
+	pub crop: CropMode,
+	/// Fix hot and dead pixels further than this from their neighbours, see
+	/// [fix_hot_pixels](Image::fix_hot_pixels)
+	pub hot_pixels: Option<f32>,
+	pub whitebalance: WhitebalanceMode,
+	pub demosaic: Demosaic,
+	/// Clean up the false colour the debayer leaves within this radius, see
+	/// [suppress_false_color](Image::suppress_false_color)
+	pub false_color: Option<usize>,
+	/// Correct the lens with what the raw says about it
+	pub lens: bool,
+	/// Noise reduction strengths, 0.0 for none. See
+	/// [denoise](Image::denoise).
+	pub denoise_luminance: f32,
+	pub denoise_chroma: f32,
+	/// In stops
+	pub exposure: f32,
+	pub contrast: f32,
+	pub saturation: f32,
+	pub curve: Option<ToneCurve>,
+	pub sharpen: Option<Sharpen>,
+	/// Turn the image upright
+	pub orientation: bool,
+}
+
+impl Default for Recipe {
+	fn default() -> Self {
+		Self {
+			crop: CropMode::Default,
+			hot_pixels: None,
+			whitebalance: WhitebalanceMode::AsShot,
+			demosaic: Demosaic::default(),
+			false_color: None,
+			lens: true,
+			denoise_luminance: 0.0,
+			denoise_chroma: 0.0,
+			exposure: 0.0,
+			contrast: 1.0,
+			saturation: 1.0,
+			curve: None,
+			sharpen: None,
+			orientation: true,
+		}
+	}
+}
+
+impl Recipe {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Develop a raw, all the way to linear sRGB. [gamma](Image::gamma) it
+	/// to get something to save.
+	pub fn apply(&self, raw: Image<u16, BayerRgb>) -> Image<f32, LinSrgb> {
+		let mut raw = raw.normalize();
+		if self.crop != CropMode::None {
+			raw.crop();
+		}
+		if let Some(threshold) = self.hot_pixels {
+			raw.fix_hot_pixels(threshold);
+		}
+
+		self.set_whitebalance(&mut raw.metadata);
+		raw.whitebalance();
+
+		self.finish(raw.debayer_with(self.demosaic))
+	}
+
+	/// [apply](Self::apply) to whatever a [decode_dyn](crate::decode_dyn)
+	/// gave you. LinearRaw DNGs were debayered before we got them, so they
+	/// skip the steps for the mosaic.
+	pub fn apply_dyn(&self, image: DynImage<u16>) -> Result<Image<f32, LinSrgb>, Error> {
+		if image.colorspace == ColorspaceKind::Monochrome {
+			return Ok(self.apply_monochrome(image.try_into()?));
+		}
+		if image.colorspace != ColorspaceKind::LinRgb {
+			return Ok(self.apply(image.try_into()?));
+		}
+
+		let rgb: Image<u16, LinRgb> = image.try_into()?;
+		let mut rgb = rgb.normalize();
+		if self.crop != CropMode::None {
+			let area = rgb.metadata.active_area.take();
+			if let Some(region) = area.and_then(|a| a.region(rgb.width, rgb.height)) {
+				rgb.crop_region(region);
+			}
+		}
+
+		self.set_whitebalance(&mut rgb.metadata);
+		rgb.whitebalance();
+
+		Ok(self.finish(rgb))
+	}
+
+	/// A monochrome raw has one colour, so there's no whitebalance or
+	/// debayer. It comes out grey.
+	fn apply_monochrome(&self, mono: Image<u16, Monochrome>) -> Image<f32, LinSrgb> {
+		let mut mono = mono.normalize();
+		if self.crop != CropMode::None {
+			mono.crop();
+		}
+
+		self.finish(mono.to_linrgb())
+	}
+
+	fn set_whitebalance(&self, metadata: &mut RawMetadata) {
+		match self.whitebalance {
+			WhitebalanceMode::AsShot => {
+				metadata.use_whitebalance(WhitebalanceSource::AsShot);
+			}
+			WhitebalanceMode::Daylight => {
+				metadata.use_whitebalance(WhitebalanceSource::Daylight);
+			}
+			WhitebalanceMode::Preset(kind) => {
+				metadata.use_whitebalance(WhitebalanceSource::Preset(kind));
+			}
+			WhitebalanceMode::Custom(wb) => metadata.whitebalance = wb,
+		}
+	}
+
+	/// Everything after the debayer. False colour is cleaned up first, while
+	/// it's still where the debayer put it. The lens is corrected on the
+	/// active area, which is what lens corrections are measured on, and only
+	/// then is the default crop taken.
+	fn finish(&self, mut rgb: Image<f32, LinRgb>) -> Image<f32, LinSrgb> {
+		if let Some(radius) = self.false_color {
+			rgb.suppress_false_color(radius);
+		}
+		if self.lens {
+			rgb.correct_lens_embedded();
+		}
+
+		let default_crop = rgb.metadata.default_crop.take();
+		if self.crop == CropMode::Default {
+			if let Some(region) = default_crop.and_then(|c| c.region(rgb.width, rgb.height)) {
+				rgb.crop_region(region);
+			}
+		}
+
+		if self.denoise_luminance > 0.0 || self.denoise_chroma > 0.0 {
+			rgb.denoise(self.denoise_luminance, self.denoise_chroma);
+		}
+		if self.exposure != 0.0 {
+			rgb.exposure(self.exposure, None);
+		}
+		if self.contrast != 1.0 {
+			rgb.contrast(self.contrast);
+		}
+		if self.saturation != 1.0 {
+			rgb.saturation(self.saturation);
+		}
+		if let Some(curve) = &self.curve {
+			rgb.apply_curve(curve);
+		}
+
+		let mut out = rgb.to_xyz().to_linsrgb();
+		if let Some(sharpen) = self.sharpen {
+			out.unsharp_mask(sharpen.radius, sharpen.amount, sharpen.threshold);
+		}
+		if self.orientation {
+			out.apply_orientation();
+		}
+
+		out
+	}
+
+	/// Read a recipe. Anything left out is the [Default].
+	pub fn from_toml(toml: &str) -> Result<Self, Error> {
+		let table: Table = toml
+			.parse::<Value>()
+			.map_err(RecipeError::from)?
+			.try_into()
+			.map_err(|_| RecipeError::BadValue("top level"))?;
+
+		let mut recipe = Recipe::default();
+		for (key, value) in &table {
+			match key.as_str() {
+				"version" => {
+					let version = value.as_integer().ok_or(RecipeError::BadValue("version"))?;
+					if version > VERSION {
+						return Err(RecipeError::UnsupportedVersion(version).into());
+					}
+				}
+				"crop" => {
+					recipe.crop = match value.as_str() {
+						Some("none") => CropMode::None,
+						Some("active_area") => CropMode::ActiveArea,
+						Some("default") => CropMode::Default,
+						_ => return Err(RecipeError::BadValue("crop").into()),
+					}
+				}
+				"hot_pixels" => recipe.hot_pixels = Some(float(value, "hot_pixels")?),
+				"whitebalance" => recipe.whitebalance = whitebalance(value)?,
+				"demosaic" => {
+					recipe.demosaic = value
+						.as_str()
+						.and_then(Demosaic::from_name)
+						.ok_or(RecipeError::BadValue("demosaic"))?
+				}
+				"false_color" => {
+					let radius = value
+						.as_integer()
+						.and_then(|r| usize::try_from(r).ok())
+						.ok_or(RecipeError::BadValue("false_color"))?;
+					recipe.false_color = Some(radius);
+				}
+				"lens" => recipe.lens = boolean(value, "lens")?,
+				"denoise_luminance" => {
+					recipe.denoise_luminance = float(value, "denoise_luminance")?
+				}
+				"denoise_chroma" => recipe.denoise_chroma = float(value, "denoise_chroma")?,
+				"exposure" => recipe.exposure = float(value, "exposure")?,
+				"contrast" => recipe.contrast = float(value, "contrast")?,
+				"saturation" => recipe.saturation = float(value, "saturation")?,
+				"curve" => recipe.curve = Some(curve(value)?),
+				"sharpen" => {
+					let table = value.as_table().ok_or(RecipeError::BadValue("sharpen"))?;
+					let get = |name| table.get(name).map(|v| float(v, "sharpen")).transpose();
+					recipe.sharpen = Some(Sharpen {
+						radius: get("radius")?.ok_or(RecipeError::BadValue("sharpen"))?,
+						amount: get("amount")?.ok_or(RecipeError::BadValue("sharpen"))?,
+						threshold: get("threshold")?.unwrap_or(0.0),
+					});
+				}
+				"orientation" => recipe.orientation = boolean(value, "orientation")?,
+				other => return Err(RecipeError::UnknownKey(other.to_owned()).into()),
+			}
+		}
+
+		Ok(recipe)
+	}
+
+	/// Write the recipe out, every setting included, so a file says exactly
+	/// what it does even if the defaults change later
+	pub fn to_toml(&self) -> String {
+		let mut table = Table::new();
+		// Through a string, so 0.55 is written as 0.55 and not as the f64
+		// closest to the f32 closest to 0.55
+		let float = |f: f32| Value::Float(f.to_string().parse().unwrap_or(f as f64));
+		table.insert("version".into(), Value::Integer(VERSION));
+
+		let crop = match self.crop {
+			CropMode::None => "none",
+			CropMode::ActiveArea => "active_area",
+			CropMode::Default => "default",
+		};
+		table.insert("crop".into(), crop.into());
+		if let Some(threshold) = self.hot_pixels {
+			table.insert("hot_pixels".into(), float(threshold));
+		}
+
+		let whitebalance = match self.whitebalance {
+			WhitebalanceMode::Custom(wb) => Value::Array(wb.map(float).to_vec()),
+			other => other.to_string().into(),
+		};
+		table.insert("whitebalance".into(), whitebalance);
+
+		table.insert("demosaic".into(), self.demosaic.name().into());
+		if let Some(radius) = self.false_color {
+			table.insert("false_color".into(), Value::Integer(radius as i64));
+		}
+		table.insert("lens".into(), self.lens.into());
+		table.insert("denoise_luminance".into(), float(self.denoise_luminance));
+		table.insert("denoise_chroma".into(), float(self.denoise_chroma));
+		table.insert("exposure".into(), float(self.exposure));
+		table.insert("contrast".into(), float(self.contrast));
+		table.insert("saturation".into(), float(self.saturation));
+
+		if let Some(curve) = &self.curve {
+			let points = curve
+				.points()
+				.iter()
+				.map(|(x, y)| Value::Array(vec![float(*x), float(*y)]))
+				.collect();
+			table.insert("curve".into(), Value::Array(points));
+		}
+
+		if let Some(sharpen) = self.sharpen {
+			let mut s = Table::new();
+			s.insert("radius".into(), float(sharpen.radius));
+			s.insert("amount".into(), float(sharpen.amount));
+			s.insert("threshold".into(), float(sharpen.threshold));
+			table.insert("sharpen".into(), Value::Table(s));
+		}
+
+		table.insert("orientation".into(), self.orientation.into());
+
+		// Tables are always fine to serialize, and it's all tables and
+		// plain values in here
+		toml::to_string(&Value::Table(table)).unwrap()
+	}
+}
+
+/// TOML writes whole numbers without a decimal point, and people do too, so
+/// we take integers wherever we want a float
+fn float(value: &Value, name: &'static str) -> Result<f32, RecipeError> {
+	match value {
+		Value::Float(f) => Ok(*f as f32),
+		Value::Integer(i) => Ok(*i as f32),
+		_ => Err(RecipeError::BadValue(name)),
+	}
+}
+
+fn boolean(value: &Value, name: &'static str) -> Result<bool, RecipeError> {
+	value.as_bool().ok_or(RecipeError::BadValue(name))
+}
+
+fn whitebalance(value: &Value) -> Result<WhitebalanceMode, RecipeError> {
+	let bad = RecipeError::BadValue("whitebalance");
+	if let Some(coefficients) = value.as_array() {
+		let wb: Vec<f32> = coefficients
+			.iter()
+			.map(|v| float(v, "whitebalance"))
+			.collect::<Result<_, _>>()?;
+		return <[f32; 3]>::try_from(wb)
+			.map(WhitebalanceMode::Custom)
+			.map_err(|_| bad);
+	}
+
+	value.as_str().and_then(WhitebalanceMode::parse).ok_or(bad)
+}
+
+/// A curve needs two points with different inputs, or there's no line to
+/// draw
+fn curve(value: &Value) -> Result<ToneCurve, RecipeError> {
+	let bad = || RecipeError::BadValue("curve");
+	let points: Vec<(f32, f32)> = value
+		.as_array()
+		.ok_or_else(bad)?
+		.iter()
+		.map(|point| match point.as_array().map(Vec::as_slice) {
+			Some([x, y]) => Ok((float(x, "curve")?, float(y, "curve")?)),
+			_ => Err(bad()),
+		})
+		.collect::<Result<_, _>>()?;
+
+	let first = points.first().ok_or_else(bad)?.0;
+	if points.iter().all(|p| p.0 == first) {
+		return Err(bad());
+	}
+
+	Ok(ToneCurve::new(&points))
+}
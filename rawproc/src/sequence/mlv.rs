@@ -0,0 +1,265 @@
+//! Magic Lantern's MLV. It's a run of blocks, each starting with a four
+//! letter type and its size, all little endian. The ones we care about are
+//! MLVI, the file header, RAWI, which says how the frames are laid out, IDNT,
+//! for the camera's name, and VIDF, which there's one of per frame.
+//!
+//! Layouts are from Magic Lantern's mlv.h and raw.h.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use nalgebra::Matrix3;
+use rawloader::CFA;
+
+use crate::{
+	dng,
+	exif::Exif,
+	image::{Orientation, RawMetadata},
+	ljpeg, Error,
+};
+
+use super::SequenceError;
+
+// The video class flag for frames that are lossless JPEG compressed
+const VIDEO_CLASS_LJ92: u16 = 0x20;
+
+// Where things are in a RAWI block. raw_info starts after the block header
+// and the resolution.
+const RAW_INFO: usize = 20;
+const RAW_INFO_LEN: usize = 160;
+
+pub(super) struct Mlv {
+	pub width: usize,
+	pub height: usize,
+	pub metadata: RawMetadata,
+	/// Where each frame's data is and how long it is, in frame number order
+	pub frames: Vec<(u64, usize)>,
+	bits: u32,
+	compressed: bool,
+}
+
+impl Mlv {
+	/// Read every block header, keeping the ones that describe the video and
+	/// where the frames are. The frame data itself is left where it is.
+	pub fn index<R: Read + Seek>(reader: &mut R) -> Result<Self, Error> {
+		let mut compressed = false;
+		let mut raw_info = None;
+		let mut camera = None;
+		let mut frames = vec![];
+
+		let end = reader.seek(SeekFrom::End(0))?;
+		let mut offset = 0u64;
+		let mut header = [0u8; 8];
+		loop {
+			reader.seek(SeekFrom::Start(offset))?;
+			if reader.read_exact(&mut header).is_err() {
+				break;
+			}
+
+			let kind = &header[0..4];
+			let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as u64;
+			if offset == 0 && kind != b"MLVI" {
+				return Err(SequenceError::NotMlv.into());
+			}
+			if size < 8 {
+				return Err(SequenceError::Truncated.into());
+			}
+			// A recording that was cut off ends partway through a block. Every
+			// frame before it is still good, so that's where the clip ends.
+			if offset + size > end {
+				break;
+			}
+
+			match kind {
+				b"MLVI" => {
+					let block = read_block(reader, size)?;
+					compressed = u16_at(&block, 32)? & VIDEO_CLASS_LJ92 != 0;
+				}
+				b"RAWI" => raw_info = Some(read_block(reader, size)?),
+				b"IDNT" => camera = Some(read_block(reader, size)?),
+				b"VIDF" => {
+					let block = read_block(reader, 32.min(size))?;
+					let number = u32_at(&block, 16)?;
+					let frame_space = u32_at(&block, 28)? as u64;
+
+					// The frame starts after the 32 byte header and its padding
+					let start = offset + 32 + frame_space;
+					let len = size.saturating_sub(32 + frame_space) as usize;
+					frames.push((number, start, len));
+				}
+				_ => (),
+			}
+
+			offset += size;
+		}
+
+		let raw_info = raw_info.ok_or(SequenceError::NoRawInfo)?;
+		let width = u16_at(&raw_info, 16)? as usize;
+		let height = u16_at(&raw_info, 18)? as usize;
+		let info = raw_info
+			.get(RAW_INFO..RAW_INFO + RAW_INFO_LEN)
+			.ok_or(SequenceError::Truncated)?;
+		let bits = u32_at(info, 24)?;
+
+		frames.sort_by_key(|(number, _, _)| *number);
+
+		Ok(Self {
+			width,
+			height,
+			metadata: metadata(info, camera.as_deref())?,
+			frames: frames.into_iter().map(|(_, at, len)| (at, len)).collect(),
+			bits,
+			compressed,
+		})
+	}
+
+	/// Read and unpack frame `index`. `bytes` is where the packed frame is
+	/// read to, so keep passing the same one in.
+	pub fn frame<R: Read + Seek>(
+		&self,
+		reader: &mut R,
+		index: usize,
+		bytes: &mut Vec<u8>,
+	) -> Result<Vec<u16>, Error> {
+		let (at, len) = self.frames[index];
+		bytes.resize(len, 0);
+		reader.seek(SeekFrom::Start(at))?;
+		reader.read_exact(bytes)?;
+
+		let samples = self.width * self.height;
+		let data = if self.compressed {
+			ljpeg::decode(bytes)?.data
+		} else {
+			unpack(bytes, self.bits, samples)?
+		};
+
+		if data.len() < samples {
+			return Err(SequenceError::Truncated.into());
+		}
+		Ok(data)
+	}
+}
+
+/// Samples are packed most significant bit first, but in a stream of little
+/// endian 16 bit words, because that's how the camera's buffer is.
+fn unpack(bytes: &[u8], bits: u32, samples: usize) -> Result<Vec<u16>, SequenceError> {
+	if !(1..=16).contains(&bits) {
+		return Err(SequenceError::UnsupportedBitDepth(bits));
+	}
+
+	let mut out = Vec::with_capacity(samples);
+	let mut acc = 0u64;
+	let mut have = 0;
+	for word in bytes.chunks_exact(2) {
+		acc = (acc << 16) | u16::from_le_bytes([word[0], word[1]]) as u64;
+		have += 16;
+
+		while have >= bits {
+			have -= bits;
+			out.push(((acc >> have) & ((1 << bits) - 1)) as u16);
+		}
+
+		if out.len() >= samples {
+			break;
+		}
+	}
+
+	out.truncate(samples);
+	Ok(out)
+}
+
+fn metadata(info: &[u8], camera: Option<&[u8]>) -> Result<RawMetadata, SequenceError> {
+	let black = u32_at(info, 28)?.min(u16::MAX as u32) as u16;
+	let white = u32_at(info, 32)?.min(u16::MAX as u32) as u16;
+
+	// The pattern is the same as a DNG's CFAPattern, packed into an int
+	let pattern = u32_at(info, 76)?.to_le_bytes();
+	let name: String = pattern
+		.iter()
+		.map(|c| match c {
+			0 => 'R',
+			1 => 'G',
+			2 => 'B',
+			_ => 'E',
+		})
+		.collect();
+
+	// ColorMatrix1, as nine numerator and denominator pairs
+	let mut m = [0.0f32; 9];
+	for (idx, value) in m.iter_mut().enumerate() {
+		let num = u32_at(info, 84 + idx * 8)? as i32;
+		let den = u32_at(info, 88 + idx * 8)? as i32;
+		*value = if den == 0 {
+			0.0
+		} else {
+			num as f32 / den as f32
+		};
+	}
+	#[rustfmt::skip]
+	let xyz_to_cam = Matrix3::new(
+		m[0], m[1], m[2],
+		m[3], m[4], m[5],
+		m[6], m[7], m[8],
+	);
+	let cam_to_xyz = xyz_to_cam
+		.try_inverse()
+		.unwrap_or_else(Matrix3::identity)
+		.normalize();
+	let daylight_whitebalance = dng::daylight_whitebalance(&xyz_to_cam);
+
+	// IDNT has the camera's name at the start, after the block header, and
+	// its serial number after the model ID
+	let string = |range: std::ops::Range<usize>| {
+		camera.and_then(|c| c.get(range)).map(|name| {
+			let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+			String::from_utf8_lossy(&name[..end]).trim().to_owned()
+		})
+	};
+	let model = string(16..48).unwrap_or_default();
+	let serial = string(52..84).filter(|serial| !serial.is_empty());
+
+	Ok(RawMetadata {
+		whitebalance: daylight_whitebalance,
+		as_shot_whitebalance: daylight_whitebalance,
+		daylight_whitebalance,
+		whitebalance_presets: vec![],
+		whitebalance_selected: None,
+		whitebalance_fine_tune: None,
+		makernote: None,
+		sub_images: vec![],
+		lens_correction: None,
+		orientation: Orientation::Normal,
+		exif: Exif::default(),
+		// The frames are already cut out of the sensor, so there's nothing
+		// left to crop
+		active_area: None,
+		default_crop: None,
+		whitelevels: [white; 3],
+		blacklevels: [black; 3],
+		cfa: CFA::new(&name),
+		xyz_to_cam,
+		cam_to_xyz,
+		make: "Canon".into(),
+		model,
+		serial,
+	})
+}
+
+/// Read the rest of a block. `size` comes from the file, so it has to have
+/// been checked against how much of the file there is first.
+fn read_block<R: Read>(reader: &mut R, size: u64) -> Result<Vec<u8>, Error> {
+	// We already read the first eight bytes of the block, but it's easier to
+	// count from the start of it
+	let mut block = vec![0; size as usize];
+	reader.read_exact(&mut block[8..])?;
+	Ok(block)
+}
+
+fn u16_at(data: &[u8], at: usize) -> Result<u16, SequenceError> {
+	let b = data.get(at..at + 2).ok_or(SequenceError::Truncated)?;
+	Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Result<u32, SequenceError> {
+	let b = data.get(at..at + 4).ok_or(SequenceError::Truncated)?;
+	Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
@@ -0,0 +1,234 @@
+//! Raw video, a frame at a time. CinemaDNG is a folder of DNGs, one per
+//! frame, and Magic Lantern's MLV is one file with every frame in it.
+//!
+//! Every frame of a sequence gets the same metadata, the first frame's, so
+//! the whitebalance and levels don't wander from frame to frame. Frames are
+//! read one at a time as you ask for them and the file buffer is reused, so
+//! a long clip never has to fit in memory.
+//!
+//! Sequences are read from files, so this needs the `fs` feature.
+
+mod mlv;
+
+use std::{
+	fs::File,
+	io::BufReader,
+	path::{Path, PathBuf},
+};
+
+use crate::{
+	batch::{LockMode, SequenceLock},
+	colorspace::BayerRgb,
+	image::{Image, RawMetadata},
+	Error,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SequenceError {
+	#[error("There aren't any frames in this sequence")]
+	Empty,
+	#[error("There's no frame {0}")]
+	NoFrame(usize),
+	#[error("Frame {index} is {width}x{height} but the first frame isn't")]
+	FrameSize {
+		index: usize,
+		width: usize,
+		height: usize,
+	},
+	#[error("This isn't an MLV file")]
+	NotMlv,
+	#[error("The MLV file doesn't have a RAWI block, so we don't know what the frames look like")]
+	NoRawInfo,
+	#[error("An MLV block points past the end of the file")]
+	Truncated,
+	#[error("We can't read {0} bit MLV frames")]
+	UnsupportedBitDepth(u32),
+}
+
+pub struct Sequence {
+	source: Source,
+	/// The first frame's metadata and size
+	first: Option<(RawMetadata, usize, usize)>,
+	bytes: Vec<u8>,
+}
+
+enum Source {
+	CinemaDng(Vec<PathBuf>),
+	Mlv {
+		reader: BufReader<File>,
+		mlv: Box<mlv::Mlv>,
+	},
+}
+
+impl Sequence {
+	/// Every DNG in `folder`, in the order of their names. CinemaDNG names
+	/// frames with a zero padded frame number, so that's the frame order.
+	pub fn cinema_dng<P: AsRef<Path>>(folder: P) -> Result<Self, Error> {
+		let mut paths = vec![];
+		for entry in std::fs::read_dir(folder)? {
+			let path = entry?.path();
+			let is_dng = path
+				.extension()
+				.map(|ext| ext.eq_ignore_ascii_case("dng"))
+				.unwrap_or(false);
+
+			if is_dng {
+				paths.push(path);
+			}
+		}
+
+		if paths.is_empty() {
+			return Err(SequenceError::Empty.into());
+		}
+		paths.sort();
+
+		Ok(Self {
+			source: Source::CinemaDng(paths),
+			first: None,
+			bytes: vec![],
+		})
+	}
+
+	/// A Magic Lantern MLV file. Only the one file, so the .M00, .M01 and
+	/// such that long clips are split into have to be opened on their own.
+	pub fn mlv<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+		let mut reader = BufReader::new(File::open(path)?);
+		let mlv = mlv::Mlv::index(&mut reader)?;
+
+		if mlv.frames.is_empty() {
+			return Err(SequenceError::Empty.into());
+		}
+
+		Ok(Self {
+			first: Some((mlv.metadata.clone(), mlv.width, mlv.height)),
+			source: Source::Mlv {
+				reader,
+				mlv: Box::new(mlv),
+			},
+			bytes: vec![],
+		})
+	}
+
+	pub fn len(&self) -> usize {
+		match &self.source {
+			Source::CinemaDng(paths) => paths.len(),
+			Source::Mlv { mlv, .. } => mlv.frames.len(),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// The metadata every frame gets. For CinemaDNG that's the first frame's,
+	/// which gets decoded to find it if it hasn't been already.
+	pub fn metadata(&mut self) -> Result<&RawMetadata, Error> {
+		if self.first.is_none() {
+			self.frame(0)?;
+		}
+
+		Ok(&self.first.as_ref().unwrap().0)
+	}
+
+	/// Decode frame `index`, counting from 0
+	pub fn frame(&mut self, index: usize) -> Result<Image<u16, BayerRgb>, Error> {
+		if index >= self.len() {
+			return Err(SequenceError::NoFrame(index).into());
+		}
+
+		// Every frame gets frame 0's metadata, whichever one you ask for first
+		if self.first.is_none() && index != 0 {
+			self.frame(0)?;
+		}
+
+		match &mut self.source {
+			Source::CinemaDng(paths) => {
+				let mut file = File::open(&paths[index])?;
+				let mut image = crate::decode_with_buffer(&mut file, &mut self.bytes)?;
+
+				match &self.first {
+					Some((_, width, height))
+						if (image.width, image.height) != (*width, *height) =>
+					{
+						Err(SequenceError::FrameSize {
+							index,
+							width: image.width,
+							height: image.height,
+						}
+						.into())
+					}
+					Some((metadata, _, _)) => {
+						image.metadata = metadata.clone();
+						Ok(image)
+					}
+					None => {
+						self.first = Some((image.metadata.clone(), image.width, image.height));
+						Ok(image)
+					}
+				}
+			}
+			Source::Mlv { reader, mlv } => {
+				let data = mlv.frame(reader, index, &mut self.bytes)?;
+
+				Ok(Image {
+					width: mlv.width,
+					height: mlv.height,
+					metadata: mlv.metadata.clone(),
+					phantom: Default::default(),
+
+					data,
+				})
+			}
+		}
+	}
+
+	/// Work out a whitebalance and exposure to give every frame, so they all
+	/// match. [LockMode::Average] decodes every frame to get there, one at a
+	/// time.
+	pub fn lock(&mut self, mode: LockMode) -> Result<SequenceLock, Error> {
+		let frames = match mode {
+			LockMode::FirstFrame => 1,
+			LockMode::Average => self.len(),
+		};
+
+		let mut locks = Vec::with_capacity(frames);
+		for index in 0..frames {
+			let frame = self.frame(index)?.normalize();
+			locks.push(SequenceLock::from_frame(&frame));
+		}
+
+		SequenceLock::average(&locks).ok_or_else(|| SequenceError::Empty.into())
+	}
+
+	/// Every frame, in order, decoded as the iterator gets to it
+	pub fn frames(&mut self) -> Frames<'_> {
+		Frames {
+			sequence: self,
+			next: 0,
+		}
+	}
+}
+
+pub struct Frames<'a> {
+	sequence: &'a mut Sequence,
+	next: usize,
+}
+
+impl<'a> Iterator for Frames<'a> {
+	type Item = Result<Image<u16, BayerRgb>, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.next >= self.sequence.len() {
+			return None;
+		}
+
+		let frame = self.sequence.frame(self.next);
+		self.next += 1;
+		Some(frame)
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let left = self.sequence.len().saturating_sub(self.next);
+		(left, Some(left))
+	}
+}
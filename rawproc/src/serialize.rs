@@ -0,0 +1,63 @@
+//! serde for the types we use from other crates, which can't derive it
+//! themselves. Each module here is for a `#[serde(with = "...")]`.
+
+/// rawloader's CFA, as its pattern and size
+pub(crate) mod cfa {
+	use rawloader::CFA;
+	use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+	use crate::image::cfa_from_pattern;
+
+	#[derive(Serialize, Deserialize)]
+	struct Pattern {
+		pattern: String,
+		width: usize,
+		height: usize,
+	}
+
+	pub(crate) fn serialize<S: Serializer>(cfa: &CFA, serializer: S) -> Result<S::Ok, S::Error> {
+		Pattern {
+			pattern: cfa.name.clone(),
+			width: cfa.width,
+			height: cfa.height,
+		}
+		.serialize(serializer)
+	}
+
+	pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<CFA, D::Error> {
+		let Pattern {
+			pattern,
+			width,
+			height,
+		} = Pattern::deserialize(deserializer)?;
+
+		// Monochrome sensors don't have one at all
+		if pattern.is_empty() {
+			return Ok(CFA::new(""));
+		}
+
+		cfa_from_pattern(&pattern, width, height)
+			.ok_or_else(|| D::Error::custom(format!("{pattern} isn't a CFA pattern we know")))
+	}
+}
+
+/// nalgebra's 3x3 matrices, row by row
+pub(crate) mod matrix {
+	use nalgebra::Matrix3;
+	use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+	pub(crate) fn serialize<S: Serializer>(
+		m: &Matrix3<f32>,
+		serializer: S,
+	) -> Result<S::Ok, S::Error> {
+		let rows: [[f32; 3]; 3] = [0, 1, 2].map(|r| [m[(r, 0)], m[(r, 1)], m[(r, 2)]]);
+		rows.serialize(serializer)
+	}
+
+	pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+		deserializer: D,
+	) -> Result<Matrix3<f32>, D::Error> {
+		let rows = <[[f32; 3]; 3]>::deserialize(deserializer)?;
+		Ok(Matrix3::from_fn(|r, c| rows[r][c]))
+	}
+}
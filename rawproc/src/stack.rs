@@ -0,0 +1,192 @@
+//! Merging a few frames of the same scene into one, before the debayer.
+//! Bracketed exposures merge into one raw with the highlights of the short
+//! frames and the clean shadows of the long ones, and frames at the same
+//! exposure average out each other's noise.
+//!
+//! Everything happens on the mosaic, so the frames have to line up: a
+//! tripod, and nothing moving, or you'll get ghosts.
+
+use crate::{
+	colorspace::BayerRgb,
+	image::{noise::median, Image, RawMetadata},
+	par::*,
+	Error,
+};
+
+// A sample this close to white is clipped. Normalizing doesn't leave
+// clipped samples at exactly 1.0.
+const CLIPPED: f32 = 0.99;
+// Where a sample starts counting for less, so the switch from one frame to
+// the next as they clip is gradual and doesn't leave an edge
+const SHOULDER: f32 = 0.9;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StackError {
+	#[error("There aren't any frames to stack")]
+	NoFrames,
+	#[error("Frame {index} is {width}x{height} but the first is {first_width}x{first_height}")]
+	SizeMismatch {
+		index: usize,
+		width: usize,
+		height: usize,
+		first_width: usize,
+		first_height: usize,
+	},
+	#[error("Frame {0} has a different CFA pattern than the first")]
+	CfaMismatch(usize),
+	#[error("Frame {0} doesn't say how long it was exposed for, give its exposure yourself")]
+	UnknownExposure(usize),
+	#[error("Frame {0}'s exposure has to be more than zero")]
+	BadExposure(usize),
+	#[error("There are {frames} frames but {exposures} exposures")]
+	ExposureCount { frames: usize, exposures: usize },
+}
+
+/// How to combine the samples the frames have for each spot. In all of them,
+/// a clipped sample is left out unless every frame is clipped there, and then
+/// we use the frame that let the least light in.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Merge {
+	/// Each sample counts as much as the light its frame let in. Longer
+	/// exposures have less noise, so they get the say where they aren't
+	/// clipped. This is the one for brackets.
+	#[default]
+	Weighted,
+	/// A plain average. For frames at the same exposure, where it's the same
+	/// as Weighted.
+	Mean,
+	/// The middle sample. Noisier than the mean, but something that's only
+	/// in one frame, like a bird or a hot pixel, is dropped.
+	Median,
+}
+
+/// How much light a frame let in, relative to a second at f/1 and ISO 100,
+/// from its EXIF. An aperture or ISO we don't know is taken to be the same
+/// across the frames, which is usually right for a bracket. None if we don't
+/// know the exposure time.
+pub fn exposure(metadata: &RawMetadata) -> Option<f32> {
+	let exif = &metadata.exif;
+	let time = exif.exposure_time.filter(|t| *t > 0.0)?;
+	let f_number = exif.f_number.filter(|f| *f > 0.0).unwrap_or(1.0);
+	let iso = exif.iso.filter(|i| *i > 0).unwrap_or(100) as f32;
+
+	Some(time * (iso / 100.0) / (f_number * f_number))
+}
+
+/// [merge_exposures] with the exposures from each frame's EXIF. See
+/// [exposure].
+pub fn merge(frames: &[Image<f32, BayerRgb>], merge: Merge) -> Result<Image<f32, BayerRgb>, Error> {
+	let exposures = frames
+		.iter()
+		.enumerate()
+		.map(|(idx, frame)| exposure(&frame.metadata).ok_or(StackError::UnknownExposure(idx)))
+		.collect::<Result<Vec<f32>, _>>()?;
+
+	merge_exposures(frames, &exposures, merge)
+}
+
+/// Merge `frames`, which let in `exposures` light, into one. The exposures
+/// only matter relative to each other, so stops work as well as seconds, as
+/// long as they're linear: a frame that's a stop brighter has twice the
+/// exposure.
+///
+/// The frames should be [normalized](Image::normalize) and not whitebalanced
+/// yet. They're scaled to the brightness of the first frame, so the result
+/// is the first frame with more range: anything the first frame clipped is
+/// over 1.0, and has its metadata. Whitebalance and debayer it like any
+/// other raw.
+pub fn merge_exposures(
+	frames: &[Image<f32, BayerRgb>],
+	exposures: &[f32],
+	merge: Merge,
+) -> Result<Image<f32, BayerRgb>, Error> {
+	let first = frames.first().ok_or(StackError::NoFrames)?;
+	if frames.len() != exposures.len() {
+		return Err(StackError::ExposureCount {
+			frames: frames.len(),
+			exposures: exposures.len(),
+		}
+		.into());
+	}
+
+	if let Some(index) = exposures.iter().position(|e| !(*e > 0.0 && e.is_finite())) {
+		return Err(StackError::BadExposure(index).into());
+	}
+
+	for (index, frame) in frames.iter().enumerate() {
+		if frame.width != first.width || frame.height != first.height {
+			return Err(StackError::SizeMismatch {
+				index,
+				width: frame.width,
+				height: frame.height,
+				first_width: first.width,
+				first_height: first.height,
+			}
+			.into());
+		}
+		if frame.metadata.cfa.name != first.metadata.cfa.name {
+			return Err(StackError::CfaMismatch(index).into());
+		}
+	}
+
+	// What to multiply each frame by to bring it to the first's brightness
+	let scales: Vec<f32> = exposures.iter().map(|e| exposures[0] / e).collect();
+	let darkest = exposures
+		.iter()
+		.enumerate()
+		.min_by(|a, b| a.1.total_cmp(b.1))
+		.map(|(idx, _)| idx)
+		.unwrap_or(0);
+
+	let mut data = vec![0.0; first.data.len()];
+	data.par_chunks_mut(first.width.max(1))
+		.enumerate()
+		.for_each(|(y, row)| {
+			let mut samples = Vec::with_capacity(frames.len());
+			let mut weights = Vec::with_capacity(frames.len());
+
+			for (x, out) in row.iter_mut().enumerate() {
+				let idx = y * first.width + x;
+				samples.clear();
+				weights.clear();
+
+				for ((frame, exposure), scale) in frames.iter().zip(exposures).zip(&scales) {
+					let value = frame.data[idx];
+					let weight = weight(value) * exposure;
+					if weight > 0.0 {
+						samples.push(value * scale);
+						weights.push(weight);
+					}
+				}
+
+				*out = match merge {
+					_ if samples.is_empty() => frames[darkest].data[idx] * scales[darkest],
+					Merge::Weighted => {
+						let total: f32 = weights.iter().sum();
+						let sum: f32 = samples.iter().zip(&weights).map(|(s, w)| s * w).sum();
+						sum / total
+					}
+					Merge::Mean => samples.iter().sum::<f32>() / samples.len() as f32,
+					Merge::Median => median(&mut samples),
+				};
+			}
+		});
+
+	Ok(Image::from_raw_parts(
+		first.width,
+		first.height,
+		first.metadata.clone(),
+		data,
+	))
+}
+
+/// How much a sample can be trusted, from 1.0 down to 0.0 as it nears clipping
+fn weight(value: f32) -> f32 {
+	if value >= CLIPPED {
+		0.0
+	} else if value <= SHOULDER {
+		1.0
+	} else {
+		(CLIPPED - value) / (CLIPPED - SHOULDER)
+	}
+}
@@ -0,0 +1,248 @@
+//! How much of a raw we understand. A camera rawloader knows the sensor of,
+//! but not everything about, decodes fine and then comes out with nonsense
+//! levels or no colour matrix, so [decode](crate::decode) checks what it got
+//! and errors with [PartialSupport](crate::Error::PartialSupport) when the
+//! pipeline won't have what it needs.
+//!
+//! [probe](crate::probe) tells you all of it up front, and
+//! [decode_partial](crate::decode_partial) gets you the image anyway.
+
+use crate::{
+	colormatrix,
+	colorspace::ColorspaceKind,
+	cr2, dng,
+	image::{CfaLayout, DynImage},
+	tiff::{self, Tiff},
+};
+
+/// Whether a stage of the pipeline has what it needs
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Stage {
+	/// The raw told us everything
+	Ready,
+	/// The raw didn't say, so we filled in something reasonable. It's what
+	/// whitebalance does when there's no as shot, it uses daylight.
+	Fallback,
+	/// There's nothing to go on, and what comes out would be wrong
+	Missing,
+	/// This image doesn't go through the stage, like whitebalance for a
+	/// monochrome camera
+	NotNeeded,
+}
+
+impl Stage {
+	pub fn is_usable(&self) -> bool {
+		*self != Stage::Missing
+	}
+}
+
+/// What each stage that depends on the camera has to work with
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Support {
+	/// The black and white levels [normalize](crate::image::Image::normalize)
+	/// needs. Missing if white isn't above black.
+	pub levels: Stage,
+	/// The as shot whitebalance
+	pub whitebalance: Stage,
+	/// The colour matrix, from the file or from our own
+	/// [table](crate::colormatrix). Missing if neither has one we can invert.
+	pub matrix: Stage,
+	/// The active area and default crop. A raw without them doesn't need
+	/// cropping and is Ready, it's Missing if they don't fit on the sensor.
+	pub crop: Stage,
+}
+
+impl Support {
+	/// Look over a decoded raw for anything that won't work
+	pub fn of(image: &DynImage<u16>) -> Self {
+		let meta = &image.metadata;
+		let colour = image.colorspace != ColorspaceKind::Monochrome;
+
+		let levels = (0..3).all(|c| meta.whitelevels[c] > meta.blacklevels[c]);
+
+		// We only ever put daylight in when the camera didn't say. It being
+		// exactly what the camera said, to the bit, isn't going to happen.
+		let whitebalance = if !colour {
+			Stage::NotNeeded
+		} else if !meta.whitebalance.iter().all(|c| c.is_finite() && *c > 0.0) {
+			Stage::Missing
+		} else if meta.as_shot_whitebalance == meta.daylight_whitebalance {
+			Stage::Fallback
+		} else {
+			Stage::Ready
+		};
+
+		// Identity is what we use when there's nothing at all
+		let matrix = if !colour {
+			Stage::NotNeeded
+		} else if colormatrix::is_usable(&meta.xyz_to_cam) && !meta.xyz_to_cam.is_identity(0.0) {
+			Stage::Ready
+		} else {
+			Stage::Missing
+		};
+
+		let area = match meta.active_area {
+			Some(area) => {
+				let (across, down) = (area.left + area.right, area.top + area.bottom);
+				(across < image.width && down < image.height)
+					.then(|| (image.width - across, image.height - down))
+			}
+			None => Some((image.width, image.height)),
+		};
+		let crop = match (area, meta.default_crop) {
+			(None, _) => false,
+			(Some(_), None) => true,
+			(Some((width, height)), Some(crop)) => {
+				crop.left + crop.right < width && crop.top + crop.bottom < height
+			}
+		};
+
+		let stage = |ok| if ok { Stage::Ready } else { Stage::Missing };
+		Self {
+			levels: stage(levels),
+			whitebalance,
+			matrix,
+			crop: stage(crop),
+		}
+	}
+
+	/// Nothing's missing. Fallbacks are fine.
+	pub fn is_complete(&self) -> bool {
+		self.stages().iter().all(|(_, stage)| stage.is_usable())
+	}
+
+	/// What's missing, by name, like "colour matrix"
+	pub fn missing(&self) -> Vec<&'static str> {
+		self.stages()
+			.into_iter()
+			.filter(|(_, stage)| !stage.is_usable())
+			.map(|(name, _)| name)
+			.collect()
+	}
+
+	fn stages(&self) -> [(&'static str, Stage); 4] {
+		[
+			("levels", self.levels),
+			("whitebalance", self.whitebalance),
+			("colour matrix", self.matrix),
+			("crop", self.crop),
+		]
+	}
+}
+
+/// What kind of file a raw is
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Container {
+	Dng,
+	Cr2,
+	/// Something else built on TIFF, like NEF, ARW, or PEF
+	Tiff,
+	/// Anything that isn't TIFF at all, like RAF or CR3
+	Other,
+}
+
+/// How the samples were stored in the file
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Compression {
+	Uncompressed,
+	LosslessJpeg,
+	Deflate,
+	/// The TIFF Compression tag, for the ones the camera makers made up
+	Other(u16),
+	/// We couldn't find the image in the file ourselves, rawloader could
+	Unknown,
+}
+
+/// Everything [probe](crate::probe) found out about a raw
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FormatInfo {
+	pub container: Container,
+	/// Empty if the file doesn't say, same as in [RawMetadata](crate::image::RawMetadata)
+	pub make: String,
+	pub model: String,
+	/// The size of the whole sensor, before any crop
+	pub width: usize,
+	pub height: usize,
+	/// How many bits the samples are. From the file if it says, and from the
+	/// whitelevel if it doesn't.
+	pub bit_depth: u8,
+	pub compression: Compression,
+	/// None if there's no colour filter, or the image was already demosaiced
+	pub cfa: Option<CfaLayout>,
+	pub colorspace: ColorspaceKind,
+	pub support: Support,
+}
+
+/// Put together the [FormatInfo] for a raw we've decoded from `bytes`
+pub(crate) fn info(bytes: &[u8], image: &DynImage<u16>) -> FormatInfo {
+	let meta = &image.metadata;
+	let tiff = Tiff::new(bytes);
+
+	let container = if dng::is_dng(bytes) {
+		Container::Dng
+	} else if cr2::is_cr2(bytes) {
+		Container::Cr2
+	} else if tiff.is_some() {
+		Container::Tiff
+	} else {
+		Container::Other
+	};
+
+	// The raw IFD is the one the size of the sensor. CR2s don't give theirs
+	// a size, but they're always lossless JPEG.
+	let raw_ifd = tiff.as_ref().and_then(|tiff| {
+		let tag = |ifd: &tiff::Ifd, tag| {
+			ifd.get(tag)
+				.and_then(|e| tiff.u32s(e))
+				.and_then(|v| v.first().copied())
+		};
+
+		dng::image_ifds(tiff)
+			.into_iter()
+			.find(|ifd| {
+				tag(ifd, tiff::TAG_IMAGE_WIDTH) == Some(image.width as u32)
+					&& tag(ifd, tiff::TAG_IMAGE_LENGTH) == Some(image.height as u32)
+			})
+			.map(|ifd| {
+				(
+					tag(&ifd, tiff::TAG_COMPRESSION).map(|c| c as u16),
+					tag(&ifd, tiff::TAG_BITS_PER_SAMPLE),
+				)
+			})
+	});
+
+	let compression = match (container, raw_ifd) {
+		(Container::Cr2, _) => Compression::LosslessJpeg,
+		(_, Some((Some(tiff::COMPRESSION_NONE), _))) => Compression::Uncompressed,
+		(_, Some((Some(tiff::COMPRESSION_LJPEG), _))) => Compression::LosslessJpeg,
+		(_, Some((Some(tiff::COMPRESSION_DEFLATE), _))) => Compression::Deflate,
+		(_, Some((Some(other), _))) => Compression::Other(other),
+		_ => Compression::Unknown,
+	};
+
+	let bit_depth = match raw_ifd {
+		Some((_, Some(bits @ 1..=16))) => bits as u8,
+		_ => {
+			let white = meta.whitelevels.iter().max().copied().unwrap_or(0);
+			(u16::BITS - white.leading_zeros()) as u8
+		}
+	};
+
+	FormatInfo {
+		container,
+		make: meta.make.clone(),
+		model: meta.model.clone(),
+		width: image.width,
+		height: image.height,
+		bit_depth,
+		compression,
+		cfa: (image.colorspace == ColorspaceKind::BayerRgb).then(|| CfaLayout::of(&meta.cfa)),
+		colorspace: image.colorspace,
+		support: Support::of(image),
+	}
+}
@@ -0,0 +1,451 @@
+//! Just enough TIFF to go digging through the parts of a raw file that
+//! rawloader doesn't hand to us, like the EXIF and makernote IFDs, and to
+//! write the single strip files we make ourselves.
+//!
+//! Nothing in here allocates more than the entry list of an IFD and every
+//! read is bounds checked, so a mangled file gets us a None and not a panic.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Endian {
+	Little,
+	Big,
+}
+
+pub(crate) const TAG_IMAGE_WIDTH: u16 = 0x0100;
+pub(crate) const TAG_IMAGE_LENGTH: u16 = 0x0101;
+pub(crate) const TAG_BITS_PER_SAMPLE: u16 = 0x0102;
+pub(crate) const TAG_COMPRESSION: u16 = 0x0103;
+pub(crate) const TAG_MAKE: u16 = 0x010F;
+pub(crate) const TAG_MODEL: u16 = 0x0110;
+pub(crate) const TAG_ORIENTATION: u16 = 0x0112;
+pub(crate) const TAG_EXIF_IFD: u16 = 0x8769;
+pub(crate) const TAG_MAKERNOTE: u16 = 0x927C;
+const TAG_BODY_SERIAL_NUMBER: u16 = 0xA431;
+const TAG_CAMERA_SERIAL_NUMBER: u16 = 0xC62F;
+
+pub(crate) const COMPRESSION_NONE: u16 = 1;
+pub(crate) const COMPRESSION_LJPEG: u16 = 7;
+pub(crate) const COMPRESSION_DEFLATE: u16 = 8;
+
+// No real file has anywhere near this many IFDs in its chain
+const MAX_CHAIN: usize = 16;
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Tiff<'a> {
+	data: &'a [u8],
+	endian: Endian,
+}
+
+impl<'a> Tiff<'a> {
+	/// Read a TIFF header from the start of `data`. All offsets are then
+	/// relative to the start of the slice.
+	pub fn new(data: &'a [u8]) -> Option<Self> {
+		let endian = match data.get(0..2)? {
+			b"II" => Endian::Little,
+			b"MM" => Endian::Big,
+			_ => return None,
+		};
+
+		let tiff = Self { data, endian };
+		if tiff.u16_at(2)? != 42 {
+			return None;
+		}
+
+		Some(tiff)
+	}
+
+	/// For IFDs that borrow their parents header, like Canon's makernote.
+	pub fn with_endian(data: &'a [u8], endian: Endian) -> Self {
+		Self { data, endian }
+	}
+
+	pub fn data(&self) -> &'a [u8] {
+		self.data
+	}
+
+	pub fn endian(&self) -> Endian {
+		self.endian
+	}
+
+	pub fn first_ifd(&self) -> Option<Ifd> {
+		self.ifd(self.u32_at(4)? as usize)
+	}
+
+	pub fn ifd(&self, offset: usize) -> Option<Ifd> {
+		let count = self.u16_at(offset)? as usize;
+
+		let mut entries = Vec::with_capacity(count);
+		for idx in 0..count {
+			let at = offset + 2 + idx * 12;
+			entries.push(Entry {
+				tag: self.u16_at(at)?,
+				kind: self.u16_at(at + 2)?,
+				count: self.u32_at(at + 4)?,
+				field: at + 8,
+			});
+		}
+
+		let next = self.u32_at(offset + 2 + count * 12).unwrap_or(0);
+		Some(Ifd { entries, next })
+	}
+
+	/// IFD0 and every IFD chained after it. A broken file could loop the
+	/// chain back on itself, so we stop after a handful.
+	pub fn chain(&self) -> Vec<Ifd> {
+		let mut ifds = vec![];
+		let mut next = self.u32_at(4).unwrap_or(0);
+		while next != 0 && ifds.len() < MAX_CHAIN {
+			match self.ifd(next as usize) {
+				Some(ifd) => {
+					next = ifd.next;
+					ifds.push(ifd);
+				}
+				None => break,
+			}
+		}
+
+		ifds
+	}
+
+	/// Follow a pointer tag, like the EXIF IFD, to the IFD it points to
+	pub fn sub_ifd(&self, ifd: &Ifd, tag: u16) -> Option<Ifd> {
+		let offset = *self.u32s(ifd.get(tag)?)?.first()?;
+		self.ifd(offset as usize)
+	}
+
+	/// Where the value of this entry lives in the data
+	pub fn value_offset(&self, entry: &Entry) -> Option<usize> {
+		if entry.byte_len()? <= 4 {
+			Some(entry.field)
+		} else {
+			self.u32_at(entry.field).map(|off| off as usize)
+		}
+	}
+
+	pub fn bytes(&self, entry: &Entry) -> Option<&'a [u8]> {
+		let start = self.value_offset(entry)?;
+		self.data.get(start..start.checked_add(entry.byte_len()?)?)
+	}
+
+	pub fn u16s(&self, entry: &Entry) -> Option<Vec<u16>> {
+		let bytes = self.bytes(entry)?;
+		match entry.kind {
+			TYPE_BYTE | TYPE_UNDEFINED => Some(bytes.iter().map(|b| *b as u16).collect()),
+			TYPE_SHORT | TYPE_SSHORT => Some(
+				bytes
+					.chunks_exact(2)
+					.map(|c| self.endian.u16([c[0], c[1]]))
+					.collect(),
+			),
+			_ => None,
+		}
+	}
+
+	pub fn u32s(&self, entry: &Entry) -> Option<Vec<u32>> {
+		let bytes = self.bytes(entry)?;
+		match entry.kind {
+			TYPE_LONG | TYPE_SLONG | TYPE_IFD => Some(
+				bytes
+					.chunks_exact(4)
+					.map(|c| self.endian.u32([c[0], c[1], c[2], c[3]]))
+					.collect(),
+			),
+			_ => self
+				.u16s(entry)
+				.map(|shorts| shorts.into_iter().map(|s| s as u32).collect()),
+		}
+	}
+
+	pub fn rationals(&self, entry: &Entry) -> Option<Vec<f32>> {
+		let bytes = self.bytes(entry)?;
+		match entry.kind {
+			TYPE_RATIONAL => Some(
+				bytes
+					.chunks_exact(8)
+					.map(|c| {
+						let num = self.endian.u32([c[0], c[1], c[2], c[3]]);
+						let den = self.endian.u32([c[4], c[5], c[6], c[7]]);
+						num as f32 / den as f32
+					})
+					.collect(),
+			),
+			TYPE_SRATIONAL => Some(
+				bytes
+					.chunks_exact(8)
+					.map(|c| {
+						let num = self.endian.u32([c[0], c[1], c[2], c[3]]) as i32;
+						let den = self.endian.u32([c[4], c[5], c[6], c[7]]) as i32;
+						num as f32 / den as f32
+					})
+					.collect(),
+			),
+			_ => self
+				.u32s(entry)
+				.map(|longs| longs.into_iter().map(|l| l as f32).collect()),
+		}
+	}
+
+	/// ASCII values, with the NUL and any padding spaces trimmed off.
+	pub fn string(&self, entry: &Entry) -> Option<String> {
+		let bytes = self.bytes(entry)?;
+		let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+		Some(String::from_utf8_lossy(&bytes[..end]).trim().to_owned())
+	}
+
+	pub fn u16_at(&self, offset: usize) -> Option<u16> {
+		let b = self.data.get(offset..offset + 2)?;
+		Some(self.endian.u16([b[0], b[1]]))
+	}
+
+	pub fn u32_at(&self, offset: usize) -> Option<u32> {
+		let b = self.data.get(offset..offset + 4)?;
+		Some(self.endian.u32([b[0], b[1], b[2], b[3]]))
+	}
+}
+
+/// The camera's serial number. EXIF has it as BodySerialNumber and DNG as
+/// CameraSerialNumber, and a file might have either.
+pub(crate) fn serial_number(data: &[u8]) -> Option<String> {
+	let tiff = Tiff::new(data)?;
+	let ifd0 = tiff.first_ifd()?;
+
+	let body = tiff
+		.sub_ifd(&ifd0, TAG_EXIF_IFD)
+		.and_then(|exif| tiff.string(exif.get(TAG_BODY_SERIAL_NUMBER)?));
+	body.or_else(|| tiff.string(ifd0.get(TAG_CAMERA_SERIAL_NUMBER)?))
+		.filter(|serial| !serial.is_empty())
+}
+
+impl Endian {
+	pub fn u16(&self, b: [u8; 2]) -> u16 {
+		match self {
+			Endian::Little => u16::from_le_bytes(b),
+			Endian::Big => u16::from_be_bytes(b),
+		}
+	}
+
+	pub fn u32(&self, b: [u8; 4]) -> u32 {
+		match self {
+			Endian::Little => u32::from_le_bytes(b),
+			Endian::Big => u32::from_be_bytes(b),
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Ifd {
+	pub entries: Vec<Entry>,
+	/// Offset of the next IFD in the chain, 0 if there isn't one
+	pub next: u32,
+}
+
+impl Ifd {
+	pub fn get(&self, tag: u16) -> Option<&Entry> {
+		self.entries.iter().find(|e| e.tag == tag)
+	}
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Entry {
+	pub tag: u16,
+	pub kind: u16,
+	pub count: u32,
+	/// Offset of the 4 byte value/offset field of the entry
+	field: usize,
+}
+
+impl Entry {
+	fn byte_len(&self) -> Option<usize> {
+		(self.count as usize).checked_mul(type_size(self.kind))
+	}
+}
+
+pub(crate) const TYPE_BYTE: u16 = 1;
+pub(crate) const TYPE_ASCII: u16 = 2;
+pub(crate) const TYPE_SHORT: u16 = 3;
+pub(crate) const TYPE_LONG: u16 = 4;
+pub(crate) const TYPE_RATIONAL: u16 = 5;
+pub(crate) const TYPE_UNDEFINED: u16 = 7;
+pub(crate) const TYPE_SSHORT: u16 = 8;
+pub(crate) const TYPE_SLONG: u16 = 9;
+pub(crate) const TYPE_SRATIONAL: u16 = 10;
+pub(crate) const TYPE_IFD: u16 = 13;
+
+fn type_size(kind: u16) -> usize {
+	match kind {
+		TYPE_BYTE | TYPE_ASCII | TYPE_UNDEFINED | 6 => 1,
+		TYPE_SHORT | TYPE_SSHORT => 2,
+		TYPE_LONG | TYPE_SLONG | TYPE_IFD | 11 => 4,
+		TYPE_RATIONAL | TYPE_SRATIONAL | 12 => 8,
+		_ => 1,
+	}
+}
+
+/// Builds a little endian TIFF with a single IFD and a single strip, or just
+/// the IFD to put in a TIFF of your own. Sub IFDs, like EXIF's, go after it.
+pub(crate) struct IfdWriter {
+	entries: Vec<(u16, u16, u32, Vec<u8>)>,
+	strip: Vec<u8>,
+	subs: Vec<(u16, IfdWriter)>,
+}
+
+impl IfdWriter {
+	pub fn new() -> Self {
+		Self {
+			entries: vec![],
+			strip: vec![],
+			subs: vec![],
+		}
+	}
+
+	/// Add an entry, replacing the one with the same tag if there is one
+	pub fn push(&mut self, tag: u16, kind: u16, count: usize, data: Vec<u8>) {
+		self.entries.retain(|e| e.0 != tag);
+		self.entries.push((tag, kind, count as u32, data));
+	}
+
+	pub fn byte(&mut self, tag: u16, v: &[u8]) {
+		self.push(tag, 1, v.len(), v.to_vec());
+	}
+
+	pub fn ascii(&mut self, tag: u16, s: &str) {
+		let mut data = s.as_bytes().to_vec();
+		data.push(0);
+		self.push(tag, 2, data.len(), data);
+	}
+
+	pub fn short(&mut self, tag: u16, v: &[u16]) {
+		let data = v.iter().flat_map(|s| s.to_le_bytes()).collect();
+		self.push(tag, 3, v.len(), data);
+	}
+
+	pub fn long(&mut self, tag: u16, v: &[u32]) {
+		let data = v.iter().flat_map(|l| l.to_le_bytes()).collect();
+		self.push(tag, 4, v.len(), data);
+	}
+
+	pub fn rational(&mut self, tag: u16, v: &[f32]) {
+		let data = v
+			.iter()
+			.flat_map(|f| {
+				let num = (f.max(0.0) * 1_000_000.0) as u32;
+				[num.to_le_bytes(), 1_000_000u32.to_le_bytes()]
+			})
+			.flatten()
+			.collect();
+		self.push(tag, 5, v.len(), data);
+	}
+
+	pub fn undefined(&mut self, tag: u16, v: &[u8]) {
+		self.push(tag, 7, v.len(), v.to_vec());
+	}
+
+	pub fn srational(&mut self, tag: u16, v: &[f32]) {
+		let data = v
+			.iter()
+			.flat_map(|f| {
+				let num = (f * 10_000.0).round() as i32;
+				[num.to_le_bytes(), 10_000i32.to_le_bytes()]
+			})
+			.flatten()
+			.collect();
+		self.push(tag, 10, v.len(), data);
+	}
+
+	/// Point `tag` at another IFD. The pointer is filled in when we're laid
+	/// out.
+	pub fn sub_ifd(&mut self, tag: u16, ifd: IfdWriter) {
+		self.long(tag, &[0]);
+		self.subs.retain(|(t, _)| *t != tag);
+		self.subs.push((tag, ifd));
+	}
+
+	pub fn strip(&mut self, strip: Vec<u8>) {
+		self.strip = strip;
+	}
+
+	pub fn finish(mut self) -> Vec<u8> {
+		// StripOffsets and StripByteCounts. The strip goes right after the
+		// IFD and its values, so we know the offset once we know how big
+		// those are.
+		let strip = std::mem::take(&mut self.strip);
+		self.long(0x0111, &[0]);
+		self.long(0x0117, &[strip.len() as u32]);
+		let strip_offset = 8 + self.len();
+		self.long(0x0111, &[strip_offset as u32]);
+
+		let mut out = Vec::with_capacity(strip_offset + strip.len());
+		out.extend_from_slice(b"II");
+		out.extend_from_slice(&42u16.to_le_bytes());
+		out.extend_from_slice(&8u32.to_le_bytes());
+		out.extend_from_slice(&self.encode_at(8));
+		out.extend_from_slice(&strip);
+		out
+	}
+
+	/// How many bytes [encode_at](Self::encode_at) will make
+	pub fn len(&self) -> usize {
+		let values: usize = self
+			.entries
+			.iter()
+			.map(|(_, _, _, data)| data.len())
+			.filter(|len| *len > 4)
+			.map(|len| len + len % 2)
+			.sum();
+
+		let subs: usize = self.subs.iter().map(|(_, sub)| sub.len()).sum();
+		2 + self.entries.len() * 12 + 4 + values + subs
+	}
+
+	/// Just the IFD, the values that don't fit in it, and the sub IFDs, laid
+	/// out as if it starts `offset` bytes into the file. There's no next IFD.
+	pub fn encode_at(mut self, offset: usize) -> Vec<u8> {
+		self.entries.sort_by_key(|e| e.0);
+
+		let len = self.len();
+		let subs = std::mem::take(&mut self.subs);
+		let mut sub_offset = offset + len - subs.iter().map(|(_, sub)| sub.len()).sum::<usize>();
+		let mut sub_offsets = Vec::with_capacity(subs.len());
+		for (tag, sub) in &subs {
+			if let Some(entry) = self.entries.iter_mut().find(|e| e.0 == *tag) {
+				entry.3 = (sub_offset as u32).to_le_bytes().to_vec();
+			}
+			sub_offsets.push(sub_offset);
+			sub_offset += sub.len();
+		}
+
+		let mut values_offset = offset + 2 + self.entries.len() * 12 + 4;
+		let mut out = Vec::with_capacity(len);
+		out.extend_from_slice(&(self.entries.len() as u16).to_le_bytes());
+		for (tag, kind, count, data) in &self.entries {
+			out.extend_from_slice(&tag.to_le_bytes());
+			out.extend_from_slice(&kind.to_le_bytes());
+			out.extend_from_slice(&count.to_le_bytes());
+
+			if data.len() > 4 {
+				out.extend_from_slice(&(values_offset as u32).to_le_bytes());
+				values_offset += data.len() + data.len() % 2;
+			} else {
+				let mut field = [0u8; 4];
+				field[..data.len()].copy_from_slice(data);
+				out.extend_from_slice(&field);
+			}
+		}
+		out.extend_from_slice(&0u32.to_le_bytes());
+
+		for (_, _, _, data) in &self.entries {
+			if data.len() > 4 {
+				out.extend_from_slice(data);
+				if data.len() % 2 == 1 {
+					out.push(0);
+				}
+			}
+		}
+
+		for ((_, sub), at) in subs.into_iter().zip(sub_offsets) {
+			out.extend_from_slice(&sub.encode_at(at));
+		}
+
+		out
+	}
+}
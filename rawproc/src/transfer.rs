@@ -0,0 +1,122 @@
+//! Transfer functions. The curve that takes linear light to the encoded
+//! values a file stores, and back again.
+//!
+//! `encode` goes from linear to encoded, which is what you do on export.
+//! `decode` goes from encoded to linear, which is what you do when you bring
+//! in something that's already been encoded, like a JPEG.
+//!
+//! - sRGB, IEC 61966-2-1
+//! - Rec.709, ITU-R BT.709. This is the camera side (OETF) curve.
+//! - PQ, SMPTE ST 2084. Linear 1.0 is 10,000 nits.
+//! - HLG, ITU-R BT.2100. This is the OETF, linear 1.0 is nominal peak.
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TransferFunction {
+	Linear,
+	Srgb,
+	/// A plain power curve, like 1.8 or 2.2. Encoding raises to `1 / gamma`
+	Gamma(f32),
+	Rec709,
+	Pq,
+	Hlg,
+}
+
+impl TransferFunction {
+	pub const GAMMA_18: TransferFunction = TransferFunction::Gamma(1.8);
+	pub const GAMMA_22: TransferFunction = TransferFunction::Gamma(2.2);
+
+	/// Linear light to encoded values. Input is clamped to 0..1
+	pub fn encode(&self, linear: f32) -> f32 {
+		let l = linear.clamp(0.0, 1.0);
+
+		match self {
+			TransferFunction::Linear => l,
+			TransferFunction::Srgb => {
+				if l <= 0.0031308 {
+					l * 12.92
+				} else {
+					1.055 * l.powf(1.0 / 2.4) - 0.055
+				}
+			}
+			TransferFunction::Gamma(gamma) => l.powf(1.0 / gamma),
+			TransferFunction::Rec709 => {
+				if l < 0.018 {
+					l * 4.5
+				} else {
+					1.099 * l.powf(0.45) - 0.099
+				}
+			}
+			TransferFunction::Pq => {
+				let lm1 = l.powf(PQ_M1);
+				((PQ_C1 + PQ_C2 * lm1) / (1.0 + PQ_C3 * lm1)).powf(PQ_M2)
+			}
+			TransferFunction::Hlg => {
+				if l <= 1.0 / 12.0 {
+					(3.0 * l).sqrt()
+				} else {
+					HLG_A * (12.0 * l - HLG_B).ln() + HLG_C
+				}
+			}
+		}
+		.clamp(0.0, 1.0)
+	}
+
+	/// Encoded values to linear light. Input is clamped to 0..1
+	pub fn decode(&self, encoded: f32) -> f32 {
+		let v = encoded.clamp(0.0, 1.0);
+
+		match self {
+			TransferFunction::Linear => v,
+			TransferFunction::Srgb => {
+				if v <= 0.04045 {
+					v / 12.92
+				} else {
+					((v + 0.055) / 1.055).powf(2.4)
+				}
+			}
+			TransferFunction::Gamma(gamma) => v.powf(*gamma),
+			TransferFunction::Rec709 => {
+				if v < 0.081 {
+					v / 4.5
+				} else {
+					((v + 0.099) / 1.099).powf(1.0 / 0.45)
+				}
+			}
+			TransferFunction::Pq => {
+				let vm2 = v.powf(1.0 / PQ_M2);
+				((vm2 - PQ_C1).max(0.0) / (PQ_C2 - PQ_C3 * vm2)).powf(1.0 / PQ_M1)
+			}
+			TransferFunction::Hlg => {
+				if v <= 0.5 {
+					v * v / 3.0
+				} else {
+					(((v - HLG_C) / HLG_A).exp() + HLG_B) / 12.0
+				}
+			}
+		}
+		.clamp(0.0, 1.0)
+	}
+
+	/// The gamma you'd write down in a file format that only knows about a
+	/// single number, like PNG's gAMA chunk. An approximation for the curves
+	/// that aren't a plain power.
+	pub fn approximate_gamma(&self) -> f32 {
+		match self {
+			TransferFunction::Linear => 1.0,
+			TransferFunction::Srgb => 2.2,
+			TransferFunction::Gamma(gamma) => *gamma,
+			TransferFunction::Rec709 => 1.0 / 0.45,
+			TransferFunction::Pq | TransferFunction::Hlg => 2.4,
+		}
+	}
+}
+
+const PQ_M1: f32 = 2610.0 / 16384.0;
+const PQ_M2: f32 = 2523.0 / 4096.0 * 128.0;
+const PQ_C1: f32 = 3424.0 / 4096.0;
+const PQ_C2: f32 = 2413.0 / 4096.0 * 32.0;
+const PQ_C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+const HLG_A: f32 = 0.17883277;
+const HLG_B: f32 = 0.28466892;
+const HLG_C: f32 = 0.559_910_7;
@@ -0,0 +1,79 @@
+//! Alpha making it through resizing and out to a file
+
+mod common;
+
+use std::io::Cursor;
+
+use rawproc::{
+	colorspace::{LinSrgb, Srgb},
+	image::{AlphaImage, Filter, Image},
+};
+use tiff::{
+	decoder::{Decoder, DecodingResult},
+	ColorType,
+};
+
+const WIDTH: usize = 16;
+const HEIGHT: usize = 12;
+
+/// Red on the left half, transparent green on the right
+fn half_transparent<C: rawproc::colorspace::Colorspace>() -> AlphaImage<f32, C> {
+	let mut data = vec![];
+	let mut alpha = vec![];
+	for _ in 0..HEIGHT {
+		for x in 0..WIDTH {
+			let opaque = x < WIDTH / 2;
+			data.extend_from_slice(if opaque {
+				&[1.0, 0.0, 0.0]
+			} else {
+				&[0.0, 1.0, 0.0]
+			});
+			alpha.push(if opaque { 1.0 } else { 0.0 });
+		}
+	}
+
+	Image::from_raw_parts(WIDTH, HEIGHT, common::metadata(), data).with_alpha(alpha)
+}
+
+#[test]
+fn resize_keeps_alpha() {
+	let image: AlphaImage<f32, LinSrgb> = half_transparent();
+	let small = image.resize(WIDTH / 2, HEIGHT / 2, Filter::Lanczos3);
+
+	assert_eq!((small.width(), small.height()), (WIDTH / 2, HEIGHT / 2));
+	assert_eq!(small.alpha.len(), WIDTH / 2 * HEIGHT / 2);
+	assert!(small.alpha.iter().all(|a| (0.0..=1.0).contains(a)));
+
+	// The left edge is still opaque and the right is still transparent
+	assert!(small.alpha[0] > 0.99);
+	assert!(small.alpha[WIDTH / 2 - 1] < 0.01);
+}
+
+#[test]
+fn transparent_colour_doesnt_bleed() {
+	let image: AlphaImage<f32, Srgb> = half_transparent();
+	let small = image.resize(WIDTH / 4, HEIGHT / 4, Filter::Lanczos3);
+
+	// Every pixel with any alpha at all is red, none of the hidden green
+	for (px, a) in small.image.data.chunks_exact(3).zip(&small.alpha) {
+		if *a > 0.01 {
+			assert!(px[1] < 0.01, "green bled into {px:?} at alpha {a}");
+		}
+	}
+}
+
+#[test]
+fn tiff_has_alpha() {
+	let image: AlphaImage<f32, Srgb> = half_transparent();
+	let mut decoder = Decoder::new(Cursor::new(image.to_tiff16())).unwrap();
+
+	assert_eq!(decoder.dimensions().unwrap(), (WIDTH as u32, HEIGHT as u32));
+	assert_eq!(decoder.colortype().unwrap(), ColorType::RGBA(16));
+	let DecodingResult::U16(samples) = decoder.read_image().unwrap() else {
+		panic!("we wrote 16 bit samples");
+	};
+
+	assert_eq!(&samples[..4], &[u16::MAX, 0, 0, u16::MAX]);
+	let last = samples.len() - 4;
+	assert_eq!(&samples[last..], &[0, u16::MAX, 0, 0]);
+}
@@ -0,0 +1,56 @@
+//! Culling scores for DNGs we write, mosaiced and not
+
+mod common;
+
+use rawproc::{
+	batch,
+	colorspace::{BayerRgb, LinRgb},
+	encode::DngWriter,
+	image::Image,
+};
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+
+fn twelve_bit(values: Vec<f32>) -> Vec<u16> {
+	values.into_iter().map(|v| (v * 4095.0) as u16).collect()
+}
+
+#[test]
+fn mosaic() {
+	let data = twelve_bit(common::gradient(WIDTH, HEIGHT, 1));
+	let image: Image<u16, BayerRgb> =
+		Image::from_raw_parts(WIDTH, HEIGHT, common::metadata(), data);
+	let dng = DngWriter::new().encode(&image);
+
+	let analysis = batch::analyze_reader(&mut dng.as_slice()).unwrap();
+	assert!(analysis.sharpness > 0.0);
+	assert!(analysis.mean_luminance > 0.0 && analysis.mean_luminance < 1.0);
+	assert!(!analysis.is_unusable());
+}
+
+#[test]
+fn linear_raw() {
+	let data = twelve_bit(common::gradient(WIDTH, HEIGHT, 3));
+	let image: Image<u16, LinRgb> = Image::from_raw_parts(WIDTH, HEIGHT, common::metadata(), data);
+	let dng = DngWriter::new().encode_linear(&image);
+
+	let analysis = batch::analyze_reader(&mut dng.as_slice()).unwrap();
+	assert!(analysis.sharpness > 0.0);
+	assert!(!analysis.is_unusable());
+}
+
+#[test]
+fn blown_out() {
+	let image: Image<u16, BayerRgb> = Image::from_raw_parts(
+		WIDTH,
+		HEIGHT,
+		common::metadata(),
+		vec![4095; WIDTH * HEIGHT],
+	);
+	let dng = DngWriter::new().encode(&image);
+
+	let analysis = batch::analyze_reader(&mut dng.as_slice()).unwrap();
+	assert_eq!(analysis.clipped_highlights, 1.0);
+	assert!(analysis.is_unusable());
+}
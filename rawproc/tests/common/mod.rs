@@ -0,0 +1,53 @@
+//! What the tests share, like benches/common
+
+#![allow(dead_code)]
+
+use nalgebra::Matrix3;
+use rawproc::{
+	colormatrix,
+	exif::Exif,
+	image::{Orientation, RawMetadata},
+};
+
+/// Metadata for a made up twelve bit camera. It has a real camera's matrix
+/// so anything checking for one, like decoding a DNG we wrote, is happy.
+pub fn metadata() -> RawMetadata {
+	let xyz_to_cam = colormatrix::lookup("Sony", "ILCE-7M3").expect("it's in the table");
+
+	RawMetadata {
+		whitebalance: [2.1, 1.0, 1.6],
+		as_shot_whitebalance: [2.1, 1.0, 1.6],
+		daylight_whitebalance: [2.0, 1.0, 1.5],
+		whitebalance_presets: vec![],
+		whitebalance_selected: None,
+		whitebalance_fine_tune: None,
+		whitelevels: [4095; 3],
+		blacklevels: [0; 3],
+		active_area: None,
+		default_crop: None,
+		cfa: rawloader::CFA::new("RGGB"),
+		xyz_to_cam,
+		cam_to_xyz: xyz_to_cam.try_inverse().unwrap_or_else(Matrix3::identity),
+		make: String::from("Test"),
+		model: String::from("Camera"),
+		serial: None,
+		makernote: None,
+		sub_images: vec![],
+		lens_correction: None,
+		orientation: Orientation::Normal,
+		exif: Exif::default(),
+	}
+}
+
+/// A smooth gradient with some detail, `width * height * channels` long and
+/// all in 0 to 1. The same every time.
+pub fn gradient(width: usize, height: usize, channels: usize) -> Vec<f32> {
+	(0..width * height * channels)
+		.map(|idx| {
+			let (px, c) = (idx / channels, idx % channels);
+			let (x, y) = ((px % width) as f32, (px / width) as f32);
+			let wave = ((x * 0.7 + c as f32).sin() * (y * 0.3).cos() + 1.0) / 4.0;
+			(x / width as f32 + y / height as f32) / 4.0 + wave
+		})
+		.collect()
+}
@@ -0,0 +1,116 @@
+//! Camera RGB to XYZ to linear sRGB to sRGB, and the curve on the end
+
+mod common;
+
+use nalgebra::Matrix3;
+use rawproc::{
+	colorspace::{AdobeRgb, LinRgb, LinSrgb, Srgb, XYZ},
+	image::{Image, RawMetadata},
+	Error,
+};
+
+// sRGB's primaries and D65, from Bruce Lindbloom
+#[rustfmt::skip]
+const SRGB_TO_XYZ: Matrix3<f32> = Matrix3::new(
+	0.4124564, 0.3575761, 0.1804375,
+	0.2126729, 0.7151522, 0.0721750,
+	0.0193339, 0.119192, 0.950304,
+);
+
+/// A camera that sees exactly what sRGB does
+fn srgb_camera() -> RawMetadata {
+	let mut meta = common::metadata();
+	meta.cam_to_xyz = SRGB_TO_XYZ;
+	meta.xyz_to_cam = SRGB_TO_XYZ.try_inverse().unwrap();
+	meta
+}
+
+fn image<C: rawproc::colorspace::Colorspace>(meta: RawMetadata, data: Vec<f32>) -> Image<f32, C> {
+	Image::from_raw_parts(data.len() / 3, 1, meta, data)
+}
+
+fn assert_close(got: &[f32], want: &[f32], tolerance: f32) {
+	assert_eq!(got.len(), want.len());
+	for (idx, (g, w)) in got.iter().zip(want).enumerate() {
+		assert!((g - w).abs() <= tolerance, "{g} isn't {w}, at {idx}");
+	}
+}
+
+#[test]
+fn to_xyz_uses_the_camera_matrix() {
+	let meta = srgb_camera();
+	let rgb: Image<f32, LinRgb> = image(meta, vec![1.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+	let xyz: Image<f32, XYZ> = rgb.to_xyz();
+
+	// Each primary comes out as its column of the matrix
+	assert_close(&xyz.data[..3], &[0.4124564, 0.2126729, 0.0193339], 1e-6);
+	assert_close(&xyz.data[3..], &[0.1804375, 0.0721750, 0.950304], 1e-6);
+}
+
+#[test]
+fn srgb_camera_roundtrips() {
+	let data = common::gradient(32, 1, 3);
+	let rgb: Image<f32, LinRgb> = image(srgb_camera(), data.clone());
+	let linear: Image<f32, LinSrgb> = rgb.to_xyz().to_linsrgb();
+
+	assert_close(&linear.data, &data, 1e-3);
+}
+
+#[test]
+fn neutral_stays_neutral() {
+	// The camera's white is adapted to D65, so whatever the camera, its grey
+	// is sRGB's grey
+	let rgb: Image<f32, LinRgb> = image(common::metadata(), vec![0.5; 3]);
+	let linear = rgb.to_xyz().to_linsrgb();
+
+	assert_close(&linear.data, &[0.5; 3], 1e-3);
+}
+
+#[test]
+fn gamma_is_the_srgb_curve() {
+	let linear: Image<f32, LinSrgb> = image(srgb_camera(), vec![0.0, 0.002, 0.18, 0.5, 1.0, 1.0]);
+	let srgb: Image<f32, Srgb> = linear.gamma();
+
+	// The straight bit at the bottom, and then the curve
+	let want = [0.0, 0.002 * 12.92, 0.461_356, 0.735_357, 1.0, 1.0];
+	assert_close(&srgb.data, &want, 1e-4);
+}
+
+#[test]
+fn linearize_undoes_gamma() {
+	let data = common::gradient(32, 1, 3);
+	let linear: Image<f32, LinSrgb> = image(srgb_camera(), data.clone());
+
+	assert_close(&linear.gamma().linearize().data, &data, 1e-5);
+}
+
+#[test]
+fn to_srgb_is_every_step() {
+	let data = common::gradient(32, 1, 3);
+	let rgb: Image<f32, LinRgb> = image(common::metadata(), data);
+	let steps = rgb.clone().to_xyz().to_linsrgb().gamma();
+
+	assert_eq!(rgb.to_srgb().data, steps.data);
+}
+
+#[test]
+fn sixteen_bit_pipeline() {
+	// Half of the whitelevel is mid grey, which the curve takes to 0.735
+	let rgb: Image<u16, LinRgb> =
+		Image::from_raw_parts(1, 1, common::metadata(), vec![2048, 2048, 2048]);
+	let srgb: Image<u16, Srgb> = rgb.to_xyz().to_linsrgb().gamma();
+
+	for v in srgb.data {
+		assert!((v as i32 - 3011).abs() <= 4, "{v} isn't 3011");
+	}
+}
+
+#[test]
+fn to_rgb_needs_primaries() {
+	let xyz: Image<f32, XYZ> = image(srgb_camera(), vec![0.5; 3]);
+	assert!(xyz.clone().to_rgb::<AdobeRgb>().is_ok());
+	assert!(matches!(
+		xyz.to_rgb::<XYZ>(),
+		Err(Error::UnsupportedConversion { .. })
+	));
+}
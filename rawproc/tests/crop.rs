@@ -0,0 +1,54 @@
+//! Cropping to the crops the metadata gives us
+
+mod common;
+
+use rawproc::{
+	colorspace::BayerRgb,
+	image::{Crop, Image},
+};
+
+fn mosaic(active_area: Crop) -> Image<u16, BayerRgb> {
+	let mut metadata = common::metadata();
+	metadata.active_area = Some(active_area);
+	Image::from_raw_parts(8, 6, metadata, (0..48).collect())
+}
+
+#[test]
+fn active_area() {
+	let mut raw = mosaic(Crop {
+		top: 2,
+		right: 1,
+		bottom: 0,
+		left: 3,
+	});
+	raw.crop();
+
+	assert_eq!((raw.width, raw.height), (4, 4));
+	assert_eq!(&raw.data[..4], &[19, 20, 21, 22]);
+	assert!(raw.metadata.active_area.is_none());
+}
+
+#[test]
+fn bigger_than_the_image() {
+	// Wider than the image by one, and so tall it'd overflow
+	for area in [
+		Crop {
+			top: 0,
+			right: 5,
+			bottom: 0,
+			left: 4,
+		},
+		Crop {
+			top: usize::MAX,
+			right: 0,
+			bottom: 1,
+			left: 0,
+		},
+	] {
+		let mut raw = mosaic(area);
+		raw.crop();
+
+		assert_eq!((raw.width, raw.height), (8, 6));
+		assert_eq!(raw.data, (0..48).collect::<Vec<u16>>());
+	}
+}
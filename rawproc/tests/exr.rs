@@ -0,0 +1,110 @@
+//! Write EXRs and read them back with the exr crate, so what we write is
+//! what everything else reads
+
+mod common;
+
+use std::io::Cursor;
+
+use exr::prelude::{read, FlatSamples, ReadChannels, ReadLayers};
+use rawproc::{
+	colorspace::LinSrgb,
+	exr::{ExrWriter, Timecode},
+	image::Image,
+};
+
+const WIDTH: usize = 13;
+const HEIGHT: usize = 7;
+
+/// Every channel by name, and the size of the image
+fn decode(bytes: Vec<u8>) -> (usize, usize, Vec<(String, Vec<f32>)>) {
+	let image = read()
+		.no_deep_data()
+		.largest_resolution_level()
+		.all_channels()
+		.first_valid_layer()
+		.all_attributes()
+		.from_buffered(Cursor::new(bytes))
+		.expect("exr couldn't read what we wrote");
+
+	let layer = image.layer_data;
+	let channels = layer
+		.channel_data
+		.list
+		.into_iter()
+		.map(|channel| {
+			let samples = match channel.sample_data {
+				FlatSamples::F32(samples) => samples,
+				FlatSamples::F16(samples) => samples.into_iter().map(f32::from).collect(),
+				FlatSamples::U32(_) => panic!("we never write u32 channels"),
+			};
+			(channel.name.to_string(), samples)
+		})
+		.collect();
+
+	(layer.size.0, layer.size.1, channels)
+}
+
+fn image() -> Image<f32, LinSrgb> {
+	Image::from_raw_parts(
+		WIDTH,
+		HEIGHT,
+		common::metadata(),
+		common::gradient(WIDTH, HEIGHT, 3),
+	)
+}
+
+/// Pull one channel back out of the RGB samples
+fn channel(data: &[f32], c: usize) -> Vec<f32> {
+	data.iter().skip(c).step_by(3).copied().collect()
+}
+
+#[test]
+fn float_roundtrip() {
+	let image = image();
+	let (width, height, channels) = decode(ExrWriter::new().encode(&image));
+
+	assert_eq!((width, height), (WIDTH, HEIGHT));
+	let names: Vec<&str> = channels.iter().map(|(name, _)| name.as_str()).collect();
+	assert_eq!(names, ["B", "G", "R"]);
+	for (name, samples) in &channels {
+		let c = match name.as_str() {
+			"R" => 0,
+			"G" => 1,
+			_ => 2,
+		};
+		assert_eq!(samples, &channel(&image.data, c), "channel {name}");
+	}
+}
+
+#[test]
+fn half_roundtrip() {
+	let image = image();
+	let (_, _, channels) = decode(ExrWriter::new().half(true).encode(&image));
+
+	// Halves have eleven bits of precision, and everything's under 1
+	let (_, red) = channels.iter().find(|(name, _)| name == "R").unwrap();
+	for (read, wrote) in red.iter().zip(channel(&image.data, 0)) {
+		assert!((read - wrote).abs() <= 1.0 / 2048.0, "{read} isn't {wrote}");
+	}
+}
+
+#[test]
+fn alpha_roundtrip() {
+	let alpha = common::gradient(WIDTH, HEIGHT, 1);
+	let image = image().with_alpha(alpha.clone());
+	let writer = ExrWriter::new()
+		.frame_rate(24000, 1001)
+		.timecode(Timecode::from_frame(100, 23.976));
+	let (_, _, channels) = decode(writer.encode_alpha(&image));
+
+	let names: Vec<&str> = channels.iter().map(|(name, _)| name.as_str()).collect();
+	assert_eq!(names, ["A", "B", "G", "R"]);
+	assert_eq!(channels[0].1, alpha);
+}
+
+#[test]
+fn empty_image() {
+	let image: Image<f32, LinSrgb> = Image::from_raw_parts(0, 4, common::metadata(), vec![]);
+	// exr won't read an image with no pixels, we only care that we don't panic
+	assert!(!ExrWriter::new().encode(&image).is_empty());
+}
@@ -0,0 +1,107 @@
+//! Golden images. What the pipeline makes of a made up raw is kept in
+//! tests/golden, as PPMs so you can look at them, and anything that changes
+//! the output shows up here. NearestRandom is seeded, which is what makes
+//! it the same every run.
+//!
+//! When a change is meant to change the output, run these with
+//! `RAWPROC_BLESS=1` to write new golden images, and look at what changed
+//! before committing them.
+
+mod common;
+
+use std::{fs, path::PathBuf};
+
+use rawproc::{
+	colorspace::BayerRgb,
+	image::{Demosaic, Image},
+	recipe::Recipe,
+};
+
+const WIDTH: usize = 16;
+const HEIGHT: usize = 12;
+const SEED: u64 = 550;
+
+/// The gradient as a twelve bit RGGB mosaic
+fn mosaic() -> Image<u16, BayerRgb> {
+	let data = common::gradient(WIDTH, HEIGHT, 1)
+		.into_iter()
+		.map(|v| (v * 4095.0).round() as u16)
+		.collect();
+
+	Image::from_raw_parts(WIDTH, HEIGHT, common::metadata(), data)
+}
+
+/// A binary PPM. Over 255 it's two bytes a sample, big endian.
+fn ppm(width: usize, height: usize, maxval: u16, samples: &[u16]) -> Vec<u8> {
+	let mut out = format!("P6\n{width} {height}\n{maxval}\n").into_bytes();
+	for &sample in samples {
+		match maxval {
+			0..=255 => out.push(sample as u8),
+			_ => out.extend_from_slice(&sample.to_be_bytes()),
+		}
+	}
+	out
+}
+
+/// Compare against the golden image `name`, every byte within `tolerance`
+fn check(name: &str, got: &[u8], tolerance: u8) {
+	let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+		.join("tests/golden")
+		.join(name);
+
+	if std::env::var_os("RAWPROC_BLESS").is_some() {
+		fs::write(&path, got).unwrap();
+		return;
+	}
+
+	let want = fs::read(&path).unwrap_or_else(|_| {
+		panic!(
+			"there's no {}, run with RAWPROC_BLESS=1 to make it",
+			path.display()
+		)
+	});
+	assert_eq!(got.len(), want.len(), "{name} is a different size");
+	for (idx, (g, w)) in got.iter().zip(&want).enumerate() {
+		assert!(
+			g.abs_diff(*w) <= tolerance,
+			"{name} is {g} at byte {idx}, but the golden image is {w}"
+		);
+	}
+}
+
+#[test]
+fn nearest_random_is_seeded() {
+	let debayer = |seed| mosaic().debayer_with(Demosaic::NearestRandom { seed }).data;
+
+	assert_eq!(debayer(SEED), debayer(SEED));
+	assert_ne!(debayer(SEED), debayer(SEED + 1));
+}
+
+#[test]
+fn nearest_random_golden() {
+	let rgb = mosaic().debayer_with(Demosaic::NearestRandom { seed: SEED });
+
+	// It only ever picks a neighbour, so there's no arithmetic to differ
+	check(
+		"nearest_random.ppm",
+		&ppm(rgb.width, rgb.height, 4095, &rgb.data),
+		0,
+	);
+}
+
+#[test]
+fn recipe_golden() {
+	let recipe = Recipe {
+		demosaic: Demosaic::NearestRandom { seed: SEED },
+		..Recipe::new()
+	};
+	let srgb = recipe.apply(mosaic()).gamma().bytes();
+	let samples: Vec<u16> = srgb.data.iter().map(|&v| v as u16).collect();
+
+	// Floats can come out a hair different on another platform
+	check(
+		"recipe.ppm",
+		&ppm(srgb.width, srgb.height, 255, &samples),
+		1,
+	);
+}
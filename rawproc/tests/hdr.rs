@@ -0,0 +1,27 @@
+//! Radiance HDR output
+
+mod common;
+
+use rawproc::{colorspace::LinSrgb, image::Image};
+
+#[test]
+fn header_then_pixels() {
+	let image: Image<f32, LinSrgb> = Image::from_raw_parts(3, 2, common::metadata(), vec![0.5; 18]);
+	let mut out = vec![];
+	image.to_hdr(&mut out).unwrap();
+
+	assert!(out.starts_with(b"#?RADIANCE\n"));
+	let size = b"\n-Y 2 +X 3\n";
+	let at = out.windows(size.len()).position(|w| w == size).unwrap();
+	// Too narrow to run length encode, so it's four bytes a pixel
+	assert_eq!(out.len() - (at + size.len()), 3 * 2 * 4);
+}
+
+#[test]
+fn empty_image() {
+	let image: Image<f32, LinSrgb> = Image::from_raw_parts(0, 4, common::metadata(), vec![]);
+	let mut out = vec![];
+	image.to_hdr(&mut out).unwrap();
+
+	assert!(out.ends_with(b"\n-Y 4 +X 0\n"));
+}
@@ -0,0 +1,40 @@
+//! Black and white levels, and what's left of them after normalizing
+
+mod common;
+
+use rawproc::{colorspace::BayerRgb, image::Image};
+
+fn mosaic(value: u16) -> Image<u16, BayerRgb> {
+	let mut meta = common::metadata();
+	meta.blacklevels = [256, 512, 256];
+	Image::from_raw_parts(8, 8, meta, vec![value; 64])
+}
+
+#[test]
+fn normalize_levels_are_zero_and_one() {
+	let floats = mosaic(1024).normalize();
+
+	assert_eq!(floats.metadata.blacklevels, [0; 3]);
+	assert_eq!(floats.metadata.whitelevels, [1; 3]);
+}
+
+#[test]
+fn normalize_into_levels_are_zero_and_one() {
+	let floats = mosaic(1024).normalize_into(vec![0.5; 3]);
+
+	assert_eq!(floats.metadata.blacklevels, [0; 3]);
+	assert_eq!(floats.metadata.whitelevels, [1; 3]);
+	assert_eq!(floats.data.len(), 64);
+}
+
+#[test]
+fn hot_pixels_after_normalizing() {
+	// The same hot pixel has to be found whether the levels are the
+	// sensor's or normalized ones
+	let mut raw = mosaic(1024);
+	raw.data[3 * 8 + 3] = 4095;
+	let mut floats = raw.clone().normalize();
+
+	assert_eq!(raw.fix_hot_pixels(0.25), 1);
+	assert_eq!(floats.fix_hot_pixels(0.25), 1);
+}
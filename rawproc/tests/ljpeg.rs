@@ -0,0 +1,305 @@
+//! Lossless JPEG. The streams here are put together by a small reference
+//! encoder, straight out of T.81, so every predictor and restart intervals
+//! get decoded, not only what our own encoder writes.
+
+mod common;
+
+use rawproc::{
+	colorspace::BayerRgb,
+	encode::DngWriter,
+	image::Image,
+	ljpeg::{self, LjpegError},
+};
+
+/// Samples that jump around enough to need every size of difference
+fn samples(count: usize, precision: u8) -> Vec<u16> {
+	let max = (1u32 << precision) - 1;
+	(0..count as u32)
+		.map(|i| (i.wrapping_mul(2_654_435_761) >> 7) % (max + 1))
+		.map(|v| v as u16)
+		.collect()
+}
+
+/// Bits, most significant first, with a 0 stuffed after every 0xFF
+struct Bits {
+	out: Vec<u8>,
+	acc: u32,
+	count: u32,
+}
+
+impl Bits {
+	fn write(&mut self, value: u32, len: u32) {
+		for bit in (0..len).rev() {
+			self.acc = (self.acc << 1) | ((value >> bit) & 1);
+			self.count += 1;
+			if self.count == 8 {
+				self.out.push(self.acc as u8);
+				if self.acc == 0xFF {
+					self.out.push(0);
+				}
+				self.acc = 0;
+				self.count = 0;
+			}
+		}
+	}
+
+	/// Pad the last byte out with ones
+	fn flush(&mut self) {
+		if self.count > 0 {
+			self.write(0xFF, 8 - self.count);
+		}
+	}
+}
+
+fn segment(out: &mut Vec<u8>, marker: u16, data: &[u8]) {
+	out.extend_from_slice(&marker.to_be_bytes());
+	out.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+	out.extend_from_slice(data);
+}
+
+/// A lossless JPEG with any predictor and restart interval. The huffman
+/// table is every difference size with a five bit code, wasteful but easy.
+fn reference(
+	data: &[u16],
+	(width, height, components): (usize, usize, usize),
+	precision: u8,
+	predictor: u8,
+	restart: usize,
+) -> Vec<u8> {
+	let mut out = vec![0xFF, 0xD8];
+
+	let mut sof = vec![precision];
+	sof.extend_from_slice(&(height as u16).to_be_bytes());
+	sof.extend_from_slice(&(width as u16).to_be_bytes());
+	sof.push(components as u8);
+	for c in 0..components {
+		sof.extend_from_slice(&[c as u8, 0x11, 0]);
+	}
+	segment(&mut out, 0xFFC3, &sof);
+
+	let mut dht = vec![0, 0, 0, 0, 0, 17];
+	dht.extend_from_slice(&[0; 11]);
+	dht.extend(0..=16u8);
+	segment(&mut out, 0xFFC4, &dht);
+
+	if restart > 0 {
+		segment(&mut out, 0xFFDD, &(restart as u16).to_be_bytes());
+	}
+
+	let mut sos = vec![components as u8];
+	for c in 0..components {
+		sos.extend_from_slice(&[c as u8, 0x00]);
+	}
+	sos.extend_from_slice(&[predictor, 0, 0]);
+	segment(&mut out, 0xFFDA, &sos);
+
+	let row_len = width * components;
+	let initial = 1i32 << (precision - 1);
+	let mut bits = Bits {
+		out,
+		acc: 0,
+		count: 0,
+	};
+	let mut first_line = true;
+	for y in 0..height {
+		for x in 0..width {
+			let mcu = y * width + x;
+			if restart > 0 && mcu > 0 && mcu % restart == 0 {
+				bits.flush();
+				let rst = ((mcu / restart - 1) % 8) as u8;
+				bits.out.extend_from_slice(&[0xFF, 0xD0 + rst]);
+				first_line = true;
+			}
+
+			for c in 0..components {
+				let idx = y * row_len + x * components + c;
+				let sample = |idx: usize| data[idx] as i32;
+				let prediction = if x == 0 && (y == 0 || first_line) {
+					initial
+				} else if first_line {
+					sample(idx - components)
+				} else if x == 0 {
+					sample(idx - row_len)
+				} else {
+					let (ra, rb, rc) = (
+						sample(idx - components),
+						sample(idx - row_len),
+						sample(idx - row_len - components),
+					);
+					match predictor {
+						1 => ra,
+						2 => rb,
+						3 => rc,
+						4 => ra + rb - rc,
+						5 => ra + ((rb - rc) >> 1),
+						6 => rb + ((ra - rc) >> 1),
+						_ => (ra + rb) >> 1,
+					}
+				};
+
+				let mut diff = (sample(idx) - prediction) & 0xFFFF;
+				if diff > 32768 {
+					diff -= 65536;
+				}
+				let ssss = if diff == 32768 {
+					16
+				} else {
+					32 - diff.unsigned_abs().leading_zeros()
+				};
+
+				bits.write(ssss, 5);
+				if ssss != 0 && ssss != 16 {
+					let extra = if diff < 0 { diff - 1 } else { diff };
+					bits.write(extra as u32 & ((1 << ssss) - 1), ssss);
+				}
+			}
+		}
+		first_line = false;
+	}
+
+	bits.flush();
+	let mut out = bits.out;
+	out.extend_from_slice(&[0xFF, 0xD9]);
+	out
+}
+
+#[test]
+fn every_predictor() {
+	let (width, height, components) = (9, 6, 2);
+	let data = samples(width * height * components, 12);
+
+	for predictor in 1..=7 {
+		let jpeg = reference(&data, (width, height, components), 12, predictor, 0);
+		let image = ljpeg::decode(&jpeg).unwrap();
+
+		assert_eq!((image.width, image.height), (width, height));
+		assert_eq!((image.components, image.precision), (components, 12));
+		assert_eq!(image.data, data, "predictor {predictor}");
+	}
+}
+
+#[test]
+fn restart_intervals() {
+	let (width, height, components) = (7, 5, 2);
+	let data = samples(width * height * components, 14);
+
+	// A row at a time, and one that restarts in the middle of rows
+	for restart in [width, 3] {
+		for predictor in [1, 4, 7] {
+			let jpeg = reference(&data, (width, height, components), 14, predictor, restart);
+			let image = ljpeg::decode(&jpeg).unwrap();
+
+			assert_eq!(
+				image.data, data,
+				"predictor {predictor}, restarting every {restart}"
+			);
+		}
+	}
+}
+
+#[test]
+fn sixteen_bit_differences() {
+	// 0 then 65535 is a difference of 32768 either way round, the one that's
+	// written without any extra bits
+	let data = [0, 65535, 0, 65535, 32768, 0];
+	let jpeg = reference(&data, (3, 2, 1), 16, 1, 0);
+
+	assert_eq!(ljpeg::decode(&jpeg).unwrap().data, data);
+}
+
+#[test]
+fn bad_predictor() {
+	let data = samples(4 * 4, 12);
+	for predictor in [0, 8] {
+		let jpeg = reference(&data, (4, 4, 1), 12, predictor, 0);
+		assert!(matches!(
+			ljpeg::decode(&jpeg),
+			Err(LjpegError::BadPredictor(p)) if p == predictor
+		));
+	}
+}
+
+#[test]
+fn not_lossless() {
+	let mut jpeg = reference(&samples(4, 12), (2, 2, 1), 12, 1, 0);
+	// Make the SOF3 a baseline SOF0
+	jpeg[3] = 0xC0;
+
+	assert!(matches!(
+		ljpeg::decode(&jpeg),
+		Err(LjpegError::NotLossless(0xFFC0))
+	));
+}
+
+#[test]
+fn missing_table() {
+	let mut jpeg = reference(&samples(4, 12), (2, 2, 1), 12, 1, 0);
+	// Point the scan at table 1, which was never defined. It's the byte
+	// after the one component's id in the SOS.
+	let sos = jpeg.windows(2).position(|w| w == [0xFF, 0xDA]).unwrap();
+	jpeg[sos + 6] = 0x10;
+
+	assert!(matches!(
+		ljpeg::decode(&jpeg),
+		Err(LjpegError::MissingTable(1))
+	));
+}
+
+#[test]
+fn truncated() {
+	let (width, height) = (6, 4);
+	let data = samples(width * height * 2, 12);
+	let jpeg = reference(&data, (width, height, 2), 12, 6, 4);
+	let scan = jpeg.windows(2).position(|w| w == [0xFF, 0xDA]).unwrap();
+
+	// Cut off anywhere in the headers it's an error
+	for len in 0..scan + 12 {
+		assert!(ljpeg::decode(&jpeg[..len]).is_err(), "cut at {len}");
+	}
+
+	// In the entropy coded data it reads zeros from where it stopped, like
+	// libjpeg does. What comes out is wrong, but it comes out.
+	for len in scan + 12..jpeg.len() {
+		let image = ljpeg::decode(&jpeg[..len]).unwrap();
+		assert_eq!(image.data.len(), data.len());
+	}
+}
+
+#[test]
+fn encode_round_trip() {
+	// Odd widths too, and one component as well as the two a bayer row gets
+	for (width, height, components) in [(1, 1, 1), (7, 5, 1), (13, 3, 2), (9, 4, 3)] {
+		for precision in [12, 16] {
+			let data = samples(width * height * components, precision);
+			let jpeg = ljpeg::encode(&data, width, height, components, precision);
+			let image = ljpeg::decode(&jpeg).unwrap();
+
+			assert_eq!((image.width, image.height), (width, height));
+			assert_eq!(image.components, components);
+			assert_eq!(image.precision, precision);
+			assert_eq!(
+				image.data, data,
+				"{width}x{height}x{components} at {precision} bits"
+			);
+		}
+	}
+}
+
+#[test]
+fn compressed_dng_round_trip() {
+	for (width, height) in [(32, 8), (33, 9)] {
+		for precision in [12, 16] {
+			let mut metadata = common::metadata();
+			metadata.whitelevels = [((1u32 << precision) - 1) as u16; 3];
+			let data = samples(width * height, precision);
+			let image: Image<u16, BayerRgb> =
+				Image::from_raw_parts(width, height, metadata, data.clone());
+
+			let dng = DngWriter::new().compress(true).encode(&image);
+			let decoded = rawproc::decode_slice(&dng).unwrap();
+
+			assert_eq!((decoded.width, decoded.height), (width, height));
+			assert_eq!(decoded.data, data, "{width}x{height} at {precision} bits");
+		}
+	}
+}
@@ -0,0 +1,105 @@
+//! Recipes, saved and loaded and applied
+
+mod common;
+
+use rawproc::{
+	colorspace::BayerRgb,
+	image::{Crop, Demosaic, Image, ToneCurve},
+	recipe::{CropMode, Recipe, Sharpen, WhitebalanceMode},
+};
+
+/// A flat grey mosaic, and the same with one pixel stuck bright
+fn flat(hot: bool) -> Image<u16, BayerRgb> {
+	let mut data = vec![1024; 16 * 16];
+	if hot {
+		data[7 * 16 + 7] = 4095;
+	}
+	Image::from_raw_parts(16, 16, common::metadata(), data)
+}
+
+/// Nothing left at its default, so a setting that doesn't survive the trip
+/// can't hide behind one
+fn everything() -> Recipe {
+	Recipe {
+		crop: CropMode::ActiveArea,
+		hot_pixels: Some(0.25),
+		whitebalance: WhitebalanceMode::Custom([2.0, 1.0, 1.5]),
+		demosaic: Demosaic::Ahd,
+		false_color: Some(2),
+		lens: false,
+		denoise_luminance: 0.5,
+		denoise_chroma: 0.25,
+		exposure: 0.5,
+		contrast: 1.25,
+		saturation: 0.75,
+		curve: Some(ToneCurve::new(&[(0.0, 0.0), (0.5, 0.55), (1.0, 1.0)])),
+		sharpen: Some(Sharpen {
+			radius: 1.0,
+			amount: 0.5,
+			threshold: 0.01,
+		}),
+		orientation: false,
+	}
+}
+
+#[test]
+fn toml_round_trip() {
+	let recipe = everything();
+	let toml = recipe.to_toml();
+
+	assert!(toml.contains("false_color = 2"));
+	assert_eq!(Recipe::from_toml(&toml).unwrap(), recipe);
+}
+
+#[test]
+fn false_color_key() {
+	let recipe = Recipe::from_toml("false_color = 1").unwrap();
+	assert_eq!(recipe.false_color, Some(1));
+
+	assert!(Recipe::from_toml("false_color = -1").is_err());
+	assert!(Recipe::from_toml("false_color = 1.5").is_err());
+	assert_eq!(Recipe::default().false_color, None);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+	// toml 0.5 can't serialize enum variants that hold something, like a
+	// custom whitebalance
+	let recipe = Recipe {
+		whitebalance: WhitebalanceMode::Daylight,
+		..everything()
+	};
+	let value = toml::Value::try_from(&recipe).unwrap();
+	let back: Recipe = value.try_into().unwrap();
+
+	assert_eq!(back, recipe);
+}
+
+#[test]
+fn hot_pixels() {
+	// It's found after the normalize, so it has to be against those levels
+	let recipe = Recipe {
+		hot_pixels: Some(0.25),
+		..Recipe::new()
+	};
+	let fixed = recipe.apply(flat(true));
+	assert_eq!(fixed.data, recipe.apply(flat(false)).data);
+
+	let left = Recipe::new().apply(flat(true));
+	assert_ne!(left.data, fixed.data);
+}
+
+#[test]
+fn active_area_too_big() {
+	let mut raw = flat(false);
+	raw.metadata.active_area = Some(Crop {
+		top: 0,
+		right: 8,
+		bottom: 0,
+		left: 9,
+	});
+
+	let rgb = Recipe::new().apply(raw);
+	assert_eq!((rgb.width, rgb.height), (16, 16));
+}
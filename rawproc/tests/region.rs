@@ -0,0 +1,56 @@
+//! Debayering part of a mosaic
+
+mod common;
+
+use rawproc::{
+	colorspace::BayerRgb,
+	image::{Demosaic, Image, Region},
+	Error,
+};
+
+const WIDTH: usize = 32;
+const HEIGHT: usize = 24;
+
+fn mosaic() -> Image<u16, BayerRgb> {
+	let data = common::gradient(WIDTH, HEIGHT, 1)
+		.into_iter()
+		.map(|v| (v * 4095.0) as u16)
+		.collect();
+	Image::from_raw_parts(WIDTH, HEIGHT, common::metadata(), data)
+}
+
+#[test]
+fn same_as_a_full_debayer() {
+	let raw = mosaic();
+	let region = Region::new(5, 3, 10, 8);
+
+	for demosaic in [Demosaic::Bilinear, Demosaic::Ahd] {
+		let part = raw.debayer_region(region, demosaic).unwrap();
+		let mut full = raw.clone().debayer_with(demosaic);
+		full.crop_region(region);
+
+		assert_eq!((part.width, part.height), (10, 8));
+		assert_eq!(part.data, full.data, "{demosaic:?}");
+	}
+}
+
+#[test]
+fn out_of_bounds() {
+	let raw = mosaic();
+
+	// Past the edge, and so far past it that adding up the edge overflows
+	for region in [
+		Region::new(30, 0, 4, 4),
+		Region::new(0, 0, WIDTH, HEIGHT + 1),
+		Region::new(usize::MAX, 0, 2, 2),
+		Region::new(0, 1, 2, usize::MAX),
+	] {
+		assert!(matches!(
+			raw.debayer_region(region, Demosaic::Bilinear),
+			Err(Error::RegionOutOfBounds {
+				width: WIDTH,
+				height: HEIGHT
+			})
+		));
+	}
+}